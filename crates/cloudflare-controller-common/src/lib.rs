@@ -0,0 +1,48 @@
+//! Names shared by the tunnel and ingress controllers, kept in one place so they can't drift.
+
+/// Api group of every resource of this operator.
+pub const GROUP: &str = "cloudflare.ar2ro.io";
+
+pub const FINALIZER_NAME: &str = "tunnel.cloudflare.ar2ro.io/finalizer";
+pub const INGRESS_FINALIZER: &str = "ingress.cloudflare.ar2ro.io/finalizer";
+pub const TUNNEL_INGRESS_FINALIZER: &str = "tunnelingress.cloudflare.ar2ro.io/finalizer";
+pub const HTTPROUTE_FINALIZER: &str = "httproute.cloudflare.ar2ro.io/finalizer";
+
+/// Marks the tunnel Ingresses and TunnelIngresses go to when they don't name one.
+pub const DEFAULT_ANNOTATION: &str = "cloudflare.ar2ro.io/default-tunnel";
+/// `spec.controller` of the IngressClasses handled by the ingress controller.
+pub const INGRESS_CONTROLLER: &str = "cloudflare.ar2ro.io/ingress-controller";
+
+pub const NAME_LABEL: &str = "app.kubernetes.io/name";
+pub const MANAGED_BY_LABEL: &str = "app.kubernetes.io/managed-by";
+/// Value of `MANAGED_BY_LABEL` on everything the operator creates.
+pub const MANAGED_BY: &str = "cloudflare-tunnel-operator";
+
+/// Seconds between reconciles of a tunnel that's in sync.
+pub const RECONCILE_TIMER: u64 = 60;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn finalizer_name_is_valid_format() {
+        for finalizer in [
+            FINALIZER_NAME,
+            INGRESS_FINALIZER,
+            TUNNEL_INGRESS_FINALIZER,
+            HTTPROUTE_FINALIZER,
+        ] {
+            let (domain, suffix) = finalizer.split_once('/').unwrap();
+            let (resource, group) = domain.split_once('.').unwrap();
+
+            assert_eq!(suffix, "finalizer", "{}", finalizer);
+            assert_eq!(group, GROUP, "{}", finalizer);
+            assert!(
+                !resource.is_empty() && resource.chars().all(|c| c.is_ascii_lowercase()),
+                "{}",
+                finalizer
+            );
+        }
+    }
+}
@@ -1,58 +1,532 @@
+use crate::tunnel_configuration::{
+    self, GetTunnelConfiguration, TunnelConfiguration, TunnelConfigurationResult,
+    UpdateTunnelConfiguration,
+};
 use crate::AuthlessClient;
+use base64::{engine::general_purpose::STANDARD, Engine};
 use cloudflare::{
     endpoints::cfd_tunnel::{
-        create_tunnel, delete_tunnel, get_tunnel, get_tunnel_token, update_configuration,
-        ConfigurationSrc, Tunnel, TunnelConfiguration, TunnelToken,
+        create_tunnel, delete_tunnel, get_tunnel, get_tunnel_token, ConfigurationSrc, Tunnel,
+        TunnelToken,
     },
     framework::auth::Credentials,
-    framework::response::ApiFailure,
+    framework::endpoint::Endpoint,
+    framework::response::{ApiFailure, ApiResult},
 };
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
 use uuid::Uuid;
 
-#[allow(async_fn_in_trait)]
-pub trait CloudflaredTunnel: Send + Sync {
-    async fn create_tunnel<'a>(
+/// How many times a configuration write is retried after losing a race to another writer.
+const MAX_CONFIGURATION_ATTEMPTS: usize = 3;
+
+/// Pages `list_tunnels_paginated` fetches at most when the caller doesn't set a limit.
+pub const DEFAULT_MAX_PAGES: u32 = 100;
+
+static CONFIGURATION_CONFLICTS: AtomicU64 = AtomicU64::new(0);
+
+/// Total number of configuration write conflicts seen by this process.
+pub fn configuration_conflicts() -> u64 {
+    CONFIGURATION_CONFLICTS.load(Ordering::Relaxed)
+}
+
+/// What to do with configuration changes made outside of the operator.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DriftPolicy {
+    /// Hand the current remote configuration to the caller so it can be merged.
+    #[default]
+    Merge,
+    /// Ignore the current remote configuration and force our own.
+    Overwrite,
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigurationError {
+    #[error("Cloudflare api returned an error {0}")]
+    ApiFailure(#[from] ApiFailure),
+    #[error("configuration was modified concurrently {attempts} times in a row")]
+    Conflict { attempts: usize },
+    #[error("Cloudflare stored {stored} of {sent} rules, missing hostnames {missing_hostnames:?}")]
+    Truncated {
+        version: i64,
+        sent: usize,
+        stored: usize,
+        missing_hostnames: Vec<String>,
+    },
+}
+
+fn missing_hostnames(
+    sent: &TunnelConfiguration,
+    stored: Option<&TunnelConfiguration>,
+) -> Vec<String> {
+    let stored: HashSet<&str> = stored
+        .map(|stored| {
+            stored
+                .ingress
+                .iter()
+                .filter_map(|rule| rule.hostname.as_deref())
+                .collect()
+        })
+        .unwrap_or_default();
+
+    let mut missing: Vec<String> = sent
+        .ingress
+        .iter()
+        .filter_map(|rule| rule.hostname.as_deref())
+        .filter(|hostname| !stored.contains(hostname))
+        .map(|hostname| hostname.to_owned())
+        .collect();
+    missing.sort();
+    missing.dedup();
+    missing
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ListTunnelsParams<'a> {
+    pub per_page: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<&'a str>,
+    pub is_deleted: bool,
+}
+
+pub struct ListTunnels<'a> {
+    pub account_identifier: &'a str,
+    pub params: ListTunnelsParams<'a>,
+}
+
+impl Endpoint<Vec<Tunnel>> for ListTunnels<'_> {
+    fn method(&self) -> http::Method {
+        http::Method::GET
+    }
+
+    fn path(&self) -> String {
+        format!("accounts/{}/cfd_tunnel", self.account_identifier)
+    }
+
+    fn query(&self) -> Option<String> {
+        serde_urlencoded::to_string(&self.params).ok()
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RotateTunnelSecretParams {
+    pub tunnel_secret: String,
+}
+
+pub struct RotateTunnelSecret<'a> {
+    pub account_identifier: &'a str,
+    pub tunnel_id: Uuid,
+    pub params: RotateTunnelSecretParams,
+}
+
+impl Endpoint<Tunnel> for RotateTunnelSecret<'_> {
+    fn method(&self) -> http::Method {
+        http::Method::PATCH
+    }
+
+    fn path(&self) -> String {
+        format!(
+            "accounts/{}/cfd_tunnel/{}",
+            self.account_identifier, self.tunnel_id
+        )
+    }
+
+    fn body(&self) -> Option<String> {
+        Some(serde_json::to_string(&self.params).unwrap())
+    }
+
+    fn content_type(&self) -> Cow<'static, str> {
+        Cow::Borrowed("application/json")
+    }
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct RenameTunnelParams {
+    pub name: String,
+}
+
+pub struct RenameTunnel<'a> {
+    pub account_identifier: &'a str,
+    pub tunnel_id: Uuid,
+    pub params: RenameTunnelParams,
+}
+
+impl Endpoint<Tunnel> for RenameTunnel<'_> {
+    fn method(&self) -> http::Method {
+        http::Method::PATCH
+    }
+
+    fn path(&self) -> String {
+        format!(
+            "accounts/{}/cfd_tunnel/{}",
+            self.account_identifier, self.tunnel_id
+        )
+    }
+
+    fn body(&self) -> Option<String> {
+        Some(serde_json::to_string(&self.params).unwrap())
+    }
+
+    fn content_type(&self) -> Cow<'static, str> {
+        Cow::Borrowed("application/json")
+    }
+}
+
+/// A cloudflared instance registered with a tunnel, `conns` are its open edge connections.
+#[derive(Deserialize, Debug, Clone)]
+pub struct TunnelConnector {
+    pub id: Uuid,
+    #[serde(default)]
+    pub conns: Vec<TunnelConnection>,
+}
+
+impl ApiResult for TunnelConnector {}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct TunnelConnection {
+    pub id: Uuid,
+    #[serde(default)]
+    pub colo_name: String,
+    #[serde(default)]
+    pub is_pending_reconnect: bool,
+}
+
+pub struct ListTunnelConnections<'a> {
+    pub account_identifier: &'a str,
+    pub tunnel_id: Uuid,
+}
+
+impl Endpoint<Vec<TunnelConnector>> for ListTunnelConnections<'_> {
+    fn method(&self) -> http::Method {
+        http::Method::GET
+    }
+
+    fn path(&self) -> String {
+        format!(
+            "accounts/{}/cfd_tunnel/{}/connections",
+            self.account_identifier, self.tunnel_id
+        )
+    }
+}
+
+// INFO: Removes connections Cloudflare still lists for connectors that are gone.
+pub struct CleanupTunnelConnections<'a> {
+    pub account_identifier: &'a str,
+    pub tunnel_id: Uuid,
+}
+
+impl Endpoint<serde_json::Value> for CleanupTunnelConnections<'_> {
+    fn method(&self) -> http::Method {
+        http::Method::DELETE
+    }
+
+    fn path(&self) -> String {
+        format!(
+            "accounts/{}/cfd_tunnel/{}/connections",
+            self.account_identifier, self.tunnel_id
+        )
+    }
+}
+
+// INFO: The cloudflare crate leaves `result_info` untyped, only the cursor is needed here.
+#[derive(Deserialize, Debug, Default)]
+struct ResultInfo {
+    #[serde(default)]
+    cursor: Option<String>,
+    #[serde(default)]
+    cursors: Option<Cursors>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+struct Cursors {
+    #[serde(default)]
+    after: Option<String>,
+}
+
+impl ResultInfo {
+    fn next_cursor(result_info: Option<serde_json::Value>) -> Option<String> {
+        let result_info: ResultInfo = serde_json::from_value(result_info?).ok()?;
+        result_info
+            .cursors
+            .and_then(|cursors| cursors.after)
+            .or(result_info.cursor)
+            .filter(|cursor| !cursor.is_empty())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AppliedConfiguration {
+    pub version: i64,
+    pub changed: bool,
+}
+
+// INFO: Cloudflare can accept a configuration and silently drop rules it doesn't like, so check
+// what was stored.
+fn stored_as_sent(
+    sent: &TunnelConfiguration,
+    version: i64,
+    stored: Option<&TunnelConfiguration>,
+) -> Result<AppliedConfiguration, ConfigurationError> {
+    let stored_rules = stored.map_or(0, |stored| stored.ingress.len());
+    let missing_hostnames = missing_hostnames(sent, stored);
+
+    if stored_rules != sent.ingress.len() || !missing_hostnames.is_empty() {
+        return Err(ConfigurationError::Truncated {
+            version,
+            sent: sent.ingress.len(),
+            stored: stored_rules,
+            missing_hostnames,
+        });
+    }
+
+    Ok(AppliedConfiguration {
+        version,
+        changed: true,
+    })
+}
+
+/// Reads and writes of the remotely managed configuration of a tunnel.
+pub trait TunnelConfigurations: Send + Sync {
+    fn get_configuration(
         &self,
         credentials: &Credentials,
         account_id: &str,
-        name: &str,
-        tunnel_secret: Option<&'a [u8]>,
-        config_src: ConfigurationSrc,
-    ) -> Result<Tunnel, ApiFailure>;
-    async fn delete_tunnel(
+        tunnel_id: Uuid,
+    ) -> impl Future<Output = Result<TunnelConfigurationResult, ApiFailure>> + Send;
+    fn update_configuration(
         &self,
         credentials: &Credentials,
         account_id: &str,
         tunnel_id: Uuid,
-    ) -> Result<(), ApiFailure>;
-    async fn update_configuration(
+        config: &TunnelConfiguration,
+    ) -> impl Future<Output = Result<TunnelConfigurationResult, ApiFailure>> + Send;
+
+    /// Compare-then-write for the remotely managed configuration.
+    ///
+    /// With `Merge`, `desired` builds the configuration we want from the one currently stored
+    /// in Cloudflare and the write is skipped when nothing changed. Cloudflare's PUT doesn't
+    /// take an expected version, so the version is read again right before writing and one that
+    /// moved since the merge restarts the read/merge/write cycle instead of overwriting the
+    /// other writer. The configuration is read back after writing as well, a version we didn't
+    /// produce means another writer replaced ours and is retried the same way.
+    ///
+    /// With `Overwrite`, `desired` gets `None` and the result is written unconditionally.
+    ///
+    /// A write that Cloudflare stored with fewer rules than we sent is reported as `Truncated`.
+    fn apply_configuration<F>(
         &self,
         credentials: &Credentials,
         account_id: &str,
         tunnel_id: Uuid,
-        config: TunnelConfiguration,
-    ) -> Result<Option<TunnelConfiguration>, ApiFailure>;
-    async fn get_tunnel_token(
+        policy: DriftPolicy,
+        desired: F,
+    ) -> impl Future<Output = Result<AppliedConfiguration, ConfigurationError>> + Send
+    where
+        F: Fn(Option<&TunnelConfiguration>) -> TunnelConfiguration + Send,
+    {
+        async move {
+            if policy == DriftPolicy::Overwrite {
+                let config = desired(None);
+                let written = self
+                    .update_configuration(credentials, account_id, tunnel_id, &config)
+                    .await?;
+                let stored = match written.config {
+                    Some(_) => written.config,
+                    None => {
+                        self.get_configuration(credentials, account_id, tunnel_id)
+                            .await?
+                            .config
+                    }
+                };
+
+                return stored_as_sent(&config, written.version, stored.as_ref());
+            }
+
+            for _ in 0..MAX_CONFIGURATION_ATTEMPTS {
+                let current = self
+                    .get_configuration(credentials, account_id, tunnel_id)
+                    .await?;
+
+                let config = desired(current.config.as_ref());
+                if current.config.as_ref() == Some(&config) {
+                    return Ok(AppliedConfiguration {
+                        version: current.version,
+                        changed: false,
+                    });
+                }
+
+                let latest = self
+                    .get_configuration(credentials, account_id, tunnel_id)
+                    .await?;
+                if latest.version != current.version {
+                    CONFIGURATION_CONFLICTS.fetch_add(1, Ordering::Relaxed);
+                    continue;
+                }
+
+                let written = self
+                    .update_configuration(credentials, account_id, tunnel_id, &config)
+                    .await?;
+
+                let verified = self
+                    .get_configuration(credentials, account_id, tunnel_id)
+                    .await?;
+
+                if verified.version == written.version {
+                    // INFO: The PUT response is preferred when it echoes the configuration.
+                    let stored = written.config.as_ref().or(verified.config.as_ref());
+                    return stored_as_sent(&config, written.version, stored);
+                }
+
+                CONFIGURATION_CONFLICTS.fetch_add(1, Ordering::Relaxed);
+            }
+
+            Err(ConfigurationError::Conflict {
+                attempts: MAX_CONFIGURATION_ATTEMPTS,
+            })
+        }
+    }
+}
+
+pub trait CloudflaredTunnel: TunnelConfigurations {
+    fn create_tunnel(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        name: &str,
+        tunnel_secret: Option<&[u8]>,
+        config_src: ConfigurationSrc,
+    ) -> impl Future<Output = Result<Tunnel, ApiFailure>> + Send;
+    /// Without `cascade` Cloudflare refuses to delete a tunnel that still has connections.
+    fn delete_tunnel(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        tunnel_id: Uuid,
+        cascade: bool,
+    ) -> impl Future<Output = Result<(), ApiFailure>> + Send;
+    fn get_tunnel_token(
         &self,
         credentials: &Credentials,
         account_id: &str,
         tunnel_id: &str,
-    ) -> Result<TunnelToken, ApiFailure>;
-    async fn get_tunnel(
+    ) -> impl Future<Output = Result<TunnelToken, ApiFailure>> + Send;
+    fn get_tunnel(
         &self,
         credentials: &Credentials,
         account_id: &str,
         tunnel_id: &str,
-    ) -> Result<Tunnel, ApiFailure>;
+    ) -> impl Future<Output = Result<Tunnel, ApiFailure>> + Send;
+    /// Every tunnel of the account that isn't deleted, following the cursor until Cloudflare
+    /// stops returning one or `max_pages` (default [`DEFAULT_MAX_PAGES`]) pages were fetched.
+    fn list_tunnels_paginated(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        page_size: u32,
+        max_pages: Option<u32>,
+    ) -> impl Future<Output = Result<Vec<Tunnel>, ApiFailure>> + Send;
+    /// Replaces the secret of an existing tunnel, connectors using the old token are dropped.
+    fn rotate_tunnel_secret(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        tunnel_id: Uuid,
+        tunnel_secret: &[u8],
+    ) -> impl Future<Output = Result<Tunnel, ApiFailure>> + Send;
+    /// Changes the name of an existing tunnel, its id and connectors are kept.
+    fn rename_tunnel(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        tunnel_id: Uuid,
+        name: &str,
+    ) -> impl Future<Output = Result<Tunnel, ApiFailure>> + Send;
+    /// The tunnel that isn't deleted with exactly `name`, names are unique within an account.
+    fn find_tunnel_by_name(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        name: &str,
+    ) -> impl Future<Output = Result<Option<Tunnel>, ApiFailure>> + Send;
+    /// Connectors registered with the tunnel, including ones without open connections.
+    fn list_connections(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        tunnel_id: Uuid,
+    ) -> impl Future<Output = Result<Vec<TunnelConnector>, ApiFailure>> + Send;
+    fn cleanup_connections(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        tunnel_id: Uuid,
+    ) -> impl Future<Output = Result<(), ApiFailure>> + Send;
+}
+
+fn delete_tunnel_endpoint<'a>(
+    account_id: &'a str,
+    tunnel_id: &'a str,
+    cascade: bool,
+) -> delete_tunnel::DeleteTunnel<'a> {
+    delete_tunnel::DeleteTunnel {
+        account_identifier: account_id,
+        tunnel_id,
+        params: delete_tunnel::Params { cascade },
+    }
+}
+
+impl TunnelConfigurations for AuthlessClient {
+    async fn get_configuration(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        tunnel_id: Uuid,
+    ) -> Result<TunnelConfigurationResult, ApiFailure> {
+        let endpoint = GetTunnelConfiguration {
+            account_identifier: account_id,
+            tunnel_id,
+        };
+
+        match self.request(credentials, &endpoint).await {
+            Ok(res) => Ok(res.result),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn update_configuration(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        tunnel_id: Uuid,
+        config: &TunnelConfiguration,
+    ) -> Result<TunnelConfigurationResult, ApiFailure> {
+        let params = tunnel_configuration::Params { config };
+
+        let endpoint = UpdateTunnelConfiguration {
+            account_identifier: account_id,
+            tunnel_id,
+            params,
+        };
+
+        match self.request(credentials, &endpoint).await {
+            Ok(res) => Ok(res.result),
+            Err(err) => Err(err),
+        }
+    }
 }
 
 impl CloudflaredTunnel for AuthlessClient {
-    async fn create_tunnel<'a>(
+    async fn create_tunnel(
         &self,
         credentials: &Credentials,
         account_id: &str,
         name: &str,
-        tunnel_secret: Option<&'a [u8]>,
+        tunnel_secret: Option<&[u8]>,
         config_src: ConfigurationSrc,
     ) -> Result<Tunnel, ApiFailure> {
         let params = create_tunnel::Params {
@@ -78,15 +552,10 @@ impl CloudflaredTunnel for AuthlessClient {
         credentials: &Credentials,
         account_id: &str,
         tunnel_id: Uuid,
+        cascade: bool,
     ) -> Result<(), ApiFailure> {
-        let params = delete_tunnel::Params { cascade: true };
-
         let tunnel_id = tunnel_id.to_string();
-        let endpoint = delete_tunnel::DeleteTunnel {
-            account_identifier: account_id,
-            tunnel_id: &tunnel_id,
-            params,
-        };
+        let endpoint = delete_tunnel_endpoint(account_id, &tunnel_id, cascade);
 
         match self.request(credentials, &endpoint).await {
             Ok(_) => Ok(()),
@@ -94,53 +563,95 @@ impl CloudflaredTunnel for AuthlessClient {
         }
     }
 
-    async fn update_configuration(
+    async fn get_tunnel_token(
         &self,
         credentials: &Credentials,
         account_id: &str,
-        tunnel_id: Uuid,
-        config: TunnelConfiguration,
-    ) -> Result<Option<TunnelConfiguration>, ApiFailure> {
-        let params = update_configuration::Params { config };
-
-        let endpoint = update_configuration::UpdateTunnelConfiguration {
+        tunnel_id: &str,
+    ) -> Result<TunnelToken, ApiFailure> {
+        let endpoint = get_tunnel_token::TunnelToken {
             account_identifier: account_id,
             tunnel_id,
-            params,
         };
 
-        match self.request(credentials, &endpoint).await {
-            Ok(res) => Ok(res.result.config),
+        match self.request::<TunnelToken>(credentials, &endpoint).await {
+            Ok(res) => Ok(res.result),
             Err(err) => Err(err),
         }
     }
 
-    async fn get_tunnel_token(
+    async fn get_tunnel(
         &self,
         credentials: &Credentials,
         account_id: &str,
         tunnel_id: &str,
-    ) -> Result<TunnelToken, ApiFailure> {
-        let endpoint = get_tunnel_token::TunnelToken {
+    ) -> Result<Tunnel, ApiFailure> {
+        let endpoint = get_tunnel::GetTunnel {
             account_identifier: account_id,
             tunnel_id,
         };
 
-        match self.request::<TunnelToken>(credentials, &endpoint).await {
+        match self.request::<Tunnel>(credentials, &endpoint).await {
             Ok(res) => Ok(res.result),
             Err(err) => Err(err),
         }
     }
 
-    async fn get_tunnel(
+    async fn list_tunnels_paginated(
         &self,
         credentials: &Credentials,
         account_id: &str,
-        tunnel_id: &str,
+        page_size: u32,
+        max_pages: Option<u32>,
+    ) -> Result<Vec<Tunnel>, ApiFailure> {
+        let max_pages = max_pages.unwrap_or(DEFAULT_MAX_PAGES);
+        let mut tunnels = Vec::new();
+        let mut cursor: Option<String> = None;
+
+        for _ in 0..max_pages {
+            let endpoint = ListTunnels {
+                account_identifier: account_id,
+                params: ListTunnelsParams {
+                    per_page: page_size,
+                    cursor: cursor.as_deref(),
+                    name: None,
+                    is_deleted: false,
+                },
+            };
+
+            let page = match self.request::<Vec<Tunnel>>(credentials, &endpoint).await {
+                Ok(page) => page,
+                Err(err) => return Err(err),
+            };
+            tunnels.extend(page.result);
+
+            cursor = match ResultInfo::next_cursor(page.result_info) {
+                Some(cursor) => Some(cursor),
+                None => return Ok(tunnels),
+            };
+        }
+
+        println!(
+            "Stopped listing tunnels of account {} after {} pages",
+            account_id, max_pages
+        );
+        Ok(tunnels)
+    }
+
+    async fn rotate_tunnel_secret(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        tunnel_id: Uuid,
+        tunnel_secret: &[u8],
     ) -> Result<Tunnel, ApiFailure> {
-        let endpoint = get_tunnel::GetTunnel {
+        // INFO: Encoded the same way the cloudflare crate encodes the secret on create.
+        let endpoint = RotateTunnelSecret {
             account_identifier: account_id,
             tunnel_id,
+            params: RotateTunnelSecretParams {
+                tunnel_secret: STANDARD.encode(tunnel_secret),
+            },
         };
 
         match self.request::<Tunnel>(credentials, &endpoint).await {
@@ -148,4 +659,347 @@ impl CloudflaredTunnel for AuthlessClient {
             Err(err) => Err(err),
         }
     }
+
+    async fn rename_tunnel(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        tunnel_id: Uuid,
+        name: &str,
+    ) -> Result<Tunnel, ApiFailure> {
+        let endpoint = RenameTunnel {
+            account_identifier: account_id,
+            tunnel_id,
+            params: RenameTunnelParams {
+                name: name.to_owned(),
+            },
+        };
+
+        match self.request::<Tunnel>(credentials, &endpoint).await {
+            Ok(res) => Ok(res.result),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn find_tunnel_by_name(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        name: &str,
+    ) -> Result<Option<Tunnel>, ApiFailure> {
+        let endpoint = ListTunnels {
+            account_identifier: account_id,
+            params: ListTunnelsParams {
+                per_page: 50,
+                cursor: None,
+                name: Some(name),
+                is_deleted: false,
+            },
+        };
+
+        // INFO: The name filter is a prefix match on some accounts, so compare exactly.
+        match self.request::<Vec<Tunnel>>(credentials, &endpoint).await {
+            Ok(res) => Ok(res
+                .result
+                .into_iter()
+                .find(|tunnel| tunnel.name == name && tunnel.deleted_at.is_none())),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn list_connections(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        tunnel_id: Uuid,
+    ) -> Result<Vec<TunnelConnector>, ApiFailure> {
+        let endpoint = ListTunnelConnections {
+            account_identifier: account_id,
+            tunnel_id,
+        };
+
+        match self
+            .request::<Vec<TunnelConnector>>(credentials, &endpoint)
+            .await
+        {
+            Ok(res) => Ok(res.result),
+            Err(err) => Err(err),
+        }
+    }
+
+    async fn cleanup_connections(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        tunnel_id: Uuid,
+    ) -> Result<(), ApiFailure> {
+        let endpoint = CleanupTunnelConnections {
+            account_identifier: account_id,
+            tunnel_id,
+        };
+
+        match self
+            .request::<serde_json::Value>(credentials, &endpoint)
+            .await
+        {
+            Ok(_) => Ok(()),
+            Err(err) => Err(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tunnel_configuration::IngressRule;
+    use std::sync::Mutex;
+
+    /// Keeps a single configuration. The next `read_races` reads and `write_races` writes are
+    /// each followed by a write of another client before our next request. Rules for the
+    /// `dropped` hostname are silently left out of every write.
+    #[derive(Default)]
+    struct FakeConfigurations {
+        stored: Mutex<(i64, Option<TunnelConfiguration>)>,
+        read_races: Mutex<usize>,
+        write_races: Mutex<usize>,
+        dropped: Option<&'static str>,
+    }
+
+    impl FakeConfigurations {
+        fn result(&self, tunnel_id: Uuid) -> TunnelConfigurationResult {
+            let (version, config) = self.stored.lock().unwrap().clone();
+            TunnelConfigurationResult {
+                tunnel_id,
+                version,
+                config,
+            }
+        }
+
+        fn write(&self, config: TunnelConfiguration) {
+            let mut stored = self.stored.lock().unwrap();
+            *stored = (stored.0 + 1, Some(config));
+        }
+
+        fn race(&self, races: &Mutex<usize>) {
+            let mut races = races.lock().unwrap();
+            if *races > 0 {
+                *races -= 1;
+                self.write(TunnelConfiguration {
+                    ingress: vec![rule(&format!("other-{}.example.com", races))],
+                    ..TunnelConfiguration::default()
+                });
+            }
+        }
+    }
+
+    fn rule(hostname: &str) -> IngressRule {
+        IngressRule {
+            hostname: Some(hostname.to_owned()),
+            service: "http://web.default:80".to_owned(),
+            ..IngressRule::default()
+        }
+    }
+
+    fn hostnames(config: &TunnelConfiguration) -> Vec<&str> {
+        config
+            .ingress
+            .iter()
+            .filter_map(|rule| rule.hostname.as_deref())
+            .collect()
+    }
+
+    // INFO: Adds our rule to whatever is stored, like the ingress controller does.
+    fn with_ours(current: Option<&TunnelConfiguration>) -> TunnelConfiguration {
+        let mut config = current.cloned().unwrap_or_default();
+        if !hostnames(&config).contains(&"ours.example.com") {
+            config.ingress.push(rule("ours.example.com"));
+        }
+        config
+    }
+
+    impl TunnelConfigurations for FakeConfigurations {
+        async fn get_configuration(
+            &self,
+            _: &Credentials,
+            _: &str,
+            tunnel_id: Uuid,
+        ) -> Result<TunnelConfigurationResult, ApiFailure> {
+            let read = self.result(tunnel_id);
+            self.race(&self.read_races);
+            Ok(read)
+        }
+
+        async fn update_configuration(
+            &self,
+            _: &Credentials,
+            _: &str,
+            tunnel_id: Uuid,
+            config: &TunnelConfiguration,
+        ) -> Result<TunnelConfigurationResult, ApiFailure> {
+            let mut config = config.clone();
+            config
+                .ingress
+                .retain(|rule| rule.hostname.as_deref() != self.dropped);
+            self.write(config);
+            let written = self.result(tunnel_id);
+            self.race(&self.write_races);
+            Ok(written)
+        }
+    }
+
+    fn credentials() -> Credentials {
+        Credentials::UserAuthToken {
+            token: "token".to_owned(),
+        }
+    }
+
+    async fn apply(
+        client: &FakeConfigurations,
+        policy: DriftPolicy,
+    ) -> Result<AppliedConfiguration, ConfigurationError> {
+        client
+            .apply_configuration(&credentials(), "account", Uuid::nil(), policy, with_ours)
+            .await
+    }
+
+    fn stored_hostnames(client: &FakeConfigurations) -> Vec<String> {
+        let stored = client.result(Uuid::nil()).config.unwrap_or_default();
+        hostnames(&stored).into_iter().map(str::to_owned).collect()
+    }
+
+    #[tokio::test]
+    async fn write_before_ours_is_merged_instead_of_overwritten() {
+        let client = FakeConfigurations {
+            read_races: Mutex::new(1),
+            ..FakeConfigurations::default()
+        };
+        let conflicts = configuration_conflicts();
+
+        let applied = apply(&client, DriftPolicy::Merge).await.unwrap();
+
+        assert!(applied.changed);
+        // INFO: The other client's write and our merged one, nothing was written in between.
+        assert_eq!(applied.version, 2);
+        assert_eq!(
+            stored_hostnames(&client),
+            ["other-0.example.com", "ours.example.com"]
+        );
+        assert!(configuration_conflicts() > conflicts);
+    }
+
+    #[tokio::test]
+    async fn write_after_ours_is_merged_and_retried() {
+        let client = FakeConfigurations {
+            write_races: Mutex::new(1),
+            ..FakeConfigurations::default()
+        };
+        let conflicts = configuration_conflicts();
+
+        let applied = apply(&client, DriftPolicy::Merge).await.unwrap();
+
+        assert!(applied.changed);
+        // INFO: Our write, the other client's write and our retry.
+        assert_eq!(applied.version, 3);
+        assert_eq!(
+            stored_hostnames(&client),
+            ["other-0.example.com", "ours.example.com"]
+        );
+        assert!(configuration_conflicts() > conflicts);
+    }
+
+    #[tokio::test]
+    async fn unchanged_configuration_is_not_written() {
+        let client = FakeConfigurations::default();
+        client.write(with_ours(None));
+
+        let applied = apply(&client, DriftPolicy::Merge).await.unwrap();
+
+        assert!(!applied.changed);
+        assert_eq!(applied.version, 1);
+    }
+
+    #[tokio::test]
+    async fn losing_every_race_is_a_conflict() {
+        let client = FakeConfigurations {
+            read_races: Mutex::new(usize::MAX),
+            ..FakeConfigurations::default()
+        };
+
+        let result = apply(&client, DriftPolicy::Merge).await;
+
+        assert!(matches!(
+            result,
+            Err(ConfigurationError::Conflict {
+                attempts: MAX_CONFIGURATION_ATTEMPTS
+            })
+        ));
+        assert!(!stored_hostnames(&client).contains(&"ours.example.com".to_owned()));
+    }
+
+    #[tokio::test]
+    async fn overwrite_writes_without_looking_at_other_writers() {
+        let client = FakeConfigurations {
+            read_races: Mutex::new(usize::MAX),
+            ..FakeConfigurations::default()
+        };
+        client.write(with_ours(None));
+        client.write(TunnelConfiguration {
+            ingress: vec![rule("manual.example.com")],
+            ..TunnelConfiguration::default()
+        });
+
+        let applied = apply(&client, DriftPolicy::Overwrite).await.unwrap();
+
+        // INFO: Any read would have let another client write after it.
+        assert!(applied.changed);
+        assert_eq!(applied.version, 3);
+        assert_eq!(client.result(Uuid::nil()).version, 3);
+        assert_eq!(stored_hostnames(&client), ["ours.example.com"]);
+    }
+
+    #[tokio::test]
+    async fn dropped_rule_is_reported_as_truncated() {
+        let client = FakeConfigurations {
+            dropped: Some("invalid.example.com"),
+            ..FakeConfigurations::default()
+        };
+
+        let result = client
+            .apply_configuration(
+                &credentials(),
+                "account",
+                Uuid::nil(),
+                DriftPolicy::Merge,
+                |_| TunnelConfiguration {
+                    ingress: vec![rule("ours.example.com"), rule("invalid.example.com")],
+                    ..TunnelConfiguration::default()
+                },
+            )
+            .await;
+
+        match result {
+            Err(ConfigurationError::Truncated {
+                version,
+                sent,
+                stored,
+                missing_hostnames,
+            }) => {
+                assert_eq!(version, 1);
+                assert_eq!((sent, stored), (2, 1));
+                assert_eq!(missing_hostnames, ["invalid.example.com"]);
+            }
+            result => panic!("expected a truncated configuration, got {:?}", result),
+        }
+    }
+
+    #[test]
+    fn delete_passes_cascade_to_the_endpoint() {
+        for cascade in [true, false] {
+            let endpoint = delete_tunnel_endpoint("account", "tunnel", cascade);
+
+            assert_eq!(endpoint.params.cascade, cascade);
+            assert_eq!(endpoint.account_identifier, "account");
+            assert_eq!(endpoint.tunnel_id, "tunnel");
+        }
+    }
 }
@@ -0,0 +1,246 @@
+//! Wrapper that keeps every read against Cloudflare and only logs the writes.
+use crate::cfd_tunnel::{CloudflaredTunnel, TunnelConfigurations, TunnelConnector};
+use crate::tunnel_configuration::{TunnelConfiguration, TunnelConfigurationResult};
+use chrono::Utc;
+use cloudflare::{
+    endpoints::cfd_tunnel::{ConfigurationSrc, Tunnel, TunnelToken},
+    framework::auth::Credentials,
+    framework::response::ApiFailure,
+};
+use uuid::Uuid;
+
+/// Passes everything through to `inner` unless `dry_run` is set, in which case writes print
+/// what they would have done and answer with what Cloudflare currently has.
+pub struct DryRunCloudflareClient<C> {
+    inner: C,
+    dry_run: bool,
+}
+
+impl<C: CloudflaredTunnel> DryRunCloudflareClient<C> {
+    pub fn new(inner: C, dry_run: bool) -> DryRunCloudflareClient<C> {
+        Self { inner, dry_run }
+    }
+
+    pub fn is_dry_run(&self) -> bool {
+        self.dry_run
+    }
+}
+
+impl<C: CloudflaredTunnel> TunnelConfigurations for DryRunCloudflareClient<C> {
+    async fn get_configuration(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        tunnel_id: Uuid,
+    ) -> Result<TunnelConfigurationResult, ApiFailure> {
+        self.inner
+            .get_configuration(credentials, account_id, tunnel_id)
+            .await
+    }
+
+    async fn update_configuration(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        tunnel_id: Uuid,
+        config: &TunnelConfiguration,
+    ) -> Result<TunnelConfigurationResult, ApiFailure> {
+        if !self.dry_run {
+            return self
+                .inner
+                .update_configuration(credentials, account_id, tunnel_id, config)
+                .await;
+        }
+
+        // INFO: Answers with the stored version so `apply_configuration` sees no other writer.
+        println!(
+            "DRY RUN: would call update_configuration for {} with {} ingress rules",
+            tunnel_id,
+            config.ingress.len()
+        );
+        let current = self
+            .inner
+            .get_configuration(credentials, account_id, tunnel_id)
+            .await?;
+        Ok(TunnelConfigurationResult {
+            config: Some(config.clone()),
+            ..current
+        })
+    }
+}
+
+impl<C: CloudflaredTunnel> CloudflaredTunnel for DryRunCloudflareClient<C> {
+    async fn create_tunnel(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        name: &str,
+        tunnel_secret: Option<&[u8]>,
+        config_src: ConfigurationSrc,
+    ) -> Result<Tunnel, ApiFailure> {
+        if !self.dry_run {
+            return self
+                .inner
+                .create_tunnel(credentials, account_id, name, tunnel_secret, config_src)
+                .await;
+        }
+
+        // INFO: Nothing exists on Cloudflare, so the nil id makes the follow up reads fail
+        // visibly instead of acting on someone else's tunnel.
+        println!(
+            "DRY RUN: would call create_tunnel for {} in account {}",
+            name, account_id
+        );
+        Ok(Tunnel {
+            id: Uuid::nil(),
+            created_at: Utc::now(),
+            deleted_at: None,
+            name: name.to_owned(),
+            connections: Vec::new(),
+            metadata: serde_json::Value::Null,
+        })
+    }
+
+    async fn delete_tunnel(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        tunnel_id: Uuid,
+        cascade: bool,
+    ) -> Result<(), ApiFailure> {
+        if !self.dry_run {
+            return self
+                .inner
+                .delete_tunnel(credentials, account_id, tunnel_id, cascade)
+                .await;
+        }
+
+        println!(
+            "DRY RUN: would call delete_tunnel for {} in account {} (cascade: {})",
+            tunnel_id, account_id, cascade
+        );
+        Ok(())
+    }
+
+    async fn get_tunnel_token(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        tunnel_id: &str,
+    ) -> Result<TunnelToken, ApiFailure> {
+        self.inner
+            .get_tunnel_token(credentials, account_id, tunnel_id)
+            .await
+    }
+
+    async fn get_tunnel(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        tunnel_id: &str,
+    ) -> Result<Tunnel, ApiFailure> {
+        self.inner
+            .get_tunnel(credentials, account_id, tunnel_id)
+            .await
+    }
+
+    async fn list_tunnels_paginated(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        page_size: u32,
+        max_pages: Option<u32>,
+    ) -> Result<Vec<Tunnel>, ApiFailure> {
+        self.inner
+            .list_tunnels_paginated(credentials, account_id, page_size, max_pages)
+            .await
+    }
+
+    async fn rotate_tunnel_secret(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        tunnel_id: Uuid,
+        tunnel_secret: &[u8],
+    ) -> Result<Tunnel, ApiFailure> {
+        if !self.dry_run {
+            return self
+                .inner
+                .rotate_tunnel_secret(credentials, account_id, tunnel_id, tunnel_secret)
+                .await;
+        }
+
+        println!(
+            "DRY RUN: would call rotate_tunnel_secret for {} in account {}",
+            tunnel_id, account_id
+        );
+        self.inner
+            .get_tunnel(credentials, account_id, tunnel_id.to_string().as_ref())
+            .await
+    }
+
+    async fn rename_tunnel(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        tunnel_id: Uuid,
+        name: &str,
+    ) -> Result<Tunnel, ApiFailure> {
+        if !self.dry_run {
+            return self
+                .inner
+                .rename_tunnel(credentials, account_id, tunnel_id, name)
+                .await;
+        }
+
+        println!(
+            "DRY RUN: would call rename_tunnel for {} to {} in account {}",
+            tunnel_id, name, account_id
+        );
+        self.inner
+            .get_tunnel(credentials, account_id, tunnel_id.to_string().as_ref())
+            .await
+    }
+
+    async fn find_tunnel_by_name(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        name: &str,
+    ) -> Result<Option<Tunnel>, ApiFailure> {
+        self.inner
+            .find_tunnel_by_name(credentials, account_id, name)
+            .await
+    }
+
+    async fn list_connections(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        tunnel_id: Uuid,
+    ) -> Result<Vec<TunnelConnector>, ApiFailure> {
+        self.inner
+            .list_connections(credentials, account_id, tunnel_id)
+            .await
+    }
+
+    async fn cleanup_connections(
+        &self,
+        credentials: &Credentials,
+        account_id: &str,
+        tunnel_id: Uuid,
+    ) -> Result<(), ApiFailure> {
+        if !self.dry_run {
+            return self
+                .inner
+                .cleanup_connections(credentials, account_id, tunnel_id)
+                .await;
+        }
+
+        println!(
+            "DRY RUN: would call cleanup_connections for {} in account {}",
+            tunnel_id, account_id
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,72 @@
+use cloudflare::framework::response::ApiFailure;
+use reqwest::StatusCode;
+use std::error::Error as StdError;
+use std::fmt;
+
+/// Coarse classification of why a request to the Cloudflare api failed, so transport problems
+/// in the cluster aren't mistaken for Cloudflare rejecting the request.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FailureKind {
+    DnsResolution(String),
+    Connection(String),
+    Tls(String),
+    Timeout(String),
+    Api(StatusCode),
+    InvalidResponse,
+}
+
+impl fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FailureKind::DnsResolution(host) => write!(f, "DNS resolution failed for {}", host),
+            FailureKind::Connection(host) => write!(f, "connection to {} failed", host),
+            FailureKind::Tls(host) => write!(f, "TLS handshake with {} failed", host),
+            FailureKind::Timeout(host) => write!(f, "request to {} timed out", host),
+            FailureKind::Api(status) => write!(f, "api responded with {}", status),
+            FailureKind::InvalidResponse => write!(f, "api response could not be parsed"),
+        }
+    }
+}
+
+pub trait ApiFailureExt {
+    fn kind(&self) -> FailureKind;
+}
+
+impl ApiFailureExt for ApiFailure {
+    fn kind(&self) -> FailureKind {
+        match self {
+            ApiFailure::Error(status, _) => FailureKind::Api(*status),
+            ApiFailure::Invalid(err) => classify_reqwest_error(err),
+        }
+    }
+}
+
+fn classify_reqwest_error(err: &reqwest::Error) -> FailureKind {
+    let host = err
+        .url()
+        .and_then(|url| url.host_str())
+        .unwrap_or("unknown host")
+        .to_owned();
+
+    // INFO: reqwest doesn't expose the failure stage, so walk the source chain and look at the
+    // messages hyper and the TLS backend put in there.
+    let mut source: Option<&dyn StdError> = err.source();
+    while let Some(inner) = source {
+        let message = inner.to_string().to_lowercase();
+        if message.contains("dns error") || message.contains("failed to lookup address") {
+            return FailureKind::DnsResolution(host);
+        }
+        if message.contains("tls") || message.contains("certificate") {
+            return FailureKind::Tls(host);
+        }
+        source = inner.source();
+    }
+
+    if err.is_timeout() {
+        FailureKind::Timeout(host)
+    } else if err.is_connect() {
+        FailureKind::Connection(host)
+    } else {
+        FailureKind::InvalidResponse
+    }
+}
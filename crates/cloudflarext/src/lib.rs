@@ -5,7 +5,30 @@ use cloudflare::framework::{
     Environment, Error, HttpApiClientConfig,
 };
 
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::Duration;
+
+pub const DEFAULT_MAX_RETRIES: u32 = 3;
+pub const DEFAULT_RETRY_BASE_DELAY: Duration = Duration::from_secs(1);
+// INFO: Cloudflare gives up on api requests after 30 seconds as well.
+pub const DEFAULT_REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+// INFO: Rate limits and gateway errors are transient, anything else won't change on a retry.
+const RETRY_STATUS_CODES: [u16; 5] = [429, 500, 502, 503, 504];
+// INFO: A POST or PATCH may have gone through before the error, only a rate limited one is
+// known to be rejected and safe to send again.
+const RATE_LIMITED: u16 = 429;
+// INFO: Caps the exponent so the delay stops doubling at 32 times the base delay.
+const MAX_RETRY_EXPONENT: u32 = 5;
+
 pub mod cfd_tunnel;
+pub mod dry_run;
+pub mod failure;
+pub mod local_config;
+pub mod tunnel_configuration;
 
 trait CredentialsExt {
     fn header_map(&self) -> http::HeaderMap;
@@ -34,9 +57,89 @@ impl CredentialsExt for Credentials {
     }
 }
 
+/// Static DNS override for a hostname, parsed from `<domain>=<ip>:<port>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ResolveOverride {
+    pub domain: String,
+    pub addr: SocketAddr,
+}
+
+impl FromStr for ResolveOverride {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (domain, addr) = s
+            .split_once('=')
+            .ok_or_else(|| format!("expected <domain>=<ip>:<port>, got {}", s))?;
+
+        if domain.is_empty() {
+            return Err(format!("missing domain in {}", s));
+        }
+
+        let addr = addr
+            .parse::<SocketAddr>()
+            .map_err(|err| format!("invalid address {}: {}", addr, err))?;
+
+        Ok(ResolveOverride {
+            domain: domain.to_owned(),
+            addr,
+        })
+    }
+}
+
+/// Client options that aren't covered by `HttpApiClientConfig`.
+#[derive(Debug, Clone)]
+pub struct ClientConfig {
+    // INFO: Lets egress restricted clusters reach the api without cluster DNS.
+    pub resolve_overrides: Vec<ResolveOverride>,
+    /// Retries after the first attempt for network errors and transient status codes, requests
+    /// that aren't idempotent are only retried when rate limited or never connected.
+    pub max_retries: u32,
+    /// Delay before the first retry, doubled on every following one.
+    pub retry_base_delay: Duration,
+    /// Idle connections kept open to the api, reqwest keeps every one of them when unset.
+    pub max_idle_connections_per_host: Option<usize>,
+    /// Time allowed to establish a connection, bounded only by `request_timeout` when unset.
+    pub connection_timeout: Option<Duration>,
+    /// Time allowed for a whole request, from connecting to reading the body.
+    pub request_timeout: Option<Duration>,
+}
+
+impl Default for ClientConfig {
+    fn default() -> ClientConfig {
+        ClientConfig {
+            resolve_overrides: Vec::new(),
+            max_retries: DEFAULT_MAX_RETRIES,
+            retry_base_delay: DEFAULT_RETRY_BASE_DELAY,
+            max_idle_connections_per_host: None,
+            connection_timeout: None,
+            request_timeout: Some(DEFAULT_REQUEST_TIMEOUT),
+        }
+    }
+}
+
 pub struct AuthlessClient {
     environment: Environment,
     http_client: reqwest::Client,
+    max_retries: u32,
+    retry_base_delay: Duration,
+}
+
+fn is_idempotent(method: &http::Method) -> bool {
+    matches!(
+        *method,
+        http::Method::GET | http::Method::PUT | http::Method::DELETE | http::Method::HEAD
+    )
+}
+
+/// Truncated binary exponential backoff with ±25% jitter.
+fn retry_delay(base_delay: Duration, attempt: u32) -> Duration {
+    let delay = base_delay * 2u32.pow(attempt.min(MAX_RETRY_EXPONENT));
+    // INFO: A freshly seeded hasher is random enough for jitter without pulling in rand.
+    let random = RandomState::new().build_hasher().finish();
+    let jitter = 0.75 + (random % 1001) as f64 / 2000.0;
+
+    delay.mul_f64(jitter)
 }
 
 impl AuthlessClient {
@@ -44,19 +147,42 @@ impl AuthlessClient {
         config: HttpApiClientConfig,
         environment: Environment,
     ) -> Result<AuthlessClient, Error> {
-        let builder = reqwest::Client::builder().default_headers(config.default_headers);
+        Self::try_new_with(config, ClientConfig::default(), environment)
+    }
+
+    pub fn try_new_with(
+        config: HttpApiClientConfig,
+        client_config: ClientConfig,
+        environment: Environment,
+    ) -> Result<AuthlessClient, Error> {
+        let mut builder = reqwest::Client::builder().default_headers(config.default_headers);
+        for resolve in &client_config.resolve_overrides {
+            builder = builder.resolve(&resolve.domain, resolve.addr);
+        }
+        if let Some(max_idle) = client_config.max_idle_connections_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
+        }
+        if let Some(connection_timeout) = client_config.connection_timeout {
+            builder = builder.connect_timeout(connection_timeout);
+        }
+        if let Some(request_timeout) = client_config.request_timeout {
+            builder = builder.timeout(request_timeout);
+        }
+
         let http_client = builder.build()?;
         Ok(AuthlessClient {
             environment,
             http_client,
+            max_retries: client_config.max_retries,
+            retry_base_delay: client_config.retry_base_delay,
         })
     }
 
-    pub async fn request<ResultType>(
+    async fn send<ResultType>(
         &self,
         credentials: &Credentials,
         endpoint: &(dyn Endpoint<ResultType> + Send + Sync),
-    ) -> ApiResponse<ResultType>
+    ) -> Result<reqwest::Response, reqwest::Error>
     where
         ResultType: ApiResult,
     {
@@ -71,8 +197,54 @@ impl AuthlessClient {
             );
         }
 
-        let response = request.headers(credentials.header_map()).send().await?;
-        map_api_response(response).await
+        request.headers(credentials.header_map()).send().await
+    }
+
+    pub async fn request<ResultType>(
+        &self,
+        credentials: &Credentials,
+        endpoint: &(dyn Endpoint<ResultType> + Send + Sync),
+    ) -> ApiResponse<ResultType>
+    where
+        ResultType: ApiResult,
+    {
+        let idempotent = is_idempotent(&endpoint.method());
+        let mut attempt = 0;
+        loop {
+            let reason = match self.send(credentials, endpoint).await {
+                Ok(response)
+                    if response.status().as_u16() == RATE_LIMITED
+                        || (idempotent
+                            && RETRY_STATUS_CODES.contains(&response.status().as_u16())) =>
+                {
+                    if attempt >= self.max_retries {
+                        return map_api_response(response).await;
+                    }
+                    response.status().to_string()
+                }
+                Ok(response) => return map_api_response(response).await,
+                // INFO: A failed connect never reached Cloudflare, any method can be sent again.
+                Err(err) if idempotent || err.is_connect() => {
+                    if attempt >= self.max_retries {
+                        return Err(err.into());
+                    }
+                    err.to_string()
+                }
+                Err(err) => return Err(err.into()),
+            };
+
+            let delay = retry_delay(self.retry_base_delay, attempt);
+            attempt += 1;
+            println!(
+                "Cloudflare request to {} failed with {}, retry {}/{} in {:?}",
+                endpoint.path(),
+                reason,
+                attempt,
+                self.max_retries,
+                delay
+            );
+            tokio::time::sleep(delay).await;
+        }
     }
 }
 
@@ -95,3 +267,235 @@ async fn map_api_response<ResultType: ApiResult>(
         Err(ApiFailure::Error(status, errors))
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfd_tunnel::{
+        ListTunnels, ListTunnelsParams, RotateTunnelSecret, RotateTunnelSecretParams,
+    };
+    use crate::failure::{ApiFailureExt, FailureKind};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+
+    /// Answers every request with `status` and an empty result, counts the requests it got.
+    async fn serve(status: u16) -> (SocketAddr, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let requests = Arc::new(AtomicUsize::new(0));
+
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (mut stream, _) = listener.accept().await.unwrap();
+                let mut head = Vec::new();
+                while !head.ends_with(b"\r\n\r\n") {
+                    let mut byte = [0; 1];
+                    if stream.read(&mut byte).await.unwrap() == 0 {
+                        break;
+                    }
+                    head.push(byte[0]);
+                }
+                counter.fetch_add(1, Ordering::SeqCst);
+
+                let body =
+                    r#"{"result":[],"result_info":null,"success":true,"errors":[],"messages":[]}"#;
+                let response = format!(
+                    "HTTP/1.1 {} Status\r\ncontent-type: application/json\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        (addr, requests)
+    }
+
+    fn client(base_url: &str, client_config: ClientConfig) -> AuthlessClient {
+        let environment = Environment::Custom(reqwest::Url::parse(base_url).unwrap());
+        AuthlessClient::try_new_with(HttpApiClientConfig::default(), client_config, environment)
+            .unwrap()
+    }
+
+    fn credentials() -> Credentials {
+        Credentials::UserAuthToken {
+            token: "token".to_owned(),
+        }
+    }
+
+    fn list_tunnels() -> ListTunnels<'static> {
+        ListTunnels {
+            account_identifier: "account",
+            params: ListTunnelsParams {
+                per_page: 1,
+                ..ListTunnelsParams::default()
+            },
+        }
+    }
+
+    #[test]
+    fn resolve_override_is_parsed() {
+        assert_eq!(
+            "api.cloudflare.com=104.16.0.1:443".parse(),
+            Ok(ResolveOverride {
+                domain: "api.cloudflare.com".to_owned(),
+                addr: "104.16.0.1:443".parse().unwrap(),
+            })
+        );
+
+        for invalid in [
+            "api.cloudflare.com",
+            "=104.16.0.1:443",
+            "api.cloudflare.com=104.16.0.1",
+        ] {
+            assert!(invalid.parse::<ResolveOverride>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[tokio::test]
+    async fn resolve_override_routes_the_api_host() {
+        let (addr, requests) = serve(200).await;
+        // INFO: reqwest takes the port from the url, only the ip of the override is used.
+        let client = client(
+            &format!("http://api.cloudflare.test:{}/client/v4/", addr.port()),
+            ClientConfig {
+                resolve_overrides: vec![format!("api.cloudflare.test={}", addr).parse().unwrap()],
+                ..ClientConfig::default()
+            },
+        );
+
+        assert!(client
+            .request(&credentials(), &list_tunnels())
+            .await
+            .is_ok());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn unresolvable_host_is_a_dns_failure() {
+        let client = client(
+            "http://api.cloudflare.invalid/client/v4/",
+            ClientConfig {
+                max_retries: 0,
+                ..ClientConfig::default()
+            },
+        );
+
+        let failure = client
+            .request(&credentials(), &list_tunnels())
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            failure.kind(),
+            FailureKind::DnsResolution("api.cloudflare.invalid".to_owned())
+        );
+        assert_eq!(
+            failure.kind().to_string(),
+            "DNS resolution failed for api.cloudflare.invalid"
+        );
+    }
+
+    #[tokio::test]
+    async fn refused_connection_is_a_connection_failure() {
+        let port = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let client = client(
+            &format!("http://127.0.0.1:{}/client/v4/", port),
+            ClientConfig {
+                max_retries: 0,
+                ..ClientConfig::default()
+            },
+        );
+
+        let failure = client
+            .request(&credentials(), &list_tunnels())
+            .await
+            .unwrap_err();
+
+        assert_eq!(
+            failure.kind(),
+            FailureKind::Connection("127.0.0.1".to_owned())
+        );
+    }
+
+    fn rotate_tunnel_secret() -> RotateTunnelSecret<'static> {
+        RotateTunnelSecret {
+            account_identifier: "account",
+            tunnel_id: uuid::Uuid::nil(),
+            params: RotateTunnelSecretParams {
+                tunnel_secret: "secret".to_owned(),
+            },
+        }
+    }
+
+    fn retrying(addr: SocketAddr) -> AuthlessClient {
+        client(
+            &format!("http://{}/client/v4/", addr),
+            ClientConfig {
+                max_retries: 2,
+                retry_base_delay: Duration::from_millis(1),
+                ..ClientConfig::default()
+            },
+        )
+    }
+
+    #[tokio::test]
+    async fn idempotent_requests_are_retried() {
+        let (addr, requests) = serve(503).await;
+
+        let result = retrying(addr)
+            .request(&credentials(), &list_tunnels())
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn non_idempotent_requests_are_sent_once() {
+        let (addr, requests) = serve(503).await;
+
+        let result = retrying(addr)
+            .request(&credentials(), &rotate_tunnel_secret())
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn rate_limited_requests_are_retried_for_any_method() {
+        let (addr, requests) = serve(429).await;
+
+        let result = retrying(addr)
+            .request(&credentials(), &rotate_tunnel_secret())
+            .await;
+
+        assert!(result.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+    }
+
+    #[test]
+    fn only_safe_methods_are_idempotent() {
+        for method in [
+            http::Method::GET,
+            http::Method::PUT,
+            http::Method::DELETE,
+            http::Method::HEAD,
+        ] {
+            assert!(is_idempotent(&method), "{}", method);
+        }
+        for method in [http::Method::POST, http::Method::PATCH] {
+            assert!(!is_idempotent(&method), "{}", method);
+        }
+    }
+}
@@ -0,0 +1,216 @@
+use crate::tunnel_configuration::IngressRule;
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use uuid::Uuid;
+
+// INFO: The api takes these as seconds while cloudflared's config file wants go durations.
+const DURATION_FIELDS: [&str; 4] = [
+    "connectTimeout",
+    "tlsTimeout",
+    "tcpKeepAlive",
+    "keepAliveTimeout",
+];
+
+#[derive(Debug, thiserror::Error)]
+pub enum LocalConfigError {
+    #[error("tunnel token is not valid base64: {0}")]
+    TokenEncoding(#[from] base64::DecodeError),
+    #[error("tunnel token is not valid json: {0}")]
+    TokenFormat(#[from] serde_json::Error),
+    #[error("failed to render config.yaml: {0}")]
+    Render(#[from] serde_yaml::Error),
+}
+
+/// Credentials file cloudflared reads for a locally managed tunnel.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CredentialsFile {
+    #[serde(rename = "AccountTag")]
+    pub account_tag: String,
+    #[serde(rename = "TunnelSecret")]
+    pub tunnel_secret: String,
+    #[serde(rename = "TunnelID")]
+    pub tunnel_id: Uuid,
+}
+
+#[derive(Deserialize)]
+struct TunnelTokenClaims {
+    a: String,
+    s: String,
+    t: Uuid,
+}
+
+impl CredentialsFile {
+    /// A tunnel token is the base64 encoded credentials with shortened keys.
+    pub fn from_token(token: &str) -> Result<CredentialsFile, LocalConfigError> {
+        let claims: TunnelTokenClaims = serde_json::from_slice(&STANDARD.decode(token.trim())?)?;
+
+        Ok(CredentialsFile {
+            account_tag: claims.a,
+            tunnel_secret: claims.s,
+            tunnel_id: claims.t,
+        })
+    }
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "kebab-case")]
+struct ConfigFile<'a> {
+    tunnel: Uuid,
+    credentials_file: &'a str,
+    ingress: Vec<Value>,
+}
+
+fn origin_request_durations(rule: &mut Value) {
+    let origin_request = match rule.get_mut("originRequest").and_then(Value::as_object_mut) {
+        Some(origin_request) => origin_request,
+        None => return,
+    };
+
+    for field in DURATION_FIELDS {
+        if let Some(seconds) = origin_request.get(field).and_then(Value::as_i64) {
+            origin_request.insert(field.to_owned(), Value::String(format!("{}s", seconds)));
+        }
+    }
+}
+
+/// Renders a cloudflared `config.yaml`, a catch-all rule sending to `catch_all_service` is
+/// appended when the last rule still matches on a hostname since cloudflared refuses to start
+/// without one.
+pub fn render_config(
+    tunnel_id: Uuid,
+    credentials_file: &str,
+    rules: &[IngressRule],
+    catch_all_service: &str,
+) -> Result<String, LocalConfigError> {
+    let mut ingress = rules
+        .iter()
+        .map(|rule| {
+            let mut rule = serde_json::to_value(rule)?;
+            origin_request_durations(&mut rule);
+            Ok(rule)
+        })
+        .collect::<Result<Vec<Value>, serde_json::Error>>()?;
+
+    if rules.last().is_none_or(|rule| rule.hostname.is_some()) {
+        ingress.push(serde_json::to_value(IngressRule {
+            service: catch_all_service.to_owned(),
+            ..IngressRule::default()
+        })?);
+    }
+
+    Ok(serde_yaml::to_string(&ConfigFile {
+        tunnel: tunnel_id,
+        credentials_file,
+        ingress,
+    })?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tunnel_configuration::{OriginRequest, Seconds};
+    use serde_json::json;
+
+    #[test]
+    fn credentials_file_is_decoded_from_the_token() {
+        let tunnel_id = Uuid::new_v4();
+        let token =
+            STANDARD.encode(json!({ "a": "account", "s": "c2VjcmV0", "t": tunnel_id }).to_string());
+
+        assert_eq!(
+            CredentialsFile::from_token(&token).unwrap(),
+            CredentialsFile {
+                account_tag: "account".to_owned(),
+                tunnel_secret: "c2VjcmV0".to_owned(),
+                tunnel_id,
+            }
+        );
+        assert!(matches!(
+            CredentialsFile::from_token("not base64!"),
+            Err(LocalConfigError::TokenEncoding(_))
+        ));
+        assert!(matches!(
+            CredentialsFile::from_token(&STANDARD.encode("{}")),
+            Err(LocalConfigError::TokenFormat(_))
+        ));
+    }
+
+    #[test]
+    fn config_file_gets_a_catch_all_and_go_durations() {
+        let rules = [IngressRule {
+            hostname: Some("app.example.com".to_owned()),
+            path: Some("/api".to_owned()),
+            service: "http://web.default:80".to_owned(),
+            origin_request: Some(OriginRequest {
+                connect_timeout: Some(Seconds(30)),
+                no_tls_verify: Some(true),
+                ..OriginRequest::default()
+            }),
+        }];
+
+        let config = render_config(
+            Uuid::nil(),
+            "/etc/cloudflared/creds/credentials.json",
+            &rules,
+            "http_status:404",
+        )
+        .unwrap();
+
+        let config: Value = serde_yaml::from_str(&config).unwrap();
+        assert_eq!(
+            config,
+            json!({
+                "tunnel": Uuid::nil(),
+                "credentials-file": "/etc/cloudflared/creds/credentials.json",
+                "ingress": [
+                    {
+                        "hostname": "app.example.com",
+                        "path": "/api",
+                        "service": "http://web.default:80",
+                        "originRequest": { "connectTimeout": "30s", "noTLSVerify": true },
+                    },
+                    { "service": "http_status:404" },
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn config_file_ends_with_the_given_catch_all() {
+        let rules = [IngressRule {
+            hostname: Some("app.example.com".to_owned()),
+            service: "http://web.default:80".to_owned(),
+            ..IngressRule::default()
+        }];
+
+        let config =
+            render_config(Uuid::nil(), "credentials.json", &rules, "http_status:503").unwrap();
+
+        let config: Value = serde_yaml::from_str(&config).unwrap();
+        assert_eq!(
+            config["ingress"],
+            json!([
+                { "hostname": "app.example.com", "service": "http://web.default:80" },
+                { "service": "http_status:503" },
+            ])
+        );
+    }
+
+    #[test]
+    fn existing_catch_all_is_kept() {
+        let rules = [IngressRule {
+            service: "http://fallback.default:80".to_owned(),
+            ..IngressRule::default()
+        }];
+
+        let config =
+            render_config(Uuid::nil(), "credentials.json", &rules, "http_status:404").unwrap();
+
+        let config: Value = serde_yaml::from_str(&config).unwrap();
+        assert_eq!(
+            config["ingress"],
+            json!([{ "service": "http://fallback.default:80" }])
+        );
+    }
+}
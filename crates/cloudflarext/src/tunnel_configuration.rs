@@ -0,0 +1,413 @@
+use cloudflare::framework::{endpoint::Endpoint, response::ApiResult};
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Metadata, NumberValidation, Schema, SchemaObject};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use uuid::Uuid;
+
+/// Service of the last rule, cloudflared requires one that matches every request.
+pub const CATCH_ALL_SERVICE: &str = "http_status:404";
+
+/// Remotely managed cloudflared configuration, mirrors the `config` object returned by the
+/// `cfd_tunnel/{tunnel_id}/configurations` endpoints.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct TunnelConfiguration {
+    #[serde(default)]
+    pub ingress: Vec<IngressRule>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin_request: Option<OriginRequest>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct IngressRule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hostname: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    pub service: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin_request: Option<OriginRequest>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OriginRequest {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub connect_timeout: Option<Seconds>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls_timeout: Option<Seconds>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tcp_keep_alive: Option<Seconds>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_alive_timeout: Option<Seconds>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub keep_alive_connections: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub no_happy_eyeballs: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http_host_header: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub origin_server_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_pool: Option<String>,
+    // INFO: cloudflared spells this one with an uppercase TLS.
+    #[serde(
+        default,
+        rename = "noTLSVerify",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub no_tls_verify: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub disable_chunked_encoding: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub http2_origin: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy_type: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access: Option<OriginRequestAccess>,
+}
+
+/// Timeout in whole seconds, the unit the Cloudflare api uses. Serialized as a plain integer.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(transparent)]
+pub struct Seconds(pub i32);
+
+// INFO: Inlined so the unit and the lower bound end up on every field of the CRD schema.
+impl JsonSchema for Seconds {
+    fn is_referenceable() -> bool {
+        false
+    }
+
+    fn schema_name() -> String {
+        "Seconds".to_owned()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            instance_type: Some(InstanceType::Integer.into()),
+            format: Some("int32".to_owned()),
+            metadata: Some(Box::new(Metadata {
+                description: Some("Duration in whole seconds, must be positive.".to_owned()),
+                ..Metadata::default()
+            })),
+            number: Some(Box::new(NumberValidation {
+                minimum: Some(1.0),
+                ..NumberValidation::default()
+            })),
+            extensions: [("x-kubernetes-int-or-string".to_owned(), false.into())]
+                .into_iter()
+                .collect(),
+            ..SchemaObject::default()
+        }
+        .into()
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OriginRequestAccess {
+    #[serde(default)]
+    pub required: bool,
+    pub team_name: String,
+    #[serde(default)]
+    pub aud_tag: Vec<String>,
+}
+
+/// Two rules for the same hostname and path ask for contradictory origin settings, so which one
+/// applies would only depend on rule order.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("conflicting originRequest settings for {hostname}{path}: {fields:?}")]
+pub struct ConflictingOriginSettings {
+    pub hostname: String,
+    pub path: String,
+    pub fields: Vec<String>,
+}
+
+/// Reduces a cloudflared path regex to the literal prefix it matches so overlapping paths can
+/// be compared, e.g. `^/api/.*` and `/api` both become `/api`.
+pub fn canonical_path(path: Option<&str>) -> String {
+    let path = path.unwrap_or_default();
+    let path = path.strip_prefix('^').unwrap_or(path);
+    let path = path.strip_suffix('$').unwrap_or(path);
+    let path = path.strip_suffix(".*").unwrap_or(path);
+    let path = path.strip_suffix('*').unwrap_or(path);
+
+    let mut canonical = String::with_capacity(path.len() + 1);
+    if !path.starts_with('/') {
+        canonical.push('/');
+    }
+    for c in path.chars() {
+        if c == '/' && canonical.ends_with('/') {
+            continue;
+        }
+        canonical.push(c);
+    }
+
+    if canonical.len() > 1 && canonical.ends_with('/') {
+        canonical.pop();
+    }
+
+    canonical
+}
+
+// INFO: Only options set on both sides can contradict each other, an option set on one side is
+// just inherited.
+fn contradicting_fields(a: &OriginRequest, b: &OriginRequest) -> Vec<String> {
+    let a = serde_json::to_value(a).unwrap_or_default();
+    let b = serde_json::to_value(b).unwrap_or_default();
+
+    match (a.as_object(), b.as_object()) {
+        (Some(a), Some(b)) => a
+            .iter()
+            .filter(|(key, value)| b.get(*key).is_some_and(|other| other != *value))
+            .map(|(key, _)| key.to_owned())
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Normalizes rules that share a hostname so the most specific path always wins.
+///
+/// cloudflared uses the first matching rule, so rules for the same hostname are reordered from
+/// the most to the least specific path, which lets a nested path keep its own originRequest
+/// settings. Rules on the exact same path with contradictory settings can't be normalized and
+/// are returned as conflicts. Rules for different hostnames keep their relative order.
+pub fn normalize_origin_settings(
+    mut rules: Vec<IngressRule>,
+) -> Result<Vec<IngressRule>, Vec<ConflictingOriginSettings>> {
+    let mut groups: BTreeMap<Option<String>, Vec<usize>> = BTreeMap::new();
+    for (index, rule) in rules.iter().enumerate() {
+        groups.entry(rule.hostname.clone()).or_default().push(index);
+    }
+
+    let mut conflicts = Vec::new();
+    for (hostname, indexes) in groups {
+        let mut group: Vec<(String, IngressRule)> = indexes
+            .iter()
+            .map(|index| {
+                let rule = std::mem::take(&mut rules[*index]);
+                (canonical_path(rule.path.as_deref()), rule)
+            })
+            .collect();
+
+        // INFO: Only rules on the same canonical path can conflict, bucketing by path keeps a
+        // hostname with hundreds of distinct paths linear instead of comparing every pair.
+        let mut paths: HashMap<&str, Vec<usize>> = HashMap::with_capacity(group.len());
+        for (position, (path, _)) in group.iter().enumerate() {
+            paths.entry(path.as_str()).or_default().push(position);
+        }
+
+        let mut group_conflicts = Vec::new();
+        for positions in paths.values().filter(|positions| positions.len() > 1) {
+            for (n, i) in positions.iter().enumerate() {
+                for j in &positions[n + 1..] {
+                    let ((path, rule), (_, other)) = (&group[*i], &group[*j]);
+                    if let (Some(a), Some(b)) = (&rule.origin_request, &other.origin_request) {
+                        let fields = contradicting_fields(a, b);
+                        if !fields.is_empty() {
+                            group_conflicts.push((
+                                (*i, *j),
+                                ConflictingOriginSettings {
+                                    hostname: hostname.clone().unwrap_or_else(|| "*".to_owned()),
+                                    path: path.clone(),
+                                    fields,
+                                },
+                            ));
+                        }
+                    }
+                }
+            }
+        }
+
+        // INFO: Reports conflicts in rule order no matter how the buckets were iterated.
+        group_conflicts.sort_by_key(|(positions, _)| *positions);
+        conflicts.extend(group_conflicts.into_iter().map(|(_, conflict)| conflict));
+
+        // INFO: A path containing another is always shorter, so longest first puts nested
+        // paths ahead of their parents. The sort is stable for equal lengths.
+        group.sort_by_key(|(path, _)| std::cmp::Reverse(path.len()));
+        for (index, (_, rule)) in indexes.into_iter().zip(group) {
+            rules[index] = rule;
+        }
+    }
+
+    if conflicts.is_empty() {
+        Ok(rules)
+    } else {
+        Err(conflicts)
+    }
+}
+
+/// Configuration document as stored by Cloudflare, `version` is bumped on every write.
+#[derive(Deserialize, Debug, Clone)]
+pub struct TunnelConfigurationResult {
+    pub tunnel_id: Uuid,
+    pub version: i64,
+    #[serde(default)]
+    pub config: Option<TunnelConfiguration>,
+}
+
+impl ApiResult for TunnelConfigurationResult {}
+
+#[derive(Serialize, Debug)]
+pub struct Params<'a> {
+    pub config: &'a TunnelConfiguration,
+}
+
+pub struct GetTunnelConfiguration<'a> {
+    pub account_identifier: &'a str,
+    pub tunnel_id: Uuid,
+}
+
+impl Endpoint<TunnelConfigurationResult> for GetTunnelConfiguration<'_> {
+    fn method(&self) -> http::Method {
+        http::Method::GET
+    }
+
+    fn path(&self) -> String {
+        format!(
+            "accounts/{}/cfd_tunnel/{}/configurations",
+            self.account_identifier, self.tunnel_id
+        )
+    }
+}
+
+pub struct UpdateTunnelConfiguration<'a> {
+    pub account_identifier: &'a str,
+    pub tunnel_id: Uuid,
+    pub params: Params<'a>,
+}
+
+impl Endpoint<TunnelConfigurationResult> for UpdateTunnelConfiguration<'_> {
+    fn method(&self) -> http::Method {
+        http::Method::PUT
+    }
+
+    fn path(&self) -> String {
+        format!(
+            "accounts/{}/cfd_tunnel/{}/configurations",
+            self.account_identifier, self.tunnel_id
+        )
+    }
+
+    fn body(&self) -> Option<String> {
+        Some(serde_json::to_string(&self.params).unwrap())
+    }
+
+    fn content_type(&self) -> Cow<'static, str> {
+        Cow::Borrowed("application/json")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rule(hostname: &str, path: &str, no_tls_verify: Option<bool>) -> IngressRule {
+        IngressRule {
+            hostname: Some(hostname.to_owned()),
+            path: Some(path.to_owned()),
+            service: format!("http://{}", path.trim_matches(|c| c == '/' || c == '^')),
+            origin_request: no_tls_verify.map(|no_tls_verify| OriginRequest {
+                no_tls_verify: Some(no_tls_verify),
+                ..OriginRequest::default()
+            }),
+        }
+    }
+
+    fn paths(rules: &[IngressRule]) -> Vec<&str> {
+        rules
+            .iter()
+            .map(|rule| rule.path.as_deref().unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn equivalent_paths_share_a_canonical_form() {
+        for path in ["/api", "^/api/.*", "api/", "//api", "/api$", "/api/*"] {
+            assert_eq!(canonical_path(Some(path)), "/api", "{}", path);
+        }
+        assert_eq!(canonical_path(None), "/");
+        assert_eq!(canonical_path(Some("^/.*")), "/");
+    }
+
+    #[test]
+    fn nested_prefixes_with_agreeing_options_put_the_nested_path_first() {
+        let rules = vec![
+            rule("example.com", "/api", Some(true)),
+            rule("example.com", "/api/v1", Some(true)),
+            rule("example.com", "/api/v1/admin", None),
+        ];
+
+        let rules = normalize_origin_settings(rules).unwrap();
+
+        assert_eq!(paths(&rules), ["/api/v1/admin", "/api/v1", "/api"]);
+    }
+
+    #[test]
+    fn nested_prefixes_with_conflicting_options_let_the_most_specific_path_win() {
+        let rules = vec![
+            rule("example.com", "^/api/.*", Some(false)),
+            rule("example.com", "^/api/internal/.*", Some(true)),
+        ];
+
+        let rules = normalize_origin_settings(rules).unwrap();
+
+        assert_eq!(paths(&rules), ["^/api/internal/.*", "^/api/.*"]);
+        assert_eq!(
+            rules[0].origin_request.as_ref().unwrap().no_tls_verify,
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn same_path_with_conflicting_options_is_a_conflict() {
+        let rules = vec![
+            rule("example.com", "/api", Some(false)),
+            rule("other.com", "/api", Some(true)),
+            rule("example.com", "^/api/.*", Some(true)),
+        ];
+
+        assert_eq!(
+            normalize_origin_settings(rules),
+            Err(vec![ConflictingOriginSettings {
+                hostname: "example.com".to_owned(),
+                path: "/api".to_owned(),
+                fields: vec!["noTLSVerify".to_owned()],
+            }])
+        );
+    }
+
+    #[test]
+    fn other_hostnames_keep_their_positions() {
+        let rules = vec![
+            rule("a.example.com", "/", None),
+            rule("b.example.com", "/", None),
+            rule("a.example.com", "/nested", None),
+        ];
+
+        let rules = normalize_origin_settings(rules).unwrap();
+
+        let order: Vec<_> = rules
+            .iter()
+            .map(|rule| {
+                (
+                    rule.hostname.as_deref().unwrap(),
+                    rule.path.as_deref().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            order,
+            [
+                ("a.example.com", "/nested"),
+                ("b.example.com", "/"),
+                ("a.example.com", "/"),
+            ]
+        );
+    }
+}
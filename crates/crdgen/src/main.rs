@@ -1,3 +1,179 @@
+use kube::CustomResourceExt;
+use tunnel_controller::crd::{
+    cluster_tunnel::ClusterTunnel, credentials::Credentials, tunnel::Tunnel,
+    tunnel_ingress::TunnelIngress,
+};
+
+fn render() -> String {
+    let crds = [
+        Credentials::crd(),
+        Tunnel::crd(),
+        ClusterTunnel::crd(),
+        TunnelIngress::crd(),
+    ];
+
+    crds.iter()
+        .map(|crd| format!("---\n{}", serde_yaml::to_string(crd).unwrap()))
+        .collect()
+}
+
 fn main() {
-    println!("Hello, world!");
+    print!("{}", render());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_yaml::Value;
+
+    fn crd(kind: &str) -> Value {
+        let rendered = format!("\n{}", render());
+        rendered
+            .split("\n---\n")
+            .filter(|document| !document.is_empty())
+            .map(|document| serde_yaml::from_str::<Value>(document).unwrap())
+            .find(|crd| crd["spec"]["names"]["kind"] == kind)
+            .unwrap()
+    }
+
+    fn yaml(document: &str) -> Value {
+        serde_yaml::from_str(document).unwrap()
+    }
+
+    #[test]
+    fn tunnel_names_and_columns() {
+        let crd = crd("Tunnel");
+
+        assert_eq!(
+            crd["spec"]["names"],
+            yaml(
+                r#"
+categories: [cloudflare]
+kind: Tunnel
+plural: tunnels
+shortNames: [tun]
+singular: tunnel
+"#
+            )
+        );
+        assert_eq!(
+            crd["spec"]["versions"][0]["additionalPrinterColumns"],
+            yaml(
+                r#"
+- {name: UUID, type: string, jsonPath: .spec.uuid}
+- {name: Replicas, type: integer, jsonPath: .spec.replicas}
+- {name: Ready, type: integer, jsonPath: .status.readyReplicas}
+- {name: Credentials, type: string, jsonPath: .spec.credentials}
+- {name: Tunnel-URL, type: string, jsonPath: .status.tunnelUrl}
+- {name: Age, type: date, jsonPath: .metadata.creationTimestamp}
+"#
+            )
+        );
+    }
+
+    #[test]
+    fn credentials_names_and_columns() {
+        let crd = crd("Credentials");
+
+        assert_eq!(
+            crd["spec"]["names"],
+            yaml(
+                r#"
+categories: [cloudflare]
+kind: Credentials
+plural: credentials
+shortNames: [cfcreds]
+singular: credentials
+"#
+            )
+        );
+        assert_eq!(
+            crd["spec"]["versions"][0]["additionalPrinterColumns"],
+            yaml(
+                r#"
+- {name: Account-ID, type: string, jsonPath: .spec.accountId}
+- {name: Valid, type: boolean, jsonPath: .status.valid}
+- {name: Age, type: date, jsonPath: .metadata.creationTimestamp}
+"#
+            )
+        );
+    }
+
+    #[test]
+    fn tunnel_ingress_names_and_columns() {
+        let crd = crd("TunnelIngress");
+
+        assert_eq!(
+            crd["spec"]["names"],
+            yaml(
+                r#"
+categories: [cloudflare]
+kind: TunnelIngress
+plural: tunnelingresses
+shortNames: [tunin]
+singular: tunnelingress
+"#
+            )
+        );
+        assert_eq!(
+            crd["spec"]["versions"][0]["additionalPrinterColumns"],
+            yaml(
+                r#"
+- {name: Hostname, type: string, jsonPath: ".spec.rules[0].hostname"}
+- {name: Service, type: string, jsonPath: ".spec.rules[0].service"}
+- {name: Tunnel, type: string, jsonPath: .spec.tunnelRef.name}
+- {name: Synced, type: boolean, jsonPath: .status.synced}
+- {name: Age, type: date, jsonPath: .metadata.creationTimestamp}
+"#
+            )
+        );
+    }
+
+    #[test]
+    fn cluster_tunnel_is_listed_with_the_other_kinds() {
+        let crd = crd("ClusterTunnel");
+
+        assert_eq!(crd["spec"]["scope"], "Cluster");
+        assert_eq!(crd["spec"]["names"]["shortNames"], yaml("[ctun]"));
+        assert_eq!(crd["spec"]["names"]["categories"], yaml("[cloudflare]"));
+        assert_eq!(
+            crd["spec"]["versions"][0]["additionalPrinterColumns"],
+            self::crd("Tunnel")["spec"]["versions"][0]["additionalPrinterColumns"]
+        );
+    }
+
+    #[test]
+    fn tunnel_spec_constraints() {
+        for kind in ["Tunnel", "ClusterTunnel"] {
+            let crd = crd(kind);
+            let spec =
+                &crd["spec"]["versions"][0]["schema"]["openAPIV3Schema"]["properties"]["spec"];
+            let properties = &spec["properties"];
+
+            assert_eq!(
+                properties["replicas"],
+                yaml("{default: 2, format: int32, minimum: 0.0, type: integer}")
+            );
+            assert_eq!(
+                properties["uuid"],
+                yaml(
+                    r#"
+format: uuid
+nullable: true
+pattern: ^[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-4[0-9a-fA-F]{3}-[89abAB][0-9a-fA-F]{3}-[0-9a-fA-F]{12}$
+type: string
+"#
+                )
+            );
+            assert_eq!(
+                properties["image"],
+                yaml("{minLength: 1, nullable: true, type: string}")
+            );
+            assert_eq!(
+                properties["credentials"],
+                yaml("{minLength: 1, type: string}")
+            );
+            assert_eq!(spec["required"], yaml("[credentials]"));
+        }
+    }
 }
@@ -0,0 +1,545 @@
+//! Gateway API support, only the fields of `gateway.networking.k8s.io` that tunnel rules are
+//! built from are modeled. HTTPRoute rules are merged with the ones of the Ingresses and
+//! TunnelIngresses on the same tunnel by `sync_tunnel`.
+use crate::{
+    exact_path, most_specific_first, patch_finalizers, prefix_path, recorded_tunnel, rule_rank,
+    service_url, sync_tunnel, Context, Error, IngressController, PathRank, DEFAULT_SERVICE_PORT,
+};
+use cloudflare_controller_common::HTTPROUTE_FINALIZER;
+use cloudflarext::cfd_tunnel::CloudflaredTunnel;
+use cloudflarext::tunnel_configuration::{IngressRule, CATCH_ALL_SERVICE};
+use futures::StreamExt;
+use kube::runtime::controller::Action;
+use kube::runtime::reflector::{self, ObjectRef, Store};
+use kube::runtime::watcher::{self, watcher};
+use kube::runtime::{Controller, WatchStreamExt};
+use kube::{Api, CustomResource, CustomResourceExt, Resource, ResourceExt};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::future::ready;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio_util::sync::CancellationToken;
+use tunnel_controller::{crd::tunnel::Tunnel, AnyTunnel, TunnelStores};
+
+pub const GATEWAY_CONTROLLER: &str = "cloudflare.ar2ro.io/gateway-controller";
+
+#[derive(CustomResource, Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[kube(
+    group = "gateway.networking.k8s.io",
+    version = "v1",
+    kind = "GatewayClass"
+)]
+pub struct GatewayClassSpec {
+    pub controller_name: String,
+    #[serde(default)]
+    pub parameters_ref: Option<ParametersReference>,
+}
+
+/// Points a GatewayClass of this controller at a `Tunnel` or `ClusterTunnel`.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ParametersReference {
+    pub group: String,
+    pub kind: String,
+    pub name: String,
+    #[serde(default)]
+    pub namespace: Option<String>,
+}
+
+#[derive(CustomResource, Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[kube(
+    group = "gateway.networking.k8s.io",
+    version = "v1",
+    kind = "Gateway",
+    namespaced
+)]
+pub struct GatewaySpec {
+    pub gateway_class_name: String,
+}
+
+#[derive(CustomResource, Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[kube(
+    group = "gateway.networking.k8s.io",
+    version = "v1",
+    kind = "HTTPRoute",
+    namespaced
+)]
+pub struct HTTPRouteSpec {
+    #[serde(default)]
+    pub parent_refs: Vec<ParentReference>,
+    #[serde(default)]
+    pub hostnames: Vec<String>,
+    #[serde(default)]
+    pub rules: Vec<HTTPRouteRule>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ParentReference {
+    #[serde(default)]
+    pub kind: Option<String>,
+    #[serde(default)]
+    pub namespace: Option<String>,
+    pub name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HTTPRouteRule {
+    #[serde(default)]
+    pub matches: Vec<HTTPRouteMatch>,
+    #[serde(default)]
+    pub backend_refs: Vec<HTTPBackendRef>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HTTPRouteMatch {
+    #[serde(default)]
+    pub path: Option<HTTPPathMatch>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HTTPPathMatch {
+    #[serde(default, rename = "type")]
+    pub type_: PathMatchType,
+    #[serde(default)]
+    pub value: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+pub enum PathMatchType {
+    Exact,
+    #[default]
+    PathPrefix,
+    RegularExpression,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HTTPBackendRef {
+    #[serde(default)]
+    pub kind: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub namespace: Option<String>,
+    #[serde(default)]
+    pub port: Option<i32>,
+}
+
+/// Lets routes of other namespaces point at objects in the namespace of the grant.
+#[derive(CustomResource, Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[kube(
+    group = "gateway.networking.k8s.io",
+    version = "v1beta1",
+    kind = "ReferenceGrant",
+    namespaced
+)]
+pub struct ReferenceGrantSpec {
+    pub from: Vec<ReferenceGrantFrom>,
+    pub to: Vec<ReferenceGrantTo>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReferenceGrantFrom {
+    pub group: String,
+    pub kind: String,
+    pub namespace: String,
+}
+
+/// An empty `group` is the core api group, no `name` grants every object of the kind.
+#[derive(Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReferenceGrantTo {
+    pub group: String,
+    pub kind: String,
+    #[serde(default)]
+    pub name: Option<String>,
+}
+
+/// The Gateway API objects routes are resolved with, left empty in Ingress mode.
+#[derive(Clone)]
+pub(crate) struct GatewayStores {
+    pub(crate) gateway_classes: Store<GatewayClass>,
+    pub(crate) gateways: Store<Gateway>,
+    pub(crate) routes: Store<HTTPRoute>,
+    pub(crate) reference_grants: Store<ReferenceGrant>,
+}
+
+impl Default for GatewayStores {
+    fn default() -> Self {
+        GatewayStores {
+            gateway_classes: reflector::store().0,
+            gateways: reflector::store().0,
+            routes: reflector::store().0,
+            reference_grants: reflector::store().0,
+        }
+    }
+}
+
+fn tunnel_path(path: &HTTPPathMatch) -> Option<String> {
+    let value = path.value.as_deref().filter(|value| !value.is_empty())?;
+
+    match path.type_ {
+        PathMatchType::Exact => Some(exact_path(value)),
+        PathMatchType::PathPrefix => prefix_path(value),
+        PathMatchType::RegularExpression => Some(value.to_owned()),
+    }
+}
+
+// INFO: Whether a ReferenceGrant in the namespace of `backend` lets HTTPRoutes of `namespace`
+// send traffic to it.
+fn granted(
+    reference_grants: &Store<ReferenceGrant>,
+    namespace: &str,
+    backend: &HTTPBackendRef,
+    backend_namespace: &str,
+) -> bool {
+    reference_grants
+        .state()
+        .iter()
+        .filter(|grant| grant.metadata.namespace.as_deref() == Some(backend_namespace))
+        .any(|grant| {
+            grant.spec.from.iter().any(|from| {
+                from.group == HTTPRoute::group(&())
+                    && from.kind == HTTPRoute::kind(&())
+                    && from.namespace == namespace
+            }) && grant.spec.to.iter().any(|to| {
+                to.group.is_empty()
+                    && to.kind == "Service"
+                    && to.name.as_ref().is_none_or(|name| *name == backend.name)
+            })
+        })
+}
+
+// INFO: cloudflared sends a rule to a single service, so weighted backends can't be split and
+// only the first Service backend of a rule is used. One in another namespace is only used once
+// a ReferenceGrant there allows it.
+fn backend_service(
+    rule: &HTTPRouteRule,
+    namespace: &str,
+    reference_grants: &Store<ReferenceGrant>,
+) -> Option<String> {
+    let backend = rule
+        .backend_refs
+        .iter()
+        .find(|backend| backend.kind.as_deref().unwrap_or("Service") == "Service")?;
+    let backend_namespace = backend.namespace.as_deref().unwrap_or(namespace);
+
+    if backend_namespace != namespace
+        && !granted(reference_grants, namespace, backend, backend_namespace)
+    {
+        return None;
+    }
+
+    Some(service_url(
+        &backend.name,
+        backend_namespace,
+        backend.port.unwrap_or(DEFAULT_SERVICE_PORT),
+    ))
+}
+
+/// Rules for every hostname and path match of `route`, ranked like the paths of an Ingress.
+pub(crate) fn httproute_path_rules(
+    route: &HTTPRoute,
+    reference_grants: &Store<ReferenceGrant>,
+) -> Vec<(PathRank, IngressRule)> {
+    let namespace = route.metadata.namespace.as_deref().unwrap_or_default();
+    let hostnames = match route.spec.hostnames.is_empty() {
+        true => vec![None],
+        false => route.spec.hostnames.iter().cloned().map(Some).collect(),
+    };
+
+    hostnames
+        .iter()
+        .flat_map(|hostname| {
+            route.spec.rules.iter().filter_map(move |rule| {
+                let service = backend_service(rule, namespace, reference_grants)?;
+                // INFO: A rule without matches matches every path.
+                let paths = match rule.matches.is_empty() {
+                    true => vec![None],
+                    false => rule
+                        .matches
+                        .iter()
+                        .map(|route_match| route_match.path.as_ref().and_then(tunnel_path))
+                        .collect(),
+                };
+
+                Some(paths.into_iter().map(move |path| IngressRule {
+                    hostname: hostname.clone(),
+                    path,
+                    service: service.clone(),
+                    origin_request: None,
+                }))
+            })
+        })
+        .flatten()
+        .map(|rule| (rule_rank(&rule), rule))
+        .collect()
+}
+
+/// Tunnel ingress rules for every hostname and path match of `route`, followed by a catch-all
+/// that serves a 404.
+pub fn httproute_to_tunnel_rules(
+    route: &HTTPRoute,
+    reference_grants: &Store<ReferenceGrant>,
+) -> Vec<IngressRule> {
+    let mut rules = most_specific_first(httproute_path_rules(route, reference_grants));
+
+    rules.push(IngressRule {
+        service: CATCH_ALL_SERVICE.to_owned(),
+        ..IngressRule::default()
+    });
+
+    rules
+}
+
+fn gateway_class(route: &HTTPRoute, gateway_stores: &GatewayStores) -> Option<Arc<GatewayClass>> {
+    let namespace = route.metadata.namespace.as_deref().unwrap_or_default();
+
+    // INFO: The first parent Gateway whose class belongs to this controller picks the tunnel.
+    route
+        .spec
+        .parent_refs
+        .iter()
+        .filter(|parent| parent.kind.as_deref().unwrap_or("Gateway") == "Gateway")
+        .find_map(|parent| {
+            let obj_ref = ObjectRef::new(&parent.name)
+                .within(parent.namespace.as_deref().unwrap_or(namespace));
+            let gateway = gateway_stores.gateways.get(&obj_ref)?;
+            let gateway_class = gateway_stores
+                .gateway_classes
+                .get(&ObjectRef::new(&gateway.spec.gateway_class_name))?;
+
+            match gateway_class.spec.controller_name == GATEWAY_CONTROLLER {
+                true => Some(gateway_class),
+                false => None,
+            }
+        })
+}
+
+fn gateway_tunnel(
+    gateway_class: &GatewayClass,
+    tunnel_stores: &TunnelStores,
+) -> Result<AnyTunnel, Error> {
+    match gateway_class.spec.parameters_ref.as_ref() {
+        Some(parameters) => {
+            if !Tunnel::crd().spec.group.eq(&parameters.group) {
+                return Err(Error::InvalidGatewayClassParameters(
+                    "parametersRef doesn't point at a Tunnel or ClusterTunnel",
+                ));
+            }
+
+            match tunnel_stores.get(
+                &parameters.kind,
+                &parameters.name,
+                parameters.namespace.as_deref(),
+            ) {
+                Some(tunnel) => Ok(tunnel),
+                None => Err(Error::MissingTunnel(parameters.name.clone())),
+            }
+        }
+        None => match tunnel_stores.default_tunnel() {
+            Ok(Some(tunnel)) => Ok(tunnel),
+            Ok(None) => Err(Error::MissingDefaultTunnel),
+            Err(err) => Err(Error::AmbiguousDefaultTunnel(err)),
+        },
+    }
+}
+
+/// The tunnel `route` is routed through, none when none of its parent Gateways are ours.
+pub(crate) fn route_tunnel<C: CloudflaredTunnel>(
+    route: &HTTPRoute,
+    ctx: &Context<C>,
+) -> Result<Option<AnyTunnel>, Error> {
+    gateway_class(route, &ctx.gateway_stores)
+        .map(|gateway_class| gateway_tunnel(&gateway_class, &ctx.tunnel_stores))
+        .transpose()
+}
+
+fn has_finalizer(route: &HTTPRoute) -> bool {
+    route
+        .finalizers()
+        .iter()
+        .any(|finalizer| finalizer == HTTPROUTE_FINALIZER)
+}
+
+// INFO: Rebuilds the tunnel the rules were written to without this HTTPRoute, then lets it go.
+// Runs on deletion and when the route no longer goes through one of our remote tunnels.
+async fn release<C: CloudflaredTunnel>(
+    route: &HTTPRoute,
+    ctx: &Context<C>,
+) -> Result<Action, Error> {
+    if let Some(tunnel_uuid) = recorded_tunnel(route) {
+        match ctx.tunnel_stores.find_by_uuid(tunnel_uuid) {
+            Some(tunnel) if !tunnel.spec().is_local() => {
+                sync_tunnel(&tunnel, tunnel_uuid, ctx).await?;
+            }
+            Some(_) => {}
+            None => println!(
+                "Tunnel {} of HTTPRoute {} is gone, nothing to remove",
+                tunnel_uuid,
+                route.name_any()
+            ),
+        }
+    }
+
+    let finalizers = route
+        .finalizers()
+        .iter()
+        .filter(|finalizer| *finalizer != HTTPROUTE_FINALIZER)
+        .cloned()
+        .collect();
+    patch_finalizers(route, ctx, finalizers, None).await?;
+
+    Ok(Action::await_change())
+}
+
+pub(crate) async fn reconcile_route<C: CloudflaredTunnel>(
+    route: &HTTPRoute,
+    ctx: &Context<C>,
+) -> Result<Action, Error> {
+    // INFO: Locally managed tunnels render their rules into config.yaml, which HTTPRoutes aren't
+    // part of.
+    let tunnel = match route.metadata.deletion_timestamp {
+        Some(_) => None,
+        None => route_tunnel(route, ctx)?.filter(|tunnel| !tunnel.spec().is_local()),
+    };
+    let tunnel = match tunnel {
+        Some(tunnel) => tunnel,
+        None => {
+            return match has_finalizer(route) {
+                true => release(route, ctx).await,
+                false => Ok(Action::await_change()),
+            }
+        }
+    };
+
+    let tunnel_uuid = match tunnel.get_uuid() {
+        Some(tunnel_uuid) => tunnel_uuid,
+        // Requeue in 2 minutes as the tunnel is not ready.
+        None => return Ok(Action::requeue(Duration::from_secs(60 * 2))),
+    };
+
+    let previous = recorded_tunnel(route);
+    if !has_finalizer(route) || previous != Some(tunnel_uuid) {
+        let mut finalizers = route.finalizers().to_vec();
+        if !has_finalizer(route) {
+            finalizers.push(HTTPROUTE_FINALIZER.to_owned());
+        }
+        patch_finalizers(route, ctx, finalizers, Some(tunnel_uuid)).await?;
+    }
+
+    if let Some(rules) = sync_tunnel(&tunnel, tunnel_uuid, ctx).await? {
+        println!(
+            "Applied {} rules to {} {} ({}) for HTTPRoute {}",
+            rules,
+            tunnel.kind(),
+            tunnel.name(),
+            tunnel_uuid,
+            route.name_any()
+        );
+    }
+
+    // INFO: The route moved to another tunnel, its rules are dropped from the old one.
+    if let Some(previous) = previous.filter(|previous| *previous != tunnel_uuid) {
+        if let Some(previous_tunnel) = ctx.tunnel_stores.find_by_uuid(previous) {
+            sync_tunnel(&previous_tunnel, previous, ctx).await?;
+        }
+    }
+
+    Ok(Action::requeue(Duration::from_secs(60)))
+}
+
+async fn reconcile(route: Arc<HTTPRoute>, ctx: Arc<Context>) -> Result<Action, Error> {
+    reconcile_route(&route, &ctx).await
+}
+
+fn error_policy(route: Arc<HTTPRoute>, error: &Error, _ctx: Arc<Context>) -> Action {
+    println!(
+        "Failed to reconcile HTTPRoute {}: {}",
+        route.name_any(),
+        error
+    );
+    Action::requeue(Duration::from_secs(60))
+}
+
+pub(crate) async fn run_controller(
+    route_api: Api<HTTPRoute>,
+    wc: watcher::Config,
+    ctx: Arc<Context>,
+    shutdown: CancellationToken,
+) {
+    Controller::new(route_api, wc)
+        // INFO: Stops pulling new events once cancelled but lets in-flight reconciles finish.
+        .graceful_shutdown_on(shutdown.cancelled_owned())
+        .run(reconcile, error_policy, ctx)
+        .for_each(|_| ready(()))
+        .await;
+}
+
+impl IngressController {
+    // INFO: Every tunnel sync merges in the HTTPRoute rules, so all of these have to be populated
+    // before the first one.
+    pub(crate) async fn watch_gateway_api(
+        &self,
+        wc: &watcher::Config,
+    ) -> anyhow::Result<GatewayStores> {
+        let gateway_class_api: Api<GatewayClass> = Api::all(self.kubernetes_client.clone());
+        let gateway_api: Api<Gateway> = Api::all(self.kubernetes_client.clone());
+        let route_api: Api<HTTPRoute> = Api::all(self.kubernetes_client.clone());
+        let reference_grant_api: Api<ReferenceGrant> = Api::all(self.kubernetes_client.clone());
+
+        let (gateway_classes, gateway_class_writer) = reflector::store();
+        let (gateways, gateway_writer) = reflector::store();
+        let (routes, route_writer) = reflector::store();
+        let (reference_grants, reference_grant_writer) = reflector::store();
+
+        tokio::spawn(
+            watcher(gateway_class_api, wc.clone())
+                .reflect(gateway_class_writer)
+                .default_backoff()
+                .touched_objects()
+                .for_each(|_| ready(())),
+        );
+        tokio::spawn(
+            watcher(gateway_api, wc.clone())
+                .reflect(gateway_writer)
+                .default_backoff()
+                .touched_objects()
+                .for_each(|_| ready(())),
+        );
+        tokio::spawn(
+            watcher(route_api, wc.clone())
+                .reflect(route_writer)
+                .default_backoff()
+                .touched_objects()
+                .for_each(|_| ready(())),
+        );
+        tokio::spawn(
+            watcher(reference_grant_api, wc.clone())
+                .reflect(reference_grant_writer)
+                .default_backoff()
+                .touched_objects()
+                .for_each(|_| ready(())),
+        );
+        gateway_classes.wait_until_ready().await?;
+        gateways.wait_until_ready().await?;
+        routes.wait_until_ready().await?;
+        reference_grants.wait_until_ready().await?;
+
+        Ok(GatewayStores {
+            gateway_classes,
+            gateways,
+            routes,
+            reference_grants,
+        })
+    }
+}
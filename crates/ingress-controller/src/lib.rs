@@ -1,37 +1,93 @@
-use cloudflarext::{cfd_tunnel::CloudflaredTunnel, AuthlessClient as CloudflareClient};
+use cloudflare_controller_common::{INGRESS_CONTROLLER, INGRESS_FINALIZER};
+use cloudflarext::{
+    cfd_tunnel::{CloudflaredTunnel, ConfigurationError, DriftPolicy},
+    tunnel_configuration::{IngressRule, OriginRequest, TunnelConfiguration, CATCH_ALL_SERVICE},
+};
+use dashmap::DashMap;
 use futures::{Stream, StreamExt, TryFutureExt, TryStream, TryStreamExt};
-use k8s_openapi::api::networking::v1::{Ingress, IngressClass};
+use gateway::{GatewayStores, HTTPRoute};
+use k8s_openapi::api::core::v1::ObjectReference;
+use k8s_openapi::api::core::v1::Service;
+use k8s_openapi::api::networking::v1::{
+    HTTPIngressPath, Ingress, IngressBackend, IngressClass, ServiceBackendPort,
+};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Time;
+use k8s_openapi::chrono::{DateTime, Utc};
+use k8s_openapi::NamespaceResourceScope;
+use kube::core::DynamicObject;
 use kube::runtime::controller::Action;
 use kube::runtime::reflector::ObjectRef;
 use kube::runtime::Controller;
 use kube::CustomResourceExt;
 use kube::Resource;
 use kube::{
-    api::{Api, ResourceExt},
+    api::{Api, Patch, ResourceExt},
     runtime::{
+        events::{Event, EventType, Recorder, Reporter},
         reflector::{self, reflector, Lookup, Store},
         utils::EventDecode,
-        watcher::{self, watcher, Event},
+        watcher::{self, watcher},
         WatchStreamExt,
     },
     Client,
 };
+use origin::{origin_annotation, origin_request, ORIGIN_PROTOCOLS, ORIGIN_PROTOCOL_ANNOTATION};
+use regex::Regex;
+use serde::de::DeserializeOwned;
+use serde_json::json;
+use std::cmp::Reverse;
+use std::collections::HashMap;
+use std::fmt::Debug;
 use std::future::{ready, Future, IntoFuture};
 use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use tokio_util::sync::CancellationToken;
 use tunnel_controller::{
-    crd::tunnel::{Tunnel, TunnelCrd},
-    TunnelStoreExt,
+    crd::{
+        cluster_tunnel::ClusterTunnel,
+        credentials::{Credentials, CredentialsApiExt},
+        tunnel::Tunnel,
+        tunnel_ingress::{cross_namespace_denial, TunnelIngress},
+    },
+    resources::patch_params,
+    AnyTunnel, CloudflareClient, DefaultTunnelError, TunnelStoreExt, TunnelStores,
 };
+use uuid::Uuid;
+
+pub mod gateway;
+pub mod origin;
+mod tunnel_ingress;
 
-const INGRESS_CONTROLLER: &str = "cloudflare.ar2ro.io/ingress-controller";
+const DEFAULT_CLASS_ANNOTATION: &str = "ingressclass.kubernetes.io/is-default-class";
+const DEFAULT_SERVICE_PORT: i32 = 80;
+// INFO: Remembers the tunnel the rules were written to, the ingress class may point elsewhere
+// or be gone by the time they have to be removed.
+const TUNNEL_ID_ANNOTATION: &str = "ingress.cloudflare.ar2ro.io/tunnel-id";
+// INFO: Rules without a host would catch every hostname routed to the tunnel, an Ingress has
+// to opt in before they're used.
+const CATCH_ALL_ANNOTATION: &str = "cloudflare.ar2ro.io/catch-all";
+// INFO: `<namespace>/<name>` or just `<name>` of a Tunnel in the Ingress's namespace, wins over
+// the IngressClass parameters and the default tunnel.
+const TUNNEL_ANNOTATION: &str = "cloudflare.ar2ro.io/tunnel";
+// INFO: `<name>` of a ClusterTunnel, only looked at when the tunnel annotation isn't set.
+const CLUSTER_TUNNEL_ANNOTATION: &str = "cloudflare.ar2ro.io/cluster-tunnel";
+
+// INFO: Whether the rule is for every host, whether its host is a wildcard, how long its path is
+// and whether it's a prefix.
+type PathRank = (bool, bool, Reverse<usize>, bool);
 
 trait StoreIngressClassExt<T> {
-    fn ingress_class_names(&self) -> Vec<String>;
+    fn default_ingress_classes(&self) -> Vec<Arc<T>>;
 }
 
 trait IngressClassExt {
     fn controller_name(&self) -> Option<&String>;
+
+    fn is_ours(&self) -> bool {
+        self.controller_name()
+            .is_some_and(|controller_name| controller_name == INGRESS_CONTROLLER)
+    }
 }
 
 trait IngressExt {
@@ -44,26 +100,127 @@ pub enum Error {
     KubeError(#[source] kube::Error),
     #[error("missing default tunnel")]
     MissingDefaultTunnel,
+    #[error("{0}")]
+    AmbiguousDefaultTunnel(#[source] DefaultTunnelError),
     #[error("invalid ingress class parameters: {0}")]
     InvalidIngressClassParameters(&'static str),
+    #[error("invalid gateway class parameters: {0}")]
+    InvalidGatewayClassParameters(&'static str),
     #[error("missing tunnel {0}")]
     MissingTunnel(String),
+    #[error("{0}")]
+    CrossNamespaceTunnel(String),
+    #[error("Tunnel Error: {0}")]
+    TunnelError(#[source] tunnel_controller::Error),
+    #[error("Configuration Error: {0}")]
+    ConfigurationError(#[source] ConfigurationError),
+}
+
+/// Which routing resources are turned into tunnel rules.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ControllerMode {
+    #[default]
+    Ingress,
+    /// `HTTPRoute`s whose parent Gateway has a GatewayClass of this controller, on top of the
+    /// Ingresses.
+    GatewayApi,
 }
 
 pub struct IngressController {
     kubernetes_client: Client,
     cloudflare_client: CloudflareClient,
-    tunnel_store: Store<Tunnel>,
+    tunnel_stores: TunnelStores,
+    mode: ControllerMode,
+    classless_ingress_policy: bool,
+    default_ingress_class: bool,
+    watch_namespaces: Vec<String>,
+    allow_cross_namespace_refs: bool,
+    shutdown: CancellationToken,
 }
 
-struct Context {
+struct Context<C = CloudflareClient> {
     kubernetes_client: Client,
-    cloudflare_client: CloudflareClient,
+    cloudflare_client: C,
     ingress_api: Api<Ingress>,
     ingress_store: Store<Ingress>,
+    tunnel_ingress_store: Store<TunnelIngress>,
     ingress_class_api: Api<IngressClass>,
     ingress_class_store: Store<IngressClass>,
-    tunnel_store: Store<Tunnel>,
+    service_store: Store<Service>,
+    credentials_api: Api<Credentials>,
+    tunnel_stores: TunnelStores,
+    gateway_stores: GatewayStores,
+    applied_rules: DashMap<Uuid, Vec<IngressRule>>,
+    // INFO: The tunnel every managed Ingress resolved to, so a change to the tunnel can be
+    // mapped back to the Ingresses routed through it.
+    ingress_tunnels: Arc<DashMap<ObjectRef<Ingress>, ObjectRef<DynamicObject>>>,
+    classless_ingress_policy: bool,
+    default_ingress_class: bool,
+    // INFO: Empty when every namespace is watched.
+    watch_namespaces: Vec<String>,
+    allow_cross_namespace_refs: bool,
+    // INFO: Set while more than one of our classes claims to be the default, so it's only
+    // reported once.
+    conflicting_default_classes: AtomicBool,
+    recorder: Recorder,
+}
+
+/// A hostname and path routed to different services by more than one Ingress, TunnelIngress or
+/// HTTPRoute on a tunnel, a TunnelIngress keeps it over the others and otherwise the oldest one
+/// does, the rules of the others are skipped.
+struct RuleConflict {
+    object: ObjectReference,
+    name: String,
+    hostname: String,
+    path: String,
+    owner: String,
+}
+
+/// The ranked rules of an Ingress, a TunnelIngress or an HTTPRoute routed through a tunnel.
+struct RuleSource {
+    object: ObjectReference,
+    /// `<Kind> <namespace>/<name>`, how the source is named in conflicts.
+    name: String,
+    created: Option<Time>,
+    rules: Vec<(PathRank, IngressRule)>,
+}
+
+impl RuleSource {
+    fn new<K: Resource<DynamicType = ()>>(
+        object: &K,
+        rules: Vec<(PathRank, IngressRule)>,
+    ) -> RuleSource {
+        RuleSource {
+            object: object.object_ref(&()),
+            name: format!(
+                "{} {}/{}",
+                K::kind(&()),
+                object.meta().namespace.as_deref().unwrap_or_default(),
+                object.meta().name.as_deref().unwrap_or_default()
+            ),
+            created: object.meta().creation_timestamp.clone(),
+            rules,
+        }
+    }
+
+    // INFO: TunnelIngress objects are written for the tunnel on purpose, they win over an
+    // Ingress or HTTPRoute routing the same host and path. Otherwise the oldest wins, the name
+    // breaks ties so the order never depends on which reconcile ran first.
+    fn precedence(&self) -> (bool, Option<DateTime<Utc>>, &str) {
+        (
+            self.object.kind.as_deref() != Some(<TunnelIngress as Resource>::kind(&()).as_ref()),
+            self.created.as_ref().map(|time| time.0),
+            &self.name,
+        )
+    }
+}
+
+/// Where cloudflared sends the traffic of a backend.
+struct Origin {
+    service: String,
+    origin_request: Option<OriginRequest>,
+    /// Why annotations that couldn't be parsed were left out of `origin_request`.
+    invalid_annotations: Vec<String>,
 }
 
 impl IntoFuture for IngressController {
@@ -75,85 +232,1014 @@ impl IntoFuture for IngressController {
     }
 }
 
-async fn reconcile(ingress: Arc<Ingress>, ctx: Arc<Context>) -> Result<Action, Error> {
-    // INFO: Return early if we don't own this ingress class.
+// INFO: Named ports are resolved against the Service, an ExternalName Service is reached on its
+// external hostname directly since cluster DNS would only hand cloudflared a CNAME. The scheme
+// comes from the origin protocol annotation, or https for port 443 and http otherwise.
+fn backend_service(
+    backend: &IngressBackend,
+    ingress: &Ingress,
+    namespace: &str,
+    services: &Store<Service>,
+) -> Result<Origin, String> {
+    let backend = match backend.service.as_ref() {
+        Some(backend) => backend,
+        None => return Err("only Service backends are supported".to_owned()),
+    };
+    let service = match services.get(&ObjectRef::new(&backend.name).within(namespace)) {
+        Some(service) => service,
+        None => {
+            return Err(format!(
+                "Service {}/{} doesn't exist",
+                namespace, backend.name
+            ))
+        }
+    };
+    let spec = service.spec.clone().unwrap_or_default();
+
+    let port = match backend.port.as_ref() {
+        Some(ServiceBackendPort {
+            number: Some(number),
+            ..
+        }) => *number,
+        Some(ServiceBackendPort {
+            name: Some(name), ..
+        }) => match spec
+            .ports
+            .iter()
+            .flatten()
+            .find(|port| port.name.as_ref() == Some(name))
+        {
+            Some(port) => port.port,
+            None => {
+                return Err(format!(
+                    "Service {}/{} has no port named {}",
+                    namespace, backend.name, name
+                ))
+            }
+        },
+        _ => DEFAULT_SERVICE_PORT,
+    };
+
+    let protocol = match origin_annotation(ingress, &service, ORIGIN_PROTOCOL_ANNOTATION) {
+        Some(protocol) if ORIGIN_PROTOCOLS.contains(&protocol) => protocol,
+        Some(protocol) => return Err(format!("unsupported origin protocol {}", protocol)),
+        None if port == 443 => "https",
+        None => "http",
+    };
+
+    let host = match (spec.type_.as_deref(), spec.external_name) {
+        (Some("ExternalName"), Some(external_name)) => external_name,
+        _ => service_host(&backend.name, namespace),
+    };
+
+    let (origin_request, invalid_annotations) = origin_request(ingress, &service, protocol);
+    Ok(Origin {
+        service: format!("{}://{}:{}", protocol, host, port),
+        origin_request,
+        invalid_annotations,
+    })
+}
+
+fn service_host(name: &str, namespace: &str) -> String {
+    format!("{}.{}.svc.cluster.local", name, namespace)
+}
+
+fn service_url(name: &str, namespace: &str, port: i32) -> String {
+    format!("http://{}:{}", service_host(name, namespace), port)
+}
+
+fn escape_path(path: &str) -> String {
+    let mut escaped = String::with_capacity(path.len());
+    for c in path.chars() {
+        if "\\.+*?()|[]{}^$".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+
+    escaped
+}
+
+fn exact_path(value: &str) -> String {
+    format!("^{}$", escape_path(value))
+}
+
+// INFO: A prefix of `/` matches every path, which is what leaving `path` unset does.
+fn prefix_path(value: &str) -> Option<String> {
+    match value.trim_end_matches('/') {
+        "" => None,
+        prefix => Some(format!("^{}(/.*)?$", escape_path(prefix))),
+    }
+}
+
+// INFO: cloudflared matches `path` as a regex. Kubernetes prefixes match whole path elements,
+// so `/foo` matches `/foo` and `/foo/bar` but not `/foobar`. ImplementationSpecific paths are
+// handed to cloudflared as they are, a path cloudflared would choke on is an error.
+fn tunnel_path(path: &HTTPIngressPath) -> Result<Option<String>, String> {
+    let value = match path.path.as_deref().filter(|value| !value.is_empty()) {
+        Some(value) => value,
+        None => return Ok(None),
+    };
+
+    match path.path_type.as_str() {
+        "Exact" | "Prefix" if !value.starts_with('/') => {
+            Err(format!("path {} doesn't start with /", value))
+        }
+        "Exact" => Ok(Some(exact_path(value))),
+        "Prefix" => Ok(prefix_path(value)),
+        _ => match Regex::new(value) {
+            Ok(_) => Ok(Some(value.to_owned())),
+            Err(err) => Err(format!("path {} is not a valid regex: {}", value, err)),
+        },
+    }
+}
+
+// INFO: cloudflared uses the first rule that matches where Kubernetes picks the most specific
+// one. Rules with a host go before wildcard hosts and those before the ones for every host, then
+// longer paths before shorter ones and an Exact path before a prefix of the same length. Sorting
+// is stable so rules that rank the same keep their order.
+fn path_rank(host: Option<&String>, path: &HTTPIngressPath) -> PathRank {
+    let length = path.path.as_deref().unwrap_or_default().len();
+
+    (
+        host.is_none(),
+        host.is_some_and(|host| host.starts_with("*.")),
+        Reverse(length),
+        path.path_type != "Exact",
+    )
+}
+
+fn allows_catch_all(ingress: &Ingress) -> bool {
+    ingress
+        .annotations()
+        .get(CATCH_ALL_ANNOTATION)
+        .is_some_and(|value| value == "true")
+}
+
+/// Whether `ingress` has rules without a host that are ignored for lack of the catch-all
+/// annotation.
+pub fn ignores_hostless_rules(ingress: &Ingress) -> bool {
+    !allows_catch_all(ingress)
+        && ingress
+            .spec
+            .iter()
+            .flat_map(|spec| spec.rules.iter().flatten())
+            .any(|rule| rule.host.is_none() && rule.http.is_some())
+}
+
+// INFO: Rules that don't come from an Ingress path are ranked as prefix matches.
+pub(crate) fn rule_rank(rule: &IngressRule) -> PathRank {
+    let length = rule.path.as_deref().unwrap_or_default().len();
+
+    (
+        rule.hostname.is_none(),
+        rule.hostname
+            .as_ref()
+            .is_some_and(|hostname| hostname.starts_with("*.")),
+        Reverse(length),
+        true,
+    )
+}
+
+fn most_specific_first(mut rules: Vec<(PathRank, IngressRule)>) -> Vec<IngressRule> {
+    rules.sort_by_key(|(rank, _)| *rank);
+    rules.into_iter().map(|(_, rule)| rule).collect()
+}
+
+/// Why the paths of `ingress` that can't be turned into tunnel rules were left out.
+pub fn invalid_paths(ingress: &Ingress) -> Vec<String> {
+    ingress
+        .spec
+        .iter()
+        .flat_map(|spec| spec.rules.iter().flatten())
+        .flat_map(|rule| rule.http.iter().flat_map(|http| http.paths.iter()))
+        .filter_map(|path| tunnel_path(path).err())
+        .collect()
+}
+
+/// Why the backends of `ingress` that can't be reached were left out.
+pub fn invalid_backends(ingress: &Ingress, services: &Store<Service>) -> Vec<String> {
+    let namespace = ingress.metadata.namespace.as_deref().unwrap_or_default();
+
+    ingress
+        .spec
+        .iter()
+        .flat_map(|spec| spec.rules.iter().flatten())
+        .flat_map(|rule| rule.http.iter().flat_map(|http| http.paths.iter()))
+        .filter_map(|path| backend_service(&path.backend, ingress, namespace, services).err())
+        .collect()
+}
+
+/// Why origin annotations that apply to the backends of `ingress` were left out.
+pub fn invalid_annotations(ingress: &Ingress, services: &Store<Service>) -> Vec<String> {
+    let namespace = ingress.metadata.namespace.as_deref().unwrap_or_default();
+
+    let mut invalid = ingress
+        .spec
+        .iter()
+        .flat_map(|spec| spec.rules.iter().flatten())
+        .flat_map(|rule| rule.http.iter().flat_map(|http| http.paths.iter()))
+        .filter_map(|path| backend_service(&path.backend, ingress, namespace, services).ok())
+        .flat_map(|origin| origin.invalid_annotations)
+        .collect::<Vec<_>>();
+    // INFO: Annotations of the Ingress apply to every backend, each is reported once.
+    invalid.sort();
+    invalid.dedup();
+
+    invalid
+}
+
+// INFO: Wildcard hosts are passed on as they are, cloudflared matches them itself.
+fn ingress_path_rules(
+    ingress: &Ingress,
+    namespace: &str,
+    services: &Store<Service>,
+) -> Vec<(PathRank, IngressRule)> {
+    let catch_all = allows_catch_all(ingress);
+
+    ingress
+        .spec
+        .iter()
+        .flat_map(|spec| spec.rules.iter().flatten())
+        .filter(|rule| rule.host.is_some() || catch_all)
+        .flat_map(|rule| {
+            let paths = rule.http.iter().flat_map(|http| http.paths.iter());
+            paths.filter_map(|path| {
+                let rank = path_rank(rule.host.as_ref(), path);
+                let origin = backend_service(&path.backend, ingress, namespace, services).ok()?;
+                let rule = IngressRule {
+                    hostname: rule.host.clone(),
+                    path: tunnel_path(path).ok()?,
+                    service: origin.service,
+                    origin_request: origin.origin_request,
+                };
+
+                Some((rank, rule))
+            })
+        })
+        .collect()
+}
+
+/// Tunnel ingress rules for every valid path of `ingress`, most specific first and followed by a
+/// catch-all that serves the default backend or a 404.
+pub fn ingress_to_tunnel_rules(
+    ingress: &Ingress,
+    namespace: &str,
+    services: &Store<Service>,
+) -> Vec<IngressRule> {
+    let mut rules = most_specific_first(ingress_path_rules(ingress, namespace, services));
+
+    let catch_all = ingress
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.default_backend.as_ref())
+        .and_then(|backend| backend_service(backend, ingress, namespace, services).ok());
+    rules.push(match catch_all {
+        Some(origin) => IngressRule {
+            service: origin.service,
+            origin_request: origin.origin_request,
+            ..IngressRule::default()
+        },
+        None => IngressRule {
+            service: CATCH_ALL_SERVICE.to_owned(),
+            ..IngressRule::default()
+        },
+    });
+
+    rules
+}
+
+// INFO: Every Ingress, TunnelIngress and HTTPRoute on a tunnel shares its one configuration, so the rules
+// of all of them are merged in order of precedence so the result doesn't depend on the store.
+// Default backends can't be merged, the tunnel falls back to its catch-all service.
+fn merged_tunnel_rules(
+    mut sources: Vec<RuleSource>,
+    catch_all_service: &str,
+) -> (Vec<IngressRule>, Vec<RuleConflict>) {
+    sources.sort_by(|a, b| a.precedence().cmp(&b.precedence()));
+
+    // INFO: Host and path of a rule mapped to the source that claimed it and its service.
+    let mut owners: HashMap<(Option<String>, Option<String>), (String, String)> = HashMap::new();
+    let mut conflicts = Vec::new();
+    let mut rules = Vec::new();
+    for source in sources {
+        for (rank, rule) in source.rules {
+            let key = (rule.hostname.clone(), rule.path.clone());
+            match owners.get(&key) {
+                // INFO: The same route twice is harmless, only the first copy is kept.
+                Some((_, service)) if *service == rule.service => {}
+                Some((owner, _)) => conflicts.push(RuleConflict {
+                    object: source.object.clone(),
+                    name: source.name.clone(),
+                    hostname: rule.hostname.unwrap_or_else(|| "*".to_owned()),
+                    path: rule.path.unwrap_or_else(|| "/".to_owned()),
+                    owner: owner.clone(),
+                }),
+                None => {
+                    owners.insert(key, (source.name.clone(), rule.service.clone()));
+                    rules.push((rank, rule));
+                }
+            }
+        }
+    }
+
+    let mut rules = most_specific_first(rules);
+    rules.push(IngressRule {
+        service: catch_all_service.to_owned(),
+        ..IngressRule::default()
+    });
+
+    (rules, conflicts)
+}
+
+async fn publish_event<K: Resource<DynamicType = ()>, C: CloudflaredTunnel>(
+    ctx: &Context<C>,
+    object: &K,
+    type_: EventType,
+    reason: &str,
+    note: String,
+) {
+    publish_reference_event(ctx, &object.object_ref(&()), type_, reason, note).await
+}
+
+async fn publish_reference_event<C: CloudflaredTunnel>(
+    ctx: &Context<C>,
+    reference: &ObjectReference,
+    type_: EventType,
+    reason: &str,
+    note: String,
+) {
+    let event = Event {
+        type_,
+        reason: reason.into(),
+        note: Some(note),
+        action: "Reconcile".into(),
+        secondary: None,
+    };
+
+    if let Err(err) = ctx.recorder.publish(&event, reference).await {
+        println!("Failed to publish {} event: {}", reason, err);
+    }
+}
+
+// INFO: Kubernetes hands Ingresses without a class to the IngressClass marked as default, when
+// more than one of ours claims it none of them is used.
+fn default_ingress_class<C: CloudflaredTunnel>(ctx: &Context<C>) -> Option<Arc<IngressClass>> {
+    if !ctx.default_ingress_class {
+        return None;
+    }
+
+    let mut ingress_classes = ctx.ingress_class_store.default_ingress_classes();
+    if ingress_classes.len() > 1 {
+        if !ctx
+            .conflicting_default_classes
+            .swap(true, Ordering::Relaxed)
+        {
+            println!(
+                "IngressClasses {} are all marked as default, classless Ingresses are ignored",
+                ingress_classes
+                    .iter()
+                    .map(|ingress_class| ingress_class.name_any())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            );
+        }
+        return None;
+    }
+
+    ctx.conflicting_default_classes
+        .store(false, Ordering::Relaxed);
+    ingress_classes.pop()
+}
+
+// INFO: The IngressClass of ours an Ingress is routed through, if any.
+fn resolved_ingress_class<C: CloudflaredTunnel>(
+    ingress: &Ingress,
+    ctx: &Context<C>,
+) -> Option<Arc<IngressClass>> {
+    match ingress.ingress_class_name() {
+        Some(class_name) => ctx
+            .ingress_class_store
+            .get(&ObjectRef::new(class_name))
+            .filter(|ingress_class| ingress_class.is_ours()),
+        None => default_ingress_class(ctx),
+    }
+}
+
+// INFO: `None` when the Ingress sets neither tunnel annotation. A Tunnel of another namespace
+// has to consent to the reference like it does for TunnelIngress objects.
+fn annotated_tunnel<C: CloudflaredTunnel>(
+    ingress: &Ingress,
+    ctx: &Context<C>,
+) -> Option<Result<AnyTunnel, Error>> {
+    let value = match ingress.annotations().get(TUNNEL_ANNOTATION) {
+        Some(value) => value,
+        None => {
+            let name = ingress.annotations().get(CLUSTER_TUNNEL_ANNOTATION)?;
+            return Some(
+                ctx.tunnel_stores
+                    .cluster_tunnels
+                    .get_tunnel(name, None)
+                    .map(AnyTunnel::ClusterTunnel)
+                    .ok_or_else(|| Error::MissingTunnel(format!("ClusterTunnel {}", name))),
+            );
+        }
+    };
+    let (namespace, name) = match value.split_once('/') {
+        Some((namespace, name)) => (namespace.to_owned(), name),
+        None => (
+            ingress.metadata.namespace.clone().unwrap_or_default(),
+            value.as_str(),
+        ),
+    };
+
+    let tunnel = match ctx.tunnel_stores.tunnels.get_tunnel(name, Some(&namespace)) {
+        Some(tunnel) => tunnel,
+        None => return Some(Err(Error::MissingTunnel(format!("{}/{}", namespace, name)))),
+    };
+    if let Some(denial) = cross_namespace_denial(
+        ingress.metadata.namespace.as_deref(),
+        tunnel.as_ref(),
+        ctx.allow_cross_namespace_refs,
+    ) {
+        return Some(Err(Error::CrossNamespaceTunnel(denial)));
+    }
+
+    Some(Ok(AnyTunnel::Tunnel(tunnel)))
+}
 
-    let ingress_class = match ingress.ingress_class_name() {
-        Some(class_name) => {
-            let obj_ref = ObjectRef::new(class_name);
-            match ctx.ingress_class_store.get(&obj_ref) {
-                Some(ingress_class) => ingress_class,
-                None => return Ok(Action::await_change()),
+// INFO: `None` when the ingress class isn't ours.
+fn ingress_tunnel<C: CloudflaredTunnel>(
+    ingress: &Ingress,
+    ctx: &Context<C>,
+) -> Result<Option<AnyTunnel>, Error> {
+    let ingress_class = match resolved_ingress_class(ingress, ctx) {
+        Some(ingress_class) => ingress_class,
+        // INFO: Ingresses without a class only go to the default tunnel when the policy is on.
+        None if ingress.ingress_class_name().is_none() && ctx.classless_ingress_policy => {
+            if let Some(tunnel) = annotated_tunnel(ingress, ctx) {
+                return tunnel.map(Some);
             }
+            return match ctx.tunnel_stores.default_tunnel() {
+                Ok(Some(tunnel)) => Ok(Some(tunnel)),
+                Ok(None) => Err(Error::MissingDefaultTunnel),
+                Err(err) => Err(Error::AmbiguousDefaultTunnel(err)),
+            };
         }
-        None => return Ok(Action::await_change()),
+        None => return Ok(None),
     };
 
-    let tunnel_crd = match ingress_class.spec.as_ref().unwrap().parameters.as_ref() {
+    if let Some(tunnel) = annotated_tunnel(ingress, ctx) {
+        return tunnel.map(Some);
+    }
+
+    // INFO: The spec is optional in the api, one without it has no parameters either.
+    let parameters = ingress_class
+        .spec
+        .as_ref()
+        .and_then(|spec| spec.parameters.as_ref());
+    let tunnel_crd = match parameters {
         Some(parameters) => {
-            // INFO: K8s default value for this is Cluster so we set that.
-            let default_scope = "Cluster".to_string();
-            let default_api_group = "cloudfare.ar2ro.io".to_string();
-            let scope = parameters.scope.as_ref().unwrap_or(&default_scope);
-            let kind = &parameters.kind;
-            let api_group = parameters.api_group.as_ref().unwrap_or(&default_api_group);
-
-            let tunnel = if Tunnel::crd().spec.group.eq(api_group)
-                && Tunnel::crd().spec.names.kind.eq(kind)
-                && Tunnel::crd().spec.scope.eq(scope)
-            {
-                let mut objectref = ObjectRef::new(parameters.name.as_str());
-                objectref.namespace = if "Namespace".eq(scope.as_str()) {
-                    parameters.namespace.clone()
-                } else {
+            // INFO: K8s default value for the scope is Cluster, an omitted apiGroup means ours.
+            let tunnel_definition = Tunnel::crd();
+            let cluster_tunnel_definition = ClusterTunnel::crd();
+            let scope = parameters.scope.as_deref().unwrap_or("Cluster");
+            let kind = parameters.kind.as_str();
+            let api_group = parameters
+                .api_group
+                .as_deref()
+                .unwrap_or(&tunnel_definition.spec.group);
+
+            // INFO: The api requires a namespace for Namespace scoped parameters and forbids one
+            // for Cluster scoped ones, the latter is only ignored.
+            let namespace = match (
+                scope,
+                parameters.namespace.as_deref().filter(|ns| !ns.is_empty()),
+            ) {
+                ("Namespace", None) => {
+                    return Err(Error::InvalidIngressClassParameters(
+                        "scope Namespace requires namespace",
+                    ))
+                }
+                ("Cluster", Some(namespace)) => {
+                    println!(
+                        "WARNING: IngressClass {} sets namespace {} on Cluster scoped parameters, \
+                         ignoring it",
+                        ingress_class.name_any(),
+                        namespace
+                    );
                     None
-                };
+                }
+                (_, namespace) => namespace,
+            };
 
-                match ctx.tunnel_store.get(&objectref) {
+            // INFO: IngressClass scopes are `Namespace` or `Cluster` while a CRD scope is
+            // `Namespaced` or `Cluster`, a Tunnel needs the former and a ClusterTunnel the latter.
+            let scope_matches = if kind == tunnel_definition.spec.names.kind {
+                scope == "Namespace"
+            } else if kind == cluster_tunnel_definition.spec.names.kind {
+                scope == "Cluster"
+            } else {
+                false
+            };
+
+            let tunnel = if api_group == tunnel_definition.spec.group && scope_matches {
+                match ctx
+                    .tunnel_stores
+                    .get(kind, parameters.name.as_str(), namespace)
+                {
                     Some(tunnel) => tunnel,
                     None => return Err(Error::MissingTunnel(parameters.name.clone())),
                 }
             } else {
                 return Err(Error::InvalidIngressClassParameters(
-                    "parameters don't match Tunnel Crd spec",
+                    "parameters don't match the Tunnel or ClusterTunnel Crd spec",
                 ));
             };
 
             tunnel
         }
-        None => match ctx.tunnel_store.default_tunnel() {
-            Some(tunnel) => tunnel,
-            None => return Err(Error::MissingDefaultTunnel),
+        None => match ctx.tunnel_stores.default_tunnel() {
+            Ok(Some(tunnel)) => tunnel,
+            Ok(None) => return Err(Error::MissingDefaultTunnel),
+            Err(err) => return Err(Error::AmbiguousDefaultTunnel(err)),
         },
     };
 
+    Ok(Some(tunnel_crd))
+}
+
+// INFO: Rebuilds the configuration of a tunnel from every Ingress on it. The last rules written
+// are kept per tunnel so an unchanged set doesn't reach Cloudflare at all, a failed write drops
+// them so the next reconcile compares against Cloudflare again. Returns the number of rules
+// when the configuration changed.
+async fn sync_tunnel<C: CloudflaredTunnel>(
+    tunnel: &AnyTunnel,
+    tunnel_uuid: Uuid,
+    ctx: &Context<C>,
+) -> Result<Option<usize>, Error> {
+    let ingresses = ctx
+        .ingress_store
+        .state()
+        .into_iter()
+        .filter(|other| other.metadata.deletion_timestamp.is_none())
+        // INFO: An allowlist of several namespaces still lists the whole cluster.
+        .filter(|other| in_watched_namespace(other, ctx))
+        .filter(|other| match ingress_tunnel(other, ctx) {
+            Ok(Some(other_tunnel)) => other_tunnel.get_uuid() == Some(tunnel_uuid),
+            _ => false,
+        })
+        .collect::<Vec<_>>();
+    let tunnel_ingresses = ctx
+        .tunnel_ingress_store
+        .state()
+        .into_iter()
+        .filter(|other| other.metadata.deletion_timestamp.is_none())
+        .filter(|other| tunnel_ingress::references(other, tunnel))
+        // INFO: An invalid TunnelIngress is left out instead of breaking the whole tunnel, its
+        // status says why.
+        .filter(|other| tunnel_ingress::problems(other, tunnel, ctx).is_empty())
+        .collect::<Vec<_>>();
+    let routes = ctx
+        .gateway_stores
+        .routes
+        .state()
+        .into_iter()
+        .filter(|other| other.metadata.deletion_timestamp.is_none())
+        .filter(|other| match gateway::route_tunnel(other, ctx) {
+            Ok(Some(other_tunnel)) => other_tunnel.get_uuid() == Some(tunnel_uuid),
+            _ => false,
+        })
+        .collect::<Vec<_>>();
+
+    let sources = ingresses
+        .iter()
+        .map(|ingress| {
+            let namespace = ingress.metadata.namespace.as_deref().unwrap_or_default();
+            RuleSource::new(
+                ingress.as_ref(),
+                ingress_path_rules(ingress, namespace, &ctx.service_store),
+            )
+        })
+        .chain(tunnel_ingresses.iter().map(|other| {
+            RuleSource::new(
+                other.as_ref(),
+                tunnel_ingress::tunnel_ingress_rules(other, &ctx.service_store),
+            )
+        }))
+        .chain(routes.iter().map(|route| {
+            RuleSource::new(
+                route.as_ref(),
+                gateway::httproute_path_rules(route, &ctx.gateway_stores.reference_grants),
+            )
+        }))
+        .collect();
+    let (rules, conflicts) = merged_tunnel_rules(sources, tunnel.spec().catch_all_service());
+
+    // INFO: The losing rules never make it into the configuration, so a conflict is reported
+    // even when the rules didn't change.
+    for conflict in conflicts {
+        let note = format!(
+            "Host {} path {} is already routed by {} on tunnel {}, this rule is ignored",
+            conflict.hostname, conflict.path, conflict.owner, tunnel_uuid
+        );
+        println!("{}: {}", conflict.name, note);
+        publish_reference_event(ctx, &conflict.object, EventType::Warning, "Conflict", note).await;
+    }
+
+    if ctx
+        .applied_rules
+        .get(&tunnel_uuid)
+        .is_some_and(|applied| *applied == rules)
+    {
+        return Ok(None);
+    }
+    ctx.applied_rules.remove(&tunnel_uuid);
+
+    let (account_id, credentials) = ctx
+        .credentials_api
+        .get_credentials(&tunnel.spec().credentials)
+        .await
+        .map_err(Error::TunnelError)?;
+
+    let applied = ctx
+        .cloudflare_client
+        .apply_configuration(
+            &credentials,
+            &account_id,
+            tunnel_uuid,
+            DriftPolicy::Merge,
+            |current| TunnelConfiguration {
+                ingress: rules.clone(),
+                origin_request: current.and_then(|current| current.origin_request.clone()),
+            },
+        )
+        .await
+        .map_err(Error::ConfigurationError)?;
+
+    if applied.changed {
+        println!(
+            "Updated tunnel {} to configuration version {} with {} rules from {} ingresses, {} tunnel ingresses and {} http routes",
+            tunnel_uuid,
+            applied.version,
+            rules.len(),
+            ingresses.len(),
+            tunnel_ingresses.len(),
+            routes.len()
+        );
+    }
+
+    let written = applied.changed.then_some(rules.len());
+    ctx.applied_rules.insert(tunnel_uuid, rules);
+    Ok(written)
+}
+
+fn has_finalizer(ingress: &Ingress) -> bool {
+    ingress
+        .finalizers()
+        .iter()
+        .any(|finalizer| finalizer == INGRESS_FINALIZER)
+}
+
+fn recorded_tunnel<K: ResourceExt>(object: &K) -> Option<Uuid> {
+    object
+        .annotations()
+        .get(TUNNEL_ID_ANNOTATION)
+        .and_then(|tunnel_id| tunnel_id.parse().ok())
+}
+
+// INFO: Finalizers of other controllers are kept, the resourceVersion turns a concurrent change
+// of the list into a conflict instead of dropping it.
+async fn patch_finalizers<K, C: CloudflaredTunnel>(
+    object: &K,
+    ctx: &Context<C>,
+    finalizers: Vec<String>,
+    tunnel_uuid: Option<Uuid>,
+) -> Result<(), Error>
+where
+    K: Resource<DynamicType = (), Scope = NamespaceResourceScope>
+        + Clone
+        + DeserializeOwned
+        + Debug,
+{
+    let api: Api<K> = Api::namespaced(
+        ctx.kubernetes_client.clone(),
+        object.meta().namespace.as_deref().unwrap_or_default(),
+    );
+
+    let patch = json!({
+        "metadata": {
+            "resourceVersion": object.meta().resource_version,
+            "finalizers": finalizers,
+            "annotations": {
+                TUNNEL_ID_ANNOTATION: tunnel_uuid.map(|tunnel_uuid| tunnel_uuid.to_string()),
+            },
+        }
+    });
+
+    match api
+        .patch(&object.name_any(), &patch_params(), &Patch::Merge(&patch))
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Error::KubeError(err)),
+    }
+}
+
+async fn add_finalizer<C: CloudflaredTunnel>(
+    ingress: &Ingress,
+    ctx: &Context<C>,
+    tunnel_uuid: Uuid,
+) -> Result<(), Error> {
+    let mut finalizers = ingress.finalizers().to_vec();
+    if !has_finalizer(ingress) {
+        finalizers.push(INGRESS_FINALIZER.to_owned());
+    }
+
+    patch_finalizers(ingress, ctx, finalizers, Some(tunnel_uuid)).await
+}
+
+// INFO: Rebuilds the tunnel the rules were written to without this Ingress, then lets it go.
+// Runs on deletion and when the Ingress stopped being ours.
+async fn release_ingress<C: CloudflaredTunnel>(
+    ingress: &Ingress,
+    ctx: &Context<C>,
+) -> Result<Action, Error> {
+    if let Some(tunnel_uuid) = recorded_tunnel(ingress) {
+        match ctx.tunnel_stores.find_by_uuid(tunnel_uuid) {
+            Some(tunnel) => {
+                sync_tunnel(&tunnel, tunnel_uuid, ctx).await?;
+            }
+            None => println!(
+                "Tunnel {} of Ingress {} is gone, nothing to remove",
+                tunnel_uuid,
+                ingress.name_any()
+            ),
+        }
+    }
+
+    let finalizers = ingress
+        .finalizers()
+        .iter()
+        .filter(|finalizer| *finalizer != INGRESS_FINALIZER)
+        .cloned()
+        .collect();
+    patch_finalizers(ingress, ctx, finalizers, None).await?;
+
+    Ok(Action::await_change())
+}
+
+async fn reconcile<C: CloudflaredTunnel>(
+    ingress: Arc<Ingress>,
+    ctx: Arc<Context<C>>,
+) -> Result<Action, Error> {
+    let result = reconcile_ingress(&ingress, &ctx).await;
+
+    let reason = match &result {
+        Err(Error::MissingDefaultTunnel) => "MissingDefaultTunnel",
+        Err(Error::AmbiguousDefaultTunnel(_)) => "AmbiguousDefaultTunnel",
+        Err(Error::MissingTunnel(_)) => "MissingTunnel",
+        Err(Error::CrossNamespaceTunnel(_)) => "CrossNamespaceTunnel",
+        Err(Error::InvalidIngressClassParameters(_)) => "InvalidIngressClassParameters",
+        _ => return result,
+    };
+
+    if let Err(err) = &result {
+        publish_event(
+            &ctx,
+            ingress.as_ref(),
+            EventType::Warning,
+            reason,
+            err.to_string(),
+        )
+        .await;
+    }
+
+    // INFO: Invalid parameters are fixed on the IngressClass, so that's where they're reported
+    // too.
+    if let Err(err @ Error::InvalidIngressClassParameters(_)) = &result {
+        if let Some(ingress_class) = resolved_ingress_class(&ingress, &ctx) {
+            publish_event(
+                &ctx,
+                ingress_class.as_ref(),
+                EventType::Warning,
+                reason,
+                err.to_string(),
+            )
+            .await;
+        }
+    }
+
+    result
+}
+
+fn in_watched_namespace<C: CloudflaredTunnel>(ingress: &Ingress, ctx: &Context<C>) -> bool {
+    ctx.watch_namespaces.is_empty()
+        || ingress
+            .metadata
+            .namespace
+            .as_ref()
+            .is_some_and(|namespace| ctx.watch_namespaces.contains(namespace))
+}
+
+async fn reconcile_ingress<C: CloudflaredTunnel>(
+    ingress: &Ingress,
+    ctx: &Context<C>,
+) -> Result<Action, Error> {
+    let ingress_ref = ObjectRef::from_obj(ingress);
+    if ingress.metadata.deletion_timestamp.is_some() {
+        ctx.ingress_tunnels.remove(&ingress_ref);
+        return match has_finalizer(ingress) {
+            true => release_ingress(ingress, ctx).await,
+            false => Ok(Action::await_change()),
+        };
+    }
+
+    if !in_watched_namespace(ingress, ctx) {
+        // INFO: Only Ingresses that would have been ours are worth an Event, the rest of the
+        // cluster's Ingresses are skipped quietly.
+        if resolved_ingress_class(ingress, ctx).is_some() {
+            publish_event(
+                ctx,
+                ingress,
+                EventType::Warning,
+                "NamespaceNotWatched",
+                format!(
+                    "namespace {} isn't watched by the ingress controller, this Ingress is ignored",
+                    ingress.metadata.namespace.clone().unwrap_or_default()
+                ),
+            )
+            .await;
+        }
+        return Ok(Action::await_change());
+    }
+
+    // INFO: Return early if we don't own this ingress class, one that was ours is released.
+    let tunnel_crd = match ingress_tunnel(ingress, ctx)? {
+        Some(tunnel_crd) => tunnel_crd,
+        None => {
+            ctx.ingress_tunnels.remove(&ingress_ref);
+            return match has_finalizer(ingress) {
+                true => release_ingress(ingress, ctx).await,
+                false => Ok(Action::await_change()),
+            };
+        }
+    };
+    ctx.ingress_tunnels
+        .insert(ingress_ref, tunnel_crd.object_ref());
+
     let tunnel_uuid = match tunnel_crd.get_uuid() {
         Some(tunnel_uuid) => tunnel_uuid,
         // Requeue in 2 minutes as the tunnel is not ready.
         None => return Ok(Action::requeue(std::time::Duration::from_secs(60 * 2))),
     };
 
-    // TODO: Parse the ingress.
+    if tunnel_crd.spec().is_local() {
+        println!(
+            "Ingress {} is on locally managed tunnel {}, its rules come from TunnelIngress objects",
+            ingress.name_any(),
+            tunnel_uuid
+        );
+        return match has_finalizer(ingress) {
+            true => release_ingress(ingress, ctx).await,
+            false => Ok(Action::await_change()),
+        };
+    }
+
+    for note in invalid_paths(ingress) {
+        publish_event(ctx, ingress, EventType::Warning, "InvalidPath", note).await;
+    }
+    // INFO: Services tend to be created alongside the Ingress, a missing one is checked again
+    // sooner than the usual resync.
+    let invalid_backends = invalid_backends(ingress, &ctx.service_store);
+    for note in &invalid_backends {
+        publish_event(
+            ctx,
+            ingress,
+            EventType::Warning,
+            "InvalidBackend",
+            note.clone(),
+        )
+        .await;
+    }
+    for note in invalid_annotations(ingress, &ctx.service_store) {
+        publish_event(ctx, ingress, EventType::Warning, "InvalidAnnotation", note).await;
+    }
+    if ignores_hostless_rules(ingress) {
+        let note = format!(
+            "Rules without a host are ignored unless the Ingress is annotated with {}: \"true\"",
+            CATCH_ALL_ANNOTATION
+        );
+        publish_event(ctx, ingress, EventType::Warning, "HostlessRule", note).await;
+    }
+
+    let previous = recorded_tunnel(ingress);
+    if !has_finalizer(ingress) || previous != Some(tunnel_uuid) {
+        add_finalizer(ingress, ctx, tunnel_uuid).await?;
+    }
+
+    if let Some(rules) = sync_tunnel(&tunnel_crd, tunnel_uuid, ctx).await? {
+        publish_event(
+            ctx,
+            ingress,
+            EventType::Normal,
+            "ConfigurationApplied",
+            format!(
+                "Applied {} rules to {} {} ({})",
+                rules,
+                tunnel_crd.kind(),
+                tunnel_crd.name(),
+                tunnel_uuid
+            ),
+        )
+        .await;
+    }
+
+    // INFO: The Ingress moved to another tunnel, its rules are dropped from the old one.
+    if let Some(previous) = previous.filter(|previous| *previous != tunnel_uuid) {
+        if let Some(previous_tunnel) = ctx.tunnel_stores.find_by_uuid(previous) {
+            sync_tunnel(&previous_tunnel, previous, ctx).await?;
+        }
+    }
+
+    match invalid_backends.is_empty() {
+        true => Ok(Action::requeue(std::time::Duration::from_secs(60))),
+        false => Ok(Action::requeue(std::time::Duration::from_secs(15))),
+    }
+}
+
+// INFO: An IngressClass maps to the Ingresses naming it, and to the classless ones when it's the
+// default class they're routed through.
+fn class_ingresses(
+    ingress_store: &Store<Ingress>,
+    ingress_class: &IngressClass,
+    default_ingress_class: bool,
+) -> Vec<ObjectRef<Ingress>> {
+    let is_default = default_ingress_class
+        && ingress_class
+            .annotations()
+            .get(DEFAULT_CLASS_ANNOTATION)
+            .is_some_and(|value| value == "true");
+
+    ingress_store
+        .state()
+        .into_iter()
+        .filter(|ingress| match ingress.ingress_class_name() {
+            Some(class_name) => *class_name == ingress_class.name_any(),
+            None => is_default,
+        })
+        .map(|ingress| ObjectRef::from_obj(ingress.as_ref()))
+        .collect()
+}
 
-    Ok(Action::requeue(std::time::Duration::from_secs(60)))
+fn tunnel_ingresses(
+    ingress_tunnels: &DashMap<ObjectRef<Ingress>, ObjectRef<DynamicObject>>,
+    tunnel: ObjectRef<DynamicObject>,
+) -> Vec<ObjectRef<Ingress>> {
+    ingress_tunnels
+        .iter()
+        .filter(|entry| *entry.value() == tunnel)
+        .map(|entry| entry.key().clone())
+        .collect()
 }
 
-fn error_policy<'a>(ingress: Arc<Ingress>, error: &Error, ctx: Arc<Context>) -> Action {
-    Action::requeue(std::time::Duration::from_secs(60))
+// INFO: Missing tunnels tend to show up on their own, broken class parameters wait for an edit
+// of the IngressClass which triggers a reconcile anyway.
+fn error_policy<C: CloudflaredTunnel>(
+    ingress: Arc<Ingress>,
+    error: &Error,
+    _ctx: Arc<Context<C>>,
+) -> Action {
+    println!(
+        "Failed to reconcile Ingress {}: {}",
+        ingress.name_any(),
+        error
+    );
+    let delay = match error {
+        Error::KubeError(_) => 5,
+        Error::MissingDefaultTunnel
+        | Error::AmbiguousDefaultTunnel(_)
+        | Error::MissingTunnel(_)
+        | Error::CrossNamespaceTunnel(_) => 30,
+        Error::TunnelError(_) | Error::ConfigurationError(_) => 60,
+        Error::InvalidIngressClassParameters(_) | Error::InvalidGatewayClassParameters(_) => 300,
+    };
+
+    Action::requeue(std::time::Duration::from_secs(delay))
 }
 
 impl StoreIngressClassExt<IngressClass> for Store<IngressClass> {
-    fn ingress_class_names(&self) -> Vec<String> {
+    fn default_ingress_classes(&self) -> Vec<Arc<IngressClass>> {
         self.state()
             .into_iter()
-            .filter(|ingress| {
-                ingress
-                    .controller_name()
-                    .map(|controller_name| controller_name.eq(INGRESS_CONTROLLER))
-                    .unwrap_or(false)
+            .filter(|ingress_class| {
+                ingress_class.is_ours()
+                    && ingress_class
+                        .annotations()
+                        .get(DEFAULT_CLASS_ANNOTATION)
+                        .is_some_and(|value| value == "true")
             })
-            .map(|ingress| ingress.name_any())
-            .collect::<Vec<_>>()
+            .collect()
     }
 }
 
@@ -177,13 +1263,45 @@ impl IngressExt for Ingress {
 
 impl IngressController {
     pub async fn start(self) -> anyhow::Result<()> {
+        // INFO: The tunnel stores are filled by the tunnel controller, reconciling before its
+        // initial list arrived would report every tunnel as missing.
+        self.tunnel_stores.wait_until_ready().await?;
+
         let wc = watcher::Config::default().timeout(20);
+        // INFO: HTTPRoutes are routed alongside Ingresses and TunnelIngresses, all of them end
+        // up in the same tunnel configurations.
+        let gateway_stores = match self.mode {
+            ControllerMode::Ingress => GatewayStores::default(),
+            ControllerMode::GatewayApi => self.watch_gateway_api(&wc).await?,
+        };
 
         let ingress_class_api: Api<IngressClass> = Api::all(self.kubernetes_client.clone());
-        let ingress_api: Api<Ingress> = Api::all(self.kubernetes_client.clone());
+        // INFO: A single namespace is watched on its own, an allowlist of several still watches
+        // the whole cluster and reconcile skips what's outside of it. A reflector store is
+        // replaced on every initial list, so several namespaced watches can't share one. Either
+        // way the operator needs cluster-wide list and watch on Ingresses then, like it does on
+        // Services and TunnelIngresses.
+        let ingress_api: Api<Ingress> = match self.watch_namespaces.as_slice() {
+            [namespace] => Api::namespaced(self.kubernetes_client.clone(), namespace),
+            [] => Api::all(self.kubernetes_client.clone()),
+            namespaces => {
+                println!(
+                    "Watching Ingresses in every namespace, only the ones in {} are reconciled",
+                    namespaces.join(", ")
+                );
+                Api::all(self.kubernetes_client.clone())
+            }
+        };
+        let service_api: Api<Service> = Api::all(self.kubernetes_client.clone());
+        let tunnel_api: Api<Tunnel> = Api::all(self.kubernetes_client.clone());
+        let cluster_tunnel_api: Api<ClusterTunnel> = Api::all(self.kubernetes_client.clone());
+        let tunnel_ingress_api: Api<TunnelIngress> = Api::all(self.kubernetes_client.clone());
+        let ingress_tunnels = Arc::new(DashMap::new());
 
         let (ingress_class_store, ingress_class_writer) = reflector::store();
         let (ingress_store, ingress_writer) = reflector::store();
+        let (service_store, service_writer) = reflector::store();
+        let (tunnel_ingress_store, tunnel_ingress_writer) = reflector::store();
 
         // NOTE: This needs to be started before the controller or it will stall.
         let ingress_class_watcher = watcher(ingress_class_api.clone(), wc.clone())
@@ -192,54 +1310,2473 @@ impl IngressController {
             .touched_objects()
             .for_each(|_| ready(()));
 
-        let ingress_class_store_clone = ingress_class_store.clone();
-        let ingress_watcher = watcher(ingress_api.clone(), wc.clone())
+        let service_watcher = watcher(service_api, wc.clone())
+            .reflect(service_writer)
             .default_backoff()
-            .reflect(ingress_writer)
             .touched_objects()
-            .try_filter(move |ingress| {
-                ready(ingress.ingress_class_name().map_or_else(
-                    || false,
-                    |name| {
-                        ingress_class_store_clone
-                            .ingress_class_names()
-                            .contains(name)
-                    },
-                ))
-            });
+            .for_each(|_| ready(()));
+
+        // INFO: Every tunnel sync merges in the TunnelIngress rules, an empty store would drop
+        // them from the tunnel.
+        let tunnel_ingress_watcher = watcher(tunnel_ingress_api.clone(), wc.clone())
+            .reflect(tunnel_ingress_writer)
+            .default_backoff()
+            .touched_objects()
+            .for_each(|_| ready(()));
 
         // NOTE: Starts ingress class watcher and waits for it to be populated.
         tokio::spawn(ingress_class_watcher);
+        tokio::spawn(service_watcher);
+        tokio::spawn(tunnel_ingress_watcher);
         ingress_class_store.wait_until_ready().await?;
+        service_store.wait_until_ready().await?;
+        tunnel_ingress_store.wait_until_ready().await?;
+
+        let route_api: Api<HTTPRoute> = Api::all(self.kubernetes_client.clone());
+        let recorder = Recorder::new(
+            self.kubernetes_client.clone(),
+            Reporter {
+                controller: "cloudflare-ingress-controller".into(),
+                instance: None,
+            },
+        );
 
         let ctx = Arc::new(Context {
+            credentials_api: Api::all(self.kubernetes_client.clone()),
             kubernetes_client: self.kubernetes_client,
             cloudflare_client: self.cloudflare_client,
             ingress_store: ingress_store.clone(),
-            ingress_api,
-            ingress_class_store,
+            tunnel_ingress_store,
+            ingress_api: ingress_api.clone(),
+            ingress_class_store: ingress_class_store.clone(),
             ingress_class_api: ingress_class_api.clone(),
-            tunnel_store: self.tunnel_store,
+            service_store,
+            tunnel_stores: self.tunnel_stores,
+            gateway_stores,
+            applied_rules: DashMap::new(),
+            ingress_tunnels: ingress_tunnels.clone(),
+            classless_ingress_policy: self.classless_ingress_policy,
+            default_ingress_class: self.default_ingress_class,
+            watch_namespaces: self.watch_namespaces,
+            allow_cross_namespace_refs: self.allow_cross_namespace_refs,
+            conflicting_default_classes: AtomicBool::new(false),
+            recorder,
         });
 
+        // INFO: Every Ingress reaches reconcile, which drops the ones that aren't ours with a
+        // store lookup. Filtering the stream instead would lose Ingresses created before their
+        // IngressClass, the class watch below brings those back.
+        let default_ingress_class = self.default_ingress_class;
+        let ingress_watcher = watcher(ingress_api, wc.clone())
+            .default_backoff()
+            .reflect(ingress_writer)
+            .touched_objects();
+
+        let class_ingress_store = ingress_store.clone();
+        let ingress_class_mapper = move |ingress_class: IngressClass| {
+            class_ingresses(&class_ingress_store, &ingress_class, default_ingress_class)
+        };
+
         // Controller is trigged when a change to the stream happens and when
-        Controller::for_stream(ingress_watcher, ingress_store)
-            .owns(ingress_class_api, wc.clone())
+        let tunnel_ingress_controller = Controller::new(tunnel_ingress_api, wc.clone())
+            // INFO: Tunnel changes like a new catch-all service reach its TunnelIngresses right
+            // away instead of after their requeue.
+            .watches(tunnel_api.clone(), wc.clone(), {
+                let tunnel_ingress_store = ctx.tunnel_ingress_store.clone();
+                move |tunnel: Tunnel| {
+                    tunnel_ingress::referencing(
+                        &tunnel_ingress_store,
+                        &AnyTunnel::Tunnel(Arc::new(tunnel)),
+                    )
+                }
+            })
+            .watches(cluster_tunnel_api.clone(), wc.clone(), {
+                let tunnel_ingress_store = ctx.tunnel_ingress_store.clone();
+                move |tunnel: ClusterTunnel| {
+                    tunnel_ingress::referencing(
+                        &tunnel_ingress_store,
+                        &AnyTunnel::ClusterTunnel(Arc::new(tunnel)),
+                    )
+                }
+            })
+            .graceful_shutdown_on(self.shutdown.clone().cancelled_owned())
+            .run(
+                tunnel_ingress::reconcile,
+                tunnel_ingress::error_policy,
+                ctx.clone(),
+            )
+            .for_each(|_| ready(()));
+
+        let route_controller = {
+            let gateway_api = self.mode == ControllerMode::GatewayApi;
+            let (ctx, wc, shutdown) = (ctx.clone(), wc.clone(), self.shutdown.clone());
+            async move {
+                if gateway_api {
+                    gateway::run_controller(route_api, wc, ctx, shutdown).await;
+                }
+            }
+        };
+
+        let ingress_controller = Controller::for_stream(ingress_watcher, ingress_store)
+            .watches(ingress_class_api, wc.clone(), ingress_class_mapper)
+            // INFO: A tunnel getting its uuid or being replaced reaches its Ingresses right away
+            // instead of after their requeue.
+            .watches(tunnel_api, wc.clone(), {
+                let ingress_tunnels = ingress_tunnels.clone();
+                move |tunnel: Tunnel| {
+                    tunnel_ingresses(&ingress_tunnels, ObjectRef::from_obj(&tunnel).erase())
+                }
+            })
+            .watches(
+                cluster_tunnel_api,
+                wc.clone(),
+                move |tunnel: ClusterTunnel| {
+                    tunnel_ingresses(&ingress_tunnels, ObjectRef::from_obj(&tunnel).erase())
+                },
+            )
+            // INFO: Stops pulling new events once cancelled but lets in-flight reconciles finish.
+            .graceful_shutdown_on(self.shutdown.cancelled_owned())
             .run(reconcile, error_policy, ctx)
-            .for_each(|_| ready(()))
-            .await;
+            .for_each(|_| ready(()));
+
+        tokio::join!(
+            ingress_controller,
+            tunnel_ingress_controller,
+            route_controller
+        );
         Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     pub async fn try_new(
         kubernetes_client: Client,
         cloudflare_client: CloudflareClient,
-        tunnel_store: Store<Tunnel>,
+        tunnel_stores: TunnelStores,
+        mode: ControllerMode,
+        classless_ingress_policy: bool,
+        default_ingress_class: bool,
+        watch_namespaces: Vec<String>,
+        allow_cross_namespace_refs: bool,
+        shutdown: CancellationToken,
     ) -> anyhow::Result<IngressController> {
         Ok(IngressController {
             kubernetes_client,
             cloudflare_client,
-            tunnel_store,
+            tunnel_stores,
+            mode,
+            classless_ingress_policy,
+            default_ingress_class,
+            watch_namespaces,
+            allow_cross_namespace_refs,
+            shutdown,
         })
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cloudflare_controller_common::{
+        DEFAULT_ANNOTATION, HTTPROUTE_FINALIZER, TUNNEL_INGRESS_FINALIZER,
+    };
+    use cloudflarext::tunnel_configuration::normalize_origin_settings;
+    use gateway::{Gateway, GatewayClass, ReferenceGrant, GATEWAY_CONTROLLER};
+    use serde_json::Value;
+    use std::time::{Duration, Instant};
+    use tunnel_controller::crd::credentials::{AuthKind, CredentialsCrd};
+    use tunnel_controller::crd::tunnel_ingress::{
+        ALLOW_CROSS_NAMESPACE_ANNOTATION, APPLIED_CONDITION,
+    };
+    use tunnel_controller::mock::{ApiServer, MockCloudflareClient};
+
+    const NAMESPACE: Option<&str> = Some("default");
+    const TUNNEL_ID: &str = "6f1c2b1e-8a4d-4c3e-9f7a-2d5b8e0c1a34";
+
+    fn services(services: Vec<Value>) -> Store<Service> {
+        store(
+            services
+                .into_iter()
+                .map(|service| serde_json::from_value(service).unwrap())
+                .collect(),
+        )
+    }
+
+    fn service(name: &str, spec: Value) -> Value {
+        json!({
+            "metadata": { "name": name, "namespace": "default" },
+            "spec": spec,
+        })
+    }
+
+    fn ingress(annotations: Value, spec: Value) -> Ingress {
+        serde_json::from_value(json!({
+            "metadata": { "name": "web", "namespace": "default", "annotations": annotations },
+            "spec": spec,
+        }))
+        .unwrap()
+    }
+
+    fn prefix(path: &str, service: &str, port: i32) -> Value {
+        json!({
+            "path": path,
+            "pathType": "Prefix",
+            "backend": { "service": { "name": service, "port": { "number": port } } },
+        })
+    }
+
+    fn rule(hostname: Option<&str>, path: Option<&str>, service: &str) -> IngressRule {
+        IngressRule {
+            hostname: hostname.map(str::to_owned),
+            path: path.map(str::to_owned),
+            service: service.to_owned(),
+            ..IngressRule::default()
+        }
+    }
+
+    #[test]
+    fn rules_translate_to_tunnel_rules() {
+        let services = services(vec![
+            service("api", json!({ "ports": [{ "port": 8080 }] })),
+            service("secure", json!({ "ports": [{ "port": 443 }] })),
+        ]);
+        let ingress = ingress(
+            json!({}),
+            json!({ "rules": [
+                { "host": "api.example.com", "http": { "paths": [prefix("/api", "api", 8080)] } },
+                { "host": "secure.example.com", "http": { "paths": [prefix("/", "secure", 443)] } },
+            ] }),
+        );
+
+        assert_eq!(
+            ingress_to_tunnel_rules(&ingress, "default", &services),
+            vec![
+                rule(
+                    Some("api.example.com"),
+                    Some("^/api(/.*)?$"),
+                    "http://api.default.svc.cluster.local:8080"
+                ),
+                rule(
+                    Some("secure.example.com"),
+                    None,
+                    "https://secure.default.svc.cluster.local:443"
+                ),
+                rule(None, None, CATCH_ALL_SERVICE),
+            ]
+        );
+    }
+
+    #[test]
+    fn default_backend_replaces_the_catch_all() {
+        let services = services(vec![service(
+            "fallback",
+            json!({ "ports": [{ "port": 80 }] }),
+        )]);
+        let ingress = ingress(
+            json!({}),
+            json!({
+                "defaultBackend": { "service": { "name": "fallback", "port": { "number": 80 } } },
+            }),
+        );
+
+        assert_eq!(
+            ingress_to_tunnel_rules(&ingress, "default", &services),
+            vec![rule(
+                None,
+                None,
+                "http://fallback.default.svc.cluster.local:80"
+            )]
+        );
+    }
+
+    #[test]
+    fn unreachable_backends_are_left_out() {
+        let services = services(vec![]);
+        let ingress = ingress(
+            json!({}),
+            json!({
+                "defaultBackend": { "service": { "name": "missing", "port": { "number": 80 } } },
+                "rules": [
+                    { "host": "web.example.com", "http": { "paths": [prefix("/", "missing", 80)] } },
+                ],
+            }),
+        );
+
+        assert_eq!(
+            ingress_to_tunnel_rules(&ingress, "default", &services),
+            vec![rule(None, None, CATCH_ALL_SERVICE)]
+        );
+        assert_eq!(
+            invalid_backends(&ingress, &services),
+            vec!["Service default/missing doesn't exist".to_owned()]
+        );
+    }
+
+    fn created_ingress(name: &str, created: &str, spec: Value) -> Ingress {
+        serde_json::from_value(json!({
+            "metadata": { "name": name, "namespace": "default", "creationTimestamp": created },
+            "spec": spec,
+        }))
+        .unwrap()
+    }
+
+    fn merged(ingresses: &[&Ingress], services: &Store<Service>) -> Vec<IngressRule> {
+        merged_with_conflicts(ingresses, services).0
+    }
+
+    /// The merged rules and who lost a route to whom.
+    fn merged_with_conflicts(
+        ingresses: &[&Ingress],
+        services: &Store<Service>,
+    ) -> (Vec<IngressRule>, Vec<(String, String)>) {
+        let sources = ingresses
+            .iter()
+            .map(|ingress| {
+                RuleSource::new(*ingress, ingress_path_rules(ingress, "default", services))
+            })
+            .collect();
+        let (rules, conflicts) = merged_tunnel_rules(sources, CATCH_ALL_SERVICE);
+        let conflicts = conflicts
+            .into_iter()
+            .map(|conflict| (conflict.name, conflict.owner))
+            .collect();
+        (rules, conflicts)
+    }
+
+    #[test]
+    fn ingresses_on_a_tunnel_merge_in_any_order() {
+        let services = services(vec![
+            service("api", json!({ "ports": [{ "port": 80 }] })),
+            service("web", json!({ "ports": [{ "port": 80 }] })),
+        ]);
+        let api = created_ingress(
+            "api",
+            "2024-01-01T00:00:00Z",
+            json!({ "rules": [
+                { "host": "api.example.com", "http": { "paths": [prefix("/", "api", 80)] } },
+            ] }),
+        );
+        let web = created_ingress(
+            "web",
+            "2024-01-02T00:00:00Z",
+            json!({ "rules": [
+                { "host": "web.example.com", "http": { "paths": [prefix("/", "web", 80)] } },
+            ] }),
+        );
+        let api_rule = rule(
+            Some("api.example.com"),
+            None,
+            "http://api.default.svc.cluster.local:80",
+        );
+        let web_rule = rule(
+            Some("web.example.com"),
+            None,
+            "http://web.default.svc.cluster.local:80",
+        );
+        let catch_all = rule(None, None, CATCH_ALL_SERVICE);
+
+        // INFO: Added in either order.
+        let both = vec![api_rule.clone(), web_rule.clone(), catch_all.clone()];
+        assert_eq!(merged(&[&api, &web], &services), both);
+        assert_eq!(merged(&[&web, &api], &services), both);
+
+        // INFO: Removed in either order.
+        assert_eq!(
+            merged(&[&web], &services),
+            vec![web_rule.clone(), catch_all.clone()]
+        );
+        assert_eq!(
+            merged(&[&api], &services),
+            vec![api_rule.clone(), catch_all.clone()]
+        );
+        assert_eq!(merged(&[], &services), vec![catch_all]);
+    }
+
+    #[test]
+    fn the_oldest_ingress_keeps_a_shared_route_in_any_order() {
+        let services = services(vec![
+            service("old", json!({ "ports": [{ "port": 80 }] })),
+            service("new", json!({ "ports": [{ "port": 80 }] })),
+        ]);
+        let old = created_ingress(
+            "old",
+            "2024-01-01T00:00:00Z",
+            json!({ "rules": [
+                { "host": "web.example.com", "http": { "paths": [prefix("/", "old", 80)] } },
+            ] }),
+        );
+        let new = created_ingress(
+            "new",
+            "2024-01-02T00:00:00Z",
+            json!({ "rules": [
+                { "host": "web.example.com", "http": { "paths": [prefix("/", "new", 80)] } },
+            ] }),
+        );
+        let old_rule = rule(
+            Some("web.example.com"),
+            None,
+            "http://old.default.svc.cluster.local:80",
+        );
+        let new_rule = rule(
+            Some("web.example.com"),
+            None,
+            "http://new.default.svc.cluster.local:80",
+        );
+        let catch_all = rule(None, None, CATCH_ALL_SERVICE);
+
+        let both = vec![old_rule.clone(), catch_all.clone()];
+        assert_eq!(merged(&[&old, &new], &services), both);
+        assert_eq!(merged(&[&new, &old], &services), both);
+
+        // INFO: Once the oldest is gone the route falls to the other one.
+        assert_eq!(merged(&[&new], &services), vec![new_rule, catch_all]);
+    }
+
+    fn tunnel_id() -> Uuid {
+        TUNNEL_ID.parse().unwrap()
+    }
+
+    fn store<K>(objects: Vec<K>) -> Store<K>
+    where
+        K: Resource<DynamicType = ()> + Lookup<DynamicType = ()> + Clone,
+    {
+        let (store, mut writer) = reflector::store::<K>();
+        for object in objects {
+            writer.apply_watcher_event(&watcher::Event::Apply(object));
+        }
+        store
+    }
+
+    fn context(client: Client) -> Context<MockCloudflareClient> {
+        Context {
+            kubernetes_client: client.clone(),
+            cloudflare_client: MockCloudflareClient::new(),
+            ingress_api: Api::all(client.clone()),
+            ingress_store: store(vec![]),
+            tunnel_ingress_store: store(vec![]),
+            ingress_class_api: Api::all(client.clone()),
+            ingress_class_store: store(vec![]),
+            service_store: store(vec![]),
+            credentials_api: Api::all(client.clone()),
+            tunnel_stores: TunnelStores {
+                tunnels: store(vec![]),
+                cluster_tunnels: store(vec![]),
+            },
+            gateway_stores: GatewayStores::default(),
+            applied_rules: DashMap::new(),
+            ingress_tunnels: Arc::new(DashMap::new()),
+            classless_ingress_policy: false,
+            default_ingress_class: false,
+            watch_namespaces: Vec::new(),
+            allow_cross_namespace_refs: false,
+            conflicting_default_classes: AtomicBool::new(false),
+            recorder: Recorder::new(
+                client,
+                Reporter {
+                    controller: "cloudflare-ingress-controller".into(),
+                    instance: None,
+                },
+            ),
+        }
+    }
+
+    // INFO: The stores are filled from the api server, as the reflectors would before a
+    // reconcile.
+    fn refresh(ctx: &mut Context<MockCloudflareClient>, server: &ApiServer) {
+        ctx.ingress_store = store(server.get_all(None));
+        ctx.tunnel_ingress_store = store(server.get_all(None));
+        ctx.ingress_class_store = store(server.get_all(None));
+        ctx.service_store = store(server.get_all(None));
+        ctx.tunnel_stores = TunnelStores {
+            tunnels: store(server.get_all(None)),
+            cluster_tunnels: store(server.get_all(None)),
+        };
+        ctx.gateway_stores = GatewayStores {
+            gateway_classes: store(server.get_all(None)),
+            gateways: store(server.get_all(None)),
+            routes: store(server.get_all(None)),
+            reference_grants: store(server.get_all(None)),
+        };
+    }
+
+    fn insert<K>(server: &ApiServer, object: Value)
+    where
+        K: Resource<DynamicType = ()> + DeserializeOwned + serde::Serialize,
+    {
+        server.insert(&serde_json::from_value::<K>(object).unwrap());
+    }
+
+    // INFO: Tunnel web in default with the IngressClass cloudflare pointing at it and a Service
+    // web to route to.
+    fn seed(server: &ApiServer) {
+        server.insert(&Credentials::new(
+            "creds",
+            CredentialsCrd {
+                account_id: "account".to_owned(),
+                auth: AuthKind::UserAuthToken("token".to_owned()),
+            },
+        ));
+        seed_tunnel(server, "web", TUNNEL_ID);
+        seed_ingress_class(server, "cloudflare", tunnel_parameters("web"));
+        server.insert(
+            &serde_json::from_value::<Service>(service(
+                "web",
+                json!({ "ports": [{ "port": 80 }] }),
+            ))
+            .unwrap(),
+        );
+    }
+
+    fn seed_tunnel(server: &ApiServer, name: &str, uuid: &str) {
+        insert::<Tunnel>(
+            server,
+            json!({
+                "metadata": { "name": name, "namespace": "default" },
+                "spec": { "credentials": "creds", "uuid": uuid },
+            }),
+        );
+    }
+
+    fn tunnel_parameters(name: &str) -> Value {
+        json!({
+            "apiGroup": "cloudflare.ar2ro.io",
+            "kind": "Tunnel",
+            "name": name,
+            "namespace": "default",
+            "scope": "Namespace",
+        })
+    }
+
+    // INFO: Null parameters leave them out of the spec.
+    fn seed_ingress_class(server: &ApiServer, name: &str, parameters: Value) {
+        insert::<IngressClass>(
+            server,
+            json!({
+                "metadata": { "name": name },
+                "spec": { "controller": INGRESS_CONTROLLER, "parameters": parameters },
+            }),
+        );
+    }
+
+    fn seed_ingress(server: &ApiServer, name: &str, spec: Value) {
+        insert::<Ingress>(
+            server,
+            json!({
+                "metadata": { "name": name, "namespace": "default" },
+                "spec": spec,
+            }),
+        );
+    }
+
+    fn web_ingress(host: &str) -> Value {
+        json!({
+            "ingressClassName": "cloudflare",
+            "rules": [{ "host": host, "http": { "paths": [prefix("/", "web", 80)] } }],
+        })
+    }
+
+    async fn reconcile_stored(
+        server: &ApiServer,
+        ctx: &mut Context<MockCloudflareClient>,
+        name: &str,
+    ) -> Result<Action, Error> {
+        refresh(ctx, server);
+        let ingress = server
+            .get::<Ingress>(NAMESPACE, name)
+            .expect("ingress is gone");
+        reconcile_ingress(&ingress, ctx).await
+    }
+
+    fn tunnel_rules(ctx: &Context<MockCloudflareClient>) -> Vec<IngressRule> {
+        ctx.cloudflare_client
+            .configuration(tunnel_id())
+            .map(|config| config.ingress)
+            .unwrap_or_default()
+    }
+
+    fn web_rule(host: &str) -> IngressRule {
+        rule(Some(host), None, "http://web.default.svc.cluster.local:80")
+    }
+
+    #[tokio::test]
+    async fn owned_ingress_gets_the_finalizer_and_its_rules() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_ingress(&server, "web", web_ingress("web.example.com"));
+
+        reconcile_stored(&server, &mut ctx, "web").await.unwrap();
+
+        let ingress = server.get::<Ingress>(NAMESPACE, "web").unwrap();
+        assert_eq!(ingress.finalizers(), [INGRESS_FINALIZER]);
+        assert_eq!(recorded_tunnel(&ingress), Some(tunnel_id()));
+        assert_eq!(
+            tunnel_rules(&ctx),
+            vec![
+                web_rule("web.example.com"),
+                rule(None, None, CATCH_ALL_SERVICE)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn deleted_ingress_is_removed_from_the_tunnel() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_ingress(&server, "web", web_ingress("web.example.com"));
+        seed_ingress(&server, "api", web_ingress("api.example.com"));
+        reconcile_stored(&server, &mut ctx, "web").await.unwrap();
+        reconcile_stored(&server, &mut ctx, "api").await.unwrap();
+
+        server.delete::<Ingress>(NAMESPACE, "web");
+        reconcile_stored(&server, &mut ctx, "web").await.unwrap();
+
+        assert!(server.get::<Ingress>(NAMESPACE, "web").is_none());
+        assert_eq!(
+            tunnel_rules(&ctx),
+            vec![
+                web_rule("api.example.com"),
+                rule(None, None, CATCH_ALL_SERVICE)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn ingress_switched_to_another_class_is_released() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_ingress(&server, "web", web_ingress("web.example.com"));
+        reconcile_stored(&server, &mut ctx, "web").await.unwrap();
+
+        server.update::<Ingress>(
+            NAMESPACE,
+            "web",
+            json!({ "spec": { "ingressClassName": "nginx" } }),
+        );
+        reconcile_stored(&server, &mut ctx, "web").await.unwrap();
+
+        let ingress = server.get::<Ingress>(NAMESPACE, "web").unwrap();
+        assert!(ingress.finalizers().is_empty());
+        assert_eq!(recorded_tunnel(&ingress), None);
+        assert_eq!(
+            tunnel_rules(&ctx),
+            vec![rule(None, None, CATCH_ALL_SERVICE)]
+        );
+
+        // INFO: Nothing holds the deletion anymore.
+        server.delete::<Ingress>(NAMESPACE, "web");
+        assert!(server.get::<Ingress>(NAMESPACE, "web").is_none());
+    }
+
+    #[tokio::test]
+    async fn foreign_ingress_never_gets_the_finalizer() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_ingress(
+            &server,
+            "web",
+            json!({
+                "ingressClassName": "nginx",
+                "rules": [{ "host": "web.example.com", "http": { "paths": [prefix("/", "web", 80)] } }],
+            }),
+        );
+
+        reconcile_stored(&server, &mut ctx, "web").await.unwrap();
+
+        let ingress = server.get::<Ingress>(NAMESPACE, "web").unwrap();
+        assert!(ingress.finalizers().is_empty());
+        assert_eq!(ctx.cloudflare_client.calls("update_configuration"), 0);
+    }
+
+    /// Type, reason and kind of the object of every recorded event.
+    fn events(server: &ApiServer) -> Vec<(String, String, String)> {
+        server
+            .get_all::<k8s_openapi::api::events::v1::Event>(None)
+            .into_iter()
+            .map(|event| {
+                (
+                    event.type_.unwrap_or_default(),
+                    event.reason.unwrap_or_default(),
+                    event
+                        .regarding
+                        .and_then(|regarding| regarding.kind)
+                        .unwrap_or_default(),
+                )
+            })
+            .collect()
+    }
+
+    fn event(type_: &str, reason: &str, kind: &str) -> (String, String, String) {
+        (type_.to_owned(), reason.to_owned(), kind.to_owned())
+    }
+
+    // INFO: Goes through `reconcile` since that's where failures become events.
+    async fn reconcile_reported(
+        server: &ApiServer,
+        mut ctx: Context<MockCloudflareClient>,
+        name: &str,
+    ) -> Result<Action, Error> {
+        refresh(&mut ctx, server);
+        let ingress = server.get::<Ingress>(NAMESPACE, name).unwrap();
+        reconcile(Arc::new(ingress), Arc::new(ctx)).await
+    }
+
+    #[tokio::test]
+    async fn missing_default_tunnel_is_reported() {
+        let (client, server) = ApiServer::start();
+        seed(&server);
+        seed_ingress_class(&server, "default-tunnel", Value::Null);
+        seed_ingress(
+            &server,
+            "web",
+            json!({ "ingressClassName": "default-tunnel" }),
+        );
+
+        let result = reconcile_reported(&server, context(client), "web").await;
+
+        assert!(matches!(result, Err(Error::MissingDefaultTunnel)));
+        assert_eq!(
+            events(&server),
+            vec![event("Warning", "MissingDefaultTunnel", "Ingress")]
+        );
+    }
+
+    #[tokio::test]
+    async fn ambiguous_default_tunnel_is_reported() {
+        let (client, server) = ApiServer::start();
+        seed(&server);
+        for (name, uuid) in [("first", TUNNEL_ID), ("second", OTHER_TUNNEL_ID)] {
+            insert::<Tunnel>(
+                &server,
+                json!({
+                    "metadata": {
+                        "name": name,
+                        "namespace": "default",
+                        "annotations": { DEFAULT_ANNOTATION: "True" },
+                    },
+                    "spec": { "credentials": "creds", "uuid": uuid },
+                }),
+            );
+        }
+        seed_ingress_class(&server, "default-tunnel", Value::Null);
+        seed_ingress(
+            &server,
+            "web",
+            json!({ "ingressClassName": "default-tunnel" }),
+        );
+
+        let result = reconcile_reported(&server, context(client), "web").await;
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "more than one default tunnel: Tunnel default/first, Tunnel default/second"
+        );
+        assert_eq!(
+            events(&server),
+            vec![event("Warning", "AmbiguousDefaultTunnel", "Ingress")]
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_tunnel_is_reported() {
+        let (client, server) = ApiServer::start();
+        seed(&server);
+        seed_ingress_class(
+            &server,
+            "gone",
+            json!({ "kind": "Tunnel", "name": "gone", "namespace": "default", "scope": "Namespace" }),
+        );
+        seed_ingress(&server, "web", json!({ "ingressClassName": "gone" }));
+
+        let result = reconcile_reported(&server, context(client), "web").await;
+
+        assert!(matches!(result, Err(Error::MissingTunnel(name)) if name == "gone"));
+        assert_eq!(
+            events(&server),
+            vec![event("Warning", "MissingTunnel", "Ingress")]
+        );
+    }
+
+    #[tokio::test]
+    async fn invalid_parameters_are_reported_on_the_ingress_and_its_class() {
+        let (client, server) = ApiServer::start();
+        seed(&server);
+        seed_ingress_class(
+            &server,
+            "broken",
+            json!({ "apiGroup": "apps", "kind": "Deployment", "name": "web", "scope": "Cluster" }),
+        );
+        seed_ingress(&server, "web", json!({ "ingressClassName": "broken" }));
+
+        let result = reconcile_reported(&server, context(client), "web").await;
+
+        assert!(matches!(
+            result,
+            Err(Error::InvalidIngressClassParameters(_))
+        ));
+        let mut events = events(&server);
+        events.sort();
+        assert_eq!(
+            events,
+            vec![
+                event("Warning", "InvalidIngressClassParameters", "Ingress"),
+                event("Warning", "InvalidIngressClassParameters", "IngressClass"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn ingress_class_without_a_spec_is_ignored() {
+        let (client, server) = ApiServer::start();
+        seed(&server);
+        insert::<IngressClass>(&server, json!({ "metadata": { "name": "bare" } }));
+        seed_ingress(&server, "web", json!({ "ingressClassName": "bare" }));
+
+        let result = reconcile_reported(&server, context(client), "web").await;
+
+        assert!(result.is_ok());
+        assert!(events(&server).is_empty());
+    }
+
+    #[tokio::test]
+    async fn ingress_class_without_parameters_uses_the_default_tunnel() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        insert::<Tunnel>(
+            &server,
+            json!({
+                "metadata": {
+                    "name": "other",
+                    "namespace": "default",
+                    "annotations": { DEFAULT_ANNOTATION: "true" },
+                },
+                "spec": { "credentials": "creds", "uuid": OTHER_TUNNEL_ID },
+            }),
+        );
+        seed_ingress_class(&server, "default-tunnel", Value::Null);
+
+        let tunnel = routed_tunnel(
+            &server,
+            &mut ctx,
+            json!({ "ingressClassName": "default-tunnel" }),
+        );
+
+        assert_eq!(tunnel.as_deref(), Some("other"));
+    }
+
+    fn scoped_tunnel(
+        server: &ApiServer,
+        ctx: &mut Context<MockCloudflareClient>,
+        parameters: Value,
+    ) -> Result<Option<AnyTunnel>, Error> {
+        seed_ingress_class(server, "scoped", parameters);
+        seed_ingress(server, "web", json!({ "ingressClassName": "scoped" }));
+        refresh(ctx, server);
+        let ingress = server.get::<Ingress>(NAMESPACE, "web").unwrap();
+        ingress_tunnel(&ingress, ctx)
+    }
+
+    fn seed_cluster_tunnel(server: &ApiServer) {
+        insert::<ClusterTunnel>(
+            server,
+            json!({
+                "metadata": { "name": "shared" },
+                "spec": { "credentials": "creds", "uuid": OTHER_TUNNEL_ID },
+            }),
+        );
+    }
+
+    #[tokio::test]
+    async fn namespace_scope_without_a_namespace_is_invalid() {
+        let (client, server) = ApiServer::start();
+        seed(&server);
+        seed_ingress_class(
+            &server,
+            "scoped",
+            json!({ "apiGroup": "cloudflare.ar2ro.io", "kind": "Tunnel", "name": "web", "scope": "Namespace" }),
+        );
+        seed_ingress(&server, "web", json!({ "ingressClassName": "scoped" }));
+
+        let result = reconcile_reported(&server, context(client), "web").await;
+
+        assert!(matches!(
+            result,
+            Err(Error::InvalidIngressClassParameters(
+                "scope Namespace requires namespace"
+            ))
+        ));
+        assert!(events(&server).contains(&event(
+            "Warning",
+            "InvalidIngressClassParameters",
+            "IngressClass"
+        )));
+    }
+
+    #[tokio::test]
+    async fn namespace_scope_with_a_namespace_resolves_the_tunnel() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+
+        let tunnel = scoped_tunnel(&server, &mut ctx, tunnel_parameters("web")).unwrap();
+
+        assert_eq!(tunnel.map(|tunnel| tunnel.kind()), Some("Tunnel"));
+    }
+
+    #[tokio::test]
+    async fn cluster_scope_resolves_the_cluster_tunnel() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_cluster_tunnel(&server);
+
+        let tunnel = scoped_tunnel(
+            &server,
+            &mut ctx,
+            json!({ "apiGroup": "cloudflare.ar2ro.io", "kind": "ClusterTunnel", "name": "shared", "scope": "Cluster" }),
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(tunnel.kind(), "ClusterTunnel");
+        assert_eq!(tunnel.name(), "shared");
+    }
+
+    #[tokio::test]
+    async fn cluster_scope_ignores_the_namespace() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_cluster_tunnel(&server);
+
+        let tunnel = scoped_tunnel(
+            &server,
+            &mut ctx,
+            json!({
+                "apiGroup": "cloudflare.ar2ro.io",
+                "kind": "ClusterTunnel",
+                "name": "shared",
+                "namespace": "team",
+                "scope": "Cluster",
+            }),
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(tunnel.kind(), "ClusterTunnel");
+        assert_eq!(tunnel.namespace(), None);
+    }
+
+    #[tokio::test]
+    async fn parameters_with_only_kind_and_name_resolve_the_cluster_tunnel() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_cluster_tunnel(&server);
+
+        let tunnel = scoped_tunnel(
+            &server,
+            &mut ctx,
+            json!({ "kind": "ClusterTunnel", "name": "shared" }),
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(tunnel.kind(), "ClusterTunnel");
+        assert_eq!(tunnel.name(), "shared");
+    }
+
+    #[tokio::test]
+    async fn parameters_without_an_api_group_resolve_the_tunnel() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+
+        let tunnel = scoped_tunnel(
+            &server,
+            &mut ctx,
+            json!({ "kind": "Tunnel", "name": "web", "namespace": "default", "scope": "Namespace" }),
+        )
+        .unwrap()
+        .unwrap();
+
+        assert_eq!(tunnel.kind(), "Tunnel");
+        assert_eq!(tunnel.name(), "web");
+    }
+
+    #[tokio::test]
+    async fn parameters_of_another_api_group_are_invalid() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_cluster_tunnel(&server);
+
+        let tunnel = scoped_tunnel(
+            &server,
+            &mut ctx,
+            json!({ "apiGroup": "cloudfare.ar2ro.io", "kind": "ClusterTunnel", "name": "shared" }),
+        );
+
+        assert!(matches!(
+            tunnel,
+            Err(Error::InvalidIngressClassParameters(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn kind_and_scope_mismatch_is_invalid() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_cluster_tunnel(&server);
+
+        let tunnel = scoped_tunnel(
+            &server,
+            &mut ctx,
+            json!({ "apiGroup": "cloudflare.ar2ro.io", "kind": "Tunnel", "name": "web", "scope": "Cluster" }),
+        );
+        assert!(matches!(
+            tunnel,
+            Err(Error::InvalidIngressClassParameters(_))
+        ));
+
+        let cluster_tunnel = scoped_tunnel(
+            &server,
+            &mut ctx,
+            json!({
+                "apiGroup": "cloudflare.ar2ro.io",
+                "kind": "ClusterTunnel",
+                "name": "shared",
+                "namespace": "default",
+                "scope": "Namespace",
+            }),
+        );
+        assert!(matches!(
+            cluster_tunnel,
+            Err(Error::InvalidIngressClassParameters(_))
+        ));
+    }
+
+    #[tokio::test]
+    async fn conflicting_hostname_is_reported() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        server.insert(
+            &serde_json::from_value::<Service>(service(
+                "api",
+                json!({ "ports": [{ "port": 80 }] }),
+            ))
+            .unwrap(),
+        );
+        seed_ingress(&server, "web", web_ingress("web.example.com"));
+        reconcile_stored(&server, &mut ctx, "web").await.unwrap();
+        seed_ingress(
+            &server,
+            "api",
+            json!({
+                "ingressClassName": "cloudflare",
+                "rules": [{ "host": "web.example.com", "http": { "paths": [prefix("/", "api", 80)] } }],
+            }),
+        );
+
+        reconcile_stored(&server, &mut ctx, "api").await.unwrap();
+
+        let conflicts = server
+            .get_all::<k8s_openapi::api::events::v1::Event>(None)
+            .into_iter()
+            .filter(|event| event.reason.as_deref() == Some("Conflict"))
+            .collect::<Vec<_>>();
+        assert_eq!(conflicts.len(), 1);
+        assert_eq!(conflicts[0].type_.as_deref(), Some("Warning"));
+        assert_eq!(
+            conflicts[0]
+                .regarding
+                .as_ref()
+                .and_then(|regarding| regarding.name.as_deref()),
+            Some("api")
+        );
+    }
+
+    #[tokio::test]
+    async fn applied_configuration_is_reported() {
+        let (client, server) = ApiServer::start();
+        seed(&server);
+        seed_ingress(&server, "web", web_ingress("web.example.com"));
+
+        reconcile_reported(&server, context(client), "web")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            events(&server),
+            vec![event("Normal", "ConfigurationApplied", "Ingress")]
+        );
+    }
+
+    fn routing(name: &str, created: &str, host: &str, path: &str, service: &str) -> Ingress {
+        created_ingress(
+            name,
+            created,
+            json!({ "rules": [{ "host": host, "http": { "paths": [prefix(path, service, 80)] } }] }),
+        )
+    }
+
+    #[test]
+    fn exact_duplicates_are_kept_once() {
+        let services = services(vec![service("web", json!({ "ports": [{ "port": 80 }] }))]);
+        let first = routing(
+            "first",
+            "2024-01-01T00:00:00Z",
+            "web.example.com",
+            "/",
+            "web",
+        );
+        let second = routing(
+            "second",
+            "2024-01-02T00:00:00Z",
+            "web.example.com",
+            "/",
+            "web",
+        );
+
+        let (rules, conflicts) = merged_with_conflicts(&[&second, &first], &services);
+
+        assert_eq!(
+            rules,
+            vec![
+                web_rule("web.example.com"),
+                rule(None, None, CATCH_ALL_SERVICE)
+            ]
+        );
+        assert!(conflicts.is_empty());
+    }
+
+    #[test]
+    fn overlapping_paths_are_not_conflicts() {
+        let services = services(vec![
+            service("api", json!({ "ports": [{ "port": 80 }] })),
+            service("v1", json!({ "ports": [{ "port": 80 }] })),
+        ]);
+        let api = routing(
+            "api",
+            "2024-01-01T00:00:00Z",
+            "web.example.com",
+            "/api",
+            "api",
+        );
+        let v1 = routing(
+            "v1",
+            "2024-01-02T00:00:00Z",
+            "web.example.com",
+            "/api/v1",
+            "v1",
+        );
+        let expected = vec![
+            rule(
+                Some("web.example.com"),
+                Some("^/api/v1(/.*)?$"),
+                "http://v1.default.svc.cluster.local:80",
+            ),
+            rule(
+                Some("web.example.com"),
+                Some("^/api(/.*)?$"),
+                "http://api.default.svc.cluster.local:80",
+            ),
+            rule(None, None, CATCH_ALL_SERVICE),
+        ];
+
+        for ingresses in [[&api, &v1], [&v1, &api]] {
+            let (rules, conflicts) = merged_with_conflicts(&ingresses, &services);
+            assert_eq!(rules, expected);
+            assert!(conflicts.is_empty());
+        }
+    }
+
+    #[test]
+    fn conflicts_are_resolved_by_age_then_name() {
+        let services = services(vec![
+            service("old", json!({ "ports": [{ "port": 80 }] })),
+            service("new", json!({ "ports": [{ "port": 80 }] })),
+        ]);
+        let older = routing("b", "2024-01-01T00:00:00Z", "web.example.com", "/", "old");
+        let newer = routing("a", "2024-01-02T00:00:00Z", "web.example.com", "/", "new");
+
+        for ingresses in [[&older, &newer], [&newer, &older]] {
+            let (rules, conflicts) = merged_with_conflicts(&ingresses, &services);
+            assert_eq!(rules[0].service, "http://old.default.svc.cluster.local:80");
+            assert_eq!(
+                conflicts,
+                vec![(
+                    "Ingress default/a".to_owned(),
+                    "Ingress default/b".to_owned()
+                )]
+            );
+        }
+
+        // INFO: Created in the same second, the name decides.
+        let same_age = routing("a", "2024-01-01T00:00:00Z", "web.example.com", "/", "new");
+        for ingresses in [[&older, &same_age], [&same_age, &older]] {
+            let (rules, conflicts) = merged_with_conflicts(&ingresses, &services);
+            assert_eq!(rules[0].service, "http://new.default.svc.cluster.local:80");
+            assert_eq!(
+                conflicts,
+                vec![(
+                    "Ingress default/b".to_owned(),
+                    "Ingress default/a".to_owned()
+                )]
+            );
+        }
+    }
+
+    #[test]
+    fn tunnel_ingress_wins_over_an_older_ingress() {
+        let services = services(vec![service("web", json!({ "ports": [{ "port": 80 }] }))]);
+        let ingress = routing("web", "2024-01-01T00:00:00Z", "web.example.com", "/", "web");
+        let tunnel_ingress: TunnelIngress = serde_json::from_value(json!({
+            "metadata": {
+                "name": "web",
+                "namespace": "default",
+                "creationTimestamp": "2024-01-02T00:00:00Z",
+            },
+            "spec": {
+                "tunnelRef": { "name": "web" },
+                "rules": [{ "hostname": "web.example.com", "service": "http://10.0.0.1:80" }],
+            },
+        }))
+        .unwrap();
+
+        // INFO: The store hands out the sources in either order.
+        for tunnel_ingress_first in [false, true] {
+            let mut sources = vec![
+                RuleSource::new(&ingress, ingress_path_rules(&ingress, "default", &services)),
+                RuleSource::new(
+                    &tunnel_ingress,
+                    tunnel_ingress::tunnel_ingress_rules(&tunnel_ingress, &services),
+                ),
+            ];
+            if tunnel_ingress_first {
+                sources.reverse();
+            }
+            let (rules, conflicts) = merged_tunnel_rules(sources, CATCH_ALL_SERVICE);
+
+            assert_eq!(
+                rules,
+                vec![
+                    rule(Some("web.example.com"), None, "http://10.0.0.1:80"),
+                    rule(None, None, CATCH_ALL_SERVICE)
+                ]
+            );
+            assert_eq!(conflicts.len(), 1);
+            assert_eq!(conflicts[0].name, "Ingress default/web");
+            assert_eq!(conflicts[0].owner, "TunnelIngress default/web");
+        }
+    }
+
+    fn http_path(path_type: &str, path: &str) -> HTTPIngressPath {
+        serde_json::from_value(json!({
+            "path": path,
+            "pathType": path_type,
+            "backend": { "service": { "name": "web", "port": { "number": 80 } } },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn paths_translate_by_type() {
+        let cases = [
+            ("Prefix", "/", Ok(None)),
+            ("Prefix", "", Ok(None)),
+            ("Prefix", "/api", Ok(Some("^/api(/.*)?$"))),
+            ("Prefix", "/api/", Ok(Some("^/api(/.*)?$"))),
+            ("Prefix", "/v1.0", Ok(Some("^/v1\\.0(/.*)?$"))),
+            ("Exact", "/", Ok(Some("^/$"))),
+            ("Exact", "/api", Ok(Some("^/api$"))),
+            ("Exact", "/a+b", Ok(Some("^/a\\+b$"))),
+            ("ImplementationSpecific", "/api/.*", Ok(Some("/api/.*"))),
+            (
+                "ImplementationSpecific",
+                "^/v[0-9]+$",
+                Ok(Some("^/v[0-9]+$")),
+            ),
+            ("Prefix", "api", Err("path api doesn't start with /")),
+            ("Exact", "api", Err("path api doesn't start with /")),
+        ];
+
+        for (path_type, path, expected) in cases {
+            assert_eq!(
+                tunnel_path(&http_path(path_type, path)),
+                expected
+                    .map(|path| path.map(str::to_owned))
+                    .map_err(str::to_owned),
+                "{} {}",
+                path_type,
+                path
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_regex_is_an_error() {
+        let error = tunnel_path(&http_path("ImplementationSpecific", "/api/(")).unwrap_err();
+
+        assert!(
+            error.starts_with("path /api/( is not a valid regex"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn rules_are_ordered_most_specific_first() {
+        let services = services(vec![service("web", json!({ "ports": [{ "port": 80 }] }))]);
+        let path = |path_type: &str, path: &str| {
+            json!({
+                "path": path,
+                "pathType": path_type,
+                "backend": { "service": { "name": "web", "port": { "number": 80 } } },
+            })
+        };
+        let ingress = ingress(
+            json!({ CATCH_ALL_ANNOTATION: "true" }),
+            json!({ "rules": [
+                { "http": { "paths": [path("Prefix", "/everywhere")] } },
+                { "host": "*.example.com", "http": { "paths": [path("Prefix", "/wild")] } },
+                { "host": "web.example.com", "http": { "paths": [
+                    path("Prefix", "/api"),
+                    path("Prefix", "/api/v1"),
+                    path("Prefix", "/"),
+                    path("Exact", "/api"),
+                ] } },
+            ] }),
+        );
+
+        let rules = ingress_to_tunnel_rules(&ingress, "default", &services)
+            .into_iter()
+            .map(|rule| (rule.hostname, rule.path))
+            .collect::<Vec<_>>();
+
+        let expected = [
+            (Some("web.example.com"), Some("^/api/v1(/.*)?$")),
+            (Some("web.example.com"), Some("^/api$")),
+            (Some("web.example.com"), Some("^/api(/.*)?$")),
+            (Some("web.example.com"), None),
+            (Some("*.example.com"), Some("^/wild(/.*)?$")),
+            (None, Some("^/everywhere(/.*)?$")),
+            (None, None),
+        ]
+        .map(|(host, path)| (host.map(str::to_owned), path.map(str::to_owned)));
+        assert_eq!(rules, expected);
+    }
+
+    #[test]
+    fn wildcard_host_goes_after_specific_hosts() {
+        let services = services(vec![service("web", json!({ "ports": [{ "port": 80 }] }))]);
+        let ingress = ingress(
+            json!({}),
+            json!({ "rules": [
+                { "host": "*.example.com", "http": { "paths": [prefix("/", "web", 80)] } },
+                { "host": "web.example.com", "http": { "paths": [prefix("/", "web", 80)] } },
+            ] }),
+        );
+
+        assert_eq!(
+            ingress_to_tunnel_rules(&ingress, "default", &services),
+            vec![
+                web_rule("web.example.com"),
+                web_rule("*.example.com"),
+                rule(None, None, CATCH_ALL_SERVICE)
+            ]
+        );
+    }
+
+    #[test]
+    fn hostless_rules_need_the_catch_all_annotation() {
+        let services = services(vec![service("web", json!({ "ports": [{ "port": 80 }] }))]);
+        let spec = json!({ "rules": [
+            { "http": { "paths": [prefix("/", "web", 80)] } },
+            { "host": "web.example.com", "http": { "paths": [prefix("/", "web", 80)] } },
+        ] });
+
+        let without = ingress(json!({}), spec.clone());
+        assert!(ignores_hostless_rules(&without));
+        assert_eq!(
+            ingress_to_tunnel_rules(&without, "default", &services),
+            vec![
+                web_rule("web.example.com"),
+                rule(None, None, CATCH_ALL_SERVICE)
+            ]
+        );
+
+        let with = ingress(json!({ CATCH_ALL_ANNOTATION: "true" }), spec);
+        assert!(!ignores_hostless_rules(&with));
+        assert_eq!(
+            ingress_to_tunnel_rules(&with, "default", &services),
+            vec![
+                web_rule("web.example.com"),
+                rule(None, None, "http://web.default.svc.cluster.local:80"),
+                rule(None, None, CATCH_ALL_SERVICE)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn ignored_hostless_rules_are_reported() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_ingress(
+            &server,
+            "web",
+            json!({
+                "ingressClassName": "cloudflare",
+                "rules": [{ "http": { "paths": [prefix("/", "web", 80)] } }],
+            }),
+        );
+
+        reconcile_stored(&server, &mut ctx, "web").await.unwrap();
+
+        assert!(events(&server).contains(&event("Warning", "HostlessRule", "Ingress")));
+        assert_eq!(
+            tunnel_rules(&ctx),
+            vec![rule(None, None, CATCH_ALL_SERVICE)]
+        );
+    }
+
+    fn backend_ingress(backend: Value) -> Ingress {
+        ingress(
+            json!({}),
+            json!({ "rules": [{ "host": "web.example.com", "http": { "paths": [{
+                "path": "/",
+                "pathType": "Prefix",
+                "backend": { "service": backend },
+            }] } }] }),
+        )
+    }
+
+    #[test]
+    fn backends_resolve_against_their_service() {
+        let services = services(vec![
+            service(
+                "web",
+                json!({ "ports": [{ "name": "http", "port": 8080 }, { "port": 9090 }] }),
+            ),
+            service(
+                "external",
+                json!({ "type": "ExternalName", "externalName": "origin.example.net" }),
+            ),
+        ]);
+        let cases = [
+            (
+                json!({ "name": "web", "port": { "number": 9090 } }),
+                "http://web.default.svc.cluster.local:9090",
+            ),
+            (
+                json!({ "name": "web", "port": { "name": "http" } }),
+                "http://web.default.svc.cluster.local:8080",
+            ),
+            (
+                json!({ "name": "web" }),
+                "http://web.default.svc.cluster.local:80",
+            ),
+            (
+                json!({ "name": "external", "port": { "number": 8443 } }),
+                "http://origin.example.net:8443",
+            ),
+        ];
+
+        for (backend, service) in cases {
+            let ingress = backend_ingress(backend.clone());
+            let rules = ingress_to_tunnel_rules(&ingress, "default", &services);
+
+            assert_eq!(rules[0].service, service, "{}", backend);
+            assert!(invalid_backends(&ingress, &services).is_empty());
+        }
+    }
+
+    #[test]
+    fn unresolvable_backends_are_invalid() {
+        let services = services(vec![service(
+            "web",
+            json!({ "ports": [{ "name": "http", "port": 8080 }] }),
+        )]);
+        let cases = [
+            (
+                json!({ "name": "missing", "port": { "number": 80 } }),
+                "Service default/missing doesn't exist",
+            ),
+            (
+                json!({ "name": "web", "port": { "name": "grpc" } }),
+                "Service default/web has no port named grpc",
+            ),
+        ];
+
+        for (backend, problem) in cases {
+            let ingress = backend_ingress(backend);
+
+            assert_eq!(invalid_backends(&ingress, &services), vec![problem]);
+            assert_eq!(
+                ingress_to_tunnel_rules(&ingress, "default", &services),
+                vec![rule(None, None, CATCH_ALL_SERVICE)]
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_service_is_reported_and_checked_again_soon() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_ingress(
+            &server,
+            "web",
+            json!({
+                "ingressClassName": "cloudflare",
+                "rules": [{ "host": "web.example.com", "http": { "paths": [prefix("/", "missing", 80)] } }],
+            }),
+        );
+
+        let action = reconcile_stored(&server, &mut ctx, "web").await.unwrap();
+
+        assert_eq!(action, Action::requeue(Duration::from_secs(15)));
+        assert!(events(&server).contains(&event("Warning", "InvalidBackend", "Ingress")));
+    }
+
+    fn origin_rule(annotations: Value, service_annotations: Value, port: i32) -> IngressRule {
+        let services = services(vec![json!({
+            "metadata": { "name": "web", "namespace": "default", "annotations": service_annotations },
+            "spec": { "ports": [{ "port": port }] },
+        })]);
+        let ingress = ingress(
+            annotations,
+            json!({ "rules": [
+                { "host": "web.example.com", "http": { "paths": [prefix("/", "web", port)] } },
+            ] }),
+        );
+
+        ingress_to_tunnel_rules(&ingress, "default", &services).remove(0)
+    }
+
+    #[test]
+    fn origin_protocol_sets_the_scheme() {
+        for protocol in ORIGIN_PROTOCOLS {
+            let rule = origin_rule(
+                json!({ ORIGIN_PROTOCOL_ANNOTATION: protocol }),
+                json!({}),
+                80,
+            );
+
+            assert_eq!(
+                rule.service,
+                format!("{}://web.default.svc.cluster.local:80", protocol)
+            );
+        }
+    }
+
+    #[test]
+    fn origin_protocol_defaults_by_port() {
+        assert_eq!(
+            origin_rule(json!({}), json!({}), 80).service,
+            "http://web.default.svc.cluster.local:80"
+        );
+        assert_eq!(
+            origin_rule(json!({}), json!({}), 443).service,
+            "https://web.default.svc.cluster.local:443"
+        );
+        assert_eq!(
+            origin_rule(
+                json!({ ORIGIN_PROTOCOL_ANNOTATION: "http" }),
+                json!({}),
+                443
+            )
+            .service,
+            "http://web.default.svc.cluster.local:443"
+        );
+    }
+
+    #[test]
+    fn ingress_protocol_wins_over_the_service() {
+        assert_eq!(
+            origin_rule(
+                json!({}),
+                json!({ ORIGIN_PROTOCOL_ANNOTATION: "https" }),
+                8443
+            )
+            .service,
+            "https://web.default.svc.cluster.local:8443"
+        );
+        assert_eq!(
+            origin_rule(
+                json!({ ORIGIN_PROTOCOL_ANNOTATION: "tcp" }),
+                json!({ ORIGIN_PROTOCOL_ANNOTATION: "https" }),
+                8443
+            )
+            .service,
+            "tcp://web.default.svc.cluster.local:8443"
+        );
+    }
+
+    #[test]
+    fn unsupported_origin_protocol_is_invalid() {
+        let services = services(vec![service("web", json!({ "ports": [{ "port": 80 }] }))]);
+        let ingress = ingress(
+            json!({ ORIGIN_PROTOCOL_ANNOTATION: "ftp" }),
+            json!({ "rules": [
+                { "host": "web.example.com", "http": { "paths": [prefix("/", "web", 80)] } },
+            ] }),
+        );
+
+        assert_eq!(
+            invalid_backends(&ingress, &services),
+            vec!["unsupported origin protocol ftp"]
+        );
+    }
+
+    #[test]
+    fn tls_annotations_only_apply_to_https() {
+        let annotations = json!({
+            origin::ORIGIN_SERVER_NAME_ANNOTATION: "origin.example.com",
+            origin::NO_TLS_VERIFY_ANNOTATION: "true",
+        });
+
+        let https = origin_rule(annotations.clone(), json!({}), 443);
+        assert_eq!(
+            https.origin_request,
+            Some(OriginRequest {
+                origin_server_name: Some("origin.example.com".to_owned()),
+                no_tls_verify: Some(true),
+                ..OriginRequest::default()
+            })
+        );
+
+        let http = origin_rule(annotations, json!({}), 80);
+        assert_eq!(http.origin_request, None);
+    }
+
+    const OTHER_TUNNEL_ID: &str = "0d3e4f5a-6b7c-4d8e-9f0a-1b2c3d4e5f60";
+
+    fn seed_default_class(server: &ApiServer, name: &str, tunnel: &str) {
+        insert::<IngressClass>(
+            server,
+            json!({
+                "metadata": {
+                    "name": name,
+                    "annotations": { DEFAULT_CLASS_ANNOTATION: "true" },
+                },
+                "spec": { "controller": INGRESS_CONTROLLER, "parameters": tunnel_parameters(tunnel) },
+            }),
+        );
+    }
+
+    fn routed_tunnel(
+        server: &ApiServer,
+        ctx: &mut Context<MockCloudflareClient>,
+        spec: Value,
+    ) -> Option<String> {
+        seed_ingress(server, "web", spec);
+        refresh(ctx, server);
+        let ingress = server.get::<Ingress>(NAMESPACE, "web").unwrap();
+        ingress_tunnel(&ingress, ctx)
+            .unwrap()
+            .map(|tunnel| tunnel.name())
+    }
+
+    fn web_ingress_without_class() -> Value {
+        json!({
+            "rules": [{ "host": "web.example.com", "http": { "paths": [prefix("/", "web", 80)] } }],
+        })
+    }
+
+    #[tokio::test]
+    async fn classless_ingress_goes_through_the_default_class() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        ctx.default_ingress_class = true;
+        seed(&server);
+        seed_tunnel(&server, "other", OTHER_TUNNEL_ID);
+        seed_default_class(&server, "other", "other");
+
+        let tunnel = routed_tunnel(&server, &mut ctx, web_ingress_without_class());
+
+        assert_eq!(tunnel.as_deref(), Some("other"));
+    }
+
+    #[tokio::test]
+    async fn classless_ingress_without_a_default_class_is_ignored() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        ctx.default_ingress_class = true;
+        seed(&server);
+
+        assert_eq!(
+            routed_tunnel(&server, &mut ctx, web_ingress_without_class()),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn default_class_is_ignored_unless_enabled() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_default_class(&server, "default", "web");
+
+        assert_eq!(
+            routed_tunnel(&server, &mut ctx, web_ingress_without_class()),
+            None
+        );
+    }
+
+    #[tokio::test]
+    async fn explicit_class_wins_over_the_default() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        ctx.default_ingress_class = true;
+        seed(&server);
+        seed_tunnel(&server, "other", OTHER_TUNNEL_ID);
+        seed_default_class(&server, "other", "other");
+
+        let tunnel = routed_tunnel(&server, &mut ctx, web_ingress("web.example.com"));
+
+        assert_eq!(tunnel.as_deref(), Some("web"));
+    }
+
+    #[tokio::test]
+    async fn several_default_classes_leave_classless_ingresses_alone() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        ctx.default_ingress_class = true;
+        seed(&server);
+        seed_tunnel(&server, "other", OTHER_TUNNEL_ID);
+        seed_default_class(&server, "first", "web");
+        seed_default_class(&server, "second", "other");
+
+        let tunnel = routed_tunnel(&server, &mut ctx, web_ingress_without_class());
+
+        assert_eq!(tunnel, None);
+        assert!(ctx.conflicting_default_classes.load(Ordering::Relaxed));
+    }
+
+    fn seed_annotated_ingress(server: &ApiServer, tunnel: &str, class: &str) {
+        insert::<Ingress>(
+            server,
+            json!({
+                "metadata": {
+                    "name": "web",
+                    "namespace": "default",
+                    "annotations": { TUNNEL_ANNOTATION: tunnel },
+                },
+                "spec": { "ingressClassName": class },
+            }),
+        );
+    }
+
+    fn seed_default_tunnel(server: &ApiServer, name: &str, uuid: &str) {
+        insert::<Tunnel>(
+            server,
+            json!({
+                "metadata": {
+                    "name": name,
+                    "namespace": "default",
+                    "annotations": { DEFAULT_ANNOTATION: "true" },
+                },
+                "spec": { "credentials": "creds", "uuid": uuid },
+            }),
+        );
+    }
+
+    fn resolved_tunnel(server: &ApiServer, ctx: &mut Context<MockCloudflareClient>) -> String {
+        refresh(ctx, server);
+        let ingress = server.get::<Ingress>(NAMESPACE, "web").unwrap();
+        ingress_tunnel(&ingress, ctx).unwrap().unwrap().name()
+    }
+
+    #[tokio::test]
+    async fn annotation_wins_over_the_class_parameters() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_tunnel(&server, "other", OTHER_TUNNEL_ID);
+
+        seed_annotated_ingress(&server, "other", "cloudflare");
+        assert_eq!(resolved_tunnel(&server, &mut ctx), "other");
+
+        seed_annotated_ingress(&server, "default/other", "cloudflare");
+        assert_eq!(resolved_tunnel(&server, &mut ctx), "other");
+    }
+
+    #[tokio::test]
+    async fn annotation_wins_over_the_default_tunnel() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_default_tunnel(&server, "other", OTHER_TUNNEL_ID);
+        seed_ingress_class(&server, "default-tunnel", Value::Null);
+
+        seed_annotated_ingress(&server, "web", "default-tunnel");
+
+        assert_eq!(resolved_tunnel(&server, &mut ctx), "web");
+    }
+
+    #[tokio::test]
+    async fn class_parameters_win_over_the_default_tunnel() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_default_tunnel(&server, "other", OTHER_TUNNEL_ID);
+
+        seed_ingress(&server, "web", json!({ "ingressClassName": "cloudflare" }));
+
+        assert_eq!(resolved_tunnel(&server, &mut ctx), "web");
+    }
+
+    #[tokio::test]
+    async fn annotated_tunnel_that_doesnt_exist_is_reported() {
+        let (client, server) = ApiServer::start();
+        seed(&server);
+        seed_annotated_ingress(&server, "team/web", "cloudflare");
+
+        let result = reconcile_reported(&server, context(client), "web").await;
+
+        assert!(matches!(result, Err(Error::MissingTunnel(name)) if name == "team/web"));
+        assert_eq!(
+            events(&server),
+            vec![event("Warning", "MissingTunnel", "Ingress")]
+        );
+    }
+
+    #[tokio::test]
+    async fn annotated_tunnel_of_another_namespace_needs_its_consent() {
+        let (client, server) = ApiServer::start();
+        seed(&server);
+        insert::<Tunnel>(
+            &server,
+            json!({
+                "metadata": { "name": "shared", "namespace": "team" },
+                "spec": { "credentials": "creds", "uuid": OTHER_TUNNEL_ID },
+            }),
+        );
+        seed_annotated_ingress(&server, "team/shared", "cloudflare");
+
+        let result = reconcile_reported(&server, context(client.clone()), "web").await;
+
+        assert!(matches!(result, Err(Error::CrossNamespaceTunnel(_))));
+        assert_eq!(
+            events(&server),
+            vec![event("Warning", "CrossNamespaceTunnel", "Ingress")]
+        );
+
+        let mut ctx = context(client.clone());
+        ctx.allow_cross_namespace_refs = true;
+        assert_eq!(resolved_tunnel(&server, &mut ctx), "shared");
+
+        server.update::<Tunnel>(
+            Some("team"),
+            "shared",
+            json!({ "metadata": { "annotations": { ALLOW_CROSS_NAMESPACE_ANNOTATION: "true" } } }),
+        );
+        assert_eq!(resolved_tunnel(&server, &mut context(client)), "shared");
+    }
+
+    #[tokio::test]
+    async fn annotated_cluster_tunnel_is_resolved() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_cluster_tunnel(&server);
+        let annotated = |cluster_tunnel: &str| {
+            insert::<Ingress>(
+                &server,
+                json!({
+                    "metadata": {
+                        "name": "web",
+                        "namespace": "default",
+                        "annotations": { CLUSTER_TUNNEL_ANNOTATION: cluster_tunnel },
+                    },
+                    "spec": { "ingressClassName": "cloudflare" },
+                }),
+            );
+        };
+
+        annotated("shared");
+        refresh(&mut ctx, &server);
+        let ingress = server.get::<Ingress>(NAMESPACE, "web").unwrap();
+        assert!(matches!(
+            ingress_tunnel(&ingress, &ctx),
+            Ok(Some(AnyTunnel::ClusterTunnel(tunnel))) if tunnel.name_any() == "shared"
+        ));
+
+        annotated("gone");
+        refresh(&mut ctx, &server);
+        let ingress = server.get::<Ingress>(NAMESPACE, "web").unwrap();
+        assert!(matches!(
+            ingress_tunnel(&ingress, &ctx),
+            Err(Error::MissingTunnel(name)) if name == "ClusterTunnel gone"
+        ));
+    }
+
+    fn names(refs: Vec<ObjectRef<Ingress>>) -> Vec<String> {
+        let mut names = refs
+            .into_iter()
+            .map(|obj_ref| obj_ref.name)
+            .collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    #[tokio::test]
+    async fn ingress_class_change_retriggers_its_ingresses() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_default_class(&server, "default", "web");
+        seed_ingress(&server, "web", web_ingress("web.example.com"));
+        seed_ingress(&server, "classless", web_ingress_without_class());
+        seed_ingress(&server, "nginx", json!({ "ingressClassName": "nginx" }));
+        refresh(&mut ctx, &server);
+
+        // INFO: A new parameter on the class reaches the Ingresses naming it.
+        server.update::<IngressClass>(
+            None,
+            "cloudflare",
+            json!({ "spec": { "parameters": tunnel_parameters("other") } }),
+        );
+        let cloudflare = server.get::<IngressClass>(None, "cloudflare").unwrap();
+        assert_eq!(
+            names(class_ingresses(&ctx.ingress_store, &cloudflare, true)),
+            ["web"]
+        );
+
+        // INFO: The default class also reaches classless Ingresses, but only when enabled.
+        let default = server.get::<IngressClass>(None, "default").unwrap();
+        assert_eq!(
+            names(class_ingresses(&ctx.ingress_store, &default, true)),
+            ["classless"]
+        );
+        assert!(class_ingresses(&ctx.ingress_store, &default, false).is_empty());
+    }
+
+    #[tokio::test]
+    async fn ingress_class_created_after_its_ingress_picks_it_up() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        let mut spec = web_ingress("web.example.com");
+        spec["ingressClassName"] = json!("late");
+        seed_ingress(&server, "web", spec);
+
+        // INFO: Until the class exists the Ingress isn't ours and is left alone.
+        reconcile_stored(&server, &mut ctx, "web").await.unwrap();
+        let ingress = server.get::<Ingress>(NAMESPACE, "web").unwrap();
+        assert!(ingress.finalizers().is_empty());
+        assert_eq!(ctx.cloudflare_client.calls("update_configuration"), 0);
+
+        seed_ingress_class(&server, "late", tunnel_parameters("web"));
+        refresh(&mut ctx, &server);
+        let late = server.get::<IngressClass>(None, "late").unwrap();
+        assert_eq!(
+            names(class_ingresses(&ctx.ingress_store, &late, false)),
+            ["web"]
+        );
+
+        reconcile_stored(&server, &mut ctx, "web").await.unwrap();
+        let ingress = server.get::<Ingress>(NAMESPACE, "web").unwrap();
+        assert_eq!(ingress.finalizers(), [INGRESS_FINALIZER]);
+        assert_eq!(
+            tunnel_rules(&ctx),
+            vec![
+                web_rule("web.example.com"),
+                rule(None, None, CATCH_ALL_SERVICE)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn ingresses_outside_the_watched_namespaces_are_ignored() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        ctx.watch_namespaces = vec!["default".to_owned(), "staging".to_owned()];
+        seed(&server);
+        seed_ingress(&server, "web", web_ingress("web.example.com"));
+        insert::<Ingress>(
+            &server,
+            json!({
+                "metadata": { "name": "intruder", "namespace": "team" },
+                "spec": {
+                    "ingressClassName": "cloudflare",
+                    "rules": [{ "host": "intruder.example.com", "http": { "paths": [
+                        prefix("/", "web", 80),
+                    ] } }],
+                },
+            }),
+        );
+        insert::<Service>(
+            &server,
+            json!({
+                "metadata": { "name": "web", "namespace": "team" },
+                "spec": { "ports": [{ "port": 80 }] },
+            }),
+        );
+        insert::<Ingress>(
+            &server,
+            json!({
+                "metadata": { "name": "other", "namespace": "team" },
+                "spec": { "ingressClassName": "nginx" },
+            }),
+        );
+        refresh(&mut ctx, &server);
+
+        // INFO: Only the Ingress that would have been ours gets an Event.
+        for name in ["intruder", "other"] {
+            let ingress = server.get::<Ingress>(Some("team"), name).unwrap();
+            let action = reconcile_ingress(&ingress, &ctx).await.unwrap();
+            assert_eq!(action, Action::await_change());
+            let ingress = server.get::<Ingress>(Some("team"), name).unwrap();
+            assert!(ingress.finalizers().is_empty());
+        }
+        assert_eq!(
+            events(&server),
+            vec![event("Warning", "NamespaceNotWatched", "Ingress")]
+        );
+        assert_eq!(ctx.cloudflare_client.calls("update_configuration"), 0);
+
+        reconcile_stored(&server, &mut ctx, "web").await.unwrap();
+        assert_eq!(
+            tunnel_rules(&ctx),
+            vec![
+                web_rule("web.example.com"),
+                rule(None, None, CATCH_ALL_SERVICE)
+            ]
+        );
+    }
+
+    fn seed_tunnel_ingress(server: &ApiServer, name: &str, rules: Value) {
+        insert::<TunnelIngress>(
+            server,
+            json!({
+                "metadata": { "name": name, "namespace": "default" },
+                "spec": { "tunnelRef": { "name": "web" }, "rules": rules },
+            }),
+        );
+    }
+
+    async fn reconcile_stored_tunnel_ingress(
+        server: &ApiServer,
+        ctx: &mut Context<MockCloudflareClient>,
+        name: &str,
+    ) -> Result<Action, Error> {
+        refresh(ctx, server);
+        let tunnel_ingress = server
+            .get::<TunnelIngress>(NAMESPACE, name)
+            .expect("tunnel ingress is gone");
+        tunnel_ingress::reconcile_tunnel_ingress(&tunnel_ingress, ctx).await
+    }
+
+    #[tokio::test]
+    async fn tunnel_ingress_rules_are_merged_with_the_ingresses() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_ingress(&server, "web", web_ingress("web.example.com"));
+        seed_tunnel_ingress(
+            &server,
+            "api",
+            json!([
+                { "hostname": "*.example.com", "service": "http://fallback.default:8080" },
+                { "hostname": "api.example.com", "service": { "name": "web", "port": 80 } },
+            ]),
+        );
+
+        reconcile_stored(&server, &mut ctx, "web").await.unwrap();
+        reconcile_stored_tunnel_ingress(&server, &mut ctx, "api")
+            .await
+            .unwrap();
+
+        // INFO: Explicit hostnames, then wildcards, then the catch-all.
+        assert_eq!(
+            tunnel_rules(&ctx),
+            vec![
+                web_rule("web.example.com"),
+                web_rule("api.example.com"),
+                rule(Some("*.example.com"), None, "http://fallback.default:8080"),
+                rule(None, None, CATCH_ALL_SERVICE),
+            ]
+        );
+        let tunnel_ingress = server.get::<TunnelIngress>(NAMESPACE, "api").unwrap();
+        assert_eq!(tunnel_ingress.finalizers(), [TUNNEL_INGRESS_FINALIZER]);
+        assert_eq!(recorded_tunnel(&tunnel_ingress), Some(tunnel_id()));
+        let status = tunnel_ingress.status.unwrap();
+        assert!(status.synced);
+        assert!(status
+            .conditions
+            .iter()
+            .any(|condition| condition.type_ == APPLIED_CONDITION && condition.status == "True"));
+    }
+
+    #[tokio::test]
+    async fn deleted_tunnel_ingress_is_removed_from_the_tunnel() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_ingress(&server, "web", web_ingress("web.example.com"));
+        seed_tunnel_ingress(
+            &server,
+            "api",
+            json!([{ "hostname": "api.example.com", "service": { "name": "web", "port": 80 } }]),
+        );
+        reconcile_stored(&server, &mut ctx, "web").await.unwrap();
+        reconcile_stored_tunnel_ingress(&server, &mut ctx, "api")
+            .await
+            .unwrap();
+        assert_eq!(tunnel_rules(&ctx).len(), 3);
+
+        server.delete::<TunnelIngress>(NAMESPACE, "api");
+        let action = reconcile_stored_tunnel_ingress(&server, &mut ctx, "api")
+            .await
+            .unwrap();
+
+        assert_eq!(action, Action::await_change());
+        assert!(server.get::<TunnelIngress>(NAMESPACE, "api").is_none());
+        assert_eq!(
+            tunnel_rules(&ctx),
+            vec![
+                web_rule("web.example.com"),
+                rule(None, None, CATCH_ALL_SERVICE)
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn merged_rules_are_the_same_whichever_source_reconciles_first() {
+        let mut configurations = Vec::new();
+        for tunnel_ingress_first in [false, true] {
+            let (client, server) = ApiServer::start();
+            let mut ctx = context(client);
+            seed(&server);
+            seed_ingress(&server, "web", web_ingress("web.example.com"));
+            seed_ingress(&server, "docs", web_ingress("docs.example.com"));
+            seed_tunnel_ingress(
+                &server,
+                "api",
+                json!([
+                    { "hostname": "web.example.com", "service": "http://10.0.0.1:80" },
+                    { "hostname": "api.example.com", "service": { "name": "web", "port": 80 } },
+                ]),
+            );
+
+            if tunnel_ingress_first {
+                reconcile_stored_tunnel_ingress(&server, &mut ctx, "api")
+                    .await
+                    .unwrap();
+            }
+            for name in ["web", "docs"] {
+                reconcile_stored(&server, &mut ctx, name).await.unwrap();
+            }
+            reconcile_stored_tunnel_ingress(&server, &mut ctx, "api")
+                .await
+                .unwrap();
+            let writes = ctx.cloudflare_client.calls("update_configuration");
+
+            // INFO: Every source writes the same merged document, another round changes nothing.
+            for name in ["docs", "web"] {
+                reconcile_stored(&server, &mut ctx, name).await.unwrap();
+            }
+            reconcile_stored_tunnel_ingress(&server, &mut ctx, "api")
+                .await
+                .unwrap();
+            assert_eq!(ctx.cloudflare_client.calls("update_configuration"), writes);
+
+            configurations.push(tunnel_rules(&ctx));
+        }
+
+        assert_eq!(configurations[0], configurations[1]);
+        assert_eq!(
+            configurations[0],
+            vec![
+                web_rule("docs.example.com"),
+                rule(Some("web.example.com"), None, "http://10.0.0.1:80"),
+                web_rule("api.example.com"),
+                rule(None, None, CATCH_ALL_SERVICE),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn tunnel_catch_all_service_ends_the_rules() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        server.update::<Tunnel>(
+            NAMESPACE,
+            "web",
+            json!({ "spec": { "catchAllService": "http_status:503" } }),
+        );
+        seed_ingress(&server, "web", web_ingress("web.example.com"));
+        seed_tunnel_ingress(
+            &server,
+            "api",
+            json!([{ "hostname": "api.example.com", "service": { "name": "web", "port": 80 } }]),
+        );
+
+        reconcile_stored(&server, &mut ctx, "web").await.unwrap();
+        assert_eq!(
+            tunnel_rules(&ctx),
+            vec![
+                web_rule("web.example.com"),
+                web_rule("api.example.com"),
+                rule(None, None, "http_status:503"),
+            ]
+        );
+
+        // INFO: A changed catch-all queues the TunnelIngresses of the tunnel, which rewrite it.
+        server.update::<Tunnel>(
+            NAMESPACE,
+            "web",
+            json!({ "spec": { "catchAllService": "http://maintenance.default:80" } }),
+        );
+        refresh(&mut ctx, &server);
+        let tunnel = server.get::<Tunnel>(NAMESPACE, "web").unwrap();
+        let queued = tunnel_ingress::referencing(
+            &ctx.tunnel_ingress_store,
+            &AnyTunnel::Tunnel(Arc::new(tunnel)),
+        );
+        assert_eq!(queued, [ObjectRef::new("api").within("default")]);
+
+        reconcile_stored_tunnel_ingress(&server, &mut ctx, "api")
+            .await
+            .unwrap();
+        assert_eq!(
+            tunnel_rules(&ctx).last(),
+            Some(&rule(None, None, "http://maintenance.default:80"))
+        );
+    }
+
+    #[tokio::test]
+    async fn tunnel_getting_its_uuid_retriggers_its_ingresses() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        server.update::<Tunnel>(NAMESPACE, "web", json!({ "spec": { "uuid": null } }));
+        seed_ingress(&server, "web", web_ingress("web.example.com"));
+
+        // INFO: Without a uuid the Ingress waits, but is remembered on the tunnel.
+        let action = reconcile_stored(&server, &mut ctx, "web").await.unwrap();
+        assert_eq!(action, Action::requeue(Duration::from_secs(60 * 2)));
+        assert_eq!(ctx.cloudflare_client.calls("update_configuration"), 0);
+
+        server.update::<Tunnel>(NAMESPACE, "web", json!({ "spec": { "uuid": TUNNEL_ID } }));
+        let tunnel = server.get::<Tunnel>(NAMESPACE, "web").unwrap();
+        let triggered =
+            tunnel_ingresses(&ctx.ingress_tunnels, ObjectRef::from_obj(&tunnel).erase());
+        assert_eq!(names(triggered), ["web"]);
+
+        reconcile_stored(&server, &mut ctx, "web").await.unwrap();
+        assert_eq!(
+            tunnel_rules(&ctx),
+            vec![
+                web_rule("web.example.com"),
+                rule(None, None, CATCH_ALL_SERVICE)
+            ]
+        );
+    }
+
+    // INFO: GatewayClass cloudflare pointing at Tunnel web and a Gateway web in default of it.
+    fn seed_gateway(server: &ApiServer) {
+        insert::<GatewayClass>(
+            server,
+            json!({
+                "metadata": { "name": "cloudflare" },
+                "spec": {
+                    "controllerName": GATEWAY_CONTROLLER,
+                    "parametersRef": {
+                        "group": "cloudflare.ar2ro.io",
+                        "kind": "Tunnel",
+                        "name": "web",
+                        "namespace": "default",
+                    },
+                },
+            }),
+        );
+        insert::<Gateway>(
+            server,
+            json!({
+                "metadata": { "name": "web", "namespace": "default" },
+                "spec": { "gatewayClassName": "cloudflare" },
+            }),
+        );
+    }
+
+    fn seed_route(server: &ApiServer, namespace: &str, hostname: &str, backend: Value) {
+        insert::<HTTPRoute>(
+            server,
+            json!({
+                "metadata": { "name": "api", "namespace": namespace },
+                "spec": {
+                    "parentRefs": [{ "name": "web", "namespace": "default" }],
+                    "hostnames": [hostname],
+                    "rules": [{ "backendRefs": [backend] }],
+                },
+            }),
+        );
+    }
+
+    async fn reconcile_stored_route(
+        server: &ApiServer,
+        ctx: &mut Context<MockCloudflareClient>,
+        namespace: &str,
+    ) -> Result<Action, Error> {
+        refresh(ctx, server);
+        let route = server
+            .get::<HTTPRoute>(Some(namespace), "api")
+            .expect("route is gone");
+        gateway::reconcile_route(&route, ctx).await
+    }
+
+    #[tokio::test]
+    async fn httproute_rules_are_merged_with_the_ingresses() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_gateway(&server);
+        seed_ingress(&server, "web", web_ingress("web.example.com"));
+        seed_route(
+            &server,
+            "default",
+            "api.example.com",
+            json!({ "name": "web", "port": 80 }),
+        );
+
+        reconcile_stored(&server, &mut ctx, "web").await.unwrap();
+        reconcile_stored_route(&server, &mut ctx, "default")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            tunnel_rules(&ctx),
+            vec![
+                web_rule("web.example.com"),
+                web_rule("api.example.com"),
+                rule(None, None, CATCH_ALL_SERVICE),
+            ]
+        );
+        let route = server.get::<HTTPRoute>(NAMESPACE, "api").unwrap();
+        assert_eq!(route.finalizers(), [HTTPROUTE_FINALIZER]);
+        assert_eq!(recorded_tunnel(&route), Some(tunnel_id()));
+    }
+
+    #[tokio::test]
+    async fn deleted_httproute_is_removed_from_the_tunnel() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_gateway(&server);
+        seed_ingress(&server, "web", web_ingress("web.example.com"));
+        seed_route(
+            &server,
+            "default",
+            "api.example.com",
+            json!({ "name": "web", "port": 80 }),
+        );
+        reconcile_stored(&server, &mut ctx, "web").await.unwrap();
+        reconcile_stored_route(&server, &mut ctx, "default")
+            .await
+            .unwrap();
+
+        server.delete::<HTTPRoute>(NAMESPACE, "api");
+        reconcile_stored_route(&server, &mut ctx, "default")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            tunnel_rules(&ctx),
+            vec![
+                web_rule("web.example.com"),
+                rule(None, None, CATCH_ALL_SERVICE)
+            ]
+        );
+        assert!(server.get::<HTTPRoute>(NAMESPACE, "api").is_none());
+    }
+
+    #[tokio::test]
+    async fn httproute_backend_of_another_namespace_needs_a_reference_grant() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = context(client);
+        seed(&server);
+        seed_gateway(&server);
+        seed_route(
+            &server,
+            "team",
+            "api.example.com",
+            json!({ "name": "web", "namespace": "default", "port": 80 }),
+        );
+
+        reconcile_stored_route(&server, &mut ctx, "team")
+            .await
+            .unwrap();
+        assert_eq!(
+            tunnel_rules(&ctx),
+            vec![rule(None, None, CATCH_ALL_SERVICE)]
+        );
+
+        insert::<ReferenceGrant>(
+            &server,
+            json!({
+                "metadata": { "name": "team-routes", "namespace": "default" },
+                "spec": {
+                    "from": [{
+                        "group": "gateway.networking.k8s.io",
+                        "kind": "HTTPRoute",
+                        "namespace": "team",
+                    }],
+                    "to": [{ "group": "", "kind": "Service", "name": "web" }],
+                },
+            }),
+        );
+        reconcile_stored_route(&server, &mut ctx, "team")
+            .await
+            .unwrap();
+
+        assert_eq!(
+            tunnel_rules(&ctx),
+            vec![
+                web_rule("api.example.com"),
+                rule(None, None, CATCH_ALL_SERVICE)
+            ]
+        );
+    }
+
+    #[test]
+    fn large_ingress_translates_quickly() {
+        let services = services(vec![service("web", json!({ "ports": [{ "port": 80 }] }))]);
+        let rules: Vec<Value> = (0..1000)
+            .map(|n| {
+                json!({
+                    "host": format!("host-{}.example.com", n),
+                    "http": { "paths": [prefix("/api", "web", 80)] },
+                })
+            })
+            .collect();
+        let ingress = ingress(json!({}), json!({ "rules": rules }));
+
+        let started = Instant::now();
+        let rules = ingress_to_tunnel_rules(&ingress, "default", &services);
+        let rules = normalize_origin_settings(rules).unwrap();
+        let elapsed = started.elapsed();
+
+        assert_eq!(rules.len(), 1001);
+        // INFO: 50ms is the target for an optimized build, unoptimized test builds are about
+        // five times slower.
+        let budget = match cfg!(debug_assertions) {
+            true => Duration::from_millis(250),
+            false => Duration::from_millis(50),
+        };
+        assert!(
+            elapsed < budget,
+            "translating 1000 rules took {:?}",
+            elapsed
+        );
+    }
+}
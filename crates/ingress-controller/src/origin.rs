@@ -0,0 +1,342 @@
+//! cloudflared origin settings of a backend, read from annotations on the Ingress or on the
+//! backend Service, the Ingress wins when both set one.
+//!
+//! | Annotation                                     | Value                         |
+//! |------------------------------------------------|-------------------------------|
+//! | `cloudflare.ar2ro.io/origin-protocol`          | http, https, tcp, ssh or rdp  |
+//! | `cloudflare.ar2ro.io/connect-timeout`          | seconds, `30` or `30s`        |
+//! | `cloudflare.ar2ro.io/tls-timeout`              | seconds                       |
+//! | `cloudflare.ar2ro.io/tcp-keep-alive`           | seconds                       |
+//! | `cloudflare.ar2ro.io/keep-alive-timeout`       | seconds                       |
+//! | `cloudflare.ar2ro.io/keep-alive-connections`   | number of idle connections    |
+//! | `cloudflare.ar2ro.io/http-host-header`         | Host header for the origin    |
+//! | `cloudflare.ar2ro.io/http2-origin`             | true or false                 |
+//! | `cloudflare.ar2ro.io/disable-chunked-encoding` | true or false                 |
+//! | `cloudflare.ar2ro.io/no-happy-eyeballs`        | true or false                 |
+//! | `cloudflare.ar2ro.io/origin-server-name`       | TLS server name, https only   |
+//! | `cloudflare.ar2ro.io/ca-pool`                  | CA file path, https only      |
+//! | `cloudflare.ar2ro.io/no-tls-verify`            | true or false, https only     |
+use cloudflarext::tunnel_configuration::{OriginRequest, Seconds};
+use k8s_openapi::api::core::v1::Service;
+use k8s_openapi::api::networking::v1::Ingress;
+use kube::ResourceExt;
+
+pub const ORIGIN_PROTOCOL_ANNOTATION: &str = "cloudflare.ar2ro.io/origin-protocol";
+pub const CONNECT_TIMEOUT_ANNOTATION: &str = "cloudflare.ar2ro.io/connect-timeout";
+pub const TLS_TIMEOUT_ANNOTATION: &str = "cloudflare.ar2ro.io/tls-timeout";
+pub const TCP_KEEP_ALIVE_ANNOTATION: &str = "cloudflare.ar2ro.io/tcp-keep-alive";
+pub const KEEP_ALIVE_TIMEOUT_ANNOTATION: &str = "cloudflare.ar2ro.io/keep-alive-timeout";
+pub const KEEP_ALIVE_CONNECTIONS_ANNOTATION: &str = "cloudflare.ar2ro.io/keep-alive-connections";
+pub const HTTP_HOST_HEADER_ANNOTATION: &str = "cloudflare.ar2ro.io/http-host-header";
+pub const HTTP2_ORIGIN_ANNOTATION: &str = "cloudflare.ar2ro.io/http2-origin";
+pub const DISABLE_CHUNKED_ENCODING_ANNOTATION: &str =
+    "cloudflare.ar2ro.io/disable-chunked-encoding";
+pub const NO_HAPPY_EYEBALLS_ANNOTATION: &str = "cloudflare.ar2ro.io/no-happy-eyeballs";
+pub const ORIGIN_SERVER_NAME_ANNOTATION: &str = "cloudflare.ar2ro.io/origin-server-name";
+pub const CA_POOL_ANNOTATION: &str = "cloudflare.ar2ro.io/ca-pool";
+pub const NO_TLS_VERIFY_ANNOTATION: &str = "cloudflare.ar2ro.io/no-tls-verify";
+
+pub const ORIGIN_PROTOCOLS: [&str; 5] = ["http", "https", "tcp", "ssh", "rdp"];
+
+pub(crate) fn origin_annotation<'a>(
+    ingress: &'a Ingress,
+    service: &'a Service,
+    key: &str,
+) -> Option<&'a str> {
+    ingress
+        .annotations()
+        .get(key)
+        .or_else(|| service.annotations().get(key))
+        .map(String::as_str)
+}
+
+// INFO: A value that doesn't parse is reported and left out, a typo shouldn't take the rule
+// down with it.
+fn parse_annotation<T>(
+    value: Option<&str>,
+    key: &str,
+    expected: &str,
+    parse: impl FnOnce(&str) -> Option<T>,
+    invalid: &mut Vec<String>,
+) -> Option<T> {
+    let value = value?;
+    match parse(value) {
+        Some(parsed) => Some(parsed),
+        None => {
+            invalid.push(format!(
+                "annotation {} must be {}, got {}",
+                key, expected, value
+            ));
+            None
+        }
+    }
+}
+
+fn seconds(value: &str) -> Option<Seconds> {
+    value
+        .strip_suffix('s')
+        .unwrap_or(value)
+        .parse()
+        .ok()
+        .filter(|seconds| *seconds >= 0)
+        .map(Seconds)
+}
+
+fn count(value: &str) -> Option<i32> {
+    value.parse().ok().filter(|count| *count >= 0)
+}
+
+fn boolean(value: &str) -> Option<bool> {
+    value.parse().ok()
+}
+
+fn text(value: &str) -> Option<String> {
+    Some(value.to_owned()).filter(|value| !value.is_empty())
+}
+
+/// The origin request of a backend reached over `protocol` and why the annotations that
+/// couldn't be parsed were left out. `None` when no annotation applies.
+pub(crate) fn origin_request(
+    ingress: &Ingress,
+    service: &Service,
+    protocol: &str,
+) -> (Option<OriginRequest>, Vec<String>) {
+    let annotation = |key: &str| origin_annotation(ingress, service, key);
+    // INFO: TLS settings only mean something to cloudflared for https origins.
+    let tls_annotation = |key: &str| annotation(key).filter(|_| protocol == "https");
+    let mut invalid = Vec::new();
+
+    let origin_request = OriginRequest {
+        connect_timeout: parse_annotation(
+            annotation(CONNECT_TIMEOUT_ANNOTATION),
+            CONNECT_TIMEOUT_ANNOTATION,
+            "a number of seconds",
+            seconds,
+            &mut invalid,
+        ),
+        tls_timeout: parse_annotation(
+            annotation(TLS_TIMEOUT_ANNOTATION),
+            TLS_TIMEOUT_ANNOTATION,
+            "a number of seconds",
+            seconds,
+            &mut invalid,
+        ),
+        tcp_keep_alive: parse_annotation(
+            annotation(TCP_KEEP_ALIVE_ANNOTATION),
+            TCP_KEEP_ALIVE_ANNOTATION,
+            "a number of seconds",
+            seconds,
+            &mut invalid,
+        ),
+        keep_alive_timeout: parse_annotation(
+            annotation(KEEP_ALIVE_TIMEOUT_ANNOTATION),
+            KEEP_ALIVE_TIMEOUT_ANNOTATION,
+            "a number of seconds",
+            seconds,
+            &mut invalid,
+        ),
+        keep_alive_connections: parse_annotation(
+            annotation(KEEP_ALIVE_CONNECTIONS_ANNOTATION),
+            KEEP_ALIVE_CONNECTIONS_ANNOTATION,
+            "a number of connections",
+            count,
+            &mut invalid,
+        ),
+        http_host_header: parse_annotation(
+            annotation(HTTP_HOST_HEADER_ANNOTATION),
+            HTTP_HOST_HEADER_ANNOTATION,
+            "a hostname",
+            text,
+            &mut invalid,
+        ),
+        http2_origin: parse_annotation(
+            annotation(HTTP2_ORIGIN_ANNOTATION),
+            HTTP2_ORIGIN_ANNOTATION,
+            "true or false",
+            boolean,
+            &mut invalid,
+        ),
+        disable_chunked_encoding: parse_annotation(
+            annotation(DISABLE_CHUNKED_ENCODING_ANNOTATION),
+            DISABLE_CHUNKED_ENCODING_ANNOTATION,
+            "true or false",
+            boolean,
+            &mut invalid,
+        ),
+        no_happy_eyeballs: parse_annotation(
+            annotation(NO_HAPPY_EYEBALLS_ANNOTATION),
+            NO_HAPPY_EYEBALLS_ANNOTATION,
+            "true or false",
+            boolean,
+            &mut invalid,
+        ),
+        origin_server_name: parse_annotation(
+            tls_annotation(ORIGIN_SERVER_NAME_ANNOTATION),
+            ORIGIN_SERVER_NAME_ANNOTATION,
+            "a hostname",
+            text,
+            &mut invalid,
+        ),
+        ca_pool: parse_annotation(
+            tls_annotation(CA_POOL_ANNOTATION),
+            CA_POOL_ANNOTATION,
+            "a file path",
+            text,
+            &mut invalid,
+        ),
+        no_tls_verify: parse_annotation(
+            tls_annotation(NO_TLS_VERIFY_ANNOTATION),
+            NO_TLS_VERIFY_ANNOTATION,
+            "true or false",
+            boolean,
+            &mut invalid,
+        ),
+        ..OriginRequest::default()
+    };
+
+    match origin_request == OriginRequest::default() {
+        true => (None, invalid),
+        false => (Some(origin_request), invalid),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    fn parse(annotations: Value, protocol: &str) -> (Option<OriginRequest>, Vec<String>) {
+        let ingress: Ingress =
+            serde_json::from_value(json!({ "metadata": { "annotations": annotations } })).unwrap();
+        origin_request(&ingress, &Service::default(), protocol)
+    }
+
+    #[test]
+    fn every_annotation_is_parsed() {
+        let (origin_request, invalid) = parse(
+            json!({
+                CONNECT_TIMEOUT_ANNOTATION: "30s",
+                TLS_TIMEOUT_ANNOTATION: "10",
+                TCP_KEEP_ALIVE_ANNOTATION: "15",
+                KEEP_ALIVE_TIMEOUT_ANNOTATION: "90s",
+                KEEP_ALIVE_CONNECTIONS_ANNOTATION: "100",
+                HTTP_HOST_HEADER_ANNOTATION: "internal.example.com",
+                HTTP2_ORIGIN_ANNOTATION: "true",
+                DISABLE_CHUNKED_ENCODING_ANNOTATION: "false",
+                NO_HAPPY_EYEBALLS_ANNOTATION: "true",
+                ORIGIN_SERVER_NAME_ANNOTATION: "origin.example.com",
+                CA_POOL_ANNOTATION: "/etc/ssl/ca.pem",
+                NO_TLS_VERIFY_ANNOTATION: "false",
+            }),
+            "https",
+        );
+
+        assert!(invalid.is_empty(), "{:?}", invalid);
+        assert_eq!(
+            origin_request,
+            Some(OriginRequest {
+                connect_timeout: Some(Seconds(30)),
+                tls_timeout: Some(Seconds(10)),
+                tcp_keep_alive: Some(Seconds(15)),
+                keep_alive_timeout: Some(Seconds(90)),
+                keep_alive_connections: Some(100),
+                http_host_header: Some("internal.example.com".to_owned()),
+                http2_origin: Some(true),
+                disable_chunked_encoding: Some(false),
+                no_happy_eyeballs: Some(true),
+                origin_server_name: Some("origin.example.com".to_owned()),
+                ca_pool: Some("/etc/ssl/ca.pem".to_owned()),
+                no_tls_verify: Some(false),
+                ..OriginRequest::default()
+            })
+        );
+    }
+
+    #[test]
+    fn invalid_values_are_reported_and_left_out() {
+        let (origin_request, invalid) = parse(
+            json!({
+                CONNECT_TIMEOUT_ANNOTATION: "30m",
+                KEEP_ALIVE_CONNECTIONS_ANNOTATION: "-1",
+                HTTP_HOST_HEADER_ANNOTATION: "",
+                HTTP2_ORIGIN_ANNOTATION: "yes",
+                TCP_KEEP_ALIVE_ANNOTATION: "15",
+            }),
+            "http",
+        );
+
+        assert_eq!(
+            origin_request,
+            Some(OriginRequest {
+                tcp_keep_alive: Some(Seconds(15)),
+                ..OriginRequest::default()
+            })
+        );
+        assert_eq!(
+            invalid,
+            vec![
+                format!(
+                    "annotation {} must be a number of seconds, got 30m",
+                    CONNECT_TIMEOUT_ANNOTATION
+                ),
+                format!(
+                    "annotation {} must be a number of connections, got -1",
+                    KEEP_ALIVE_CONNECTIONS_ANNOTATION
+                ),
+                format!(
+                    "annotation {} must be a hostname, got ",
+                    HTTP_HOST_HEADER_ANNOTATION
+                ),
+                format!(
+                    "annotation {} must be true or false, got yes",
+                    HTTP2_ORIGIN_ANNOTATION
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn partial_sets_only_fill_what_they_name() {
+        let (origin_request, invalid) = parse(
+            json!({ NO_TLS_VERIFY_ANNOTATION: "true", "unrelated.example.com/key": "value" }),
+            "https",
+        );
+
+        assert!(invalid.is_empty());
+        assert_eq!(
+            origin_request,
+            Some(OriginRequest {
+                no_tls_verify: Some(true),
+                ..OriginRequest::default()
+            })
+        );
+
+        assert_eq!(parse(json!({}), "https"), (None, Vec::new()));
+    }
+
+    #[test]
+    fn ingress_annotations_win_over_the_service() {
+        let ingress: Ingress = serde_json::from_value(json!({
+            "metadata": { "annotations": { CONNECT_TIMEOUT_ANNOTATION: "5" } },
+        }))
+        .unwrap();
+        let service: Service = serde_json::from_value(json!({
+            "metadata": { "annotations": {
+                CONNECT_TIMEOUT_ANNOTATION: "60",
+                TLS_TIMEOUT_ANNOTATION: "20",
+            } },
+        }))
+        .unwrap();
+
+        let (origin_request, _) = origin_request(&ingress, &service, "http");
+
+        assert_eq!(
+            origin_request,
+            Some(OriginRequest {
+                connect_timeout: Some(Seconds(5)),
+                tls_timeout: Some(Seconds(20)),
+                ..OriginRequest::default()
+            })
+        );
+    }
+}
@@ -0,0 +1,286 @@
+//! TunnelIngress objects on remotely managed tunnels. Their rules are merged with the ones of
+//! the Ingresses on the same tunnel by `sync_tunnel`, locally managed tunnels render them into
+//! config.yaml in the tunnel controller instead.
+use crate::{patch_finalizers, recorded_tunnel, rule_rank, sync_tunnel, Context, Error, PathRank};
+use cloudflare_controller_common::TUNNEL_INGRESS_FINALIZER;
+use cloudflarext::cfd_tunnel::CloudflaredTunnel;
+use cloudflarext::tunnel_configuration::IngressRule;
+use k8s_openapi::api::core::v1::Service;
+use kube::api::{Api, Patch, ResourceExt};
+use kube::runtime::controller::Action;
+use kube::runtime::reflector::{ObjectRef, Store};
+use serde_json::json;
+use std::sync::Arc;
+use std::time::Duration;
+use tunnel_controller::crd::tunnel_ingress::{TunnelIngress, TunnelService};
+use tunnel_controller::resources::patch_params;
+use tunnel_controller::AnyTunnel;
+
+fn has_finalizer(tunnel_ingress: &TunnelIngress) -> bool {
+    tunnel_ingress
+        .finalizers()
+        .iter()
+        .any(|finalizer| finalizer == TUNNEL_INGRESS_FINALIZER)
+}
+
+pub(crate) fn references(tunnel_ingress: &TunnelIngress, tunnel: &AnyTunnel) -> bool {
+    match tunnel {
+        AnyTunnel::Tunnel(tunnel) => tunnel_ingress.references(tunnel.as_ref()),
+        AnyTunnel::ClusterTunnel(tunnel) => tunnel_ingress.references(tunnel.as_ref()),
+    }
+}
+
+pub(crate) fn referencing(
+    tunnel_ingresses: &Store<TunnelIngress>,
+    tunnel: &AnyTunnel,
+) -> Vec<ObjectRef<TunnelIngress>> {
+    tunnel_ingresses
+        .state()
+        .into_iter()
+        .filter(|tunnel_ingress| references(tunnel_ingress, tunnel))
+        .map(|tunnel_ingress| ObjectRef::from_obj(tunnel_ingress.as_ref()))
+        .collect()
+}
+
+/// Why `tunnel_ingress` is left out of the configuration of `tunnel`, empty when it isn't.
+pub(crate) fn problems<C: CloudflaredTunnel>(
+    tunnel_ingress: &TunnelIngress,
+    tunnel: &AnyTunnel,
+    ctx: &Context<C>,
+) -> Vec<String> {
+    let mut problems = tunnel_ingress.validate();
+    problems.extend(match tunnel {
+        AnyTunnel::Tunnel(tunnel) => {
+            tunnel_ingress.cross_namespace_denial(tunnel.as_ref(), ctx.allow_cross_namespace_refs)
+        }
+        AnyTunnel::ClusterTunnel(_) => None,
+    });
+    problems
+}
+
+fn referenced_tunnel<C: CloudflaredTunnel>(
+    tunnel_ingress: &TunnelIngress,
+    ctx: &Context<C>,
+) -> Option<AnyTunnel> {
+    let tunnel_ref = &tunnel_ingress.spec.tunnel_ref;
+    ctx.tunnel_stores.get(
+        tunnel_ref.kind.as_str(),
+        &tunnel_ref.name,
+        tunnel_ingress.tunnel_namespace().as_deref(),
+    )
+}
+
+fn service_exists(services: &Store<Service>, namespace: &str, name: &str) -> bool {
+    services
+        .get(&ObjectRef::new(name).within(namespace))
+        .is_some()
+}
+
+/// Rules of `tunnel_ingress` ranked like the paths of an Ingress, rules pointing at a Service
+/// that doesn't exist are left out.
+pub(crate) fn tunnel_ingress_rules(
+    tunnel_ingress: &TunnelIngress,
+    services: &Store<Service>,
+) -> Vec<(PathRank, IngressRule)> {
+    let namespace = tunnel_ingress.namespace().unwrap_or_default();
+
+    tunnel_ingress
+        .spec
+        .rules
+        .iter()
+        .zip(tunnel_ingress.ingress_rules())
+        .filter(|(rule, _)| match &rule.service {
+            TunnelService::ServiceRef(service_ref) => service_exists(
+                services,
+                service_ref.namespace.as_deref().unwrap_or(&namespace),
+                &service_ref.name,
+            ),
+            TunnelService::Url(_) => true,
+        })
+        .map(|(_, rule)| (rule_rank(&rule), rule))
+        .collect()
+}
+
+fn missing_services(tunnel_ingress: &TunnelIngress, services: &Store<Service>) -> Vec<String> {
+    tunnel_ingress
+        .service_refs()
+        .into_iter()
+        .filter(|(namespace, name)| !service_exists(services, namespace, name))
+        .map(|(namespace, name)| format!("{}/{}", namespace, name))
+        .collect()
+}
+
+async fn record_status<C: CloudflaredTunnel>(
+    tunnel_ingress: &TunnelIngress,
+    ctx: &Context<C>,
+    error: Option<String>,
+) -> Result<(), Error> {
+    let current = tunnel_ingress.status.clone().unwrap_or_default();
+    let problems = match referenced_tunnel(tunnel_ingress, ctx) {
+        Some(tunnel) => problems(tunnel_ingress, &tunnel, ctx),
+        None => tunnel_ingress.validate(),
+    };
+    let status = current.after_sync(tunnel_ingress.metadata.generation, &problems, error);
+    if !current.differs(&status) {
+        return Ok(());
+    }
+
+    let tunnel_ingress_api: Api<TunnelIngress> = Api::namespaced(
+        ctx.kubernetes_client.clone(),
+        &tunnel_ingress.namespace().unwrap_or_default(),
+    );
+    tunnel_ingress_api
+        .patch_status(
+            &tunnel_ingress.name_any(),
+            &patch_params(),
+            &Patch::Merge(json!({ "status": status })),
+        )
+        .await
+        .map_err(Error::KubeError)?;
+
+    Ok(())
+}
+
+// INFO: Rebuilds the tunnel the rules were written to without this TunnelIngress, then lets it
+// go. Runs on deletion and when the tunnel became locally managed.
+async fn release<C: CloudflaredTunnel>(
+    tunnel_ingress: &TunnelIngress,
+    ctx: &Context<C>,
+) -> Result<Action, Error> {
+    if let Some(tunnel_uuid) = recorded_tunnel(tunnel_ingress) {
+        match ctx.tunnel_stores.find_by_uuid(tunnel_uuid) {
+            Some(tunnel) if !tunnel.spec().is_local() => {
+                sync_tunnel(&tunnel, tunnel_uuid, ctx).await?;
+            }
+            Some(_) => {}
+            None => println!(
+                "Tunnel {} of TunnelIngress {} is gone, nothing to remove",
+                tunnel_uuid,
+                tunnel_ingress.name_any()
+            ),
+        }
+    }
+
+    let finalizers = tunnel_ingress
+        .finalizers()
+        .iter()
+        .filter(|finalizer| *finalizer != TUNNEL_INGRESS_FINALIZER)
+        .cloned()
+        .collect();
+    patch_finalizers(tunnel_ingress, ctx, finalizers, None).await?;
+
+    Ok(Action::await_change())
+}
+
+pub(crate) async fn reconcile_tunnel_ingress<C: CloudflaredTunnel>(
+    tunnel_ingress: &TunnelIngress,
+    ctx: &Context<C>,
+) -> Result<Action, Error> {
+    if tunnel_ingress.metadata.deletion_timestamp.is_some() {
+        return match has_finalizer(tunnel_ingress) {
+            true => release(tunnel_ingress, ctx).await,
+            false => Ok(Action::await_change()),
+        };
+    }
+
+    let tunnel_ref = &tunnel_ingress.spec.tunnel_ref;
+    let tunnel = referenced_tunnel(tunnel_ingress, ctx).ok_or_else(|| {
+        Error::MissingTunnel(format!("{} {}", tunnel_ref.kind.as_str(), tunnel_ref.name))
+    })?;
+
+    // INFO: The tunnel controller renders these into config.yaml and records their status.
+    if tunnel.spec().is_local() {
+        return match has_finalizer(tunnel_ingress) {
+            true => release(tunnel_ingress, ctx).await,
+            false => Ok(Action::await_change()),
+        };
+    }
+
+    let tunnel_uuid = match tunnel.get_uuid() {
+        Some(tunnel_uuid) => tunnel_uuid,
+        // Requeue in 2 minutes as the tunnel is not ready.
+        None => return Ok(Action::requeue(Duration::from_secs(60 * 2))),
+    };
+
+    let previous = recorded_tunnel(tunnel_ingress);
+    if !has_finalizer(tunnel_ingress) || previous != Some(tunnel_uuid) {
+        let mut finalizers = tunnel_ingress.finalizers().to_vec();
+        if !has_finalizer(tunnel_ingress) {
+            finalizers.push(TUNNEL_INGRESS_FINALIZER.to_owned());
+        }
+        patch_finalizers(tunnel_ingress, ctx, finalizers, Some(tunnel_uuid)).await?;
+    }
+
+    if let Some(rules) = sync_tunnel(&tunnel, tunnel_uuid, ctx).await? {
+        println!(
+            "Applied {} rules to {} {} ({}) for TunnelIngress {}",
+            rules,
+            tunnel.kind(),
+            tunnel.name(),
+            tunnel_uuid,
+            tunnel_ingress.name_any()
+        );
+    }
+
+    // INFO: The tunnelRef moved to another tunnel, the rules are dropped from the old one.
+    if let Some(previous) = previous.filter(|previous| *previous != tunnel_uuid) {
+        if let Some(previous_tunnel) = ctx.tunnel_stores.find_by_uuid(previous) {
+            sync_tunnel(&previous_tunnel, previous, ctx).await?;
+        }
+    }
+
+    // INFO: Services tend to be created alongside, a missing one is checked again sooner.
+    let missing = missing_services(tunnel_ingress, &ctx.service_store);
+    match missing.is_empty() {
+        true => {
+            record_status(tunnel_ingress, ctx, None).await?;
+            Ok(Action::requeue(Duration::from_secs(60)))
+        }
+        false => {
+            let message = format!(
+                "rules referencing missing Services {} were left out",
+                missing.join(", ")
+            );
+            record_status(tunnel_ingress, ctx, Some(message)).await?;
+            Ok(Action::requeue(Duration::from_secs(15)))
+        }
+    }
+}
+
+pub(crate) async fn reconcile<C: CloudflaredTunnel>(
+    tunnel_ingress: Arc<TunnelIngress>,
+    ctx: Arc<Context<C>>,
+) -> Result<Action, Error> {
+    let result = reconcile_tunnel_ingress(&tunnel_ingress, &ctx).await;
+
+    if let Err(err) = &result {
+        if let Err(status_err) = record_status(&tunnel_ingress, &ctx, Some(err.to_string())).await {
+            println!(
+                "Failed to record the status of TunnelIngress {}: {}",
+                tunnel_ingress.name_any(),
+                status_err
+            );
+        }
+    }
+
+    result
+}
+
+pub(crate) fn error_policy<C: CloudflaredTunnel>(
+    tunnel_ingress: Arc<TunnelIngress>,
+    error: &Error,
+    _ctx: Arc<Context<C>>,
+) -> Action {
+    println!(
+        "Failed to reconcile TunnelIngress {}: {}",
+        tunnel_ingress.name_any(),
+        error
+    );
+    let delay = match error {
+        Error::KubeError(_) => 5,
+        Error::MissingTunnel(_) => 30,
+        _ => 60,
+    };
+
+    Action::requeue(Duration::from_secs(delay))
+}
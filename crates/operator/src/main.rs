@@ -1,3 +1,319 @@
-fn main() {
-    println!("Hello, world!");
+use clap::{ArgAction, Parser};
+use cloudflare::framework::{Environment, HttpApiClientConfig};
+use cloudflarext::{
+    dry_run::DryRunCloudflareClient, AuthlessClient as CloudflareClient, ClientConfig,
+    ResolveOverride, DEFAULT_MAX_RETRIES, DEFAULT_REQUEST_TIMEOUT,
+};
+use ingress_controller::{ControllerMode, IngressController};
+use kube::Client;
+use std::future::IntoFuture;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio_util::sync::CancellationToken;
+use tracing_subscriber::EnvFilter;
+use tunnel_controller::backoff::Backoff;
+use tunnel_controller::health::{HealthServer, DEFAULT_HEALTH_PORT};
+use tunnel_controller::resources::deployment::{ImagePolicy, DEFAULT_IMAGE};
+use tunnel_controller::state::OperatorState;
+use tunnel_controller::token_cache::TokenCache;
+use tunnel_controller::webhook::{WebhookServer, WebhookService};
+use tunnel_controller::{TunnelController, DEFAULT_CLUSTER_TUNNEL_NAMESPACE};
+
+// INFO: How long in-flight reconciles get to finish after a shutdown signal.
+const DRAIN_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// Static resolution for the Cloudflare api, `<domain>=<ip>:<port>`, can be repeated.
+    #[arg(long = "cloudflare-resolve")]
+    cloudflare_resolve: Vec<ResolveOverride>,
+
+    /// Retries of a Cloudflare api request that failed with a network error, 429 or 5xx.
+    #[arg(long, env = "CLOUDFLARE_MAX_RETRIES", default_value_t = DEFAULT_MAX_RETRIES)]
+    cloudflare_max_retries: u32,
+
+    /// Delay before the first Cloudflare api retry, doubled on every following one.
+    #[arg(long, env = "CLOUDFLARE_RETRY_DELAY_SECS", default_value_t = 1)]
+    cloudflare_retry_delay_secs: u64,
+
+    /// Time a Cloudflare api request may take before it's abandoned.
+    #[arg(long, env = "CLOUDFLARE_REQUEST_TIMEOUT_SECS", default_value_t = DEFAULT_REQUEST_TIMEOUT.as_secs())]
+    cloudflare_request_timeout_secs: u64,
+
+    /// Time allowed to connect to the Cloudflare api, only the request timeout applies when
+    /// unset.
+    #[arg(long, env = "CLOUDFLARE_CONNECT_TIMEOUT_SECS")]
+    cloudflare_connect_timeout_secs: Option<u64>,
+
+    /// Idle connections to the Cloudflare api kept for reuse, unlimited when unset.
+    #[arg(long, env = "CLOUDFLARE_MAX_IDLE_CONNECTIONS")]
+    cloudflare_max_idle_connections: Option<usize>,
+
+    /// Namespace the cloudflared Deployments of ClusterTunnels are created in.
+    #[arg(long, default_value = DEFAULT_CLUSTER_TUNNEL_NAMESPACE)]
+    cluster_tunnel_namespace: String,
+
+    /// Hibernates every tunnel regardless of its spec, meant for scheduled automation.
+    #[arg(long)]
+    hibernate_all: bool,
+
+    /// Delay before retrying a failed reconcile, doubled on every consecutive failure.
+    #[arg(long, env = "MIN_BACKOFF_SECS", default_value_t = 5)]
+    min_backoff_secs: u64,
+
+    /// Upper bound for the retry delay of a failed reconcile.
+    #[arg(long, env = "MAX_BACKOFF_SECS", default_value_t = 600)]
+    max_backoff_secs: u64,
+
+    /// Runs the reconcile logic but only prints the Cloudflare writes and sends every
+    /// Kubernetes write as a server side dry run.
+    #[arg(long, env = "DRY_RUN")]
+    dry_run: bool,
+
+    /// cloudflared image of Tunnels that don't set spec.image.
+    #[arg(long, env = "DEFAULT_IMAGE", default_value = DEFAULT_IMAGE)]
+    default_image: String,
+
+    /// Lets a Tunnel's spec.image replace the default image, when false every Tunnel runs the
+    /// default image.
+    #[arg(long, env = "ALLOW_IMAGE_OVERRIDE", default_value_t = true, action = ArgAction::Set)]
+    allow_image_override: bool,
+
+    /// Fetches tunnel tokens from Cloudflare every time instead of caching them for an hour.
+    #[arg(long)]
+    disable_token_cache: bool,
+
+    /// ConfigMap the reconciled tunnels are kept in across restarts, `<namespace>/<name>`.
+    /// Tunnels that didn't change while the operator was down aren't reconciled again on start.
+    #[arg(long, env = "STATE_CONFIGMAP")]
+    state_configmap: Option<String>,
+
+    /// Routes Gateway API HTTPRoutes through tunnels as well as Ingresses.
+    #[arg(long, env = "GATEWAY_API_ENABLED")]
+    gateway_api_enabled: bool,
+
+    /// Routes Ingresses without an ingress class through the default tunnel.
+    #[arg(long, env = "CLOUDFLARE_CLASSLESS_INGRESS_POLICY")]
+    classless_ingress_policy: bool,
+
+    /// Routes Ingresses without an ingress class through our IngressClass marked with
+    /// `ingressclass.kubernetes.io/is-default-class: "true"`.
+    #[arg(long, env = "CLOUDFLARE_DEFAULT_INGRESS_CLASS")]
+    default_ingress_class: bool,
+
+    /// Lets TunnelIngress objects route through Tunnels of other namespaces without the Tunnel
+    /// being annotated with `cloudflare.ar2ro.io/allow-cross-namespace: "true"`.
+    #[arg(long, env = "ALLOW_CROSS_NAMESPACE_TUNNEL_REFS")]
+    allow_cross_namespace_tunnel_refs: bool,
+
+    /// Namespaces the ingress controller acts on, comma separated. Every namespace when unset.
+    /// Only a single namespace narrows the Ingress watch, with several the operator still needs
+    /// cluster-wide RBAC to list and watch Ingresses.
+    #[arg(long, env = "WATCH_NAMESPACES", value_delimiter = ',')]
+    watch_namespaces: Vec<String>,
+
+    /// Port `/healthz` is served on for the liveness and readiness probes, `/metrics` as well.
+    #[arg(long, env = "HEALTH_PORT", default_value_t = DEFAULT_HEALTH_PORT)]
+    health_port: u16,
+
+    /// Serves the validating admission webhook on this address, it stays off when unset.
+    #[arg(long, requires_all = ["webhook_cert", "webhook_key", "webhook_service"])]
+    webhook_addr: Option<SocketAddr>,
+
+    /// PEM certificate chain the webhook is served with, also registered as its CA bundle.
+    #[arg(long)]
+    webhook_cert: Option<PathBuf>,
+
+    /// PEM private key of the webhook certificate.
+    #[arg(long)]
+    webhook_key: Option<PathBuf>,
+
+    /// Service the api server calls the webhook through, `<namespace>/<name>`.
+    #[arg(long)]
+    webhook_service: Option<String>,
+
+    /// Port of the webhook Service.
+    #[arg(long, default_value_t = 443)]
+    webhook_service_port: i32,
+}
+
+impl Args {
+    fn cloudflare_client(&self) -> anyhow::Result<CloudflareClient> {
+        let client_config = ClientConfig {
+            resolve_overrides: self.cloudflare_resolve.clone(),
+            max_retries: self.cloudflare_max_retries,
+            retry_base_delay: Duration::from_secs(self.cloudflare_retry_delay_secs),
+            max_idle_connections_per_host: self.cloudflare_max_idle_connections,
+            connection_timeout: self
+                .cloudflare_connect_timeout_secs
+                .map(Duration::from_secs),
+            request_timeout: Some(Duration::from_secs(self.cloudflare_request_timeout_secs)),
+        };
+
+        Ok(CloudflareClient::try_new_with(
+            HttpApiClientConfig::default(),
+            client_config,
+            Environment::Production,
+        )?)
+    }
+
+    fn webhook_server(
+        &self,
+        kubernetes_client: Client,
+        shutdown: CancellationToken,
+    ) -> anyhow::Result<Option<WebhookServer>> {
+        let (addr, cert, key, service) = match (
+            self.webhook_addr,
+            &self.webhook_cert,
+            &self.webhook_key,
+            &self.webhook_service,
+        ) {
+            (Some(addr), Some(cert), Some(key), Some(service)) => (addr, cert, key, service),
+            _ => return Ok(None),
+        };
+
+        let (namespace, name) = service
+            .split_once('/')
+            .ok_or_else(|| anyhow::anyhow!("--webhook-service must be <namespace>/<name>"))?;
+
+        Ok(Some(WebhookServer::new(
+            kubernetes_client,
+            addr,
+            cert.clone(),
+            key.clone(),
+            WebhookService {
+                namespace: namespace.to_owned(),
+                name: name.to_owned(),
+                port: self.webhook_service_port,
+            },
+            shutdown,
+        )))
+    }
+
+    async fn operator_state(&self, kubernetes_client: Client) -> anyhow::Result<OperatorState> {
+        let state_configmap = match &self.state_configmap {
+            Some(state_configmap) => state_configmap,
+            None => return Ok(OperatorState::disabled()),
+        };
+
+        let (namespace, name) = state_configmap
+            .split_once('/')
+            .ok_or_else(|| anyhow::anyhow!("--state-configmap must be <namespace>/<name>"))?;
+
+        Ok(OperatorState::load(kubernetes_client, namespace, name).await?)
+    }
+}
+
+async fn shutdown_signal() -> anyhow::Result<()> {
+    let mut sigterm = signal(SignalKind::terminate())?;
+    tokio::select! {
+        res = tokio::signal::ctrl_c() => res?,
+        _ = sigterm.recv() => {},
+    };
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    // INFO: RUST_LOG picks the levels, info and above are logged by default.
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+    let kubernetes_client = Client::try_default().await?;
+    let shutdown = CancellationToken::new();
+    tunnel_controller::resources::set_dry_run(args.dry_run);
+    if args.dry_run {
+        println!("DRY RUN: no changes are written to Cloudflare or Kubernetes");
+    }
+
+    let tunnel_controller = TunnelController::try_new(
+        kubernetes_client.clone(),
+        DryRunCloudflareClient::new(args.cloudflare_client()?, args.dry_run),
+        args.cluster_tunnel_namespace.clone(),
+        args.hibernate_all,
+        args.allow_cross_namespace_tunnel_refs,
+        Backoff::new(
+            Duration::from_secs(args.min_backoff_secs),
+            Duration::from_secs(args.max_backoff_secs),
+        ),
+        if args.disable_token_cache {
+            TokenCache::disabled()
+        } else {
+            TokenCache::default()
+        },
+        ImagePolicy {
+            default_image: args.default_image.clone(),
+            allow_override: args.allow_image_override,
+        },
+        args.operator_state(kubernetes_client.clone()).await?,
+        shutdown.clone(),
+    )
+    .await?;
+
+    let health_server = HealthServer::new(
+        tunnel_controller.health(),
+        SocketAddr::from((Ipv4Addr::UNSPECIFIED, args.health_port)),
+        shutdown.clone(),
+    );
+    let webhook_server = args.webhook_server(kubernetes_client.clone(), shutdown.clone())?;
+
+    let ingress_controller = IngressController::try_new(
+        kubernetes_client,
+        DryRunCloudflareClient::new(args.cloudflare_client()?, args.dry_run),
+        tunnel_controller.stores(),
+        if args.gateway_api_enabled {
+            ControllerMode::GatewayApi
+        } else {
+            ControllerMode::Ingress
+        },
+        args.classless_ingress_policy,
+        args.default_ingress_class,
+        args.watch_namespaces.clone(),
+        args.allow_cross_namespace_tunnel_refs,
+        shutdown.clone(),
+    )
+    .await?;
+
+    let controllers = async {
+        let webhook_server = async {
+            match webhook_server {
+                Some(webhook_server) => webhook_server.await,
+                None => Ok(()),
+            }
+        };
+
+        tokio::try_join!(
+            tunnel_controller.into_future(),
+            ingress_controller.into_future(),
+            health_server.into_future(),
+            webhook_server
+        )
+    };
+    tokio::pin!(controllers);
+
+    tokio::select! {
+        res = &mut controllers => return res.map(|_| ()),
+        res = shutdown_signal() => res?,
+    };
+
+    println!("Received shutdown signal, draining in-flight reconciles");
+    shutdown.cancel();
+
+    match tokio::time::timeout(DRAIN_TIMEOUT, controllers).await {
+        Ok(res) => res.map(|_| ()),
+        Err(_) => {
+            println!(
+                "Reconciles did not finish within {}s, forcing exit",
+                DRAIN_TIMEOUT.as_secs()
+            );
+            std::process::exit(1);
+        }
+    }
 }
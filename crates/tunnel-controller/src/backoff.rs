@@ -0,0 +1,54 @@
+//! Per object retry delays for failed reconciles.
+use dashmap::DashMap;
+use kube::core::DynamicObject;
+use kube::runtime::reflector::ObjectRef;
+use std::time::Duration;
+
+pub const DEFAULT_MIN_BACKOFF: Duration = Duration::from_secs(5);
+pub const DEFAULT_MAX_BACKOFF: Duration = Duration::from_secs(600);
+
+#[derive(Debug, Clone, Copy, Default)]
+pub struct BackoffState {
+    pub failures: u32,
+}
+
+/// Doubles the delay from `min` for every consecutive failure of the same object, capped at
+/// `max`. Keys are erased so Tunnels and ClusterTunnels share one map.
+#[derive(Debug)]
+pub struct Backoff {
+    min: Duration,
+    max: Duration,
+    states: DashMap<ObjectRef<DynamicObject>, BackoffState>,
+}
+
+impl Backoff {
+    pub fn new(min: Duration, max: Duration) -> Backoff {
+        Backoff {
+            min,
+            max: max.max(min),
+            states: DashMap::new(),
+        }
+    }
+
+    /// Records a failure and returns how long to wait before retrying.
+    pub fn next(&self, object: ObjectRef<DynamicObject>) -> Duration {
+        let mut state = self.states.entry(object).or_default();
+        let delay = match 2u32.checked_pow(state.failures) {
+            Some(factor) => self.min.saturating_mul(factor).min(self.max),
+            None => self.max,
+        };
+        state.failures = state.failures.saturating_add(1);
+
+        delay
+    }
+
+    pub fn reset(&self, object: &ObjectRef<DynamicObject>) {
+        self.states.remove(object);
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Backoff {
+        Backoff::new(DEFAULT_MIN_BACKOFF, DEFAULT_MAX_BACKOFF)
+    }
+}
@@ -0,0 +1,76 @@
+use crate::crd::tunnel::{TunnelCrd, TunnelResource, TunnelStatus};
+use kube::{Api, CustomResource, ResourceExt};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::ops::{Deref, DerefMut};
+
+// INFO: The Tunnel spec is flattened in so both kinds accept the exact same fields.
+#[derive(CustomResource, Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[kube(
+    group = "cloudflare.ar2ro.io",
+    version = "v1",
+    kind = "ClusterTunnel",
+    doc = "Cluster scoped Cloudflare Tunnel that Ingresses in any namespace can reference",
+    scale = r#"{"specReplicasPath":".spec.replicas", "statusReplicasPath":".status.replicas"}"#,
+    status = "TunnelStatus",
+    printcolumn = r#"{"name":"UUID", "type":"string", "jsonPath":".spec.uuid"}"#,
+    printcolumn = r#"{"name":"Replicas", "type":"integer", "jsonPath":".spec.replicas"}"#,
+    printcolumn = r#"{"name":"Ready", "type":"integer", "jsonPath":".status.readyReplicas"}"#,
+    printcolumn = r#"{"name":"Credentials", "type":"string", "jsonPath":".spec.credentials"}"#,
+    printcolumn = r#"{"name":"Tunnel-URL", "type":"string", "jsonPath":".status.tunnelUrl"}"#,
+    printcolumn = r#"{"name":"Age", "type":"date", "jsonPath":".metadata.creationTimestamp"}"#,
+    shortname = "ctun",
+    category = "cloudflare"
+)]
+pub struct ClusterTunnelCrd {
+    #[serde(flatten)]
+    pub tunnel: TunnelCrd,
+}
+
+impl Deref for ClusterTunnelCrd {
+    type Target = TunnelCrd;
+
+    fn deref(&self) -> &TunnelCrd {
+        &self.tunnel
+    }
+}
+
+impl DerefMut for ClusterTunnelCrd {
+    fn deref_mut(&mut self) -> &mut TunnelCrd {
+        &mut self.tunnel
+    }
+}
+
+impl TunnelResource for ClusterTunnel {
+    fn tunnel_spec(&self) -> &TunnelCrd {
+        &self.spec
+    }
+
+    fn tunnel_spec_mut(&mut self) -> &mut TunnelCrd {
+        &mut self.spec
+    }
+
+    fn tunnel_status(&self) -> Option<&TunnelStatus> {
+        self.status.as_ref()
+    }
+
+    fn api(&self, kubernetes_client: kube::Client) -> Api<Self> {
+        Api::all(kubernetes_client)
+    }
+
+    // INFO: Prefixed so it can't collide with a namespaced Tunnel of the same name living in
+    // the cluster tunnel namespace.
+    fn child_name(&self) -> String {
+        format!("cluster-tunnel-{}", self.name_any())
+    }
+
+    // INFO: Prefixed the same way so it doesn't collide with the Cloudflare tunnel of a Tunnel
+    // of the same name, ClusterTunnels created before are renamed on their next sync.
+    fn cloudflare_name(&self) -> String {
+        format!("cluster-tunnel-{}", self.name_any())
+    }
+
+    fn child_namespace(&self, cluster_namespace: &str) -> String {
+        cluster_namespace.to_owned()
+    }
+}
@@ -1,16 +1,34 @@
+use crate::resources::patch_params;
 use crate::Error;
 use cloudflare::framework::auth::Credentials as CloudflareCredentials;
+use cloudflarext::cfd_tunnel::CloudflaredTunnel;
+use cloudflarext::failure::{ApiFailureExt, FailureKind};
+use k8s_openapi::api::core::v1::Secret;
+use k8s_openapi::chrono::Utc;
+use kube::api::Patch;
 use kube::Api;
 use kube_derive::CustomResource;
+use reqwest::StatusCode;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone, JsonSchema)]
 #[serde(rename_all = "camelCase")]
 pub enum AuthKind {
     UserAuthToken(String),
-    UserAuthKey { email: String, key: String },
+    UserAuthKey {
+        email: String,
+        key: String,
+    },
     ServiceKey(String),
+    /// Api token read from a key of a Secret at reconcile time so it never lands in the spec,
+    /// credentials are cluster scoped so the Secret's namespace has to be given.
+    SecretRef {
+        name: String,
+        namespace: String,
+        key: String,
+    },
 }
 
 #[derive(CustomResource, Serialize, Deserialize, Debug, PartialEq, Clone, JsonSchema)]
@@ -23,20 +41,102 @@ pub enum AuthKind {
     singular = "credentials",
     doc = "Custom resource representation of Cloudflare Credentials",
     derive = "PartialEq",
-    scale = r#"{"specReplicasPath":".spec.replicas", "statusReplicasPath":".status.replicas"}"#
+    scale = r#"{"specReplicasPath":".spec.replicas", "statusReplicasPath":".status.replicas"}"#,
+    status = "CredentialsStatus",
+    printcolumn = r#"{"name":"Account-ID", "type":"string", "jsonPath":".spec.accountId"}"#,
+    printcolumn = r#"{"name":"Valid", "type":"boolean", "jsonPath":".status.valid"}"#,
+    printcolumn = r#"{"name":"Age", "type":"date", "jsonPath":".metadata.creationTimestamp"}"#,
+    shortname = "cfcreds",
+    category = "cloudflare"
 )]
 pub struct CredentialsCrd {
     pub account_id: String,
     pub auth: AuthKind,
 }
 
+/// Outcome of the last use of the credentials against Cloudflare.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CredentialsStatus {
+    #[serde(default)]
+    pub valid: bool,
+    /// Set once a Cloudflare call for `accountId` succeeded with these credentials.
+    #[serde(default)]
+    pub account_id_verified: bool,
+    #[serde(default)]
+    pub last_validated: Option<String>,
+    #[serde(default)]
+    pub error: Option<String>,
+}
+
+impl CredentialsStatus {
+    fn verified(&self) -> bool {
+        self.valid && self.account_id_verified && self.error.is_none()
+    }
+}
+
+/// Lists a single tunnel of the account, the cheapest read that proves the credentials may
+/// manage its tunnels.
+pub async fn validate_credentials<C: CloudflaredTunnel>(
+    credentials: &CloudflareCredentials,
+    account_id: &str,
+    client: &C,
+) -> Result<(), Error> {
+    match client
+        .list_tunnels_paginated(credentials, account_id, 1, Some(1))
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => match err.kind() {
+            FailureKind::Api(StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN) => {
+                Err(Error::InvalidCredentials(format!(
+                    "Cloudflare refused them for account {}: {}",
+                    account_id, err
+                )))
+            }
+            _ => Err(Error::CloudflareApiFailure(err)),
+        },
+    }
+}
+
 #[allow(async_fn_in_trait)]
 pub trait CredentialsApiExt {
     async fn get_credentials(&self, name: &str) -> Result<(String, CloudflareCredentials), Error>;
+    /// Marks the credentials invalid after Cloudflare refused them with a 401 or 403.
+    async fn invalidate_credentials(
+        &self,
+        name: &str,
+        account_forbidden: bool,
+        message: String,
+    ) -> Result<(), Error>;
+    /// Records that Cloudflare accepted the credentials for their account, only called after a
+    /// Cloudflare call with them succeeded.
+    async fn verify_account(&self, name: &str) -> Result<(), Error>;
+}
+
+async fn patch_credentials_status(
+    credentials_api: &Api<Credentials>,
+    name: &str,
+    status: Value,
+) -> Result<(), Error> {
+    match credentials_api
+        .patch_status(
+            name,
+            &patch_params(),
+            &Patch::Merge(json!({ "status": status })),
+        )
+        .await
+    {
+        Ok(_) => Ok(()),
+        Err(err) => Err(Error::KubeError(err)),
+    }
 }
 
-impl From<Credentials> for (String, CloudflareCredentials) {
-    fn from(item: Credentials) -> (String, CloudflareCredentials) {
+// INFO: A SecretRef has to be resolved with `get_credentials` first, it needs a client.
+impl TryFrom<Credentials> for (String, CloudflareCredentials) {
+    type Error = Error;
+
+    fn try_from(item: Credentials) -> Result<(String, CloudflareCredentials), Error> {
         let account_id = item.spec.account_id;
 
         let credentials = match item.spec.auth {
@@ -45,17 +145,109 @@ impl From<Credentials> for (String, CloudflareCredentials) {
                 CloudflareCredentials::UserAuthKey { email, key }
             }
             AuthKind::ServiceKey(key) => CloudflareCredentials::Service { key },
+            AuthKind::SecretRef {
+                name, namespace, ..
+            } => {
+                return Err(Error::InvalidCredentials(format!(
+                    "secretRef {}/{} was not resolved",
+                    namespace, name
+                )))
+            }
         };
 
-        (account_id, credentials)
+        Ok((account_id, credentials))
+    }
+}
+
+async fn secret_token(
+    kubernetes_client: kube::Client,
+    name: &str,
+    namespace: &str,
+    key: &str,
+) -> Result<String, Error> {
+    let secret_api: Api<Secret> = Api::namespaced(kubernetes_client, namespace);
+    let secret = match secret_api.get_opt(name).await.map_err(Error::KubeError)? {
+        Some(secret) => secret,
+        None => return Err(Error::MissingCredentials(format!("{}/{}", namespace, name))),
+    };
+
+    let token = match secret.data.and_then(|mut data| data.remove(key)) {
+        Some(token) => token,
+        None => {
+            return Err(Error::InvalidCredentials(format!(
+                "secret {}/{} has no key {}",
+                namespace, name, key
+            )))
+        }
+    };
+
+    // INFO: Secrets created with `--from-file` usually end with a newline.
+    match String::from_utf8(token.0) {
+        Ok(token) => Ok(token.trim().to_owned()),
+        Err(_) => Err(Error::InvalidCredentials(format!(
+            "key {} of secret {}/{} is not valid utf-8",
+            key, namespace, name
+        ))),
     }
 }
 
 impl CredentialsApiExt for Api<Credentials> {
     async fn get_credentials(&self, name: &str) -> Result<(String, CloudflareCredentials), Error> {
-        match self.get_opt(name).await.map_err(Error::KubeError)? {
-            Some(credentials) => Ok(credentials.into()),
-            None => Err(Error::MissingCredentials(name.to_string())),
+        let mut credentials = match self.get_opt(name).await.map_err(Error::KubeError)? {
+            Some(credentials) => credentials,
+            None => return Err(Error::MissingCredentials(name.to_string())),
+        };
+
+        if let AuthKind::SecretRef {
+            name,
+            namespace,
+            key,
+        } = &credentials.spec.auth
+        {
+            let token = secret_token(self.clone().into_client(), name, namespace, key).await?;
+            credentials.spec.auth = AuthKind::UserAuthToken(token);
+        }
+
+        credentials.try_into()
+    }
+
+    async fn invalidate_credentials(
+        &self,
+        name: &str,
+        account_forbidden: bool,
+        message: String,
+    ) -> Result<(), Error> {
+        let mut status = json!({
+            "valid": false,
+            "lastValidated": Utc::now().to_rfc3339(),
+            "error": message,
+        });
+        if account_forbidden {
+            status["accountIdVerified"] = Value::Bool(false);
+        }
+
+        patch_credentials_status(self, name, status).await
+    }
+
+    async fn verify_account(&self, name: &str) -> Result<(), Error> {
+        let status = match self.get_opt(name).await.map_err(Error::KubeError)? {
+            Some(credentials) => credentials.status.unwrap_or_default(),
+            None => return Err(Error::MissingCredentials(name.to_string())),
+        };
+        if status.verified() {
+            return Ok(());
         }
+
+        patch_credentials_status(
+            self,
+            name,
+            json!({
+                "valid": true,
+                "accountIdVerified": true,
+                "lastValidated": Utc::now().to_rfc3339(),
+                "error": null,
+            }),
+        )
+        .await
     }
 }
@@ -1,2 +1,5 @@
+pub mod cluster_tunnel;
 pub mod credentials;
+pub mod status;
 pub mod tunnel;
+pub mod tunnel_ingress;
@@ -0,0 +1,299 @@
+//! Pieces shared by the status subresources of our CRDs.
+//!
+//! Status is written by whichever operator version ran last, so a rolled back operator has to
+//! read status written by a newer one. Rules for evolving a status struct:
+//!
+//! - Never use `deny_unknown_fields`, unknown fields written by a newer version are dropped.
+//! - Every field is `Option` or has a `Default` and is marked `#[serde(default)]`.
+//! - Every field goes through [`lenient`] so a field whose type changed decodes as its default
+//!   instead of failing the whole object.
+//! - Never change the meaning of an existing field, add a new one instead.
+use k8s_openapi::chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static STATUS_DECODE_FAILURES: AtomicU64 = AtomicU64::new(0);
+
+/// Number of status fields that couldn't be decoded and were treated as absent.
+pub fn status_decode_failures() -> u64 {
+    STATUS_DECODE_FAILURES.load(Ordering::Relaxed)
+}
+
+/// Decodes a status field, falling back to its default when the stored value doesn't match the
+/// type this version expects.
+pub fn lenient<'de, D, T>(deserializer: D) -> Result<T, D::Error>
+where
+    D: Deserializer<'de>,
+    T: DeserializeOwned + Default,
+{
+    let value = serde_json::Value::deserialize(deserializer)?;
+    match serde_json::from_value(value) {
+        Ok(value) => Ok(value),
+        Err(err) => {
+            STATUS_DECODE_FAILURES.fetch_add(1, Ordering::Relaxed);
+            println!("Ignoring undecodable status field: {}", err);
+            Ok(T::default())
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Condition {
+    #[serde(rename = "type")]
+    pub type_: String,
+    pub status: String,
+    #[serde(default)]
+    pub reason: Option<String>,
+    #[serde(default)]
+    pub message: Option<String>,
+    #[serde(default)]
+    pub last_transition_time: Option<String>,
+}
+
+impl Condition {
+    /// Replaces the condition of the same type, the transition time is only moved when the
+    /// status flips. Returns whether anything changed.
+    pub fn set(conditions: &mut Vec<Condition>, mut condition: Condition) -> bool {
+        match conditions.iter_mut().find(|c| c.type_ == condition.type_) {
+            Some(current) => {
+                if current.status == condition.status
+                    && current.reason == condition.reason
+                    && current.message == condition.message
+                {
+                    return false;
+                }
+
+                condition.last_transition_time = if current.status == condition.status {
+                    current.last_transition_time.take()
+                } else {
+                    Some(Utc::now().to_rfc3339())
+                };
+                *current = condition;
+            }
+            None => {
+                condition.last_transition_time = Some(Utc::now().to_rfc3339());
+                conditions.push(condition);
+            }
+        }
+
+        true
+    }
+}
+
+/// Steps from creation until traffic can flow, in the order they are expected to happen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Milestone {
+    Created,
+    TunnelProvisioned,
+    WorkloadReady,
+    ConfigApplied,
+    DnsReady,
+}
+
+impl Milestone {
+    pub const ALL: [Milestone; 5] = [
+        Milestone::Created,
+        Milestone::TunnelProvisioned,
+        Milestone::WorkloadReady,
+        Milestone::ConfigApplied,
+        Milestone::DnsReady,
+    ];
+}
+
+/// RFC 3339 timestamps of when each [`Milestone`] was first reached, they are never
+/// overwritten once set. Unset fields are skipped so a merge patch can't clear them.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Milestones {
+    #[serde(
+        default,
+        deserialize_with = "lenient",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub created: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "lenient",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub tunnel_provisioned: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "lenient",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub workload_ready: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "lenient",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub config_applied: Option<String>,
+    #[serde(
+        default,
+        deserialize_with = "lenient",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub dns_ready: Option<String>,
+    /// Seconds from creation until every milestone was reached.
+    #[serde(
+        default,
+        deserialize_with = "lenient",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub ready_after_seconds: Option<i64>,
+}
+
+/// Durations to observe after a milestone was reached for the first time.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MilestoneTransition {
+    pub milestone: Milestone,
+    pub since_previous_millis: Option<u64>,
+    pub time_to_ready_millis: Option<u64>,
+}
+
+fn parse_time(time: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(time)
+        .ok()
+        .map(|time| time.with_timezone(&Utc))
+}
+
+fn millis_between(from: DateTime<Utc>, to: DateTime<Utc>) -> u64 {
+    (to - from).num_milliseconds().max(0) as u64
+}
+
+impl Milestones {
+    fn field(&mut self, milestone: Milestone) -> &mut Option<String> {
+        match milestone {
+            Milestone::Created => &mut self.created,
+            Milestone::TunnelProvisioned => &mut self.tunnel_provisioned,
+            Milestone::WorkloadReady => &mut self.workload_ready,
+            Milestone::ConfigApplied => &mut self.config_applied,
+            Milestone::DnsReady => &mut self.dns_ready,
+        }
+    }
+
+    pub fn get(&self, milestone: Milestone) -> Option<&str> {
+        match milestone {
+            Milestone::Created => self.created.as_deref(),
+            Milestone::TunnelProvisioned => self.tunnel_provisioned.as_deref(),
+            Milestone::WorkloadReady => self.workload_ready.as_deref(),
+            Milestone::ConfigApplied => self.config_applied.as_deref(),
+            Milestone::DnsReady => self.dns_ready.as_deref(),
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        Milestone::ALL
+            .iter()
+            .all(|milestone| self.get(*milestone).is_some())
+    }
+
+    /// Records `milestone` at `at`, returns None without changing anything when it was already
+    /// reached so every milestone is written exactly once.
+    pub fn reach(
+        &mut self,
+        milestone: Milestone,
+        at: DateTime<Utc>,
+    ) -> Option<MilestoneTransition> {
+        if self.get(milestone).is_some() {
+            return None;
+        }
+
+        // INFO: Milestones can be reached out of order, the duration is taken from the latest
+        // one reached before this one.
+        let since_previous_millis = Milestone::ALL
+            .iter()
+            .take_while(|previous| **previous != milestone)
+            .filter_map(|previous| self.get(*previous).and_then(parse_time))
+            .max()
+            .map(|previous| millis_between(previous, at));
+
+        *self.field(milestone) = Some(at.to_rfc3339());
+
+        let mut time_to_ready_millis = None;
+        if self.is_ready() && self.ready_after_seconds.is_none() {
+            if let Some(created) = self.created.as_deref().and_then(parse_time) {
+                let millis = millis_between(created, at);
+                self.ready_after_seconds = Some((millis / 1000) as i64);
+                time_to_ready_millis = Some(millis);
+            }
+        }
+
+        Some(MilestoneTransition {
+            milestone,
+            since_previous_millis,
+            time_to_ready_millis,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crd::tunnel::TunnelStatus;
+    use serde_json::json;
+
+    #[test]
+    fn status_from_a_newer_version_drops_unknown_fields() {
+        let status: TunnelStatus = serde_json::from_value(json!({
+            "observedGeneration": 3,
+            "readyReplicas": 2,
+            "edgeLocations": ["ams01", "fra06"],
+            "milestones": {
+                "created": "2024-01-01T00:00:00+00:00",
+                "firstRequestServed": "2024-01-01T00:01:00+00:00",
+            },
+            "conditions": [{
+                "type": "Ready",
+                "status": "True",
+                "observedGeneration": 3,
+            }],
+        }))
+        .unwrap();
+
+        assert_eq!(status.observed_generation, Some(3));
+        assert_eq!(status.ready_replicas, Some(2));
+        assert_eq!(
+            status.milestones.created.as_deref(),
+            Some("2024-01-01T00:00:00+00:00")
+        );
+        assert_eq!(status.conditions[0].type_, "Ready");
+    }
+
+    #[test]
+    fn status_from_an_older_version_fills_in_defaults() {
+        let status: TunnelStatus = serde_json::from_value(json!({ "replicas": 1 })).unwrap();
+
+        assert_eq!(
+            status,
+            TunnelStatus {
+                replicas: Some(1),
+                ..TunnelStatus::default()
+            }
+        );
+    }
+
+    #[test]
+    fn field_whose_type_changed_decodes_as_absent() {
+        let failures = status_decode_failures();
+
+        let status: TunnelStatus = serde_json::from_value(json!({
+            "replicas": 2,
+            "readyReplicas": { "cloudflared": 2 },
+            "driftDetected": "yes",
+            "milestones": { "readyAfterSeconds": "12s" },
+        }))
+        .unwrap();
+
+        assert_eq!(status.replicas, Some(2));
+        assert_eq!(status.ready_replicas, None);
+        assert!(!status.drift_detected);
+        assert_eq!(status.milestones.ready_after_seconds, None);
+        // INFO: Other tests may decode status concurrently, so only a lower bound holds.
+        assert!(status_decode_failures() >= failures + 3);
+    }
+}
@@ -1,232 +1,1073 @@
-use k8s_openapi::api::apps::v1::{Deployment, DeploymentSpec};
-use k8s_openapi::apimachinery::pkg::{apis::meta::v1::LabelSelector, util::intstr::IntOrString};
+use crate::crd::status::{lenient, Condition, Milestones};
+use crate::crd::tunnel_ingress::{valid_service, SERVICE_SCHEMES};
+use crate::resources::patch_params;
+use crate::resources::{configmap, deployment, secret, serviceaccount, LOCAL_CONFIG_PATH};
+use crate::Error;
+use cloudflare::endpoints::cfd_tunnel::ConfigurationSrc;
+use cloudflare_controller_common::FINALIZER_NAME;
+use cloudflarext::tunnel_configuration::CATCH_ALL_SERVICE;
+use k8s_openapi::api::apps::v1::Deployment;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+use k8s_openapi::apimachinery::pkg::util::intstr::IntOrString;
 use k8s_openapi::{
     api::core::v1::{
-        Container, EnvFromSource, HTTPGetAction, PodSpec, PodTemplateSpec, Probe, Secret,
-        SecretEnvSource,
+        Capabilities, ConfigMap, EnvVar, HTTPGetAction, Lifecycle, LifecycleHandler,
+        LocalObjectReference, PodSecurityContext, Probe, SeccompProfile, Secret, SecurityContext,
+        ServiceAccount, SleepAction, TopologySpreadConstraint,
     },
-    ByteString,
+    ByteString, DeepMerge,
 };
-use kube::api::{DeleteParams, ObjectMeta, Patch, PatchParams, PostParams};
-use kube::{Api, CustomResource, ResourceExt};
+use kube::api::Patch;
+use kube::{Api, CELSchema, CustomResource, Resource, ResourceExt};
 use schemars::JsonSchema;
-use serde::{Deserialize, Serialize};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
 use std::collections::{BTreeMap, HashMap};
+use std::fmt::Debug;
 use uuid::Uuid;
 
-const FINALIZER_NAME: &str = "tunnel.cloudflare.ar2ro.io/finalizer";
+const DEFAULT_METRICS_PORT: i32 = 2000;
+const DEFAULT_TERMINATION_GRACE_PERIOD: i64 = 30;
+const DEFAULT_REPLICAS: i32 = 2;
+const UUID_IMMUTABLE_RULE: &str =
+    "!has(oldSelf.uuid) || (has(self.uuid) && self.uuid == oldSelf.uuid)";
+const UUID_IMMUTABLE_MESSAGE: &str =
+    "uuid can't be changed or removed once set, delete and recreate the tunnel to move it to another Cloudflare tunnel";
+// INFO: Cloudflare generates tunnel ids as version 4 uuids.
+const UUID_V4_PATTERN: &str =
+    "^[0-9a-fA-F]{8}-[0-9a-fA-F]{4}-4[0-9a-fA-F]{3}-[89abAB][0-9a-fA-F]{3}-[0-9a-fA-F]{12}$";
+// INFO: cloudflared releases are tagged `<year>.<month>.<patch>`.
+const CLOUDFLARED_VERSION_PATTERN: &str = "^[0-9]{4}\\.[0-9]+\\.[0-9]+$";
+// INFO: Region names end up in Deployment names and label values.
+const REGION_NAME_PATTERN: &str = "^[a-z0-9]([-a-z0-9]*[a-z0-9])?$";
+// INFO: The cloudflared image runs as the distroless `nonroot` user, it has to be set numerically
+// for the kubelet to verify runAsNonRoot.
+const NONROOT_UID: i64 = 65532;
+const ZONE_TOPOLOGY_KEY: &str = "topology.kubernetes.io/zone";
+// INFO: Gives the edge time to stop routing to a connector before cloudflared gets SIGTERM.
+const PRE_STOP_SLEEP: i64 = 5;
+// INFO: The operator owns how cloudflared is started and authenticated so these can't be passed
+// through extraArgs, the token is injected through the environment.
+const RESERVED_ARGS: [&str; 4] = ["run", "--token", "--metrics", "--config"];
+// INFO: An env entry wins over envFrom, this one would replace the token the operator injects.
+const RESERVED_ENV: [&str; 1] = ["TUNNEL_TOKEN"];
 
-#[derive(CustomResource, Serialize, Deserialize, Debug, Clone, JsonSchema)]
+// INFO: The controller writes uuid back once the Cloudflare tunnel exists, changing or removing
+// it afterwards would create a second tunnel and leak the first one.
+#[derive(CustomResource, CELSchema, Serialize, Deserialize, Debug, Clone)]
 #[serde(rename_all = "camelCase")]
+#[cel_validate(rule = Rule::new(UUID_IMMUTABLE_RULE).message(UUID_IMMUTABLE_MESSAGE))]
 #[kube(
     group = "cloudflare.ar2ro.io",
     version = "v1",
     kind = "Tunnel",
     doc = "Custom resource representation of a Cloudflare Tunnel",
     scale = r#"{"specReplicasPath":".spec.replicas", "statusReplicasPath":".status.replicas"}"#,
+    status = "TunnelStatus",
+    printcolumn = r#"{"name":"UUID", "type":"string", "jsonPath":".spec.uuid"}"#,
+    printcolumn = r#"{"name":"Replicas", "type":"integer", "jsonPath":".spec.replicas"}"#,
+    printcolumn = r#"{"name":"Ready", "type":"integer", "jsonPath":".status.readyReplicas"}"#,
+    printcolumn = r#"{"name":"Credentials", "type":"string", "jsonPath":".spec.credentials"}"#,
+    printcolumn = r#"{"name":"Tunnel-URL", "type":"string", "jsonPath":".status.tunnelUrl"}"#,
+    printcolumn = r#"{"name":"Age", "type":"date", "jsonPath":".metadata.creationTimestamp"}"#,
+    shortname = "tun",
+    category = "cloudflare",
     namespaced
 )]
 pub struct TunnelCrd {
+    #[schemars(regex = "UUID_V4_PATTERN")]
     pub uuid: Option<Uuid>,
+    #[serde(default = "default_replicas")]
+    #[schemars(range(min = 0))]
     pub replicas: i32,
+    #[schemars(length(min = 1))]
     pub credentials: String,
     #[serde(default)]
+    #[schemars(length(min = 1))]
     pub image: Option<String>,
+    /// cloudflared release to run, replaces the tag of the default image and takes precedence
+    /// over `image` so restarts don't pick up whatever `latest` points at.
+    #[serde(default)]
+    #[schemars(regex = "CLOUDFLARED_VERSION_PATTERN")]
+    pub version: Option<String>,
     #[serde(default)]
     pub tunnel_secret: Option<String>,
     pub tags: Option<HashMap<String, String>>,
+    #[serde(default)]
+    pub metrics_port: Option<i32>,
+    #[serde(default)]
+    pub extra_args: Vec<String>,
+    /// Transport cloudflared connects to Cloudflare with, `Http2` for networks that block the
+    /// UDP traffic of QUIC.
+    #[serde(default)]
+    pub tunnel_protocol: Option<TunnelProtocol>,
+    /// Extra environment variables of the cloudflared container, e.g. `TUNNEL_LOGLEVEL`.
+    #[serde(default)]
+    pub env: Vec<EnvVar>,
+    #[serde(default)]
+    pub probes: Option<TunnelProbes>,
+    #[serde(default)]
+    pub termination_grace_period_seconds: Option<i64>,
+    #[serde(default)]
+    pub image_pull_policy: Option<ImagePullPolicy>,
+    #[serde(default)]
+    pub image_pull_secrets: Vec<LocalObjectReference>,
+    #[serde(default)]
+    pub service_account_name: Option<String>,
+    #[serde(default)]
+    pub priority_class_name: Option<String>,
+    /// Spreads the pods across zones, on by default when running more than one replica.
+    #[serde(default)]
+    pub topology_spread: Option<bool>,
+    #[serde(default)]
+    pub pod_security_context: Option<PodSecurityContext>,
+    #[serde(default)]
+    pub container_security_context: Option<SecurityContext>,
+    #[serde(default)]
+    pub reconcile_policy: Option<ReconcilePolicy>,
+    #[serde(default)]
+    pub configuration_source: Option<ConfigurationSource>,
+    /// Short spelling of `configurationSource`, the two have to agree when both are set.
+    #[serde(default)]
+    pub config_source: Option<ConfigurationSource>,
+    #[serde(default)]
+    pub hibernate: bool,
+    #[serde(default)]
+    pub hibernate_mode: Option<HibernateMode>,
+    /// Drops remaining connections when the tunnel is deleted, when false the delete fails
+    /// until every connector is gone.
+    #[serde(default = "default_delete_cascade")]
+    pub delete_cascade: bool,
+    #[serde(default)]
+    pub deletion_policy: Option<DeletionPolicy>,
+    /// Runs one cloudflared Deployment per region instead of a single one, all of them connect
+    /// with the same tunnel token. `replicas` is ignored while this is set.
+    #[serde(default)]
+    pub regions: Option<Vec<TunnelRegion>>,
+    /// Added to the Deployment, Secret and ConfigMap of the tunnel.
+    #[serde(default)]
+    pub annotations: Option<BTreeMap<String, String>>,
+    /// Added to the cloudflared pods, e.g. `cluster-autoscaler.kubernetes.io/safe-to-evict`.
+    #[serde(default)]
+    pub pod_annotations: Option<BTreeMap<String, String>>,
+    /// Last rule of the tunnel configuration for requests no other rule matches, a service url
+    /// or `http_status:<code>`. `http_status:404` when unset.
+    #[serde(default)]
+    pub catch_all_service: Option<String>,
+}
+
+/// A cloudflared Deployment named `<tunnel>-<clusterName>` pinned to the nodes of one region.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TunnelRegion {
+    #[schemars(regex = "REGION_NAME_PATTERN", length(max = 40))]
+    pub cluster_name: String,
+    #[serde(default = "default_replicas")]
+    #[schemars(range(min = 0))]
+    pub replicas: i32,
+    #[serde(default)]
+    pub node_selector: BTreeMap<String, String>,
+}
+
+fn default_replicas() -> i32 {
+    DEFAULT_REPLICAS
+}
+
+fn default_delete_cascade() -> bool {
+    true
+}
+
+/// Where cloudflared gets its ingress rules from.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum ConfigurationSource {
+    /// Remotely managed, cloudflared authenticates with the tunnel token.
+    #[default]
+    Cloudflare,
+    /// A `config.yaml` rendered from TunnelIngress objects and a credentials file, both
+    /// mounted into the pod.
+    Local,
+}
+
+impl From<ConfigurationSource> for ConfigurationSrc {
+    fn from(source: ConfigurationSource) -> ConfigurationSrc {
+        match source {
+            ConfigurationSource::Cloudflare => ConfigurationSrc::Cloudflare,
+            ConfigurationSource::Local => ConfigurationSrc::Local,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+pub enum ReconcilePolicy {
+    /// Drift is corrected by re-applying the desired state.
+    #[default]
+    AutoSync,
+    /// Drift is only reported through a Warning event and `status.driftDetected`.
+    DriftWarnOnly,
+}
+
+/// What happens to the Cloudflare tunnel when the object is deleted, the Kubernetes resources
+/// are removed either way.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+pub enum DeletionPolicy {
+    #[default]
+    Delete,
+    /// The tunnel and its configuration are left on Cloudflare.
+    Retain,
+}
+
+/// What happens to the cloudflared Deployment while the tunnel hibernates, the Cloudflare
+/// tunnel and its Secret are kept either way.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+pub enum HibernateMode {
+    #[default]
+    ScaleToZero,
+    Delete,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+pub enum TunnelProtocol {
+    Quic,
+    Http2,
+    /// cloudflared picks the protocol itself, QUIC with a fallback to HTTP/2.
+    #[default]
+    Auto,
+}
+
+impl TunnelProtocol {
+    /// Value of `--protocol`, `None` when cloudflared decides.
+    pub fn as_arg(&self) -> Option<&'static str> {
+        match self {
+            TunnelProtocol::Quic => Some("quic"),
+            TunnelProtocol::Http2 => Some("http2"),
+            TunnelProtocol::Auto => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+pub enum ImagePullPolicy {
+    Always,
+    #[default]
+    IfNotPresent,
+    Never,
+}
+
+impl ImagePullPolicy {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ImagePullPolicy::Always => "Always",
+            ImagePullPolicy::IfNotPresent => "IfNotPresent",
+            ImagePullPolicy::Never => "Never",
+        }
+    }
+}
+
+/// Follows the evolution rules in [`crate::crd::status`], an older operator must be able to
+/// read whatever a newer one wrote here.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TunnelStatus {
+    #[serde(default, deserialize_with = "lenient")]
+    pub observed_generation: Option<i64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub replicas: Option<i32>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub ready_replicas: Option<i32>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub conditions: Vec<Condition>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub drift_detected: bool,
+    #[serde(default, deserialize_with = "lenient")]
+    pub milestones: Milestones,
+    /// Id of the Cloudflare tunnel this object created, written before spec.uuid so a failed
+    /// spec patch doesn't lead to a second tunnel.
+    #[serde(default, deserialize_with = "lenient")]
+    pub tunnel_id: Option<Uuid>,
+    /// Set right before asking Cloudflare to create the tunnel, a later pass that finds it
+    /// without a `tunnelId` looks the tunnel up by name instead of creating another one.
+    #[serde(default, deserialize_with = "lenient")]
+    pub create_requested_at: Option<String>,
+    /// Hash of the spec.tunnelSecret the Cloudflare tunnel currently uses, a different one
+    /// rotates the secret.
+    #[serde(default, deserialize_with = "lenient")]
+    pub tunnel_secret_hash: Option<String>,
+    /// RFC 3339 time the tunnel token was last checked against Cloudflare.
+    #[serde(default, deserialize_with = "lenient")]
+    pub last_cloudflare_sync: Option<String>,
+    /// Edge connections Cloudflare lists for the tunnel's connectors.
+    #[serde(default, deserialize_with = "lenient")]
+    pub active_connections: Option<i32>,
+    /// Name of the Cloudflare tunnel, kept equal to metadata.name.
+    #[serde(default, deserialize_with = "lenient")]
+    pub cloudflare_name: Option<String>,
+    /// `<uuid>.cfargotunnel.com`, the target DNS records point at to route through the tunnel.
+    #[serde(default, deserialize_with = "lenient")]
+    pub tunnel_url: Option<String>,
+}
+
+pub fn tunnel_url(tunnel_id: Uuid) -> String {
+    format!("{}.cfargotunnel.com", tunnel_id)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TunnelProbes {
+    #[serde(default)]
+    pub liveness: Option<ProbeOverrides>,
+    #[serde(default)]
+    pub readiness: Option<ProbeOverrides>,
+}
+
+/// Timing overrides for a probe, the http check against cloudflared's `/ready` is fixed.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ProbeOverrides {
+    #[serde(default)]
+    pub initial_delay_seconds: Option<i32>,
+    #[serde(default)]
+    pub period_seconds: Option<i32>,
+    #[serde(default)]
+    pub timeout_seconds: Option<i32>,
+    #[serde(default)]
+    pub failure_threshold: Option<i32>,
+    #[serde(default)]
+    pub success_threshold: Option<i32>,
+}
+
+impl ProbeOverrides {
+    fn apply(&self, mut probe: Probe) -> Probe {
+        probe.initial_delay_seconds = self.initial_delay_seconds.or(probe.initial_delay_seconds);
+        probe.period_seconds = self.period_seconds.or(probe.period_seconds);
+        probe.timeout_seconds = self.timeout_seconds.or(probe.timeout_seconds);
+        probe.failure_threshold = self.failure_threshold.or(probe.failure_threshold);
+        probe.success_threshold = self.success_threshold.or(probe.success_threshold);
+        probe
+    }
 }
 
 pub struct Resources {
     pub deployment: Deployment,
     pub secret: Secret,
+    pub config_map: Option<ConfigMap>,
+    pub service_account: Option<ServiceAccount>,
 }
 
-impl Tunnel {
+/// Common view over [`Tunnel`] and
+/// [`ClusterTunnel`](crate::crd::cluster_tunnel::ClusterTunnel) so both kinds share the same
+/// reconcile logic.
+pub trait TunnelResource:
+    Resource<DynamicType = ()> + Clone + Debug + Serialize + DeserializeOwned + Send + Sync + 'static
+{
+    fn tunnel_spec(&self) -> &TunnelCrd;
+
+    fn tunnel_spec_mut(&mut self) -> &mut TunnelCrd;
+
+    fn tunnel_status(&self) -> Option<&TunnelStatus>;
+
+    /// Api the tunnel object itself is patched through.
+    fn api(&self, kubernetes_client: kube::Client) -> Api<Self>;
+
+    /// Name of the cloudflared Deployment and Secret.
+    fn child_name(&self) -> String;
+
+    /// Name of the tunnel on Cloudflare, unique per account rather than per namespace.
+    fn cloudflare_name(&self) -> String;
+
+    /// Namespace the cloudflared Deployment and Secret are created in, cluster scoped tunnels
+    /// use `cluster_namespace`.
+    fn child_namespace(&self, cluster_namespace: &str) -> String;
+
     #[inline]
-    pub fn get_uuid(&self) -> Option<uuid::Uuid> {
-        self.spec.uuid
+    fn get_uuid(&self) -> Option<Uuid> {
+        self.tunnel_spec().uuid
     }
 
-    pub async fn create_resources(
-        &self,
-        kubernetes_client: kube::Client,
-        labels: BTreeMap<String, String>,
-        secrets: BTreeMap<String, ByteString>,
-    ) -> Result<Resources, kube::Error> {
-        let name = self.name_any();
-        let namespace = self.metadata.namespace.clone().unwrap();
-        let postparams = PostParams::default();
-
-        let secret = Secret {
-            metadata: ObjectMeta {
-                name: Some(self.name_any()),
-                namespace: Some(namespace.clone()),
-                labels: Some(labels.clone()),
-                ..ObjectMeta::default()
-            },
-            data: Some(secrets),
-            ..Secret::default()
-        };
+    /// ServiceAccount the cloudflared pods run as, generated unless the spec names one.
+    fn service_account_name(&self) -> String {
+        match &self.tunnel_spec().service_account_name {
+            Some(name) => name.to_owned(),
+            None => serviceaccount::generated_name(self),
+        }
+    }
+}
 
-        let image = match &self.spec.image {
-            Some(image) => image.to_owned(),
-            None => "cloudflare/cloudflared:latest".to_owned(),
-        };
+impl TunnelResource for Tunnel {
+    fn tunnel_spec(&self) -> &TunnelCrd {
+        &self.spec
+    }
 
-        let env = vec![EnvFromSource {
-            secret_ref: Some(SecretEnvSource {
-                name: name.clone(),
-                optional: Some(false),
-            }),
-            ..EnvFromSource::default()
-        }];
+    fn tunnel_spec_mut(&mut self) -> &mut TunnelCrd {
+        &mut self.spec
+    }
+
+    fn tunnel_status(&self) -> Option<&TunnelStatus> {
+        self.status.as_ref()
+    }
+
+    fn api(&self, kubernetes_client: kube::Client) -> Api<Self> {
+        Api::namespaced(
+            kubernetes_client,
+            self.metadata.namespace.clone().unwrap().as_ref(),
+        )
+    }
+
+    fn child_name(&self) -> String {
+        self.name_any()
+    }
+
+    fn cloudflare_name(&self) -> String {
+        self.name_any()
+    }
+
+    fn child_namespace(&self, _cluster_namespace: &str) -> String {
+        self.metadata.namespace.clone().unwrap()
+    }
+}
+
+impl TunnelCrd {
+    #[inline]
+    pub fn configuration_source(&self) -> ConfigurationSource {
+        self.config_source
+            .or(self.configuration_source)
+            .unwrap_or_default()
+    }
+
+    #[inline]
+    pub fn is_local(&self) -> bool {
+        self.configuration_source() == ConfigurationSource::Local
+    }
+
+    /// Hash of `tunnel_secret` kept in status, no secret hashes to the hash of an empty one.
+    pub fn tunnel_secret_hash(&self) -> String {
+        let secret = self.tunnel_secret.as_deref().unwrap_or_default();
+        format!("{:x}", Sha256::digest(secret.as_bytes()))
+    }
+
+    #[inline]
+    pub fn metrics_port(&self) -> i32 {
+        self.metrics_port.unwrap_or(DEFAULT_METRICS_PORT)
+    }
+
+    pub fn regions(&self) -> &[TunnelRegion] {
+        self.regions.as_deref().unwrap_or_default()
+    }
+
+    #[inline]
+    pub fn catch_all_service(&self) -> &str {
+        self.catch_all_service
+            .as_deref()
+            .unwrap_or(CATCH_ALL_SERVICE)
+    }
+
+    pub fn validate(&self) -> Result<(), Error> {
+        if let Some(port) = self.metrics_port {
+            if !(1..=65535).contains(&port) {
+                return Err(Error::InvalidSpec(format!(
+                    "metricsPort {} is not a valid port",
+                    port
+                )));
+            }
+        }
+
+        for arg in &self.extra_args {
+            let flag = arg.split('=').next().unwrap_or_default();
+            if RESERVED_ARGS.contains(&flag) {
+                return Err(Error::InvalidSpec(format!(
+                    "extraArgs can't contain {}, it is managed by the operator",
+                    flag
+                )));
+            }
+        }
 
+        if let Some(env) = self
+            .env
+            .iter()
+            .find(|env| RESERVED_ENV.contains(&env.name.as_str()))
+        {
+            return Err(Error::InvalidSpec(format!(
+                "env can't contain {}, it is managed by the operator",
+                env.name
+            )));
+        }
+
+        if !valid_service(self.catch_all_service()) {
+            return Err(Error::InvalidSpec(format!(
+                "catchAllService {:?} must be <{}>://<host>, unix:<path> or http_status:<code>",
+                self.catch_all_service(),
+                SERVICE_SCHEMES.join("|")
+            )));
+        }
+
+        if let (Some(config_source), Some(configuration_source)) =
+            (self.config_source, self.configuration_source)
+        {
+            if config_source != configuration_source {
+                return Err(Error::InvalidSpec(format!(
+                    "configSource {:?} and configurationSource {:?} disagree",
+                    config_source, configuration_source
+                )));
+            }
+        }
+
+        let regions = self.regions();
+        for (index, region) in regions.iter().enumerate() {
+            if regions[..index]
+                .iter()
+                .any(|other| other.cluster_name == region.cluster_name)
+            {
+                return Err(Error::InvalidSpec(format!(
+                    "regions has {} more than once",
+                    region.cluster_name
+                )));
+            }
+        }
+
+        Ok(())
+    }
+
+    fn ready_probe(&self, overrides: Option<&ProbeOverrides>) -> Probe {
         let probe = Probe {
             http_get: Some(HTTPGetAction {
-                port: IntOrString::Int(2000),
+                port: IntOrString::Int(self.metrics_port()),
                 path: Some("/ready".to_owned()),
                 ..HTTPGetAction::default()
             }),
             ..Probe::default()
         };
 
-        let deployment = Deployment {
-            metadata: ObjectMeta {
-                name: Some(name.to_owned()),
-                namespace: Some(namespace.to_owned()),
-                labels: Some(labels.clone()),
-                ..ObjectMeta::default()
-            },
-            spec: Some(DeploymentSpec {
-                replicas: Some(self.spec.replicas),
-                selector: LabelSelector {
-                    match_labels: Some(labels.clone()),
-                    ..LabelSelector::default()
-                },
-                template: PodTemplateSpec {
-                    metadata: Some(ObjectMeta {
-                        name: Some(name.to_owned()),
-                        namespace: Some(namespace.to_owned()),
-                        labels: Some(labels.clone()),
-                        ..ObjectMeta::default()
-                    }),
-                    spec: Some(PodSpec {
-                        containers: vec![Container {
-                            name: "cloudflared".to_owned(),
-                            image: Some(image),
-                            env_from: Some(env),
-                            command: Some(vec![
-                                "cloudflared".into(),
-                                "tunnel".into(),
-                                "--no-autoupdate".into(),
-                                "--metrics".into(),
-                                "0.0.0.0:2000".into(),
-                                "run".into(),
-                            ]),
-                            liveness_probe: Some(probe),
-                            ..Container::default()
-                        }],
-                        ..PodSpec::default()
-                    }),
-                },
-                ..DeploymentSpec::default()
-            }),
-            ..Deployment::default()
-        };
+        match overrides {
+            Some(overrides) => overrides.apply(probe),
+            None => probe,
+        }
+    }
 
-        let deployment_api: Api<Deployment> =
-            Api::namespaced(kubernetes_client.clone(), &namespace);
+    // INFO: cloudflared takes a while to register its connections, without a delay a slow start
+    // gets the pod restarted.
+    pub fn liveness_probe(&self) -> Probe {
+        let overrides = self.probes.as_ref().and_then(|p| p.liveness.as_ref());
+        let probe = self.ready_probe(overrides);
 
-        let deployment = match deployment_api.create(&postparams, &deployment).await {
-            Ok(deployment) => deployment,
-            Err(err) => return Err(err),
-        };
+        Probe {
+            initial_delay_seconds: probe.initial_delay_seconds.or(Some(10)),
+            period_seconds: probe.period_seconds.or(Some(10)),
+            failure_threshold: probe.failure_threshold.or(Some(3)),
+            ..probe
+        }
+    }
 
-        let secret_api: Api<Secret> = Api::namespaced(kubernetes_client.clone(), &namespace);
-        let secret = match secret_api.create(&postparams, &secret).await {
-            Ok(secret) => secret,
-            Err(err) => return Err(err),
-        };
+    pub fn readiness_probe(&self) -> Probe {
+        let overrides = self.probes.as_ref().and_then(|p| p.readiness.as_ref());
+        self.ready_probe(overrides)
+    }
+
+    #[inline]
+    pub fn termination_grace_period(&self) -> i64 {
+        self.termination_grace_period_seconds
+            .unwrap_or(DEFAULT_TERMINATION_GRACE_PERIOD)
+    }
 
-        Ok(Resources { deployment, secret })
+    // INFO: The cloudflared image has no shell so the native sleep hook is used.
+    pub(crate) fn lifecycle(&self) -> Lifecycle {
+        Lifecycle {
+            pre_stop: Some(LifecycleHandler {
+                sleep: Some(SleepAction {
+                    seconds: PRE_STOP_SLEEP.min(self.termination_grace_period()),
+                }),
+                ..LifecycleHandler::default()
+            }),
+            ..Lifecycle::default()
+        }
     }
 
-    pub async fn delete_resources(
+    // INFO: ScheduleAnyway so clusters with a single zone, or without zone labels, still run
+    // every replica.
+    pub fn topology_spread_constraints(
         &self,
-        kubernetes_client: kube::Client,
-    ) -> Result<(), kube::Error> {
-        let name = self.name_any();
-        let namespace = self.metadata.namespace.clone().unwrap();
-        let deleteparams = DeleteParams::default();
+        labels: &BTreeMap<String, String>,
+    ) -> Option<Vec<TopologySpreadConstraint>> {
+        if !self.topology_spread.unwrap_or(self.replicas > 1) {
+            return None;
+        }
 
-        let deployment_api: Api<Secret> = Api::namespaced(kubernetes_client.clone(), &namespace);
+        Some(vec![TopologySpreadConstraint {
+            topology_key: ZONE_TOPOLOGY_KEY.to_owned(),
+            max_skew: 1,
+            when_unsatisfiable: "ScheduleAnyway".to_owned(),
+            label_selector: Some(LabelSelector {
+                match_labels: Some(labels.clone()),
+                ..LabelSelector::default()
+            }),
+            ..TopologySpreadConstraint::default()
+        }])
+    }
 
-        if let Err(err) = deployment_api.delete(&name, &deleteparams).await {
-            return Err(err);
+    // INFO: Defaults satisfy the `restricted` pod security standard, fields set on the spec win.
+    pub fn pod_security_context(&self) -> PodSecurityContext {
+        let mut context = PodSecurityContext {
+            run_as_non_root: Some(true),
+            run_as_user: Some(NONROOT_UID),
+            run_as_group: Some(NONROOT_UID),
+            seccomp_profile: Some(SeccompProfile {
+                type_: "RuntimeDefault".to_owned(),
+                ..SeccompProfile::default()
+            }),
+            ..PodSecurityContext::default()
         };
 
-        let secret_api: Api<Secret> = Api::namespaced(kubernetes_client.clone(), &namespace);
-        if let Err(err) = secret_api.delete(&name, &deleteparams).await {
-            return Err(err);
+        if let Some(overrides) = &self.pod_security_context {
+            context.merge_from(overrides.clone());
+        }
+
+        context
+    }
+
+    pub fn container_security_context(&self) -> SecurityContext {
+        let mut context = SecurityContext {
+            allow_privilege_escalation: Some(false),
+            read_only_root_filesystem: Some(true),
+            run_as_non_root: Some(true),
+            capabilities: Some(Capabilities {
+                drop: Some(vec!["ALL".to_owned()]),
+                ..Capabilities::default()
+            }),
+            seccomp_profile: Some(SeccompProfile {
+                type_: "RuntimeDefault".to_owned(),
+                ..SeccompProfile::default()
+            }),
+            ..SecurityContext::default()
         };
 
-        Ok(())
+        if let Some(overrides) = &self.container_security_context {
+            context.merge_from(overrides.clone());
+        }
+
+        context
     }
 
-    pub async fn add_finalizer(
-        &self,
-        kubernetes_client: kube::Client,
-    ) -> Result<Tunnel, kube::Error> {
-        let tunnel_api: Api<Tunnel> = Api::namespaced(
-            kubernetes_client.clone(),
-            self.metadata.namespace.clone().unwrap().as_ref(),
+    pub fn protocol_arg(&self) -> Option<&'static str> {
+        self.tunnel_protocol.unwrap_or_default().as_arg()
+    }
+
+    // INFO: Extra args are passed to the run subcommand, e.g. `--edge-ip-version 4` or
+    // `--loglevel debug`.
+    pub fn container_command(&self) -> Vec<String> {
+        let mut command: Vec<String> = vec![
+            "cloudflared".into(),
+            "tunnel".into(),
+            "--no-autoupdate".into(),
+            "--metrics".into(),
+            format!("0.0.0.0:{}", self.metrics_port()),
+        ];
+        if self.is_local() {
+            command.extend(["--config".into(), LOCAL_CONFIG_PATH.into()]);
+        }
+        if let Some(protocol) = self.protocol_arg() {
+            command.extend(["--protocol".into(), protocol.into()]);
+        }
+        command.push("run".into());
+        command.extend(self.extra_args.iter().cloned());
+        command
+    }
+}
+
+// INFO: These are free functions instead of trait methods so their futures stay Send when
+// called from the generic reconciler.
+pub async fn apply_resources<K: TunnelResource>(
+    tunnel: &K,
+    kubernetes_client: kube::Client,
+    namespace: &str,
+    labels: BTreeMap<String, String>,
+    secrets: BTreeMap<String, ByteString>,
+    config: Option<BTreeMap<String, String>>,
+    image: &str,
+) -> Result<Resources, kube::Error> {
+    let token_hash = secret::data_hash(&secrets);
+    let config_hash = config.as_ref().map(configmap::data_hash);
+
+    let service_account =
+        serviceaccount::apply(kubernetes_client.clone(), tunnel, namespace, labels.clone()).await?;
+    let secret = secret::apply(
+        kubernetes_client.clone(),
+        tunnel,
+        namespace,
+        labels.clone(),
+        secrets,
+    )
+    .await?;
+    let config_map = match config {
+        Some(config) => Some(
+            configmap::apply(
+                kubernetes_client.clone(),
+                tunnel,
+                namespace,
+                labels.clone(),
+                config,
+            )
+            .await?,
+        ),
+        None => None,
+    };
+    let deployment = deployment::apply(
+        kubernetes_client,
+        tunnel,
+        namespace,
+        labels,
+        &token_hash,
+        config_hash.as_deref(),
+        image,
+    )
+    .await?;
+
+    Ok(Resources {
+        deployment,
+        secret,
+        config_map,
+        service_account,
+    })
+}
+
+pub async fn delete_resources<K: TunnelResource>(
+    tunnel: &K,
+    kubernetes_client: kube::Client,
+    namespace: &str,
+) -> Result<(), kube::Error> {
+    deployment::delete(kubernetes_client.clone(), tunnel, namespace).await?;
+    configmap::delete(kubernetes_client.clone(), tunnel, namespace).await?;
+    secret::delete(kubernetes_client.clone(), tunnel, namespace).await?;
+    serviceaccount::delete(kubernetes_client, tunnel, namespace).await
+}
+
+// INFO: A tunnel re-processed after a restart may already carry the finalizer, it isn't patched
+// again then.
+pub async fn add_finalizer<K: TunnelResource>(
+    tunnel: &K,
+    kubernetes_client: kube::Client,
+) -> Result<K, kube::Error> {
+    if tunnel
+        .finalizers()
+        .iter()
+        .any(|finalizer| finalizer == FINALIZER_NAME)
+    {
+        return Ok(tunnel.clone());
+    }
+
+    let mut finalizers = tunnel.finalizers().to_vec();
+    finalizers.push(FINALIZER_NAME.to_owned());
+    patch_finalizers(tunnel, kubernetes_client, finalizers).await
+}
+
+// INFO: Merge patches only the given status fields so writers of other fields don't race.
+pub async fn patch_status<K: TunnelResource>(
+    tunnel: &K,
+    kubernetes_client: kube::Client,
+    status: Value,
+) -> Result<K, kube::Error> {
+    let tunnel_api = tunnel.api(kubernetes_client);
+
+    let patch: Value = json!({ "status": status });
+    tunnel_api
+        .patch_status(
+            tunnel.name_any().as_ref(),
+            &patch_params(),
+            &Patch::Merge(&patch),
+        )
+        .await
+}
+
+pub async fn remove_finalizer<K: TunnelResource>(
+    tunnel: &K,
+    kubernetes_client: kube::Client,
+) -> Result<K, kube::Error> {
+    let finalizers = tunnel
+        .finalizers()
+        .iter()
+        .filter(|finalizer| *finalizer != FINALIZER_NAME)
+        .cloned()
+        .collect();
+    patch_finalizers(tunnel, kubernetes_client, finalizers).await
+}
+
+// INFO: Finalizers of other controllers are kept, the resourceVersion turns a concurrent change
+// of the list into a conflict instead of dropping it.
+async fn patch_finalizers<K: TunnelResource>(
+    tunnel: &K,
+    kubernetes_client: kube::Client,
+    finalizers: Vec<String>,
+) -> Result<K, kube::Error> {
+    let tunnel_api = tunnel.api(kubernetes_client);
+
+    let patch: Value = json!({
+        "metadata": {
+            "resourceVersion": tunnel.resource_version(),
+            "finalizers": finalizers,
+        }
+    });
+
+    let patch: Patch<&Value> = Patch::Merge(&patch);
+    tunnel_api
+        .patch(tunnel.name_any().as_ref(), &patch_params(), &patch)
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kube::CustomResourceExt;
+
+    fn spec(fields: Value) -> TunnelCrd {
+        let mut spec = json!({ "credentials": "creds" });
+        spec.as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+        serde_json::from_value(spec).unwrap()
+    }
+
+    fn probe_port(probe: &Probe) -> &IntOrString {
+        &probe.http_get.as_ref().unwrap().port
+    }
+
+    #[test]
+    fn metrics_port_and_extra_args_reach_the_command_and_probes() {
+        let spec = spec(json!({
+            "metricsPort": 3000,
+            "extraArgs": ["--edge-ip-version", "6"],
+        }));
+
+        assert_eq!(
+            spec.container_command(),
+            [
+                "cloudflared",
+                "tunnel",
+                "--no-autoupdate",
+                "--metrics",
+                "0.0.0.0:3000",
+                "run",
+                "--edge-ip-version",
+                "6",
+            ]
         );
+        assert_eq!(probe_port(&spec.liveness_probe()), &IntOrString::Int(3000));
+        assert_eq!(probe_port(&spec.readiness_probe()), &IntOrString::Int(3000));
+    }
 
-        let patch: Value = json!({
-            "metadata": {
-                "finalizers": [FINALIZER_NAME]
-            }
-        });
+    #[test]
+    fn metrics_port_defaults_to_2000() {
+        let spec = spec(json!({}));
 
-        let patch: Patch<&Value> = Patch::Merge(&patch);
-        match tunnel_api
-            .patch(self.name_any().as_ref(), &PatchParams::default(), &patch)
-            .await
-        {
-            Ok(tunnel) => Ok(tunnel),
-            Err(err) => Err(err),
+        assert!(spec
+            .container_command()
+            .contains(&"0.0.0.0:2000".to_owned()));
+        assert_eq!(probe_port(&spec.liveness_probe()), &IntOrString::Int(2000));
+    }
+
+    #[test]
+    fn extra_args_cant_replace_managed_flags() {
+        for arg in [
+            "run",
+            "--token",
+            "--token=abc",
+            "--metrics=0.0.0.0:1",
+            "--config",
+        ] {
+            let spec = spec(json!({ "extraArgs": [arg] }));
+            assert!(
+                matches!(spec.validate(), Err(Error::InvalidSpec(_))),
+                "{} was accepted",
+                arg
+            );
         }
+
+        assert!(spec(json!({ "extraArgs": ["--loglevel", "debug"] }))
+            .validate()
+            .is_ok());
     }
 
-    pub async fn remove_finalizer(
-        &self,
-        kubernetes_client: kube::Client,
-    ) -> Result<Tunnel, kube::Error> {
-        let tunnel_api: Api<Tunnel> = Api::namespaced(
-            kubernetes_client.clone(),
-            self.metadata.namespace.clone().unwrap().as_ref(),
+    #[test]
+    fn metrics_port_has_to_be_a_port() {
+        for port in [0, 65536] {
+            assert!(spec(json!({ "metricsPort": port })).validate().is_err());
+        }
+    }
+
+    #[test]
+    fn catch_all_service_defaults_to_a_404() {
+        assert_eq!(spec(json!({})).catch_all_service(), "http_status:404");
+        assert_eq!(
+            spec(json!({ "catchAllService": "http://maintenance.default:80" })).catch_all_service(),
+            "http://maintenance.default:80"
         );
+    }
 
-        let patch: Value = json!({
-            "metadata": {
-                "finalizers": null,
-           }
-        });
+    #[test]
+    fn catch_all_service_has_to_be_a_service() {
+        for service in [
+            "http_status:503",
+            "http://maintenance.default:80",
+            "https://maintenance.default",
+            "unix:/run/maintenance.sock",
+        ] {
+            assert!(
+                spec(json!({ "catchAllService": service }))
+                    .validate()
+                    .is_ok(),
+                "{}",
+                service
+            );
+        }
 
-        let patch: Patch<&Value> = Patch::Merge(&patch);
+        for service in [
+            "",
+            "maintenance.default:80",
+            "ftp://maintenance.default",
+            "http://",
+            "unix:",
+            "http_status:",
+            "http_status:abc",
+            "http_status:99",
+            "http_status:600",
+        ] {
+            assert!(
+                matches!(
+                    spec(json!({ "catchAllService": service })).validate(),
+                    Err(Error::InvalidSpec(_))
+                ),
+                "{}",
+                service
+            );
+        }
+    }
 
-        match tunnel_api
-            .patch(
-                self.metadata.namespace.clone().unwrap().as_ref(),
-                &PatchParams::default(),
-                &patch,
-            )
-            .await
-        {
-            Ok(tunnel) => Ok(tunnel),
-            Err(err) => Err(err),
+    #[test]
+    fn both_probes_check_ready() {
+        let spec = spec(json!({}));
+
+        for probe in [spec.liveness_probe(), spec.readiness_probe()] {
+            let http_get = probe.http_get.unwrap();
+            assert_eq!(http_get.path.as_deref(), Some("/ready"));
         }
+        assert_eq!(spec.liveness_probe().initial_delay_seconds, Some(10));
+        assert_eq!(spec.readiness_probe().initial_delay_seconds, None);
+    }
+
+    #[test]
+    fn probes_are_overridable() {
+        let spec = spec(json!({
+            "probes": {
+                "liveness": { "initialDelaySeconds": 30, "failureThreshold": 5 },
+                "readiness": { "periodSeconds": 2, "successThreshold": 2 },
+            },
+        }));
+
+        let liveness = spec.liveness_probe();
+        assert_eq!(liveness.initial_delay_seconds, Some(30));
+        assert_eq!(liveness.failure_threshold, Some(5));
+        assert_eq!(liveness.period_seconds, Some(10));
+
+        let readiness = spec.readiness_probe();
+        assert_eq!(readiness.period_seconds, Some(2));
+        assert_eq!(readiness.success_threshold, Some(2));
+        assert_eq!(probe_port(&readiness), &IntOrString::Int(2000));
+    }
+
+    #[test]
+    fn pre_stop_sleep_fits_the_grace_period() {
+        let sleep = |spec: &TunnelCrd| {
+            spec.lifecycle()
+                .pre_stop
+                .and_then(|handler| handler.sleep)
+                .unwrap()
+                .seconds
+        };
+
+        let spec = spec(json!({}));
+        assert_eq!(spec.termination_grace_period(), 30);
+        assert_eq!(sleep(&spec), PRE_STOP_SLEEP);
+
+        let spec = self::spec(json!({ "terminationGracePeriodSeconds": 3 }));
+        assert_eq!(spec.termination_grace_period(), 3);
+        assert_eq!(sleep(&spec), 3);
+    }
+
+    #[test]
+    fn security_contexts_default_to_restricted() {
+        let spec = spec(json!({}));
+
+        let pod = spec.pod_security_context();
+        assert_eq!(pod.run_as_non_root, Some(true));
+        assert_eq!(pod.run_as_user, Some(NONROOT_UID));
+        assert_eq!(pod.seccomp_profile.unwrap().type_, "RuntimeDefault");
+
+        let container = spec.container_security_context();
+        assert_eq!(container.allow_privilege_escalation, Some(false));
+        assert_eq!(container.read_only_root_filesystem, Some(true));
+        assert_eq!(container.run_as_non_root, Some(true));
+        assert_eq!(
+            container.capabilities.unwrap().drop,
+            Some(vec!["ALL".to_owned()])
+        );
+        assert_eq!(container.seccomp_profile.unwrap().type_, "RuntimeDefault");
+    }
+
+    #[test]
+    fn explicit_security_contexts_win() {
+        let spec = spec(json!({
+            "podSecurityContext": { "runAsUser": 1000, "fsGroup": 2000 },
+            "containerSecurityContext": {
+                "readOnlyRootFilesystem": false,
+                "capabilities": { "add": ["NET_BIND_SERVICE"], "drop": ["ALL"] },
+            },
+        }));
+
+        let pod = spec.pod_security_context();
+        assert_eq!(pod.run_as_user, Some(1000));
+        assert_eq!(pod.fs_group, Some(2000));
+        assert_eq!(pod.run_as_non_root, Some(true));
+
+        let container = spec.container_security_context();
+        assert_eq!(container.read_only_root_filesystem, Some(false));
+        assert_eq!(
+            container.capabilities.unwrap().add,
+            Some(vec!["NET_BIND_SERVICE".to_owned()])
+        );
+        assert_eq!(container.allow_privilege_escalation, Some(false));
+    }
+
+    #[test]
+    fn uuid_transition_rule_is_in_the_schema() {
+        let crd = serde_json::to_value(Tunnel::crd()).unwrap();
+        let spec = &crd["spec"]["versions"][0]["schema"]["openAPIV3Schema"]["properties"]["spec"];
+
+        assert_eq!(
+            spec["x-kubernetes-validations"],
+            json!([{ "rule": UUID_IMMUTABLE_RULE, "message": UUID_IMMUTABLE_MESSAGE }])
+        );
+    }
+
+    #[test]
+    fn config_source_selects_the_configuration_source() {
+        assert_eq!(
+            spec(json!({})).configuration_source(),
+            ConfigurationSource::Cloudflare
+        );
+        assert!(spec(json!({ "configSource": "local" })).is_local());
+        assert!(spec(json!({ "configurationSource": "local" })).is_local());
+        assert!(!spec(json!({ "configSource": "cloudflare" })).is_local());
+    }
+
+    #[test]
+    fn config_source_has_to_agree_with_configuration_source() {
+        assert!(
+            spec(json!({ "configSource": "local", "configurationSource": "local" }))
+                .validate()
+                .is_ok()
+        );
+        assert!(matches!(
+            spec(json!({ "configSource": "local", "configurationSource": "cloudflare" }))
+                .validate(),
+            Err(Error::InvalidSpec(_))
+        ));
     }
 }
@@ -0,0 +1,634 @@
+use crate::crd::status::{lenient, Condition};
+use crate::crd::tunnel::TunnelResource;
+use cloudflarext::tunnel_configuration::{IngressRule, OriginRequest};
+use k8s_openapi::chrono::Utc;
+use kube::{CustomResource, ResourceExt};
+use regex::Regex;
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{Metadata, Schema, SchemaObject};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::sync::LazyLock;
+
+pub const ACCEPTED_CONDITION: &str = "Accepted";
+// INFO: Set to "true" on a Tunnel by its owner to let TunnelIngress objects of other namespaces
+// route through it.
+pub const ALLOW_CROSS_NAMESPACE_ANNOTATION: &str = "cloudflare.ar2ro.io/allow-cross-namespace";
+pub const APPLIED_CONDITION: &str = "Applied";
+
+// INFO: A DNS name, optionally with a leading wildcard label.
+const HOSTNAME_PATTERN: &str =
+    "^(\\*\\.)?([a-zA-Z0-9]([-a-zA-Z0-9]*[a-zA-Z0-9])?\\.)*[a-zA-Z0-9]([-a-zA-Z0-9]*[a-zA-Z0-9])?$";
+static HOSTNAME: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(HOSTNAME_PATTERN).expect("hostname pattern compiles"));
+// INFO: Schemes cloudflared proxies to, `unix:` and `http_status:` are checked on their own.
+pub(crate) const SERVICE_SCHEMES: [&str; 5] = ["http", "https", "tcp", "ssh", "rdp"];
+
+#[derive(CustomResource, Serialize, Deserialize, Debug, Clone, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[kube(
+    group = "cloudflare.ar2ro.io",
+    version = "v1",
+    kind = "TunnelIngress",
+    doc = "Ingress rules routed through a Cloudflare Tunnel",
+    status = "TunnelIngressStatus",
+    printcolumn = r#"{"name":"Hostname", "type":"string", "jsonPath":".spec.rules[0].hostname"}"#,
+    printcolumn = r#"{"name":"Service", "type":"string", "jsonPath":".spec.rules[0].service"}"#,
+    printcolumn = r#"{"name":"Tunnel", "type":"string", "jsonPath":".spec.tunnelRef.name"}"#,
+    printcolumn = r#"{"name":"Synced", "type":"boolean", "jsonPath":".status.synced"}"#,
+    printcolumn = r#"{"name":"Age", "type":"date", "jsonPath":".metadata.creationTimestamp"}"#,
+    selectable = ".spec.tunnelRef.name",
+    shortname = "tunin",
+    category = "cloudflare",
+    namespaced
+)]
+pub struct TunnelIngressCrd {
+    pub tunnel_ref: TunnelRef,
+    #[serde(default)]
+    pub rules: Vec<TunnelIngressRule>,
+}
+
+/// Whether the rules made it into the configuration of the referenced tunnel.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TunnelIngressStatus {
+    #[serde(default)]
+    pub synced: bool,
+    /// Last time the rules were written, kept while they fail to sync.
+    #[serde(default)]
+    pub last_sync_time: Option<String>,
+    /// Why the rules couldn't be synced.
+    #[serde(default)]
+    pub message: Option<String>,
+    #[serde(default)]
+    pub observed_generation: Option<i64>,
+    #[serde(default, deserialize_with = "lenient")]
+    pub conditions: Vec<Condition>,
+}
+
+impl TunnelIngressStatus {
+    /// The status after syncing the rules of `generation`. `invalid` are the problems that kept
+    /// the object out of the configuration, `error` is why the sync failed.
+    pub fn after_sync(
+        &self,
+        generation: Option<i64>,
+        invalid: &[String],
+        error: Option<String>,
+    ) -> TunnelIngressStatus {
+        let mut conditions = self.conditions.clone();
+        let invalid = Some(invalid.join(", ")).filter(|invalid| !invalid.is_empty());
+        Condition::set(
+            &mut conditions,
+            Condition {
+                type_: ACCEPTED_CONDITION.to_owned(),
+                status: match invalid {
+                    Some(_) => "False".to_owned(),
+                    None => "True".to_owned(),
+                },
+                reason: match invalid {
+                    Some(_) => Some("Invalid".to_owned()),
+                    None => Some("Valid".to_owned()),
+                },
+                message: invalid.clone(),
+                last_transition_time: None,
+            },
+        );
+
+        // INFO: An invalid object was left out, that's what it's told instead of the sync result.
+        let error = invalid.or(error);
+        Condition::set(
+            &mut conditions,
+            Condition {
+                type_: APPLIED_CONDITION.to_owned(),
+                status: match error {
+                    Some(_) => "False".to_owned(),
+                    None => "True".to_owned(),
+                },
+                reason: match error {
+                    Some(_) => Some("SyncFailed".to_owned()),
+                    None => Some("Synced".to_owned()),
+                },
+                message: error.clone(),
+                last_transition_time: None,
+            },
+        );
+
+        TunnelIngressStatus {
+            synced: error.is_none(),
+            last_sync_time: match error {
+                Some(_) => self.last_sync_time.clone(),
+                None => Some(Utc::now().to_rfc3339()),
+            },
+            message: error,
+            observed_generation: generation,
+            conditions,
+        }
+    }
+
+    // INFO: The sync time alone isn't worth a write, every status write triggers another
+    // reconcile of the controllers watching TunnelIngress objects.
+    pub fn differs(&self, other: &TunnelIngressStatus) -> bool {
+        self.synced != other.synced
+            || self.message != other.message
+            || self.observed_generation != other.observed_generation
+            || self.conditions != other.conditions
+    }
+}
+
+/// A `Tunnel` is looked up in `namespace` or the namespace of the TunnelIngress, a
+/// `ClusterTunnel` by name.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TunnelRef {
+    #[serde(default)]
+    pub kind: TunnelKind,
+    pub name: String,
+    /// Namespace of a `Tunnel` outside the TunnelIngress's own, only honored when the operator
+    /// allows cross namespace references or the Tunnel carries
+    /// `cloudflare.ar2ro.io/allow-cross-namespace: "true"`.
+    #[serde(default)]
+    pub namespace: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, JsonSchema)]
+pub enum TunnelKind {
+    #[default]
+    Tunnel,
+    ClusterTunnel,
+}
+
+impl TunnelKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            TunnelKind::Tunnel => "Tunnel",
+            TunnelKind::ClusterTunnel => "ClusterTunnel",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TunnelIngressRule {
+    #[schemars(regex = "HOSTNAME_PATTERN", length(max = 253))]
+    pub hostname: String,
+    #[serde(default)]
+    pub path: Option<String>,
+    pub service: TunnelService,
+    #[serde(default)]
+    pub origin_request: Option<OriginRequest>,
+}
+
+/// Either a url cloudflared proxies to as is or a reference to a Kubernetes Service.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum TunnelService {
+    Url(String),
+    ServiceRef(ServiceRef),
+}
+
+/// A Service looked up in the namespace of the TunnelIngress unless `namespace` is set.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ServiceRef {
+    pub name: String,
+    #[serde(default)]
+    pub namespace: Option<String>,
+    pub port: i32,
+    /// Defaults to http.
+    #[serde(default)]
+    pub scheme: Option<String>,
+}
+
+// INFO: A structural schema can't express a string or an object, the shape is left to serde.
+impl JsonSchema for TunnelService {
+    fn schema_name() -> String {
+        "TunnelService".to_owned()
+    }
+
+    fn json_schema(_: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            metadata: Some(Box::new(Metadata {
+                description: Some(
+                    "Url of the origin or a ServiceRef with name, namespace, port and scheme."
+                        .to_owned(),
+                ),
+                ..Metadata::default()
+            })),
+            extensions: [(
+                "x-kubernetes-preserve-unknown-fields".to_owned(),
+                true.into(),
+            )]
+            .into_iter()
+            .collect(),
+            ..SchemaObject::default()
+        }
+        .into()
+    }
+}
+
+impl TunnelService {
+    /// The url cloudflared proxies to, a ServiceRef without a namespace is in `namespace`.
+    pub fn url(&self, namespace: &str) -> String {
+        match self {
+            TunnelService::Url(url) => url.clone(),
+            TunnelService::ServiceRef(service_ref) => format!(
+                "{}://{}.{}.svc.cluster.local:{}",
+                service_ref.scheme.as_deref().unwrap_or("http"),
+                service_ref.name,
+                service_ref.namespace.as_deref().unwrap_or(namespace),
+                service_ref.port
+            ),
+        }
+    }
+}
+
+pub(crate) fn valid_service(service: &str) -> bool {
+    if let Some(code) = service.strip_prefix("http_status:") {
+        return code
+            .parse::<u16>()
+            .is_ok_and(|code| (100..=599).contains(&code));
+    }
+    if let Some(path) = service.strip_prefix("unix:") {
+        return !path.is_empty();
+    }
+
+    service
+        .split_once("://")
+        .is_some_and(|(scheme, host)| SERVICE_SCHEMES.contains(&scheme) && !host.is_empty())
+}
+
+impl TunnelIngress {
+    /// Why the rules can't be handed to cloudflared, empty when they can. The schema can't
+    /// check the service of an untagged enum or whether a path compiles, so it's done here.
+    pub fn validate(&self) -> Vec<String> {
+        let mut invalid = Vec::new();
+
+        for rule in &self.spec.rules {
+            if !HOSTNAME.is_match(&rule.hostname) {
+                invalid.push(format!("hostname {:?} isn't a DNS name", rule.hostname));
+            }
+            if let Some(path) = &rule.path {
+                if let Err(err) = Regex::new(path) {
+                    invalid.push(format!("path {:?} isn't a valid regex: {}", path, err));
+                }
+            }
+            match &rule.service {
+                TunnelService::Url(url) if !valid_service(url) => invalid.push(format!(
+                    "service {:?} must be <{}>://<host>, unix:<path> or http_status:<code>",
+                    url,
+                    SERVICE_SCHEMES.join("|")
+                )),
+                TunnelService::ServiceRef(ServiceRef {
+                    scheme: Some(scheme),
+                    ..
+                }) if !SERVICE_SCHEMES.contains(&scheme.as_str()) => invalid.push(format!(
+                    "scheme {:?} of the service for {} must be one of {}",
+                    scheme,
+                    rule.hostname,
+                    SERVICE_SCHEMES.join(", ")
+                )),
+                _ => {}
+            }
+        }
+
+        invalid
+    }
+
+    pub fn ingress_rules(&self) -> Vec<IngressRule> {
+        let namespace = self.namespace().unwrap_or_default();
+        self.spec
+            .rules
+            .iter()
+            .map(|rule| IngressRule {
+                hostname: Some(rule.hostname.clone()),
+                path: rule.path.clone(),
+                service: rule.service.url(&namespace),
+                origin_request: rule.origin_request.clone(),
+            })
+            .collect()
+    }
+
+    /// Services the rules reference as `(namespace, name)`.
+    pub fn service_refs(&self) -> Vec<(String, String)> {
+        let namespace = self.namespace().unwrap_or_default();
+        self.spec
+            .rules
+            .iter()
+            .filter_map(|rule| match &rule.service {
+                TunnelService::ServiceRef(service_ref) => Some((
+                    service_ref
+                        .namespace
+                        .clone()
+                        .unwrap_or_else(|| namespace.clone()),
+                    service_ref.name.clone(),
+                )),
+                TunnelService::Url(_) => None,
+            })
+            .collect()
+    }
+
+    /// Namespace of the referenced Tunnel, `None` for a ClusterTunnel.
+    pub fn tunnel_namespace(&self) -> Option<String> {
+        let tunnel_ref = &self.spec.tunnel_ref;
+        match tunnel_ref.kind {
+            TunnelKind::Tunnel => tunnel_ref.namespace.clone().or_else(|| self.namespace()),
+            TunnelKind::ClusterTunnel => None,
+        }
+    }
+
+    pub fn references<K: TunnelResource>(&self, tunnel: &K) -> bool {
+        let tunnel_ref = &self.spec.tunnel_ref;
+        tunnel_ref.kind.as_str() == K::kind(&())
+            && tunnel_ref.name == tunnel.name_any()
+            && self.tunnel_namespace() == tunnel.namespace()
+    }
+
+    /// Why routing through `tunnel` from another namespace isn't allowed, `None` when it is.
+    /// ClusterTunnels are shared by definition.
+    pub fn cross_namespace_denial<K: TunnelResource>(
+        &self,
+        tunnel: &K,
+        allow_cross_namespace_refs: bool,
+    ) -> Option<String> {
+        cross_namespace_denial(
+            self.namespace().as_deref(),
+            tunnel,
+            allow_cross_namespace_refs,
+        )
+    }
+}
+
+/// Why an object in `namespace` can't route through `tunnel` of another namespace, `None` when
+/// it can.
+pub fn cross_namespace_denial<K: TunnelResource>(
+    namespace: Option<&str>,
+    tunnel: &K,
+    allow_cross_namespace_refs: bool,
+) -> Option<String> {
+    let tunnel_namespace = tunnel.namespace()?;
+    if allow_cross_namespace_refs
+        || namespace == Some(tunnel_namespace.as_str())
+        || tunnel
+            .annotations()
+            .get(ALLOW_CROSS_NAMESPACE_ANNOTATION)
+            .is_some_and(|value| value == "true")
+    {
+        return None;
+    }
+
+    Some(format!(
+        "Tunnel {}/{} doesn't allow references from other namespaces, its owner has to annotate it with {}: \"true\"",
+        tunnel_namespace,
+        tunnel.name_any(),
+        ALLOW_CROSS_NAMESPACE_ANNOTATION
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crd::tunnel::Tunnel;
+    use cloudflarext::tunnel_configuration::{OriginRequestAccess, Seconds};
+    use kube::CustomResourceExt;
+    use serde_json::{json, Value};
+
+    // INFO: Every field set, so its serialized form names every property the schema needs.
+    fn complete_spec() -> TunnelIngressCrd {
+        TunnelIngressCrd {
+            tunnel_ref: TunnelRef {
+                kind: TunnelKind::ClusterTunnel,
+                name: "shared".to_owned(),
+                namespace: Some("platform".to_owned()),
+            },
+            rules: vec![TunnelIngressRule {
+                hostname: "app.example.com".to_owned(),
+                path: Some("/api".to_owned()),
+                service: TunnelService::Url("http://web.default:80".to_owned()),
+                origin_request: Some(OriginRequest {
+                    connect_timeout: Some(Seconds(30)),
+                    tls_timeout: Some(Seconds(10)),
+                    tcp_keep_alive: Some(Seconds(30)),
+                    keep_alive_timeout: Some(Seconds(90)),
+                    keep_alive_connections: Some(100),
+                    no_happy_eyeballs: Some(false),
+                    http_host_header: Some("app.example.com".to_owned()),
+                    origin_server_name: Some("app.example.com".to_owned()),
+                    ca_pool: Some("/etc/ssl/ca.pem".to_owned()),
+                    no_tls_verify: Some(false),
+                    disable_chunked_encoding: Some(false),
+                    http2_origin: Some(true),
+                    proxy_type: Some("socks".to_owned()),
+                    access: Some(OriginRequestAccess {
+                        required: true,
+                        team_name: "team".to_owned(),
+                        aud_tag: vec!["aud".to_owned()],
+                    }),
+                }),
+            }],
+        }
+    }
+
+    // INFO: Walks the value and the schema side by side, returns the paths the schema lacks.
+    fn missing_properties(value: &Value, schema: &Value, path: &str) -> Vec<String> {
+        if schema["x-kubernetes-preserve-unknown-fields"] == json!(true) {
+            return Vec::new();
+        }
+
+        match value {
+            Value::Object(fields) => fields
+                .iter()
+                .flat_map(|(key, value)| {
+                    let path = format!("{}.{}", path, key);
+                    match schema["properties"].get(key) {
+                        Some(schema) => missing_properties(value, schema, &path),
+                        None => vec![path],
+                    }
+                })
+                .collect(),
+            Value::Array(items) => items
+                .iter()
+                .flat_map(|item| missing_properties(item, &schema["items"], &format!("{}[]", path)))
+                .collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    fn tunnel_ingress(rules: Value) -> TunnelIngress {
+        TunnelIngress::new(
+            "web",
+            serde_json::from_value(json!({ "tunnelRef": { "name": "web" }, "rules": rules }))
+                .unwrap(),
+        )
+    }
+
+    fn service_problems(service: Value) -> Vec<String> {
+        tunnel_ingress(json!([{ "hostname": "app.example.com", "service": service }])).validate()
+    }
+
+    #[test]
+    fn allowed_services_are_valid() {
+        for service in [
+            "http://web.default:80",
+            "https://web.default:443",
+            "tcp://db.default:5432",
+            "ssh://bastion.default:22",
+            "rdp://desktop.default:3389",
+            "unix:/run/app.sock",
+            "http_status:404",
+        ] {
+            assert_eq!(
+                service_problems(json!(service)),
+                Vec::<String>::new(),
+                "{}",
+                service
+            );
+        }
+        for scheme in SERVICE_SCHEMES {
+            let service = json!({ "name": "web", "port": 80, "scheme": scheme });
+            assert_eq!(
+                service_problems(service),
+                Vec::<String>::new(),
+                "{}",
+                scheme
+            );
+        }
+    }
+
+    #[test]
+    fn unknown_services_are_invalid() {
+        for service in [
+            "ftp://files.default:21",
+            "http://",
+            "web.default:80",
+            "unix:",
+            "http_status:99",
+            "http_status:600",
+            "http_status:gone",
+        ] {
+            assert_eq!(service_problems(json!(service)).len(), 1, "{}", service);
+        }
+        let service = json!({ "name": "web", "port": 80, "scheme": "ftp" });
+        assert_eq!(
+            service_problems(service),
+            [
+                r#"scheme "ftp" of the service for app.example.com must be one of http, https, tcp, ssh, rdp"#
+            ]
+        );
+    }
+
+    #[test]
+    fn hostnames_must_be_dns_names() {
+        for hostname in [
+            "app.example.com",
+            "*.example.com",
+            "localhost",
+            "a-1.example.com",
+        ] {
+            let rules = json!([{ "hostname": hostname, "service": "http_status:404" }]);
+            assert_eq!(
+                tunnel_ingress(rules).validate(),
+                Vec::<String>::new(),
+                "{}",
+                hostname
+            );
+        }
+        for hostname in [
+            "",
+            "app..example.com",
+            "-app.example.com",
+            "app.*.com",
+            "app_1.example.com",
+        ] {
+            let rules = json!([{ "hostname": hostname, "service": "http_status:404" }]);
+            assert_eq!(
+                tunnel_ingress(rules).validate(),
+                [format!("hostname {:?} isn't a DNS name", hostname)]
+            );
+        }
+    }
+
+    #[test]
+    fn paths_must_compile() {
+        let rules = json!([
+            { "hostname": "app.example.com", "path": "^/api/(v1|v2)", "service": "http_status:404" },
+            { "hostname": "app.example.com", "path": "/api/(v1", "service": "http_status:404" },
+        ]);
+        let problems = tunnel_ingress(rules).validate();
+
+        assert_eq!(problems.len(), 1);
+        assert!(problems[0].starts_with(r#"path "/api/(v1" isn't a valid regex"#));
+    }
+
+    fn shared_tunnel(annotations: Value) -> Tunnel {
+        serde_json::from_value(json!({
+            "metadata": { "name": "shared", "namespace": "infra", "annotations": annotations },
+            "spec": { "credentials": "creds" },
+        }))
+        .unwrap()
+    }
+
+    fn routed_from(namespace: &str) -> TunnelIngress {
+        let mut tunnel_ingress = tunnel_ingress(json!([]));
+        tunnel_ingress.metadata.namespace = Some(namespace.to_owned());
+        tunnel_ingress.spec.tunnel_ref = TunnelRef {
+            kind: TunnelKind::Tunnel,
+            name: "shared".to_owned(),
+            namespace: Some("infra".to_owned()),
+        };
+        tunnel_ingress
+    }
+
+    #[test]
+    fn same_namespace_references_are_allowed() {
+        let tunnel = shared_tunnel(json!({}));
+
+        assert_eq!(
+            routed_from("infra").cross_namespace_denial(&tunnel, false),
+            None
+        );
+    }
+
+    #[test]
+    fn cross_namespace_references_need_consent() {
+        let denied = routed_from("team").cross_namespace_denial(&shared_tunnel(json!({})), false);
+        assert_eq!(
+            denied.as_deref(),
+            Some(
+                "Tunnel infra/shared doesn't allow references from other namespaces, its owner has \
+                 to annotate it with cloudflare.ar2ro.io/allow-cross-namespace: \"true\""
+            )
+        );
+        let refused = shared_tunnel(json!({ ALLOW_CROSS_NAMESPACE_ANNOTATION: "false" }));
+        assert!(routed_from("team")
+            .cross_namespace_denial(&refused, false)
+            .is_some());
+
+        // INFO: Either the tunnel owner or the operator flag allows it.
+        let consenting = shared_tunnel(json!({ ALLOW_CROSS_NAMESPACE_ANNOTATION: "true" }));
+        assert_eq!(
+            routed_from("team").cross_namespace_denial(&consenting, false),
+            None
+        );
+        assert_eq!(
+            routed_from("team").cross_namespace_denial(&shared_tunnel(json!({})), true),
+            None
+        );
+    }
+
+    #[test]
+    fn crd_schema_is_complete() {
+        let crd = serde_json::to_value(TunnelIngress::crd()).unwrap();
+        let schema = &crd["spec"]["versions"][0]["schema"]["openAPIV3Schema"]["properties"]["spec"];
+        let spec = serde_json::to_value(complete_spec()).unwrap();
+
+        assert_eq!(
+            missing_properties(&spec, schema, "spec"),
+            Vec::<String>::new()
+        );
+
+        let access = &schema["properties"]["rules"]["items"]["properties"]["originRequest"]
+            ["properties"]["access"]["properties"];
+        assert_eq!(access["audTag"]["type"], "array");
+        assert_eq!(access["audTag"]["items"]["type"], "string");
+    }
+}
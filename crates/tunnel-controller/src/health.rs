@@ -0,0 +1,247 @@
+//! `/healthz` endpoint for the liveness and readiness probes of the operator Deployment, the
+//! process metrics are served next to it on `/metrics`.
+use crate::crd::tunnel::TunnelResource;
+use crate::{is_paused, metrics, prevents_destroy, TunnelStores};
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::routing::get;
+use axum::Router;
+use cloudflare_controller_common::RECONCILE_TIMER;
+use futures::Future;
+use std::future::IntoFuture;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::net::TcpListener;
+use tokio_util::sync::CancellationToken;
+
+const HEALTH_PATH: &str = "/healthz";
+const METRICS_PATH: &str = "/metrics";
+
+pub const DEFAULT_HEALTH_PORT: u16 = 8080;
+
+/// Shared between the tunnel controller, which records its reconciles, and the health server.
+#[derive(Clone)]
+pub struct Health {
+    stores: TunnelStores,
+    populated: Arc<AtomicBool>,
+    // INFO: Unix seconds, only meaningful once the stores are populated.
+    last_reconcile: Arc<AtomicU64>,
+}
+
+// INFO: Paused tunnels and the ones whose deletion is blocked wait for a change instead of being
+// requeued, they never finish a reconcile on their own.
+fn requeued<K: TunnelResource>(tunnel: &K) -> bool {
+    match tunnel.meta().deletion_timestamp.is_some() {
+        true => !prevents_destroy(tunnel),
+        false => !is_paused(tunnel),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+impl Health {
+    pub fn new(stores: TunnelStores) -> Health {
+        Health {
+            stores,
+            populated: Arc::new(AtomicBool::new(false)),
+            last_reconcile: Arc::new(AtomicU64::new(0)),
+        }
+    }
+
+    /// Resolves once both tunnel stores received their initial list.
+    pub(crate) async fn wait_until_populated(&self) {
+        if self.stores.wait_until_ready().await.is_ok() {
+            // INFO: Tunnels listed at startup get a full reconcile window before counting as
+            // stalled.
+            self.record_reconcile();
+            self.populated.store(true, Ordering::Relaxed);
+        }
+    }
+
+    pub(crate) fn record_reconcile(&self) {
+        self.last_reconcile.store(now(), Ordering::Relaxed);
+    }
+
+    /// Why the controller isn't healthy, every tunnel that isn't paused or blocked from deletion is
+    /// requeued after `RECONCILE_TIMER` so nothing finishing for twice that long means the
+    /// controller is stuck.
+    pub fn check(&self) -> Result<(), String> {
+        if !self.populated.load(Ordering::Relaxed) {
+            return Err("tunnel stores were never populated".to_owned());
+        }
+
+        // INFO: Without any requeued tunnel there's nothing to reconcile, that isn't a stall.
+        let requeued_tunnels = self
+            .stores
+            .tunnels
+            .state()
+            .iter()
+            .any(|tunnel| requeued(tunnel.as_ref()));
+        let requeued_cluster_tunnels = self
+            .stores
+            .cluster_tunnels
+            .state()
+            .iter()
+            .any(|tunnel| requeued(tunnel.as_ref()));
+        if !requeued_tunnels && !requeued_cluster_tunnels {
+            return Ok(());
+        }
+
+        let elapsed = now().saturating_sub(self.last_reconcile.load(Ordering::Relaxed));
+        match elapsed > 2 * RECONCILE_TIMER {
+            true => Err(format!("last reconcile finished {}s ago", elapsed)),
+            false => Ok(()),
+        }
+    }
+}
+
+pub struct HealthServer {
+    health: Health,
+    addr: SocketAddr,
+    shutdown: CancellationToken,
+}
+
+async fn healthz(State(health): State<Health>) -> (StatusCode, String) {
+    match health.check() {
+        Ok(()) => (StatusCode::OK, "ok".to_owned()),
+        Err(reason) => (StatusCode::SERVICE_UNAVAILABLE, reason),
+    }
+}
+
+async fn render_metrics() -> String {
+    metrics::render()
+}
+
+impl HealthServer {
+    pub fn new(health: Health, addr: SocketAddr, shutdown: CancellationToken) -> HealthServer {
+        HealthServer {
+            health,
+            addr,
+            shutdown,
+        }
+    }
+
+    pub async fn start(self) -> anyhow::Result<()> {
+        tracing::info!("Starting Health Server on {}", self.addr);
+        let listener = TcpListener::bind(self.addr).await?;
+
+        let router = Router::new()
+            .route(HEALTH_PATH, get(healthz))
+            .route(METRICS_PATH, get(render_metrics))
+            .with_state(self.health);
+        axum::serve(listener, router)
+            .with_graceful_shutdown(self.shutdown.cancelled_owned())
+            .await?;
+
+        Ok(())
+    }
+}
+
+impl IntoFuture for HealthServer {
+    type Output = anyhow::Result<()>;
+    type IntoFuture = Pin<Box<dyn Future<Output = Self::Output>>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.start())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crd::{cluster_tunnel::ClusterTunnel, tunnel::Tunnel};
+    use cloudflare_controller_common::FINALIZER_NAME;
+    use kube::runtime::reflector::{self, store::Writer};
+    use kube::runtime::watcher::Event;
+    use serde_json::json;
+
+    fn tunnel(name: &str, metadata: serde_json::Value) -> Tunnel {
+        let mut tunnel: Tunnel = serde_json::from_value(json!({
+            "metadata": metadata,
+            "spec": { "credentials": "creds" },
+        }))
+        .unwrap();
+        tunnel.metadata.name = Some(name.to_owned());
+        tunnel.metadata.namespace = Some("default".to_owned());
+        tunnel
+    }
+
+    // INFO: Populated stores whose last reconcile finished long enough ago to count as stalled.
+    fn stalled(tunnels: Vec<Tunnel>) -> Health {
+        let (tunnel_store, mut tunnel_writer) = reflector::store::<Tunnel>();
+        let (cluster_tunnels, _) = reflector::store::<ClusterTunnel>();
+        for tunnel in tunnels {
+            tunnel_writer.apply_watcher_event(&Event::Apply(tunnel));
+        }
+        let health = Health::new(TunnelStores {
+            tunnels: tunnel_store,
+            cluster_tunnels,
+        });
+        health.populated.store(true, Ordering::Relaxed);
+        health
+            .last_reconcile
+            .store(now() - 3 * RECONCILE_TIMER, Ordering::Relaxed);
+        health
+    }
+
+    fn health_of(tunnels: Vec<Tunnel>) -> Result<(), String> {
+        stalled(tunnels).check()
+    }
+
+    #[test]
+    fn unpopulated_stores_are_unhealthy() {
+        let (tunnels, _): (_, Writer<Tunnel>) = reflector::store();
+        let (cluster_tunnels, _): (_, Writer<ClusterTunnel>) = reflector::store();
+        let health = Health::new(TunnelStores {
+            tunnels,
+            cluster_tunnels,
+        });
+
+        assert!(health.check().is_err());
+    }
+
+    #[test]
+    fn stalled_reconciles_are_unhealthy() {
+        let health = stalled(vec![tunnel("web", json!({}))]);
+        assert!(health
+            .check()
+            .unwrap_err()
+            .starts_with("last reconcile finished"));
+
+        health.record_reconcile();
+        assert_eq!(health.check(), Ok(()));
+    }
+
+    #[test]
+    fn paused_and_deletion_blocked_tunnels_are_not_stalls() {
+        let paused = tunnel(
+            "paused",
+            json!({ "annotations": { "cloudflare.ar2ro.io/paused": "true" } }),
+        );
+        let blocked = tunnel(
+            "blocked",
+            json!({
+                "annotations": { "cloudflare.ar2ro.io/prevent-destroy": "true" },
+                "deletionTimestamp": "2026-01-01T00:00:00Z",
+                "finalizers": [FINALIZER_NAME],
+            }),
+        );
+        assert_eq!(health_of(vec![]), Ok(()));
+        assert_eq!(health_of(vec![paused.clone(), blocked.clone()]), Ok(()));
+
+        // INFO: Only deleting tunnels are blocked by the annotation, otherwise they're requeued.
+        let protected = tunnel(
+            "protected",
+            json!({ "annotations": { "cloudflare.ar2ro.io/prevent-destroy": "true" } }),
+        );
+        assert!(health_of(vec![paused.clone(), protected]).is_err());
+        assert!(health_of(vec![paused, blocked, tunnel("web", json!({}))]).is_err());
+    }
+}
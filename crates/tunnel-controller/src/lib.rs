@@ -1,33 +1,95 @@
-use crate::crd::credentials::{Credentials, CredentialsApiExt};
-use crate::crd::tunnel::Tunnel;
+use crate::backoff::Backoff;
+use crate::crd::cluster_tunnel::ClusterTunnel;
+use crate::crd::credentials::{validate_credentials, Credentials, CredentialsApiExt};
+use crate::crd::status::{Condition, Milestone};
+use crate::crd::tunnel::{
+    add_finalizer, apply_resources, delete_resources, patch_status, remove_finalizer, tunnel_url,
+    DeletionPolicy, HibernateMode, ReconcilePolicy, Tunnel, TunnelCrd, TunnelResource,
+};
+use crate::crd::tunnel_ingress::TunnelIngress;
+use crate::health::Health;
+use crate::locks::ObjectLocks;
+use crate::resources::deployment::ImagePolicy;
+use crate::resources::{
+    applied_by_current_version, configmap, deployment, has_spec_annotations, patch_params, secret,
+    serviceaccount, CREDENTIALS_FILE, CREDENTIALS_PATH, LOCAL_CONFIG_FILE,
+};
+use crate::state::{OperatorState, ReconciledTunnel};
+use crate::token_cache::TokenCache;
+use cloudflare::endpoints::cfd_tunnel::Tunnel as CloudflareTunnel;
+use cloudflare::framework::auth::Credentials as CloudflareCredentials;
 use cloudflare::framework::response::ApiFailure;
-use cloudflare::{endpoints::cfd_tunnel::ConfigurationSrc, framework::HttpApiClientConfig};
-use cloudflarext::{cfd_tunnel::CloudflaredTunnel, AuthlessClient as CloudflareClient};
+use cloudflare::framework::HttpApiClientConfig;
+use cloudflare_controller_common::{MANAGED_BY, MANAGED_BY_LABEL, NAME_LABEL, RECONCILE_TIMER};
+use cloudflarext::{
+    cfd_tunnel::CloudflaredTunnel,
+    dry_run::DryRunCloudflareClient,
+    failure::{ApiFailureExt, FailureKind},
+    local_config::{render_config, CredentialsFile, LocalConfigError},
+    tunnel_configuration::{normalize_origin_settings, IngressRule},
+    AuthlessClient,
+};
+use dashmap::DashMap;
 use futures::{Future, StreamExt};
 use k8s_openapi::api::{
     apps::v1::Deployment,
-    core::v1::{ConfigMap, Secret},
+    core::v1::{ConfigMap, Secret, Service, ServiceAccount},
 };
+use k8s_openapi::chrono::{DateTime, Utc};
 use k8s_openapi::ByteString;
-use kube::api::{Patch, PatchParams};
-use kube::core::object::HasSpec;
+use kube::api::{ListParams, Patch};
+use kube::core::DynamicObject;
 use kube::runtime::controller::Action;
-use kube::runtime::reflector::Store;
+use kube::runtime::events::{Event, EventType, Recorder, Reporter};
+use kube::runtime::reflector::{self, ObjectRef, Store};
+use kube::runtime::watcher::watcher;
+use kube::runtime::WatchStreamExt;
 use kube::{
-    client::Client, runtime::watcher::Config, runtime::Controller as KubeController, Api, Resource,
+    client::Client, runtime::watcher::Config, runtime::Controller as KubeController, Api,
     ResourceExt,
 };
 use reqwest::StatusCode;
+use serde_json::json;
 use std::collections::BTreeMap;
-use std::future::IntoFuture;
+use std::future::{ready, IntoFuture};
 use std::pin::Pin;
 use std::sync::Arc;
 use tokio::time::Duration;
+use tokio_util::sync::CancellationToken;
+use uuid::Uuid;
 
+pub mod backoff;
 pub mod crd;
+pub mod health;
+pub mod locks;
+pub mod metrics;
+#[cfg(any(test, feature = "test-util"))]
+pub mod mock;
+pub mod resources;
+pub mod state;
+pub mod store;
+pub mod token_cache;
+pub mod webhook;
+
+pub use store::{AnyTunnel, DefaultTunnelError, TunnelStoreExt, TunnelStores};
 
-const RECONCILE_TIMER: u64 = 60;
-const DEFAULT_ANNOTATION: &str = "cloudflare.ar2ro.io/default-tunnel";
+pub type CloudflareClient = DryRunCloudflareClient<AuthlessClient>;
+
+pub const DEFAULT_CLUSTER_TUNNEL_NAMESPACE: &str = "cloudflare-system";
+
+const HIBERNATED_CONDITION: &str = "Hibernated";
+const PAUSED_CONDITION: &str = "Paused";
+const PAUSED_ANNOTATION: &str = "cloudflare.ar2ro.io/paused";
+const DELETION_BLOCKED_CONDITION: &str = "DeletionBlocked";
+const DEGRADED_CONDITION: &str = "Degraded";
+const NO_ACTIVE_CONNECTIONS_CONDITION: &str = "NoActiveConnections";
+const PREVENT_DESTROY_ANNOTATION: &str = "cloudflare.ar2ro.io/prevent-destroy";
+// INFO: How long a delete waits for connectors to disconnect and how often it looks again.
+const CONNECTION_DRAIN_TIMEOUT: i64 = 60;
+const CONNECTION_DRAIN_POLL: u64 = 5;
+// INFO: Safety net for tokens changed outside the operator, steady state syncs otherwise never
+// call Cloudflare.
+const CLOUDFLARE_RESYNC_INTERVAL: Duration = Duration::from_secs(15 * 60);
 
 /// All errors possible to occur during reconciliation
 #[derive(Debug, thiserror::Error)]
@@ -36,56 +98,64 @@ pub enum Error {
     #[error("Kubernetes reported error: {0}")]
     KubeError(#[from] kube::Error),
     // Any error that the cloudflare api returns
-    #[error("Cloudflare api returned an error {0}")]
+    #[error("Cloudflare api returned an error ({kind}) {0}", kind = .0.kind())]
     CloudflareApiFailure(#[from] ApiFailure),
     #[error("missing namespace for resource {0}")]
     MissingNamespace(&'static str),
     #[error("Missing credentials CRD {0}")]
     MissingCredentials(String),
+    #[error("invalid credentials: {0}")]
+    InvalidCredentials(String),
+    #[error("invalid tunnel spec: {0}")]
+    InvalidSpec(String),
+    #[error("failed to build local configuration: {0}")]
+    LocalConfig(#[from] LocalConfigError),
 }
 
-pub trait TunnelStoreExt {
-    fn default_tunnel(&self) -> Option<Arc<Tunnel>>;
-}
-
-impl TunnelStoreExt for Store<Tunnel> {
-    // INFO: If more than one tunnel is marked a default a None is returned.
-    fn default_tunnel(&self) -> Option<Arc<Tunnel>> {
-        let mut tunnels: Vec<Arc<Tunnel>> = self
-            .state()
-            .into_iter()
-            .filter(|tunnel| {
-                tunnel
-                    .metadata
-                    .annotations
-                    .as_ref()
-                    .map_or(false, |annotations| {
-                        annotations
-                            .get(DEFAULT_ANNOTATION)
-                            .map_or(false, |v| v.to_lowercase().eq("true"))
-                    })
-            })
-            .collect::<_>();
-
-        match tunnels.len() {
-            1 => tunnels.pop(),
-            _ => None,
-        }
-    }
+// INFO: Annotations are hand written, the usual spellings of true all count.
+pub(crate) fn is_truthy(value: &str) -> bool {
+    matches!(
+        value.trim().to_lowercase().as_str(),
+        "true" | "1" | "yes" | "on"
+    )
 }
 
-pub struct TunnelController {
+pub struct TunnelController<C = CloudflareClient> {
     kubernetes_client: Client,
-    cloudflare_client: CloudflareClient,
+    cloudflare_client: C,
     tunnel_api: Api<Tunnel>,
     controller: KubeController<Tunnel>,
+    cluster_controller: KubeController<ClusterTunnel>,
+    cluster_tunnel_namespace: String,
+    hibernate_all: bool,
+    allow_cross_namespace_refs: bool,
+    backoff: Backoff,
+    token_cache: TokenCache,
+    image_policy: ImagePolicy,
+    state: OperatorState,
+    health: Health,
+    shutdown: CancellationToken,
 }
 
-pub struct Context {
+pub struct Context<C = CloudflareClient> {
     kubernetes_client: Client,
-    cloudflare_client: CloudflareClient,
+    cloudflare_client: C,
     credentials_api: Api<Credentials>,
     tunnel_api: Api<Tunnel>,
+    // INFO: Only read when the api server can't select TunnelIngress objects by tunnel.
+    tunnel_ingress_store: Store<TunnelIngress>,
+    cluster_tunnel_namespace: String,
+    hibernate_all: bool,
+    allow_cross_namespace_refs: bool,
+    backoff: Backoff,
+    token_cache: TokenCache,
+    image_policy: ImagePolicy,
+    state: OperatorState,
+    locks: ObjectLocks,
+    // INFO: resourceVersion of every Credentials object Cloudflare accepted, checked before a
+    // tunnel is created so a bad token fails before anything is written.
+    validated_credentials: DashMap<String, String>,
+    recorder: Recorder,
 }
 
 #[derive(Debug)]
@@ -95,11 +165,16 @@ enum TunnelAction {
     Sync,
 }
 
-impl From<&Arc<Tunnel>> for TunnelAction {
-    fn from(s: &Arc<Tunnel>) -> TunnelAction {
+// INFO: The finalizer goes on before the Cloudflare tunnel is created, the create path runs
+// until it recorded the tunnel as provisioned.
+impl<K: TunnelResource> From<&Arc<K>> for TunnelAction {
+    fn from(s: &Arc<K>) -> TunnelAction {
+        let provisioned = s
+            .tunnel_status()
+            .is_some_and(|status| status.milestones.tunnel_provisioned.is_some());
         if s.meta().deletion_timestamp.is_some() {
             TunnelAction::Delete
-        } else if s.meta().finalizers.is_none() {
+        } else if s.meta().finalizers.is_none() || !provisioned {
             TunnelAction::Create
         } else {
             TunnelAction::Sync
@@ -107,24 +182,637 @@ impl From<&Arc<Tunnel>> for TunnelAction {
     }
 }
 
+// INFO: Only the Secrets this operator created are watched, the token Secret is restored by the
+// reconcile its change or deletion triggers. Its owner reference maps it back to the tunnel.
+fn managed_by_selector() -> String {
+    format!("{}={}", MANAGED_BY_LABEL, MANAGED_BY)
+}
+
+fn resource_labels(name: &str) -> BTreeMap<String, String> {
+    let mut labels = BTreeMap::new();
+    labels.insert(NAME_LABEL.into(), name.to_owned());
+    labels.insert(MANAGED_BY_LABEL.into(), MANAGED_BY.into());
+    labels
+}
+
+// INFO: Remotely managed tunnels get the token as an environment variable, local ones the
+// credentials file decoded from it.
+fn tunnel_secrets(spec: &TunnelCrd, token: &str) -> Result<BTreeMap<String, ByteString>, Error> {
+    let mut secrets = BTreeMap::new();
+    if spec.is_local() {
+        let credentials_file = CredentialsFile::from_token(token)?;
+        secrets.insert(
+            CREDENTIALS_FILE.to_owned(),
+            ByteString(serde_json::to_vec(&credentials_file).map_err(LocalConfigError::from)?),
+        );
+    } else {
+        secrets.insert(
+            "TUNNEL_TOKEN".to_owned(),
+            ByteString(token.to_owned().into_bytes()),
+        );
+    }
+
+    Ok(secrets)
+}
+
+// INFO: spec.tunnelRef.name is a selectable field so only the TunnelIngress objects of the
+// tunnel are listed. Api servers before 1.30 reject the field selector, the store is scanned
+// instead. A Tunnel can be referenced from other namespaces so the list is cluster wide.
+async fn tunnel_ingresses<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+) -> Result<Vec<TunnelIngress>, Error> {
+    let tunnel_ingress_api: Api<TunnelIngress> = Api::all(ctx.kubernetes_client.clone());
+    let selector = format!("spec.tunnelRef.name={}", generator.name_any());
+
+    match tunnel_ingress_api
+        .list(&ListParams::default().fields(&selector))
+        .await
+    {
+        Ok(tunnel_ingresses) => Ok(tunnel_ingresses
+            .items
+            .into_iter()
+            .filter(|tunnel_ingress| tunnel_ingress.references(generator))
+            .collect()),
+        Err(kube::Error::Api(response)) if response.code == 400 => Ok(ctx
+            .tunnel_ingress_store
+            .state()
+            .into_iter()
+            .filter(|tunnel_ingress| tunnel_ingress.references(generator))
+            .map(|tunnel_ingress| (*tunnel_ingress).clone())
+            .collect()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+// INFO: The tunnel of kind `K` a TunnelIngress routes through, a change to it reconciles that
+// tunnel. The controller queues every tunnel once however many of its TunnelIngress objects
+// changed, so a burst of changes ends in one aggregated reconcile.
+fn referenced_tunnel<K: TunnelResource>(tunnel_ingress: &TunnelIngress) -> Option<ObjectRef<K>> {
+    let tunnel_ref = &tunnel_ingress.spec.tunnel_ref;
+    (tunnel_ref.kind.as_str() == K::kind(&())).then(|| {
+        let mut obj_ref = ObjectRef::new(&tunnel_ref.name);
+        obj_ref.namespace = tunnel_ingress.tunnel_namespace();
+        obj_ref
+    })
+}
+
+async fn tunnel_ingress_problems<K: TunnelResource, C: CloudflaredTunnel>(
+    tunnel_ingress: &TunnelIngress,
+    generator: &K,
+    ctx: &Context<C>,
+) -> Result<Vec<String>, Error> {
+    let mut problems = tunnel_ingress.validate();
+    problems
+        .extend(tunnel_ingress.cross_namespace_denial(generator, ctx.allow_cross_namespace_refs));
+
+    // INFO: cloudflared only fails at request time on an unknown host, a missing Service is
+    // caught here instead.
+    for (namespace, name) in tunnel_ingress.service_refs() {
+        let service_api: Api<Service> = Api::namespaced(ctx.kubernetes_client.clone(), &namespace);
+        if service_api.get_opt(&name).await?.is_none() {
+            problems.push(format!("references missing Service {}/{}", namespace, name));
+        }
+    }
+    Ok(problems)
+}
+
+// INFO: Renders config.yaml from the rules of every TunnelIngress referencing the tunnel.
+async fn local_config<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    tunnel_id: Uuid,
+) -> Result<BTreeMap<String, String>, Error> {
+    // INFO: An invalid TunnelIngress is left out instead of breaking the whole tunnel, its
+    // status says why.
+    let mut accepted = Vec::new();
+    for tunnel_ingress in tunnel_ingresses(generator, ctx).await? {
+        if tunnel_ingress_problems(&tunnel_ingress, generator, ctx)
+            .await?
+            .is_empty()
+        {
+            accepted.push(tunnel_ingress);
+        }
+    }
+
+    let rules: Vec<IngressRule> = accepted
+        .iter()
+        .flat_map(|tunnel_ingress| tunnel_ingress.ingress_rules())
+        .collect();
+
+    let rules = normalize_origin_settings(rules).map_err(|conflicts| {
+        Error::InvalidSpec(
+            conflicts
+                .iter()
+                .map(ToString::to_string)
+                .collect::<Vec<_>>()
+                .join(", "),
+        )
+    })?;
+
+    let config = render_config(
+        tunnel_id,
+        CREDENTIALS_PATH,
+        &rules,
+        generator.tunnel_spec().catch_all_service(),
+    )?;
+    Ok(BTreeMap::from([(LOCAL_CONFIG_FILE.to_owned(), config)]))
+}
+
+// INFO: Records on every TunnelIngress referencing the tunnel whether its rules made it into the
+// rendered config.yaml. Only written when something changed since the tunnel controllers watch
+// TunnelIngress objects and every status write triggers another reconcile.
+async fn record_ingress_sync<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    error: Option<&Error>,
+) -> Result<(), Error> {
+    let message = error.map(ToString::to_string);
+    for tunnel_ingress in tunnel_ingresses(generator, ctx).await? {
+        let current = tunnel_ingress.status.clone().unwrap_or_default();
+        let status = current.after_sync(
+            tunnel_ingress.metadata.generation,
+            &tunnel_ingress_problems(&tunnel_ingress, generator, ctx).await?,
+            message.clone(),
+        );
+        if !current.differs(&status) {
+            continue;
+        }
+
+        let tunnel_ingress_api: Api<TunnelIngress> = Api::namespaced(
+            ctx.kubernetes_client.clone(),
+            &tunnel_ingress.namespace().unwrap_or_default(),
+        );
+        tunnel_ingress_api
+            .patch_status(
+                &tunnel_ingress.name_any(),
+                &patch_params(),
+                &Patch::Merge(json!({ "status": status })),
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+// INFO: `local_config` with a failure recorded on the TunnelIngress objects it was built from.
+async fn synced_local_config<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    tunnel_id: Uuid,
+) -> Result<BTreeMap<String, String>, Error> {
+    match local_config(generator, ctx, tunnel_id).await {
+        Ok(config) => Ok(config),
+        Err(err) => {
+            record_ingress_sync(generator, ctx, Some(&err)).await?;
+            Err(err)
+        }
+    }
+}
+
+// INFO: Writes the milestones reached for the first time. Earlier status writes of the same
+// reconcile made `generator` stale, so the object is read again once there is something new.
+// The patch carries the resourceVersion the decision was made on so a milestone can't be
+// written twice, a conflict just leaves it to the next reconcile.
+async fn record_milestones<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    reached: &[Milestone],
+) -> Result<(), Error> {
+    let cached = generator.tunnel_status().map(|status| &status.milestones);
+    if cached.is_some_and(|milestones| {
+        [Milestone::Created]
+            .iter()
+            .chain(reached)
+            .all(|milestone| milestones.get(*milestone).is_some())
+    }) {
+        return Ok(());
+    }
+
+    let generator = generator
+        .api(ctx.kubernetes_client.clone())
+        .get(&generator.name_any())
+        .await?;
+    let mut milestones = generator
+        .tunnel_status()
+        .map(|status| status.milestones.clone())
+        .unwrap_or_default();
+
+    let created = generator
+        .meta()
+        .creation_timestamp
+        .as_ref()
+        .map_or_else(Utc::now, |time| time.0);
+    let mut transitions: Vec<_> = milestones
+        .reach(Milestone::Created, created)
+        .into_iter()
+        .collect();
+
+    let now = Utc::now();
+    transitions.extend(
+        reached
+            .iter()
+            .filter_map(|milestone| milestones.reach(*milestone, now)),
+    );
+
+    if transitions.is_empty() {
+        return Ok(());
+    }
+
+    let patch = json!({
+        "metadata": { "resourceVersion": generator.resource_version() },
+        "status": { "milestones": milestones },
+    });
+    match generator
+        .api(ctx.kubernetes_client.clone())
+        .patch_status(
+            &generator.name_any(),
+            &patch_params(),
+            &Patch::Merge(&patch),
+        )
+        .await
+    {
+        Ok(_) => {}
+        Err(kube::Error::Api(err)) if err.code == 409 => return Ok(()),
+        Err(err) => return Err(Error::KubeError(err)),
+    }
+
+    for transition in transitions {
+        if let (Some(histogram), Some(millis)) = (
+            metrics::milestone_histogram(transition.milestone),
+            transition.since_previous_millis,
+        ) {
+            histogram.observe(millis);
+        }
+
+        if let Some(millis) = transition.time_to_ready_millis {
+            println!(
+                "Tunnel {} ready {}s after creation",
+                generator.name_any(),
+                millis / 1000
+            );
+            metrics::time_to_ready().observe(millis);
+        }
+    }
+
+    Ok(())
+}
+
 #[inline]
-pub async fn create_tunnel(generator: Arc<Tunnel>, ctx: Arc<Context>) -> Result<Action, Error> {
+// INFO: Rotates the Cloudflare tunnel secret when spec.tunnelSecret no longer matches the one
+// recorded in status and rewrites the Secret with the new token, the token hash annotation then
+// restarts the Deployment. Tunnels from before the hash was recorded only get a baseline.
+async fn rotate_tunnel_secret<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    tunnel_id: Uuid,
+    namespace: &str,
+) -> Result<(), Error> {
+    let spec = generator.tunnel_spec();
+    let desired = spec.tunnel_secret_hash();
+    let current = generator
+        .tunnel_status()
+        .and_then(|status| status.tunnel_secret_hash.as_deref());
+
+    let tunnel_secret = match (current, spec.tunnel_secret.as_ref()) {
+        (Some(current), _) if current == desired => return Ok(()),
+        (Some(_), Some(tunnel_secret)) => tunnel_secret,
+        // INFO: Nothing to rotate to once the field is removed, Cloudflare keeps the last one.
+        _ => {
+            patch_status(
+                generator,
+                ctx.kubernetes_client.clone(),
+                json!({ "tunnelSecretHash": desired }),
+            )
+            .await?;
+            return Ok(());
+        }
+    };
+
+    println!("Rotating secret of tunnel {}", generator.name_any());
+    let (account_id, credentials) = ctx
+        .credentials_api
+        .get_credentials(&spec.credentials)
+        .await?;
+    ctx.cloudflare_client
+        .rotate_tunnel_secret(
+            &credentials,
+            &account_id,
+            tunnel_id,
+            tunnel_secret.as_bytes(),
+        )
+        .await?;
+
+    ctx.token_cache.invalidate(tunnel_id);
+    let tunnel_token = tunnel_token(ctx, &credentials, &account_id, tunnel_id).await?;
+    secret::apply(
+        ctx.kubernetes_client.clone(),
+        generator,
+        namespace,
+        resource_labels(&generator.child_name()),
+        tunnel_secrets(spec, &tunnel_token)?,
+    )
+    .await?;
+
+    patch_status(
+        generator,
+        ctx.kubernetes_client.clone(),
+        json!({ "tunnelSecretHash": desired }),
+    )
+    .await?;
+
+    Ok(())
+}
+
+// INFO: An adopted tunnel (spec.uuid carried over to a recreated object) keeps the name it was
+// created with, it's renamed after metadata.name. Cloudflare is only asked once the recorded
+// name differs.
+async fn rename_tunnel<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    tunnel_id: Uuid,
+) -> Result<(), Error> {
+    let name = generator.cloudflare_name();
+    let recorded = generator
+        .tunnel_status()
+        .and_then(|status| status.cloudflare_name.as_deref());
+    if recorded == Some(name.as_str()) {
+        return Ok(());
+    }
+
+    let (account_id, credentials) = ctx
+        .credentials_api
+        .get_credentials(&generator.tunnel_spec().credentials)
+        .await?;
+    let tunnel = ctx
+        .cloudflare_client
+        .get_tunnel(&credentials, &account_id, tunnel_id.to_string().as_ref())
+        .await?;
+    if tunnel.name != name {
+        println!(
+            "Renaming tunnel {} from {} to {}",
+            tunnel_id, tunnel.name, name
+        );
+        ctx.cloudflare_client
+            .rename_tunnel(&credentials, &account_id, tunnel_id, &name)
+            .await?;
+    }
+
+    patch_status(
+        generator,
+        ctx.kubernetes_client.clone(),
+        json!({ "cloudflareName": name }),
+    )
+    .await?;
+
+    Ok(())
+}
+
+// INFO: Served from the token cache when possible, a 401 drops the cached token since the
+// credentials or the token itself changed.
+async fn tunnel_token<C: CloudflaredTunnel>(
+    ctx: &Context<C>,
+    credentials: &CloudflareCredentials,
+    account_id: &str,
+    tunnel_id: Uuid,
+) -> Result<String, Error> {
+    if let Some(token) = ctx.token_cache.get(tunnel_id) {
+        return Ok(token);
+    }
+
+    match ctx
+        .cloudflare_client
+        .get_tunnel_token(credentials, account_id, tunnel_id.to_string().as_ref())
+        .await
+    {
+        Ok(token) => {
+            let token: String = token.into();
+            ctx.token_cache.insert(tunnel_id, token.clone());
+            Ok(token)
+        }
+        Err(err) => {
+            if err.kind() == FailureKind::Api(StatusCode::UNAUTHORIZED) {
+                ctx.token_cache.invalidate(tunnel_id);
+            }
+            Err(Error::CloudflareApiFailure(err))
+        }
+    }
+}
+
+// INFO: Cloudflare is only asked again after a spec edit the last completed sync hasn't seen or
+// once the resync interval passed, everything else is served from the live Secret.
+fn cloudflare_sync_due<K: TunnelResource>(generator: &K) -> bool {
+    let status = match generator.tunnel_status() {
+        Some(status) => status,
+        None => return true,
+    };
+
+    if status.observed_generation != generator.meta().generation {
+        return true;
+    }
+
+    let last_sync = match status
+        .last_cloudflare_sync
+        .as_deref()
+        .and_then(|last_sync| DateTime::parse_from_rfc3339(last_sync).ok())
+    {
+        Some(last_sync) => last_sync.with_timezone(&Utc),
+        None => return true,
+    };
+
+    (Utc::now() - last_sync)
+        .to_std()
+        .is_ok_and(|elapsed| elapsed >= CLOUDFLARE_RESYNC_INTERVAL)
+}
+
+// INFO: Rewrites the Secret when the token Cloudflare hands out no longer matches it, returns the
+// hash of the Secret data that is now in place.
+async fn refresh_tunnel_token<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    tunnel_id: Uuid,
+    namespace: &str,
+    token_hash: String,
+) -> Result<String, Error> {
+    let spec = generator.tunnel_spec();
+    let (account_id, credentials) = ctx
+        .credentials_api
+        .get_credentials(&spec.credentials)
+        .await?;
+    // INFO: This is the check against Cloudflare, a cached token would defeat it.
+    ctx.token_cache.invalidate(tunnel_id);
+    let tunnel_token = tunnel_token(ctx, &credentials, &account_id, tunnel_id).await?;
+
+    let secrets = tunnel_secrets(spec, &tunnel_token)?;
+    let desired_hash = secret::data_hash(&secrets);
+    if desired_hash != token_hash {
+        println!(
+            "Token of tunnel {} changed in Cloudflare, updating its Secret",
+            generator.name_any()
+        );
+        secret::apply(
+            ctx.kubernetes_client.clone(),
+            generator,
+            namespace,
+            resource_labels(&generator.child_name()),
+            secrets,
+        )
+        .await?;
+    }
+
+    patch_status(
+        generator,
+        ctx.kubernetes_client.clone(),
+        json!({ "lastCloudflareSync": Utc::now().to_rfc3339() }),
+    )
+    .await?;
+
+    Ok(desired_hash)
+}
+
+// INFO: The Secret was deleted or written for the other configuration source. The tunnel still
+// exists, so only its token is fetched again. Pods stuck on the missing Secret start once it is
+// back, a changed token rolls the Deployment through the token hash annotation.
+async fn recreate_secret<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    tunnel_id: Uuid,
+    namespace: &str,
+) -> Result<String, Error> {
+    let spec = generator.tunnel_spec();
+    let (account_id, credentials) = ctx
+        .credentials_api
+        .get_credentials(&spec.credentials)
+        .await?;
+    ctx.token_cache.invalidate(tunnel_id);
+    let tunnel_token = tunnel_token(ctx, &credentials, &account_id, tunnel_id).await?;
+
+    let secrets = tunnel_secrets(spec, &tunnel_token)?;
+    let token_hash = secret::data_hash(&secrets);
+    secret::apply(
+        ctx.kubernetes_client.clone(),
+        generator,
+        namespace,
+        resource_labels(&generator.child_name()),
+        secrets,
+    )
+    .await?;
+
+    let note = format!("Secret {}/{} recreated", namespace, generator.child_name());
+    println!("{}", note);
+    let event = Event {
+        type_: EventType::Normal,
+        reason: "SecretRecreated".into(),
+        note: Some(note),
+        action: "Sync".into(),
+        secondary: None,
+    };
+    if let Err(err) = ctx
+        .recorder
+        .publish(&event, &generator.object_ref(&()))
+        .await
+    {
+        println!("Failed to publish secret event: {}", err);
+    }
+
+    Ok(token_hash)
+}
+
+// INFO: Finds a tunnel an earlier pass created but never got into spec.uuid, through the id in
+// status when only the spec patch failed or by name when the process died mid create.
+async fn recover_tunnel<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    credentials: &CloudflareCredentials,
+    account_id: &str,
+) -> Result<Option<CloudflareTunnel>, Error> {
+    let status = match generator.tunnel_status() {
+        Some(status) => status,
+        None => return Ok(None),
+    };
+
+    if let Some(tunnel_id) = status.tunnel_id {
+        let tunnel = ctx
+            .cloudflare_client
+            .get_tunnel(credentials, account_id, tunnel_id.to_string().as_ref())
+            .await?;
+        return Ok(Some(tunnel));
+    }
+
+    if status.create_requested_at.is_some() {
+        let tunnel = ctx
+            .cloudflare_client
+            .find_tunnel_by_name(credentials, account_id, &generator.cloudflare_name())
+            .await?;
+        if let Some(tunnel) = &tunnel {
+            println!(
+                "Reusing tunnel {} left over from an interrupted create of {}",
+                tunnel.id,
+                generator.name_any()
+            );
+        }
+        return Ok(tunnel);
+    }
+
+    Ok(None)
+}
+
+// INFO: Only asks Cloudflare again once the Credentials object changed.
+async fn ensure_credentials_validated<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    credentials: &CloudflareCredentials,
+    account_id: &str,
+) -> Result<(), Error> {
+    let name = &generator.tunnel_spec().credentials;
+    let resource_version = match ctx.credentials_api.get_opt(name).await? {
+        Some(credentials) => credentials.resource_version().unwrap_or_default(),
+        None => return Err(Error::MissingCredentials(name.clone())),
+    };
+    if ctx
+        .validated_credentials
+        .get(name)
+        .is_some_and(|validated| *validated == resource_version)
+    {
+        return Ok(());
+    }
+
+    validate_credentials(credentials, account_id, &ctx.cloudflare_client).await?;
+    ctx.credentials_api.verify_account(name).await?;
+    ctx.validated_credentials
+        .insert(name.clone(), resource_version);
+
+    Ok(())
+}
+
+pub async fn create_tunnel<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: Arc<K>,
+    ctx: Arc<Context<C>>,
+) -> Result<Action, Error> {
+    generator.tunnel_spec().validate()?;
+
     let name = generator.name_any();
-    let namespace = generator.metadata.namespace.clone().unwrap();
+    let namespace = generator.child_namespace(&ctx.cluster_tunnel_namespace);
     let (account_id, credentials) = ctx
         .credentials_api
-        .get_credentials(&generator.spec.credentials)
+        .get_credentials(&generator.tunnel_spec().credentials)
         .await?;
 
+    ensure_credentials_validated(generator.as_ref(), &ctx, &credentials, &account_id).await?;
+
+    // INFO: The finalizer goes on before anything exists on Cloudflare, a delete arriving
+    // mid create then waits for the delete path instead of dropping the object and leaking.
+    add_finalizer(generator.as_ref(), ctx.kubernetes_client.clone()).await?;
+
     let tunnel_secret = generator
-        .spec
+        .tunnel_spec()
         .tunnel_secret
         .as_ref()
         .map(|bytes| bytes.as_bytes());
 
     // INFO: Gets or creates a tunnel and requeues the tunnel crd if a tunnel is created to get the
     // latest metadata from kubernetes.
-    let tunnel = match generator.spec.uuid {
+    let tunnel = match generator.get_uuid() {
         Some(uuid) => match ctx
             .cloudflare_client
             .get_tunnel(&credentials, &account_id, uuid.to_string().as_ref())
@@ -134,88 +822,153 @@ pub async fn create_tunnel(generator: Arc<Tunnel>, ctx: Arc<Context>) -> Result<
             Err(err) => return Err(Error::CloudflareApiFailure(err)),
         },
 
-        None => match ctx
-            .cloudflare_client
-            .create_tunnel(
-                &credentials,
-                &account_id,
-                &name,
-                tunnel_secret,
-                ConfigurationSrc::Cloudflare,
-            )
-            .await
-        {
-            Ok(tunnel) => {
-                let crd_api: Api<Tunnel> =
-                    Api::namespaced(ctx.kubernetes_client.clone(), &namespace);
-
-                let mut crd = (*generator).clone();
-                crd.spec.uuid = Some(tunnel.id);
-                let patch: Patch<Tunnel> = Patch::Merge(crd);
-                match crd_api.patch(&name, &PatchParams::default(), &patch).await {
-                    Ok(_) => return Ok(Action::requeue(std::time::Duration::from_secs(0))),
-                    Err(err) => return Err(Error::KubeError(err)),
-                }
-            }
-            Err(err) => return Err(Error::CloudflareApiFailure(err)),
-        },
-    };
+        None => {
+            let tunnel =
+                match recover_tunnel(generator.as_ref(), &ctx, &credentials, &account_id).await? {
+                    Some(tunnel) => tunnel,
+                    None => {
+                        patch_status(
+                            generator.as_ref(),
+                            ctx.kubernetes_client.clone(),
+                            json!({ "createRequestedAt": Utc::now().to_rfc3339() }),
+                        )
+                        .await?;
 
-    let tunnel_token: String = match ctx
-        .cloudflare_client
-        .get_tunnel_token(&credentials, &account_id, tunnel.id.to_string().as_ref())
-        .await
-    {
-        Ok(token) => token.into(),
-        Err(err) => return Err(Error::CloudflareApiFailure(err)),
+                        let tunnel = ctx
+                            .cloudflare_client
+                            .create_tunnel(
+                                &credentials,
+                                &account_id,
+                                &generator.cloudflare_name(),
+                                tunnel_secret,
+                                generator.tunnel_spec().configuration_source().into(),
+                            )
+                            .await?;
+
+                        patch_status(
+                            generator.as_ref(),
+                            ctx.kubernetes_client.clone(),
+                            json!({
+                                "tunnelId": tunnel.id,
+                                "tunnelSecretHash": generator.tunnel_spec().tunnel_secret_hash(),
+                                "cloudflareName": tunnel.name,
+                                "tunnelUrl": tunnel_url(tunnel.id),
+                            }),
+                        )
+                        .await?;
+                        tunnel
+                    }
+                };
+
+            let crd_api = generator.api(ctx.kubernetes_client.clone());
+
+            // INFO: Only spec.uuid is patched, the object read at the start of the reconcile is
+            // stale after the status writes above.
+            let patch = json!({ "spec": { "uuid": tunnel.id } });
+            match crd_api
+                .patch(&name, &patch_params(), &Patch::Merge(&patch))
+                .await
+            {
+                Ok(_) => return Ok(Action::requeue(std::time::Duration::from_secs(0))),
+                Err(err) => return Err(Error::KubeError(err)),
+            }
+        }
     };
 
-    let mut labels = BTreeMap::new();
-    labels.insert("app.kubernetes.io/name".into(), name.clone());
-    labels.insert(
-        "app.kubernetes.io/managed-by".into(),
-        "cloudflare-tunnel-operator".into(),
-    );
+    let tunnel_token = tunnel_token(&ctx, &credentials, &account_id, tunnel.id).await?;
+    sync_tunnel_url(generator.as_ref(), &ctx, tunnel.id).await?;
 
-    let mut secrets = BTreeMap::new();
-    secrets.insert(
-        "TUNNEL_TOKEN".to_owned(),
-        ByteString(tunnel_token.clone().into_bytes()),
-    );
+    let labels = resource_labels(&generator.child_name());
+    let secrets = tunnel_secrets(generator.tunnel_spec(), &tunnel_token)?;
+    let config = if generator.tunnel_spec().is_local() {
+        Some(synced_local_config(generator.as_ref(), &ctx, tunnel.id).await?)
+    } else {
+        None
+    };
 
     println!("Okay we should start creating our resources now!");
 
-    if let Err(err) = generator
-        .create_resources(ctx.kubernetes_client.clone(), labels, secrets)
-        .await
+    if let Err(err) = apply_resources(
+        generator.as_ref(),
+        ctx.kubernetes_client.clone(),
+        &namespace,
+        labels,
+        secrets,
+        config,
+        &resolve_image(generator.as_ref(), &ctx).await,
+    )
+    .await
     {
         return Err(Error::KubeError(err));
     }
 
     println!(
         "Successfully created Tunnel, name: {}, namespace: {}, UUID: {}",
-        name, namespace, tunnel_token
+        name, namespace, tunnel.id
     );
 
-    match generator.add_finalizer(ctx.kubernetes_client.clone()).await {
-        Ok(_) => Ok(Action::requeue(Duration::from_secs(RECONCILE_TIMER))),
-        Err(err) => Err(Error::KubeError(err)),
+    let mut reached = vec![Milestone::TunnelProvisioned];
+    if generator.tunnel_spec().is_local() {
+        record_ingress_sync(generator.as_ref(), &ctx, None).await?;
+        reached.push(Milestone::ConfigApplied);
     }
+    record_milestones(generator.as_ref(), &ctx, &reached).await?;
+
+    Ok(Action::requeue(Duration::from_secs(RECONCILE_TIMER)))
 }
 
 #[inline]
-async fn delete_tunnel(generator: Arc<Tunnel>, ctx: Arc<Context>) -> Result<Action, Error> {
-    if let Some(uuid) = generator.get_uuid() {
+async fn delete_tunnel<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: Arc<K>,
+    ctx: Arc<Context<C>>,
+) -> Result<Action, Error> {
+    if prevents_destroy(generator.as_ref()) {
+        return block_deletion(generator.as_ref(), &ctx).await;
+    }
+
+    let namespace = generator.child_namespace(&ctx.cluster_tunnel_namespace);
+    // INFO: A retained tunnel stays on Cloudflare for whatever takes it over, only the
+    // Kubernetes side goes away.
+    let retain =
+        generator.tunnel_spec().deletion_policy.unwrap_or_default() == DeletionPolicy::Retain;
+    if let (true, Some(uuid)) = (retain, generator.get_uuid()) {
+        tracing::warn!(
+            "Retaining Cloudflare tunnel {} of {}, it has to be deleted by hand",
+            uuid,
+            generator.name_any()
+        );
+    }
+
+    if let Some(uuid) = generator.get_uuid().filter(|_| !retain) {
         let (account_id, credentials) = ctx
             .credentials_api
-            .get_credentials(&generator.spec().credentials)
+            .get_credentials(&generator.tunnel_spec().credentials)
             .await?;
+        if !drain_connections(
+            generator.as_ref(),
+            &ctx,
+            &credentials,
+            &account_id,
+            uuid,
+            &namespace,
+        )
+        .await?
+        {
+            return Ok(Action::requeue(Duration::from_secs(CONNECTION_DRAIN_POLL)));
+        }
+
+        let cascade = generator.tunnel_spec().delete_cascade;
         if let Err(err) = ctx
             .cloudflare_client
-            .delete_tunnel(&credentials, &account_id, uuid)
+            .delete_tunnel(&credentials, &account_id, uuid, cascade)
             .await
         {
             match &err {
+                ApiFailure::Error(StatusCode::BAD_REQUEST | StatusCode::CONFLICT, _)
+                    if !cascade =>
+                {
+                    return connections_remain(generator.as_ref(), &ctx).await;
+                }
                 ApiFailure::Error(status, errors) => match *status {
                     StatusCode::NOT_FOUND => println!(
                         "Ignoring cloudflare NotFound errors while deleting tunnel, {:?}",
@@ -233,100 +986,1185 @@ async fn delete_tunnel(generator: Arc<Tunnel>, ctx: Arc<Context>) -> Result<Acti
         };
     };
 
-    if let Err(err) = generator
-        .delete_resources(ctx.kubernetes_client.clone())
-        .await
+    if let Err(err) = delete_resources(
+        generator.as_ref(),
+        ctx.kubernetes_client.clone(),
+        &namespace,
+    )
+    .await
     {
         return Err(Error::KubeError(err));
     }
 
     // This should be the last thing we do as the controller wont requeue this resource
     // again
-    match generator
-        .remove_finalizer(ctx.kubernetes_client.clone())
-        .await
-    {
+    match remove_finalizer(generator.as_ref(), ctx.kubernetes_client.clone()).await {
         Ok(_) => Ok(Action::await_change()),
         Err(err) => Err(Error::KubeError(err)),
     }
 }
 
-pub async fn reconciler(generator: Arc<Tunnel>, ctx: Arc<Context>) -> Result<Action, Error> {
-    let action = TunnelAction::from(&generator);
-    println!("Action: {:?}", &action);
-    match action {
-        TunnelAction::Create => create_tunnel(generator, ctx).await,
-        TunnelAction::Delete => delete_tunnel(generator, ctx).await,
-        TunnelAction::Sync => Ok(Action::requeue(Duration::from_secs(RECONCILE_TIMER))),
+// INFO: Compares the live state against the spec without changing anything, the tunnel has to
+// still exist on Cloudflare and the Deployment has to run the requested replicas.
+async fn detect_drift<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    namespace: &str,
+) -> Result<Vec<String>, Error> {
+    let mut drift = Vec::new();
+
+    if let Some(uuid) = generator.get_uuid() {
+        let (account_id, credentials) = ctx
+            .credentials_api
+            .get_credentials(&generator.tunnel_spec().credentials)
+            .await?;
+
+        if let Err(err) = ctx
+            .cloudflare_client
+            .get_tunnel(&credentials, &account_id, uuid.to_string().as_ref())
+            .await
+        {
+            match &err {
+                ApiFailure::Error(StatusCode::NOT_FOUND, _) => {
+                    drift.push(format!("tunnel {} no longer exists on Cloudflare", uuid))
+                }
+                _ => return Err(Error::CloudflareApiFailure(err)),
+            }
+        }
     }
-}
 
-pub fn on_err(_generator: Arc<Tunnel>, error: &Error, _ctx: Arc<Context>) -> Action {
-    println!("Error: {}", error);
-    match error {
-        Error::MissingCredentials(v) => {
-            println!("Missing credentials {}, requeuing in 120 seconds", v);
-            Action::requeue(Duration::from_secs(120))
+    let deployment_api: Api<Deployment> = Api::namespaced(ctx.kubernetes_client.clone(), namespace);
+    for (name, desired) in deployment::desired_replicas(generator) {
+        match deployment_api.get_opt(&name).await? {
+            Some(deployment) => {
+                let replicas = deployment.spec.and_then(|spec| spec.replicas);
+                if replicas != Some(desired) {
+                    drift.push(format!(
+                        "deployment {}/{} has {} replicas, spec wants {}",
+                        namespace,
+                        name,
+                        replicas.unwrap_or(1),
+                        desired
+                    ));
+                }
+            }
+            None => drift.push(format!("deployment {}/{} is missing", namespace, name)),
         }
-        _ => Action::await_change(),
     }
+
+    Ok(drift)
 }
 
-impl TunnelController {
-    pub async fn start(self) -> anyhow::Result<()> {
-        println!("Starting Tunnel Controller");
-        let deployment_api: Api<Deployment> = Api::all(self.kubernetes_client.clone());
-        let configmap_api: Api<ConfigMap> = Api::all(self.kubernetes_client.clone());
-        let secret_api: Api<Secret> = Api::all(self.kubernetes_client.clone());
-        let credentials_api: Api<Credentials> = Api::all(self.kubernetes_client.clone());
+async fn set_drift_detected<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    drift_detected: bool,
+) -> Result<(), Error> {
+    let current = generator
+        .tunnel_status()
+        .is_some_and(|status| status.drift_detected);
+    if current != drift_detected {
+        patch_status(
+            generator,
+            ctx.kubernetes_client.clone(),
+            json!({ "driftDetected": drift_detected }),
+        )
+        .await?;
+    }
 
-        let ctx = Arc::new(Context {
-            kubernetes_client: self.kubernetes_client,
-            cloudflare_client: self.cloudflare_client,
-            credentials_api,
-            tunnel_api: self.tunnel_api,
-        });
+    Ok(())
+}
 
-        self.controller
-            .owns(deployment_api, Config::default())
-            .owns(configmap_api, Config::default())
-            .owns(secret_api, Config::default())
-            .run(reconciler, on_err, ctx)
-            .for_each(|result| async move {
+// INFO: A False condition is only written when the condition was reported before, so tunnels
+// that never hibernate or pause don't pick up a status write. Returns whether it changed.
+async fn set_condition<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    condition: Condition,
+) -> Result<bool, Error> {
+    let mut conditions = generator
+        .tunnel_status()
+        .map(|status| status.conditions.clone())
+        .unwrap_or_default();
+
+    if condition.status == "False" && !conditions.iter().any(|c| c.type_ == condition.type_) {
+        return Ok(false);
+    }
+
+    if !Condition::set(&mut conditions, condition) {
+        return Ok(false);
+    }
+
+    patch_status(
+        generator,
+        ctx.kubernetes_client.clone(),
+        json!({ "conditions": conditions }),
+    )
+    .await?;
+
+    Ok(true)
+}
+
+async fn set_hibernated<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    hibernated: bool,
+) -> Result<(), Error> {
+    let condition = if hibernated {
+        Condition {
+            type_: HIBERNATED_CONDITION.to_owned(),
+            status: "True".to_owned(),
+            reason: Some("HibernateRequested".to_owned()),
+            message: Some(
+                "cloudflared is stopped, the tunnel and its configuration are kept".to_owned(),
+            ),
+            last_transition_time: None,
+        }
+    } else {
+        Condition {
+            type_: HIBERNATED_CONDITION.to_owned(),
+            status: "False".to_owned(),
+            reason: Some("Awake".to_owned()),
+            message: None,
+            last_transition_time: None,
+        }
+    };
+
+    set_condition(generator, ctx, condition).await?;
+
+    Ok(())
+}
+
+// INFO: Stops cloudflared first so the tunnel isn't deleted under live connectors. The wait is
+// measured from the deletion timestamp so requeues need no extra state, once it runs out the
+// stale connections are cleaned up and the cascading delete drops whatever is left.
+async fn drain_connections<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    credentials: &CloudflareCredentials,
+    account_id: &str,
+    tunnel_id: Uuid,
+    namespace: &str,
+) -> Result<bool, Error> {
+    deployment::scale_down(ctx.kubernetes_client.clone(), generator, namespace).await?;
+
+    let connections: usize = match ctx
+        .cloudflare_client
+        .list_connections(credentials, account_id, tunnel_id)
+        .await
+    {
+        Ok(connectors) => connectors
+            .iter()
+            .map(|connector| connector.conns.len())
+            .sum(),
+        Err(ApiFailure::Error(StatusCode::NOT_FOUND, _)) => return Ok(true),
+        Err(err) => return Err(Error::CloudflareApiFailure(err)),
+    };
+    if connections == 0 {
+        return Ok(true);
+    }
+
+    let waited = generator
+        .meta()
+        .deletion_timestamp
+        .as_ref()
+        .map(|deleted_at| Utc::now().signed_duration_since(deleted_at.0))
+        .unwrap_or_default();
+    if waited.num_seconds() < CONNECTION_DRAIN_TIMEOUT {
+        println!(
+            "Waiting for {} connections of tunnel {} to drain",
+            connections,
+            generator.name_any()
+        );
+        return Ok(false);
+    }
+
+    // INFO: Without cascade the delete is meant to fail on remaining connections.
+    if !generator.tunnel_spec().delete_cascade {
+        return Ok(true);
+    }
+
+    println!(
+        "{} connections of tunnel {} didn't drain, cleaning them up",
+        connections,
+        generator.name_any()
+    );
+    match ctx
+        .cloudflare_client
+        .cleanup_connections(credentials, account_id, tunnel_id)
+        .await
+    {
+        Ok(()) | Err(ApiFailure::Error(StatusCode::NOT_FOUND, _)) => Ok(true),
+        Err(err) => Err(Error::CloudflareApiFailure(err)),
+    }
+}
+
+// INFO: The delete is retried until the connectors are gone or spec.deleteCascade is turned on.
+async fn connections_remain<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+) -> Result<Action, Error> {
+    let note = "Cloudflare refused to delete the tunnel while connections remain and \
+                deleteCascade is false"
+        .to_owned();
+    println!("Tunnel {}: {}", generator.name_any(), note);
+
+    set_condition(
+        generator,
+        ctx,
+        Condition {
+            type_: DEGRADED_CONDITION.to_owned(),
+            status: "True".to_owned(),
+            reason: Some("ConnectionsRemain".to_owned()),
+            message: Some(note),
+            last_transition_time: None,
+        },
+    )
+    .await?;
+
+    Ok(Action::requeue(Duration::from_secs(RECONCILE_TIMER)))
+}
+
+pub(crate) fn prevents_destroy<K: TunnelResource>(generator: &K) -> bool {
+    generator
+        .annotations()
+        .get(PREVENT_DESTROY_ANNOTATION)
+        .is_some_and(|prevent| is_truthy(prevent))
+}
+
+// INFO: Leaves the Cloudflare tunnel, the children and the finalizer alone, removing the
+// annotation is a change to the object and lets the delete go through.
+async fn block_deletion<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+) -> Result<Action, Error> {
+    let note = format!(
+        "Deletion is blocked by the {} annotation, remove it to delete the tunnel",
+        PREVENT_DESTROY_ANNOTATION
+    );
+    println!("Tunnel {}: {}", generator.name_any(), note);
+
+    let changed = set_condition(
+        generator,
+        ctx,
+        Condition {
+            type_: DELETION_BLOCKED_CONDITION.to_owned(),
+            status: "True".to_owned(),
+            reason: Some("PreventDestroy".to_owned()),
+            message: Some(note.clone()),
+            last_transition_time: None,
+        },
+    )
+    .await?;
+
+    if changed {
+        let event = Event {
+            type_: EventType::Warning,
+            reason: "DeletionBlocked".into(),
+            note: Some(note),
+            action: "Delete".into(),
+            secondary: None,
+        };
+        if let Err(err) = ctx
+            .recorder
+            .publish(&event, &generator.object_ref(&()))
+            .await
+        {
+            println!("Failed to publish deletion event: {}", err);
+        }
+    }
+
+    Ok(Action::await_change())
+}
+
+pub(crate) fn is_paused<K: TunnelResource>(generator: &K) -> bool {
+    generator
+        .annotations()
+        .get(PAUSED_ANNOTATION)
+        .is_some_and(|paused| is_truthy(paused))
+}
+
+// INFO: Reports the Paused condition and publishes an event when it flips, nothing else is
+// touched while a tunnel is paused.
+async fn set_paused<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    paused: bool,
+) -> Result<(), Error> {
+    let (status, reason, note) = if paused {
+        (
+            "True",
+            "Paused",
+            format!(
+                "Reconciliation is suspended by the {} annotation",
+                PAUSED_ANNOTATION
+            ),
+        )
+    } else {
+        ("False", "Resumed", "Reconciliation resumed".to_owned())
+    };
+
+    let changed = set_condition(
+        generator,
+        ctx,
+        Condition {
+            type_: PAUSED_CONDITION.to_owned(),
+            status: status.to_owned(),
+            reason: Some(reason.to_owned()),
+            message: Some(note.clone()),
+            last_transition_time: None,
+        },
+    )
+    .await?;
+
+    if changed {
+        let event = Event {
+            type_: EventType::Normal,
+            reason: reason.into(),
+            note: Some(note),
+            action: "Reconcile".into(),
+            secondary: None,
+        };
+        if let Err(err) = ctx
+            .recorder
+            .publish(&event, &generator.object_ref(&()))
+            .await
+        {
+            println!("Failed to publish pause event: {}", err);
+        }
+    }
+
+    Ok(())
+}
+
+// INFO: A Tunnel asking for an image the policy ignores gets a warning on every apply, so the
+// owner notices the operator default is running instead.
+async fn resolve_image<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+) -> String {
+    let spec = generator.tunnel_spec();
+    if let Some(image) = ctx.image_policy.ignored_override(spec) {
+        let event = Event {
+            type_: EventType::Warning,
+            reason: "ImageOverrideIgnored".into(),
+            note: Some(format!(
+                "image overrides are disabled, running {} instead of {}",
+                ctx.image_policy.default_image, image
+            )),
+            action: "Reconcile".into(),
+            secondary: None,
+        };
+        if let Err(err) = ctx
+            .recorder
+            .publish(&event, &generator.object_ref(&()))
+            .await
+        {
+            println!("Failed to publish image event: {}", err);
+        }
+    }
+
+    ctx.image_policy.resolve(spec)
+}
+
+// INFO: Backs up the CEL transition rule on spec.uuid for API servers that don't enforce it, the
+// tunnel this object created stays the one it manages. Removing uuid is undone by recover_tunnel.
+async fn ensure_uuid_unchanged<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    tunnel_id: Uuid,
+) -> Result<(), Error> {
+    let created = match generator
+        .tunnel_status()
+        .and_then(|status| status.tunnel_id)
+    {
+        Some(created) if created != tunnel_id => created,
+        _ => return Ok(()),
+    };
+
+    let event = Event {
+        type_: EventType::Warning,
+        reason: "UuidChanged".into(),
+        note: Some(format!(
+            "spec.uuid was changed from {} to {}, set it back or delete and recreate the object to move it to another Cloudflare tunnel",
+            created, tunnel_id
+        )),
+        action: "Reconcile".into(),
+        secondary: None,
+    };
+    if let Err(err) = ctx
+        .recorder
+        .publish(&event, &generator.object_ref(&()))
+        .await
+    {
+        println!("Failed to publish uuid event: {}", err);
+    }
+
+    Err(Error::InvalidSpec(format!(
+        "uuid changed from {} to {}",
+        created, tunnel_id
+    )))
+}
+
+// INFO: Takes the workload down without touching the Cloudflare tunnel, its Secret or config,
+// applying the spec again on the next sync restores it.
+async fn hibernate<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: Arc<K>,
+    ctx: Arc<Context<C>>,
+    namespace: &str,
+    labels: BTreeMap<String, String>,
+    token_hash: &str,
+) -> Result<Action, Error> {
+    let deployment = match generator.tunnel_spec().hibernate_mode.unwrap_or_default() {
+        HibernateMode::ScaleToZero => {
+            // INFO: Applied from a copy so the rest of the pod template stays as the spec has it.
+            let mut hibernated = generator.as_ref().clone();
+            hibernated.tunnel_spec_mut().replicas = 0;
+            for region in hibernated.tunnel_spec_mut().regions.iter_mut().flatten() {
+                region.replicas = 0;
+            }
+            deployment::apply(
+                ctx.kubernetes_client.clone(),
+                &hibernated,
+                namespace,
+                labels,
+                token_hash,
+                None,
+                &resolve_image(generator.as_ref(), &ctx).await,
+            )
+            .await?
+        }
+        HibernateMode::Delete => {
+            deployment::delete(ctx.kubernetes_client.clone(), generator.as_ref(), namespace)
+                .await?;
+            Deployment::default()
+        }
+    };
+
+    sync_replica_status(generator.as_ref(), &ctx, &deployment).await?;
+    set_hibernated(generator.as_ref(), &ctx, true).await?;
+
+    Ok(Action::requeue(Duration::from_secs(RECONCILE_TIMER)))
+}
+
+// INFO: Tunnels created before status.tunnelUrl existed get it on their next reconcile.
+async fn sync_tunnel_url<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    tunnel_id: Uuid,
+) -> Result<(), Error> {
+    let url = tunnel_url(tunnel_id);
+    if generator
+        .tunnel_status()
+        .is_some_and(|status| status.tunnel_url.as_ref() == Some(&url))
+    {
+        return Ok(());
+    }
+
+    patch_status(
+        generator,
+        ctx.kubernetes_client.clone(),
+        json!({ "tunnelUrl": url }),
+    )
+    .await?;
+
+    Ok(())
+}
+
+// INFO: Mirrors the Deployment's replica counts so the scale subresource reports them, only
+// written when they change so the status patch doesn't retrigger reconciles.
+async fn sync_replica_status<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    deployment: &Deployment,
+) -> Result<(), Error> {
+    let (replicas, ready_replicas) = deployment.status.as_ref().map_or((None, None), |status| {
+        (status.replicas, status.ready_replicas)
+    });
+    let observed_generation = generator.meta().generation;
+
+    let current = generator.tunnel_status();
+    if current.is_some_and(|status| {
+        status.replicas == replicas
+            && status.ready_replicas == ready_replicas
+            && status.observed_generation == observed_generation
+    }) {
+        return Ok(());
+    }
+
+    patch_status(
+        generator,
+        ctx.kubernetes_client.clone(),
+        json!({
+            "replicas": replicas.unwrap_or_default(),
+            "readyReplicas": ready_replicas.unwrap_or_default(),
+            "observedGeneration": observed_generation,
+        }),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Edge connections Cloudflare lists for the connectors of the tunnel.
+async fn active_connections<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    tunnel_id: Uuid,
+) -> Result<i32, Error> {
+    let (account_id, credentials) = ctx
+        .credentials_api
+        .get_credentials(&generator.tunnel_spec().credentials)
+        .await?;
+    Ok(ctx
+        .cloudflare_client
+        .list_connections(&credentials, &account_id, tunnel_id)
+        .await?
+        .iter()
+        .map(|connector| connector.conns.len() as i32)
+        .sum::<i32>())
+}
+
+// INFO: The ingress controller writes the configuration of a remotely managed tunnel, it counts
+// as applied once Cloudflare holds any rule for it. Only asked until the milestone is reached.
+async fn remote_config_applied<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    tunnel_id: Uuid,
+) -> Result<bool, Error> {
+    if generator
+        .tunnel_status()
+        .and_then(|status| status.milestones.get(Milestone::ConfigApplied))
+        .is_some()
+    {
+        return Ok(false);
+    }
+
+    let (account_id, credentials) = ctx
+        .credentials_api
+        .get_credentials(&generator.tunnel_spec().credentials)
+        .await?;
+    let current = ctx
+        .cloudflare_client
+        .get_configuration(&credentials, &account_id, tunnel_id)
+        .await?;
+    Ok(current
+        .config
+        .is_some_and(|config| !config.ingress.is_empty()))
+}
+
+// INFO: Ready pods only say cloudflared is up, not that it reached Cloudflare's edge. The count
+// comes from Cloudflare and is only written when it changes.
+async fn sync_connection_status<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    active_connections: i32,
+    deployment: &Deployment,
+) -> Result<(), Error> {
+    if generator
+        .tunnel_status()
+        .and_then(|status| status.active_connections)
+        != Some(active_connections)
+    {
+        patch_status(
+            generator,
+            ctx.kubernetes_client.clone(),
+            json!({ "activeConnections": active_connections }),
+        )
+        .await?;
+    }
+
+    let running = deployment
+        .status
+        .as_ref()
+        .and_then(|status| status.ready_replicas)
+        .is_some_and(|ready| ready > 0);
+    let condition = if running && active_connections == 0 {
+        Condition {
+            type_: NO_ACTIVE_CONNECTIONS_CONDITION.to_owned(),
+            status: "True".to_owned(),
+            reason: Some("NotConnected".to_owned()),
+            message: Some(
+                "cloudflared is running but Cloudflare lists no connections for the tunnel"
+                    .to_owned(),
+            ),
+            last_transition_time: None,
+        }
+    } else {
+        Condition {
+            type_: NO_ACTIVE_CONNECTIONS_CONDITION.to_owned(),
+            status: "False".to_owned(),
+            reason: Some("Connected".to_owned()),
+            message: None,
+            last_transition_time: None,
+        }
+    };
+    set_condition(generator, ctx, condition).await?;
+
+    Ok(())
+}
+
+#[inline]
+async fn warn_drift<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: Arc<K>,
+    ctx: Arc<Context<C>>,
+) -> Result<Action, Error> {
+    let namespace = generator.child_namespace(&ctx.cluster_tunnel_namespace);
+    let drift = detect_drift(generator.as_ref(), &ctx, &namespace).await?;
+
+    if !drift.is_empty() {
+        let note = drift.join(", ");
+        tracing::warn!(
+            "Drift detected on tunnel {}, not correcting it: {}",
+            generator.name_any(),
+            note
+        );
+
+        let event = Event {
+            type_: EventType::Warning,
+            reason: "DriftDetected".into(),
+            note: Some(note),
+            action: "Sync".into(),
+            secondary: None,
+        };
+        if let Err(err) = ctx
+            .recorder
+            .publish(&event, &generator.object_ref(&()))
+            .await
+        {
+            println!("Failed to publish drift event: {}", err);
+        }
+    }
+
+    set_drift_detected(generator.as_ref(), &ctx, !drift.is_empty()).await?;
+
+    Ok(Action::requeue(Duration::from_secs(RECONCILE_TIMER)))
+}
+
+// INFO: Re-applies the deployment so spec changes roll out, server side apply makes this a
+// no-op when nothing changed. The token hash comes from the live Secret so a rotated token
+// rolls the pods.
+#[inline]
+async fn sync_tunnel<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: Arc<K>,
+    ctx: Arc<Context<C>>,
+) -> Result<Action, Error> {
+    generator.tunnel_spec().validate()?;
+
+    if generator.tunnel_spec().reconcile_policy.unwrap_or_default()
+        == ReconcilePolicy::DriftWarnOnly
+    {
+        return warn_drift(generator, ctx).await;
+    }
+
+    let tunnel_id = match generator.get_uuid() {
+        Some(tunnel_id) => tunnel_id,
+        None => return create_tunnel(generator, ctx).await,
+    };
+    ensure_uuid_unchanged(generator.as_ref(), &ctx, tunnel_id).await?;
+
+    // INFO: Credentials marked invalid changed their object, Cloudflare has to accept them again
+    // before they count as valid.
+    let (account_id, credentials) = ctx
+        .credentials_api
+        .get_credentials(&generator.tunnel_spec().credentials)
+        .await?;
+    ensure_credentials_validated(generator.as_ref(), &ctx, &credentials, &account_id).await?;
+
+    let namespace = generator.child_namespace(&ctx.cluster_tunnel_namespace);
+    rotate_tunnel_secret(generator.as_ref(), &ctx, tunnel_id, &namespace).await?;
+    rename_tunnel(generator.as_ref(), &ctx, tunnel_id).await?;
+
+    let secret_key = if generator.tunnel_spec().is_local() {
+        CREDENTIALS_FILE
+    } else {
+        "TUNNEL_TOKEN"
+    };
+    let secret = secret::get(
+        ctx.kubernetes_client.clone(),
+        generator.as_ref(),
+        &namespace,
+    )
+    .await?;
+    let secret_current = secret.as_ref().is_some_and(|secret| {
+        applied_by_current_version(&secret.metadata)
+            && has_spec_annotations(&secret.metadata, generator.as_ref())
+    });
+    let token_hash = match secret
+        .and_then(|secret| secret.data)
+        .filter(|data| data.contains_key(secret_key))
+    {
+        Some(data) => {
+            // INFO: The other children are applied on every sync, the Secret only when it
+            // changes, so one written by an older operator or missing annotations of the spec
+            // is applied again as it is.
+            if !secret_current {
+                secret::apply(
+                    ctx.kubernetes_client.clone(),
+                    generator.as_ref(),
+                    &namespace,
+                    resource_labels(&generator.child_name()),
+                    data.clone(),
+                )
+                .await?;
+            }
+            secret::data_hash(&data)
+        }
+        None => recreate_secret(generator.as_ref(), &ctx, tunnel_id, &namespace).await?,
+    };
+    let sync_due = cloudflare_sync_due(generator.as_ref());
+    let token_hash = if sync_due {
+        refresh_tunnel_token(generator.as_ref(), &ctx, tunnel_id, &namespace, token_hash).await?
+    } else {
+        token_hash
+    };
+
+    let labels = resource_labels(&generator.child_name());
+    if generator.tunnel_spec().hibernate || ctx.hibernate_all {
+        return hibernate(generator, ctx, &namespace, labels, &token_hash).await;
+    }
+
+    let config_hash = if generator.tunnel_spec().is_local() {
+        let config = synced_local_config(generator.as_ref(), &ctx, tunnel_id).await?;
+        let config_hash = configmap::data_hash(&config);
+        configmap::apply(
+            ctx.kubernetes_client.clone(),
+            generator.as_ref(),
+            &namespace,
+            labels.clone(),
+            config,
+        )
+        .await?;
+        record_ingress_sync(generator.as_ref(), &ctx, None).await?;
+        Some(config_hash)
+    } else {
+        None
+    };
+
+    serviceaccount::apply(
+        ctx.kubernetes_client.clone(),
+        generator.as_ref(),
+        &namespace,
+        labels.clone(),
+    )
+    .await?;
+
+    // INFO: A Deployment scaled by hand, including the ones of the regions, goes back to the
+    // replicas of the spec.
+    for (name, replicas) in deployment::desired_replicas(generator.as_ref()) {
+        if let Some(current) =
+            deployment::get(ctx.kubernetes_client.clone(), &namespace, &name).await?
+        {
+            let current_replicas = current.spec.and_then(|spec| spec.replicas);
+            if current_replicas != Some(replicas) {
+                println!(
+                    "Deployment {}/{} was scaled to {}, restoring {} replicas",
+                    namespace,
+                    name,
+                    current_replicas.unwrap_or(1),
+                    replicas
+                );
+                deployment::scale(ctx.kubernetes_client.clone(), &namespace, &name, replicas)
+                    .await?;
+            }
+        }
+    }
+
+    let deployment = deployment::apply(
+        ctx.kubernetes_client.clone(),
+        generator.as_ref(),
+        &namespace,
+        labels,
+        &token_hash,
+        config_hash.as_deref(),
+        &resolve_image(generator.as_ref(), &ctx).await,
+    )
+    .await?;
+
+    // INFO: Connections are counted with the rest of the Cloudflare sync, in between the last
+    // count in the status stands.
+    let active_connections = if sync_due {
+        active_connections(generator.as_ref(), &ctx, tunnel_id).await?
+    } else {
+        generator
+            .tunnel_status()
+            .and_then(|status| status.active_connections)
+            .unwrap_or_default()
+    };
+
+    let mut reached = vec![Milestone::TunnelProvisioned];
+    if config_hash.is_some() || remote_config_applied(generator.as_ref(), &ctx, tunnel_id).await? {
+        reached.push(Milestone::ConfigApplied);
+    }
+    if deployment
+        .status
+        .as_ref()
+        .and_then(|status| status.ready_replicas)
+        .is_some_and(|ready| ready > 0)
+    {
+        reached.push(Milestone::WorkloadReady);
+    }
+    // INFO: DNS records aren't managed by the operator, a record pointing at the tunnelUrl
+    // resolves to a working route once the edge holds connections for the tunnel.
+    if active_connections > 0 {
+        reached.push(Milestone::DnsReady);
+    }
+    record_milestones(generator.as_ref(), &ctx, &reached).await?;
+    sync_replica_status(generator.as_ref(), &ctx, &deployment).await?;
+    sync_connection_status(generator.as_ref(), &ctx, active_connections, &deployment).await?;
+    set_hibernated(generator.as_ref(), &ctx, false).await?;
+
+    // INFO: Anything drifted was just corrected, clears a flag left over from DriftWarnOnly.
+    set_drift_detected(generator.as_ref(), &ctx, false).await?;
+
+    Ok(Action::requeue(Duration::from_secs(RECONCILE_TIMER)))
+}
+
+pub async fn reconciler<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: Arc<K>,
+    ctx: Arc<Context<C>>,
+) -> Result<Action, Error> {
+    let object = ObjectRef::from_obj(generator.as_ref()).erase();
+
+    // INFO: A delete can arrive while a slow create of the same object is still waiting on
+    // Cloudflare. Once the lock is held the object is read again, so a delete queued behind a
+    // create sees the uuid it wrote and removes that tunnel instead of leaking it.
+    let guard = ctx.locks.lock(object.clone()).await;
+    let generator = match generator
+        .api(ctx.kubernetes_client.clone())
+        .get_opt(&generator.name_any())
+        .await?
+    {
+        Some(generator) => Arc::new(generator),
+        None => {
+            drop(guard);
+            ctx.locks.forget(&object);
+            ctx.backoff.reset(&object);
+            return Ok(Action::await_change());
+        }
+    };
+
+    // INFO: Left alone by the first reconcile after a restart when the previous run already
+    // reconciled this version, the periodic resync still gets to it.
+    let resource_version = generator
+        .meta()
+        .resource_version
+        .clone()
+        .unwrap_or_default();
+    if ctx.state.unchanged(&object, &resource_version) {
+        println!(
+            "Tunnel {} is unchanged since the last run, skipping",
+            generator.name_any()
+        );
+        return Ok(Action::requeue(Duration::from_secs(RECONCILE_TIMER)));
+    }
+
+    let action = TunnelAction::from(&generator);
+    println!("Action: {:?}", &action);
+
+    // INFO: Deletes still go through while paused so the finalizer can't hold the object forever.
+    if !matches!(action, TunnelAction::Delete) {
+        let paused = is_paused(generator.as_ref());
+        set_paused(generator.as_ref(), &ctx, paused).await?;
+        if paused {
+            println!("Tunnel {} is paused, skipping", generator.name_any());
+            ctx.backoff.reset(&object);
+            return Ok(Action::await_change());
+        }
+    }
+    let deleting = matches!(action, TunnelAction::Delete);
+    let result = match action {
+        TunnelAction::Create => create_tunnel(generator.clone(), ctx.clone()).await,
+        TunnelAction::Delete => delete_tunnel(generator.clone(), ctx.clone()).await,
+        TunnelAction::Sync => sync_tunnel(generator.clone(), ctx.clone()).await,
+    };
+    drop(guard);
+
+    if result.is_ok() {
+        ctx.backoff.reset(&object);
+        if deleting {
+            ctx.locks.forget(&object);
+        }
+    }
+
+    if result.is_ok() {
+        record_state(generator.as_ref(), &ctx, &object, deleting).await;
+    }
+    record_credentials_outcome(generator.as_ref(), &ctx, &result).await;
+    result
+}
+
+// INFO: The status writes of the reconcile bumped the resourceVersion, the latest one is read
+// back so the next run can compare against it.
+async fn record_state<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    object: &ObjectRef<DynamicObject>,
+    deleting: bool,
+) {
+    if !ctx.state.is_enabled() {
+        return;
+    }
+    if deleting {
+        return ctx.state.forget(object).await;
+    }
+
+    match generator
+        .api(ctx.kubernetes_client.clone())
+        .get_opt(&generator.name_any())
+        .await
+    {
+        Ok(Some(current)) => {
+            let tunnel = ReconciledTunnel {
+                resource_version: current.meta().resource_version.clone().unwrap_or_default(),
+                tunnel_id: current.get_uuid(),
+                status: current.tunnel_status().cloned(),
+            };
+            ctx.state.record(object, tunnel).await
+        }
+        Ok(None) => ctx.state.forget(object).await,
+        Err(err) => println!(
+            "Failed to read back {} for the operator state: {}",
+            generator.name_any(),
+            err
+        ),
+    }
+}
+
+// INFO: Surfaces rejected credentials on the Credentials object instead of only in the logs of
+// every tunnel using them. Failing to write the status doesn't fail the reconcile.
+async fn record_credentials_outcome<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: &K,
+    ctx: &Context<C>,
+    result: &Result<Action, Error>,
+) {
+    let name = &generator.tunnel_spec().credentials;
+    let recorded = match result {
+        Err(Error::InvalidCredentials(message)) => {
+            ctx.credentials_api
+                .invalidate_credentials(name, false, message.clone())
+                .await
+        }
+        Err(Error::CloudflareApiFailure(err)) => match err.kind() {
+            FailureKind::Api(status @ (StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN)) => {
+                ctx.credentials_api
+                    .invalidate_credentials(name, status == StatusCode::FORBIDDEN, err.to_string())
+                    .await
+            }
+            _ => Ok(()),
+        },
+        _ => Ok(()),
+    };
+
+    if let Err(err) = recorded {
+        println!("Failed to update status of credentials {}: {}", name, err);
+    }
+}
+
+pub fn on_err<K: TunnelResource, C: CloudflaredTunnel>(
+    generator: Arc<K>,
+    error: &Error,
+    ctx: Arc<Context<C>>,
+) -> Action {
+    println!("Error: {}", error);
+    if let (Error::CloudflareApiFailure(err), Some(tunnel_id)) = (error, generator.get_uuid()) {
+        if err.kind() == FailureKind::Api(StatusCode::UNAUTHORIZED) {
+            ctx.token_cache.invalidate(tunnel_id);
+        }
+    }
+    let delay = ctx
+        .backoff
+        .next(ObjectRef::from_obj(generator.as_ref()).erase());
+    match error {
+        Error::MissingCredentials(v) => println!(
+            "Missing credentials {}, requeuing in {} seconds",
+            v,
+            delay.as_secs()
+        ),
+        _ => println!("Requeuing in {} seconds", delay.as_secs()),
+    }
+
+    Action::requeue(delay)
+}
+
+impl<C: CloudflaredTunnel + 'static> TunnelController<C> {
+    pub async fn start(self) -> anyhow::Result<()> {
+        println!("Starting Tunnel Controller");
+        let deployment_api: Api<Deployment> = Api::all(self.kubernetes_client.clone());
+        let configmap_api: Api<ConfigMap> = Api::all(self.kubernetes_client.clone());
+        let secret_api: Api<Secret> = Api::all(self.kubernetes_client.clone());
+        let sa_api: Api<ServiceAccount> = Api::all(self.kubernetes_client.clone());
+        let credentials_api: Api<Credentials> = Api::all(self.kubernetes_client.clone());
+        let tunnel_ingress_api: Api<TunnelIngress> = Api::all(self.kubernetes_client.clone());
+        let (tunnel_ingress_store, tunnel_ingress_writer) = reflector::store();
+        tokio::spawn(
+            watcher(tunnel_ingress_api.clone(), Config::default())
+                .reflect(tunnel_ingress_writer)
+                .default_backoff()
+                .touched_objects()
+                .for_each(|_| ready(())),
+        );
+        let recorder = Recorder::new(
+            self.kubernetes_client.clone(),
+            Reporter {
+                controller: "cloudflare-tunnel-operator".into(),
+                instance: None,
+            },
+        );
+
+        let ctx = Arc::new(Context {
+            kubernetes_client: self.kubernetes_client,
+            cloudflare_client: self.cloudflare_client,
+            credentials_api,
+            tunnel_api: self.tunnel_api,
+            tunnel_ingress_store,
+            cluster_tunnel_namespace: self.cluster_tunnel_namespace,
+            hibernate_all: self.hibernate_all,
+            allow_cross_namespace_refs: self.allow_cross_namespace_refs,
+            backoff: self.backoff,
+            token_cache: self.token_cache,
+            image_policy: self.image_policy,
+            state: self.state,
+            locks: ObjectLocks::new(),
+            validated_credentials: DashMap::new(),
+            recorder,
+        });
+
+        let health = self.health.clone();
+        let tunnels = self
+            .controller
+            .owns(deployment_api.clone(), Config::default())
+            .owns(configmap_api.clone(), Config::default())
+            .owns(
+                secret_api.clone(),
+                Config::default().labels(&managed_by_selector()),
+            )
+            .owns(sa_api.clone(), Config::default())
+            // INFO: Locally configured tunnels render their rules from TunnelIngress objects.
+            .watches(
+                tunnel_ingress_api.clone(),
+                Config::default(),
+                |tunnel_ingress| referenced_tunnel(&tunnel_ingress),
+            )
+            // INFO: Stops pulling new events once cancelled but lets in-flight reconciles finish.
+            .graceful_shutdown_on(self.shutdown.clone().cancelled_owned())
+            .run(reconciler, on_err, ctx.clone())
+            .for_each(|result| async {
+                health.record_reconcile();
                 match result {
                     Ok(result) => println!("Successfully reconciled tunnel: {:?}", result),
                     Err(err) => println!("Failed to reconcile tunnel: {:?}", err),
                 }
+            });
+
+        let cluster_tunnels = self
+            .cluster_controller
+            .owns(deployment_api, Config::default())
+            .owns(configmap_api, Config::default())
+            .owns(secret_api, Config::default().labels(&managed_by_selector()))
+            .owns(sa_api, Config::default())
+            .watches(tunnel_ingress_api, Config::default(), |tunnel_ingress| {
+                referenced_tunnel(&tunnel_ingress)
             })
-            .await;
+            .graceful_shutdown_on(self.shutdown.cancelled_owned())
+            .run(reconciler, on_err, ctx)
+            .for_each(|result| async {
+                health.record_reconcile();
+                match result {
+                    Ok(result) => println!("Successfully reconciled cluster tunnel: {:?}", result),
+                    Err(err) => println!("Failed to reconcile cluster tunnel: {:?}", err),
+                }
+            });
+
+        futures::join!(tunnels, cluster_tunnels, health.wait_until_populated());
 
         Ok(())
     }
 }
 
-impl TunnelController {
+impl<C: CloudflaredTunnel + 'static> TunnelController<C> {
+    #[allow(clippy::too_many_arguments)]
     pub async fn try_new(
         kubernetes_client: Client,
-        cloudflare_client: CloudflareClient,
-    ) -> anyhow::Result<TunnelController> {
+        cloudflare_client: C,
+        cluster_tunnel_namespace: String,
+        hibernate_all: bool,
+        allow_cross_namespace_refs: bool,
+        backoff: Backoff,
+        token_cache: TokenCache,
+        image_policy: ImagePolicy,
+        state: OperatorState,
+        shutdown: CancellationToken,
+    ) -> anyhow::Result<TunnelController<C>> {
         let tunnel_api: Api<Tunnel> = Api::all(kubernetes_client.clone());
+        let cluster_tunnel_api: Api<ClusterTunnel> = Api::all(kubernetes_client.clone());
 
         let controller = KubeController::new(tunnel_api.clone(), Config::default());
+        let cluster_controller = KubeController::new(cluster_tunnel_api, Config::default());
+        let health = Health::new(TunnelStores {
+            tunnels: controller.store(),
+            cluster_tunnels: cluster_controller.store(),
+        });
 
         Ok(Self {
             kubernetes_client,
             cloudflare_client,
             tunnel_api,
             controller,
+            cluster_controller,
+            cluster_tunnel_namespace,
+            hibernate_all,
+            allow_cross_namespace_refs,
+            backoff,
+            token_cache,
+            image_policy,
+            state,
+            health,
+            shutdown,
         })
     }
 
+    /// `try_new` with the operator's defaults around an already built Cloudflare client.
+    pub async fn try_new_with_cloudflare(
+        kubernetes_client: Client,
+        cloudflare_client: C,
+    ) -> anyhow::Result<TunnelController<C>> {
+        Self::try_new(
+            kubernetes_client,
+            cloudflare_client,
+            DEFAULT_CLUSTER_TUNNEL_NAMESPACE.to_owned(),
+            false,
+            false,
+            Backoff::default(),
+            TokenCache::default(),
+            ImagePolicy::default(),
+            OperatorState::disabled(),
+            CancellationToken::new(),
+        )
+        .await
+    }
+
     pub fn store(&self) -> Store<Tunnel> {
         self.controller.store()
     }
+
+    pub fn cluster_store(&self) -> Store<ClusterTunnel> {
+        self.cluster_controller.store()
+    }
+
+    pub fn health(&self) -> Health {
+        self.health.clone()
+    }
+
+    pub fn stores(&self) -> TunnelStores {
+        TunnelStores {
+            tunnels: self.store(),
+            cluster_tunnels: self.cluster_store(),
+        }
+    }
 }
 
-impl IntoFuture for TunnelController {
+impl<C: CloudflaredTunnel + 'static> IntoFuture for TunnelController<C> {
     type Output = anyhow::Result<()>;
     type IntoFuture = Pin<Box<dyn Future<Output = Self::Output>>>;
 
@@ -334,3 +2172,1213 @@ impl IntoFuture for TunnelController {
         Box::pin(self.start())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crd::credentials::{AuthKind, CredentialsCrd};
+    use crate::crd::tunnel_ingress::ACCEPTED_CONDITION;
+    use crate::mock::{context, ApiServer, MockCloudflareClient};
+    use cloudflare_controller_common::FINALIZER_NAME;
+    use cloudflarext::cfd_tunnel::TunnelConfigurations;
+    use cloudflarext::tunnel_configuration::TunnelConfiguration;
+    use serde_json::Value;
+
+    const NAMESPACE: Option<&str> = Some("default");
+
+    fn seed(server: &ApiServer, annotations: Value) {
+        server.insert(&Credentials::new(
+            "creds",
+            CredentialsCrd {
+                account_id: "account".to_owned(),
+                auth: AuthKind::UserAuthToken("token".to_owned()),
+            },
+        ));
+        let tunnel: Tunnel = serde_json::from_value(json!({
+            "apiVersion": "cloudflare.ar2ro.io/v1",
+            "kind": "Tunnel",
+            "metadata": { "name": "web", "namespace": "default", "annotations": annotations },
+            "spec": { "credentials": "creds" },
+        }))
+        .unwrap();
+        server.insert(&tunnel);
+    }
+
+    fn stored(server: &ApiServer) -> Option<Tunnel> {
+        server.get::<Tunnel>(NAMESPACE, "web")
+    }
+
+    async fn reconcile<C: CloudflaredTunnel>(
+        server: &ApiServer,
+        ctx: &Arc<Context<C>>,
+    ) -> Result<Action, Error> {
+        let tunnel = stored(server).expect("tunnel was deleted");
+        reconciler(Arc::new(tunnel), ctx.clone()).await
+    }
+
+    // INFO: The first pass creates the Cloudflare tunnel and requeues, the second applies the
+    // children and adds the finalizer.
+    async fn provision<C: CloudflaredTunnel>(server: &ApiServer, ctx: &Arc<Context<C>>) {
+        reconcile(server, ctx).await.unwrap();
+        reconcile(server, ctx).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn create_provisions_tunnel_and_workload() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+
+        provision(&server, &ctx).await;
+        reconcile(&server, &ctx).await.unwrap();
+
+        let tunnel_ids = ctx.cloudflare_client.tunnel_ids();
+        assert_eq!(tunnel_ids.len(), 1);
+        assert_eq!(ctx.cloudflare_client.calls("create_tunnel"), 1);
+
+        let tunnel = stored(&server).unwrap();
+        assert_eq!(tunnel.spec.uuid, Some(tunnel_ids[0]));
+        assert_eq!(
+            tunnel.finalizers(),
+            ["tunnel.cloudflare.ar2ro.io/finalizer"]
+        );
+        assert_eq!(
+            tunnel.status.and_then(|status| status.tunnel_id),
+            Some(tunnel_ids[0])
+        );
+
+        let secret = server.get::<Secret>(NAMESPACE, "web").unwrap();
+        assert!(secret.data.unwrap().contains_key("TUNNEL_TOKEN"));
+        assert!(server.get::<Deployment>(NAMESPACE, "web").is_some());
+    }
+
+    #[tokio::test]
+    async fn cloudflare_failure_fails_the_reconcile() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        ctx.cloudflare_client
+            .fail("create_tunnel", StatusCode::INTERNAL_SERVER_ERROR);
+        seed(&server, json!({}));
+
+        let result = reconcile(&server, &ctx).await;
+
+        assert!(matches!(result, Err(Error::CloudflareApiFailure(_))));
+        assert!(ctx.cloudflare_client.tunnel_ids().is_empty());
+        assert_eq!(stored(&server).unwrap().spec.uuid, None);
+    }
+
+    #[tokio::test]
+    async fn delete_removes_the_cloudflare_tunnel() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        provision(&server, &ctx).await;
+
+        server.delete::<Tunnel>(NAMESPACE, "web");
+        reconcile(&server, &ctx).await.unwrap();
+
+        assert_eq!(ctx.cloudflare_client.calls("delete_tunnel"), 1);
+        assert!(ctx.cloudflare_client.tunnel_ids().is_empty());
+        assert!(stored(&server).is_none());
+        assert!(server.get::<Deployment>(NAMESPACE, "web").is_none());
+        assert!(server.get::<Secret>(NAMESPACE, "web").is_none());
+    }
+
+    #[tokio::test]
+    async fn finalizers_of_other_controllers_are_kept() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        server.update::<Tunnel>(
+            NAMESPACE,
+            "web",
+            json!({ "metadata": { "finalizers": ["example.com/backup"] } }),
+        );
+
+        provision(&server, &ctx).await;
+        assert_eq!(
+            stored(&server).unwrap().finalizers(),
+            ["example.com/backup", FINALIZER_NAME]
+        );
+
+        server.delete::<Tunnel>(NAMESPACE, "web");
+        reconcile(&server, &ctx).await.unwrap();
+
+        assert_eq!(ctx.cloudflare_client.calls("delete_tunnel"), 1);
+        assert_eq!(
+            stored(&server).unwrap().finalizers(),
+            ["example.com/backup"]
+        );
+    }
+
+    fn credentials_valid(server: &ApiServer) -> bool {
+        server
+            .get::<Credentials>(None, "creds")
+            .and_then(|credentials| credentials.status)
+            .is_some_and(|status| status.valid)
+    }
+
+    #[tokio::test]
+    async fn invalidated_credentials_stay_invalid_until_cloudflare_accepts_them() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        provision(&server, &ctx).await;
+        assert!(credentials_valid(&server));
+
+        ctx.cloudflare_client
+            .fail("list_tunnels_paginated", StatusCode::UNAUTHORIZED);
+        ctx.credentials_api
+            .invalidate_credentials("creds", false, "revoked".to_owned())
+            .await
+            .unwrap();
+
+        assert!(reconcile(&server, &ctx).await.is_err());
+        assert!(!credentials_valid(&server));
+        assert!(reconcile(&server, &ctx).await.is_err());
+        assert!(!credentials_valid(&server));
+    }
+
+    #[tokio::test]
+    async fn invalidated_credentials_are_revalidated_with_cloudflare() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        provision(&server, &ctx).await;
+        let validations = ctx.cloudflare_client.calls("list_tunnels_paginated");
+
+        ctx.credentials_api
+            .invalidate_credentials("creds", false, "revoked".to_owned())
+            .await
+            .unwrap();
+        reconcile(&server, &ctx).await.unwrap();
+
+        assert!(credentials_valid(&server));
+        assert_eq!(
+            ctx.cloudflare_client.calls("list_tunnels_paginated"),
+            validations + 1
+        );
+    }
+
+    #[tokio::test]
+    async fn cluster_tunnel_reconciles_like_a_tunnel() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        let cluster_tunnel: ClusterTunnel = serde_json::from_value(json!({
+            "apiVersion": "cloudflare.ar2ro.io/v1",
+            "kind": "ClusterTunnel",
+            "metadata": { "name": "web" },
+            "spec": { "credentials": "creds" },
+        }))
+        .unwrap();
+        server.insert(&cluster_tunnel);
+
+        provision(&server, &ctx).await;
+        for _ in 0..2 {
+            let cluster_tunnel = server.get::<ClusterTunnel>(None, "web").unwrap();
+            reconciler(Arc::new(cluster_tunnel), ctx.clone())
+                .await
+                .unwrap();
+        }
+
+        assert_eq!(
+            ctx.cloudflare_client.tunnel_names(),
+            ["cluster-tunnel-web", "web"]
+        );
+        let tunnel = stored(&server).unwrap();
+        let cluster_tunnel = server.get::<ClusterTunnel>(None, "web").unwrap();
+        assert!(cluster_tunnel.spec.uuid.is_some());
+        assert_ne!(cluster_tunnel.spec.uuid, tunnel.spec.uuid);
+        assert_eq!(cluster_tunnel.finalizers(), tunnel.finalizers());
+
+        // INFO: Same children, only named and placed for the cluster scope.
+        let cluster_namespace = Some(DEFAULT_CLUSTER_TUNNEL_NAMESPACE);
+        let deployment = server.get::<Deployment>(NAMESPACE, "web").unwrap();
+        let cluster_deployment = server
+            .get::<Deployment>(cluster_namespace, "cluster-tunnel-web")
+            .unwrap();
+        let pod_spec = |deployment: Deployment| {
+            let mut pod_spec = deployment.spec.unwrap().template.spec.unwrap();
+            pod_spec.service_account_name = None;
+            pod_spec.topology_spread_constraints = None;
+            pod_spec.containers[0].env_from = None;
+            pod_spec
+        };
+        assert_eq!(pod_spec(cluster_deployment), pod_spec(deployment));
+        assert!(server
+            .get::<Secret>(cluster_namespace, "cluster-tunnel-web")
+            .is_some());
+    }
+
+    // INFO: ClusterTunnels used to take their plain name on Cloudflare, which a Tunnel of the
+    // same name then failed to create with a 409.
+    #[tokio::test]
+    async fn cluster_tunnel_frees_the_name_of_a_tunnel() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        server.delete::<Tunnel>(NAMESPACE, "web");
+        let cluster_tunnel: ClusterTunnel = serde_json::from_value(json!({
+            "apiVersion": "cloudflare.ar2ro.io/v1",
+            "kind": "ClusterTunnel",
+            "metadata": { "name": "web" },
+            "spec": { "credentials": "creds" },
+        }))
+        .unwrap();
+        server.insert(&cluster_tunnel);
+        let reconcile_cluster_tunnel = || async {
+            let cluster_tunnel = server.get::<ClusterTunnel>(None, "web").unwrap();
+            reconciler(Arc::new(cluster_tunnel), ctx.clone()).await
+        };
+        reconcile_cluster_tunnel().await.unwrap();
+        reconcile_cluster_tunnel().await.unwrap();
+
+        let tunnel_id = ctx.cloudflare_client.tunnel_ids()[0];
+        let credentials = cloudflare::framework::auth::Credentials::UserAuthToken {
+            token: "token".to_owned(),
+        };
+        ctx.cloudflare_client
+            .rename_tunnel(&credentials, "account", tunnel_id, "web")
+            .await
+            .unwrap();
+        server.update::<ClusterTunnel>(
+            None,
+            "web",
+            json!({ "status": { "cloudflareName": "web" } }),
+        );
+
+        reconcile_cluster_tunnel().await.unwrap();
+        seed(&server, json!({}));
+        provision(&server, &ctx).await;
+
+        assert_eq!(
+            ctx.cloudflare_client.tunnel_names(),
+            ["cluster-tunnel-web", "web"]
+        );
+        assert_eq!(
+            server.get::<ClusterTunnel>(None, "web").unwrap().spec.uuid,
+            Some(tunnel_id)
+        );
+        assert!(stored(&server).unwrap().spec.uuid.is_some());
+    }
+
+    #[tokio::test]
+    async fn milestones_are_reached_once_in_order() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        let observed = metrics::time_to_ready().snapshot().count;
+
+        provision(&server, &ctx).await;
+        let milestones = stored(&server).unwrap().status.unwrap().milestones;
+        assert!(milestones.created.is_some());
+        assert!(milestones.tunnel_provisioned.is_some());
+        assert!(!milestones.is_ready());
+
+        // INFO: The pods come up, the ingress controller writes the remote configuration and
+        // cloudflared connects to the edge.
+        let tunnel_id = ctx.cloudflare_client.tunnel_ids()[0];
+        server.update::<Deployment>(
+            NAMESPACE,
+            "web",
+            json!({ "status": { "readyReplicas": 1 } }),
+        );
+        let config = TunnelConfiguration {
+            ingress: vec![IngressRule {
+                hostname: Some("web.example.com".to_owned()),
+                service: "http://web.default:80".to_owned(),
+                ..IngressRule::default()
+            }],
+            ..TunnelConfiguration::default()
+        };
+        let credentials = cloudflare::framework::auth::Credentials::UserAuthToken {
+            token: "token".to_owned(),
+        };
+        ctx.cloudflare_client
+            .update_configuration(&credentials, "account", tunnel_id, &config)
+            .await
+            .unwrap();
+        ctx.cloudflare_client.connect(tunnel_id);
+        reconcile(&server, &ctx).await.unwrap();
+
+        let milestones = stored(&server).unwrap().status.unwrap().milestones;
+        assert!(milestones.is_ready());
+        assert!(milestones.ready_after_seconds.is_some());
+        let times: Vec<DateTime<Utc>> = Milestone::ALL
+            .iter()
+            .map(|milestone| milestones.get(*milestone).unwrap().parse().unwrap())
+            .collect();
+        assert!(times.windows(2).all(|pair| pair[0] <= pair[1]));
+        assert!(metrics::time_to_ready().snapshot().count > observed);
+
+        // INFO: Later reconciles leave every timestamp as it was.
+        let configurations = ctx.cloudflare_client.calls("get_configuration");
+        reconcile(&server, &ctx).await.unwrap();
+        assert_eq!(
+            stored(&server).unwrap().status.unwrap().milestones,
+            milestones
+        );
+        assert_eq!(
+            ctx.cloudflare_client.calls("get_configuration"),
+            configurations
+        );
+    }
+
+    #[tokio::test]
+    async fn scale_patch_reaches_the_deployment_and_status() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        provision(&server, &ctx).await;
+
+        // INFO: What `kubectl scale tunnel/web --replicas=5` does through the scale subresource.
+        server.update::<Tunnel>(NAMESPACE, "web", json!({ "spec": { "replicas": 5 } }));
+        server.update::<Deployment>(
+            NAMESPACE,
+            "web",
+            json!({ "status": { "replicas": 5, "readyReplicas": 3 } }),
+        );
+        reconcile(&server, &ctx).await.unwrap();
+
+        let deployment = server.get::<Deployment>(NAMESPACE, "web").unwrap();
+        assert_eq!(deployment.spec.unwrap().replicas, Some(5));
+        let status = stored(&server).unwrap().status.unwrap();
+        assert_eq!(status.replicas, Some(5));
+        assert_eq!(status.ready_replicas, Some(3));
+    }
+
+    #[tokio::test]
+    async fn region_deployments_scaled_by_hand_are_restored() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        server.update::<Tunnel>(
+            NAMESPACE,
+            "web",
+            json!({ "spec": { "regions": [
+                { "clusterName": "eu", "replicas": 3 },
+                { "clusterName": "us", "replicas": 1 },
+            ] } }),
+        );
+        provision(&server, &ctx).await;
+
+        server.update::<Deployment>(NAMESPACE, "web-eu", json!({ "spec": { "replicas": 7 } }));
+        server.update::<Deployment>(NAMESPACE, "web-us", json!({ "spec": { "replicas": 0 } }));
+        reconcile(&server, &ctx).await.unwrap();
+
+        let replicas = |name: &str| server.get::<Deployment>(NAMESPACE, name)?.spec?.replicas;
+        assert_eq!(replicas("web-eu"), Some(3));
+        assert_eq!(replicas("web-us"), Some(1));
+    }
+
+    fn condition(server: &ApiServer, type_: &str) -> Option<Condition> {
+        stored(server)?
+            .status?
+            .conditions
+            .into_iter()
+            .find(|condition| condition.type_ == type_)
+    }
+
+    fn deployment_replicas(server: &ApiServer) -> Option<i32> {
+        server.get::<Deployment>(NAMESPACE, "web")?.spec?.replicas
+    }
+
+    #[tokio::test]
+    async fn hibernation_scales_to_zero_and_back() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        provision(&server, &ctx).await;
+        let tunnel_ids = ctx.cloudflare_client.tunnel_ids();
+        let configurations = ctx.cloudflare_client.calls("update_configuration");
+
+        server.update::<Tunnel>(NAMESPACE, "web", json!({ "spec": { "hibernate": true } }));
+        reconcile(&server, &ctx).await.unwrap();
+
+        assert_eq!(deployment_replicas(&server), Some(0));
+        assert_eq!(
+            condition(&server, HIBERNATED_CONDITION).map(|condition| condition.status),
+            Some("True".to_owned())
+        );
+        assert!(server.get::<Secret>(NAMESPACE, "web").is_some());
+
+        server.update::<Tunnel>(NAMESPACE, "web", json!({ "spec": { "hibernate": false } }));
+        reconcile(&server, &ctx).await.unwrap();
+
+        assert_eq!(
+            deployment_replicas(&server),
+            Some(stored(&server).unwrap().spec.replicas)
+        );
+        assert_eq!(
+            condition(&server, HIBERNATED_CONDITION).map(|condition| condition.status),
+            Some("False".to_owned())
+        );
+
+        // INFO: The Cloudflare tunnel and its configuration are never touched.
+        assert_eq!(ctx.cloudflare_client.tunnel_ids(), tunnel_ids);
+        assert_eq!(ctx.cloudflare_client.calls("delete_tunnel"), 0);
+        assert_eq!(
+            ctx.cloudflare_client.calls("update_configuration"),
+            configurations
+        );
+    }
+
+    #[tokio::test]
+    async fn hibernation_can_delete_the_workload() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        provision(&server, &ctx).await;
+
+        server.update::<Tunnel>(
+            NAMESPACE,
+            "web",
+            json!({ "spec": { "hibernate": true, "hibernateMode": "Delete" } }),
+        );
+        reconcile(&server, &ctx).await.unwrap();
+
+        assert!(server.get::<Deployment>(NAMESPACE, "web").is_none());
+        assert!(server.get::<Secret>(NAMESPACE, "web").is_some());
+        assert_eq!(ctx.cloudflare_client.tunnel_ids().len(), 1);
+
+        server.update::<Tunnel>(NAMESPACE, "web", json!({ "spec": { "hibernate": false } }));
+        reconcile(&server, &ctx).await.unwrap();
+
+        assert!(server.get::<Deployment>(NAMESPACE, "web").is_some());
+    }
+
+    #[tokio::test]
+    async fn uuid_set_without_a_recorded_tunnel_is_adopted() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        provision(&server, &ctx).await;
+        let uuid = stored(&server).unwrap().spec.uuid;
+
+        // INFO: Same as a user filling in uuid on a fresh object.
+        server.update::<Tunnel>(NAMESPACE, "web", json!({ "status": null }));
+        reconcile(&server, &ctx).await.unwrap();
+
+        assert_eq!(stored(&server).unwrap().spec.uuid, uuid);
+        assert_eq!(ctx.cloudflare_client.calls("create_tunnel"), 1);
+    }
+
+    #[tokio::test]
+    async fn changed_uuid_is_refused() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        provision(&server, &ctx).await;
+        let tunnel_ids = ctx.cloudflare_client.tunnel_ids();
+        let secret = server.get::<Secret>(NAMESPACE, "web").unwrap();
+
+        server.update::<Tunnel>(
+            NAMESPACE,
+            "web",
+            json!({ "spec": { "uuid": Uuid::new_v4() } }),
+        );
+        let result = reconcile(&server, &ctx).await;
+
+        assert!(matches!(result, Err(Error::InvalidSpec(_))));
+        assert_eq!(ctx.cloudflare_client.tunnel_ids(), tunnel_ids);
+        assert_eq!(ctx.cloudflare_client.calls("create_tunnel"), 1);
+        assert_eq!(
+            server.get::<Secret>(NAMESPACE, "web").unwrap().data,
+            secret.data
+        );
+    }
+
+    #[tokio::test]
+    async fn removed_uuid_is_restored() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        provision(&server, &ctx).await;
+        let uuid = stored(&server).unwrap().spec.uuid;
+
+        server.update::<Tunnel>(NAMESPACE, "web", json!({ "spec": { "uuid": null } }));
+        reconcile(&server, &ctx).await.unwrap();
+
+        assert_eq!(stored(&server).unwrap().spec.uuid, uuid);
+        assert_eq!(ctx.cloudflare_client.calls("create_tunnel"), 1);
+        assert_eq!(ctx.cloudflare_client.tunnel_ids().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn paused_create_touches_nothing() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({ PAUSED_ANNOTATION: "yes" }));
+
+        let action = reconcile(&server, &ctx).await.unwrap();
+
+        assert_eq!(action, Action::await_change());
+        assert_eq!(ctx.cloudflare_client.calls("create_tunnel"), 0);
+        assert!(ctx.cloudflare_client.tunnel_ids().is_empty());
+        assert!(stored(&server).unwrap().spec.uuid.is_none());
+        assert!(server.get::<Deployment>(NAMESPACE, "web").is_none());
+        assert_eq!(
+            condition(&server, PAUSED_CONDITION).map(|condition| condition.status),
+            Some("True".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn paused_sync_touches_nothing() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        provision(&server, &ctx).await;
+        let replicas = deployment_replicas(&server);
+        let configurations = ctx.cloudflare_client.calls("update_configuration");
+
+        server.update::<Tunnel>(
+            NAMESPACE,
+            "web",
+            json!({
+                "metadata": { "annotations": { PAUSED_ANNOTATION: "True" } },
+                "spec": { "replicas": 5 },
+            }),
+        );
+        let action = reconcile(&server, &ctx).await.unwrap();
+
+        assert_eq!(action, Action::await_change());
+        assert_eq!(deployment_replicas(&server), replicas);
+        assert_eq!(
+            ctx.cloudflare_client.calls("update_configuration"),
+            configurations
+        );
+        assert_eq!(
+            condition(&server, PAUSED_CONDITION).map(|condition| condition.status),
+            Some("True".to_owned())
+        );
+
+        server.update::<Tunnel>(
+            NAMESPACE,
+            "web",
+            json!({ "metadata": { "annotations": { PAUSED_ANNOTATION: null } } }),
+        );
+        reconcile(&server, &ctx).await.unwrap();
+
+        assert_eq!(deployment_replicas(&server), Some(5));
+        assert_eq!(
+            condition(&server, PAUSED_CONDITION).map(|condition| condition.status),
+            Some("False".to_owned())
+        );
+    }
+
+    #[tokio::test]
+    async fn paused_delete_still_runs_the_finalizer() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        provision(&server, &ctx).await;
+
+        server.update::<Tunnel>(
+            NAMESPACE,
+            "web",
+            json!({ "metadata": { "annotations": { PAUSED_ANNOTATION: "true" } } }),
+        );
+        server.delete::<Tunnel>(NAMESPACE, "web");
+        reconcile(&server, &ctx).await.unwrap();
+
+        assert_eq!(ctx.cloudflare_client.calls("delete_tunnel"), 1);
+        assert!(stored(&server).is_none());
+    }
+
+    #[tokio::test]
+    async fn prevent_destroy_keeps_the_finalizer_and_the_tunnel() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({ PREVENT_DESTROY_ANNOTATION: "On" }));
+        provision(&server, &ctx).await;
+
+        server.delete::<Tunnel>(NAMESPACE, "web");
+        let action = reconcile(&server, &ctx).await.unwrap();
+
+        assert_eq!(action, Action::await_change());
+        let tunnel = stored(&server).unwrap();
+        assert!(tunnel
+            .finalizers()
+            .iter()
+            .any(|name| name == FINALIZER_NAME));
+        assert_eq!(ctx.cloudflare_client.calls("delete_tunnel"), 0);
+        assert_eq!(ctx.cloudflare_client.tunnel_ids().len(), 1);
+        assert!(server.get::<Deployment>(NAMESPACE, "web").is_some());
+        assert!(server.get::<Secret>(NAMESPACE, "web").is_some());
+        assert_eq!(
+            condition(&server, DELETION_BLOCKED_CONDITION).map(|condition| condition.status),
+            Some("True".to_owned())
+        );
+
+        server.update::<Tunnel>(
+            NAMESPACE,
+            "web",
+            json!({ "metadata": { "annotations": { PREVENT_DESTROY_ANNOTATION: null } } }),
+        );
+        reconcile(&server, &ctx).await.unwrap();
+
+        assert_eq!(ctx.cloudflare_client.calls("delete_tunnel"), 1);
+        assert!(stored(&server).is_none());
+    }
+
+    #[tokio::test]
+    async fn config_source_local_mounts_the_configuration() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        server.update::<Tunnel>(
+            NAMESPACE,
+            "web",
+            json!({ "spec": { "configSource": "local" } }),
+        );
+        provision(&server, &ctx).await;
+
+        let deployment = server.get::<Deployment>(NAMESPACE, "web").unwrap();
+        let container = &deployment.spec.unwrap().template.spec.unwrap().containers[0];
+        assert!(container
+            .command
+            .as_ref()
+            .unwrap()
+            .iter()
+            .any(|arg| arg == "--config"));
+        assert!(container.env_from.is_none());
+        assert!(server.get::<ConfigMap>(NAMESPACE, "web").is_some());
+        assert_eq!(ctx.cloudflare_client.calls("update_configuration"), 0);
+    }
+
+    #[tokio::test]
+    async fn local_configuration_ends_with_the_catch_all_service() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        server.update::<Tunnel>(
+            NAMESPACE,
+            "web",
+            json!({ "spec": { "configSource": "local" } }),
+        );
+        let config = |server: &ApiServer| {
+            server
+                .get::<ConfigMap>(NAMESPACE, "web")
+                .unwrap()
+                .data
+                .unwrap()[LOCAL_CONFIG_FILE]
+                .clone()
+        };
+
+        provision(&server, &ctx).await;
+        assert!(config(&server).contains("http_status:404"));
+
+        server.update::<Tunnel>(
+            NAMESPACE,
+            "web",
+            json!({ "spec": { "catchAllService": "http://maintenance.default:80" } }),
+        );
+        reconcile(&server, &ctx).await.unwrap();
+        let config = config(&server);
+        assert!(config.contains("http://maintenance.default:80"));
+        assert!(!config.contains("http_status:404"));
+    }
+
+    fn seed_tunnel_ingress(server: &ApiServer, name: &str, hostname: &str, service: &str) {
+        let tunnel_ingress: TunnelIngress = serde_json::from_value(json!({
+            "metadata": { "name": name, "namespace": "default" },
+            "spec": {
+                "tunnelRef": { "name": "web" },
+                "rules": [{ "hostname": hostname, "service": { "name": service, "port": 80 } }],
+            },
+        }))
+        .unwrap();
+        server.insert(&tunnel_ingress);
+    }
+
+    #[tokio::test]
+    async fn tunnel_ingress_with_a_missing_service_is_left_out() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        server.update::<Tunnel>(
+            NAMESPACE,
+            "web",
+            json!({ "spec": { "configSource": "local" } }),
+        );
+        let service: Service = serde_json::from_value(json!({
+            "metadata": { "name": "web", "namespace": "default" },
+            "spec": { "ports": [{ "port": 80 }] },
+        }))
+        .unwrap();
+        server.insert(&service);
+        seed_tunnel_ingress(&server, "web", "web.example.com", "web");
+        seed_tunnel_ingress(&server, "gone", "gone.example.com", "gone");
+
+        provision(&server, &ctx).await;
+        reconcile(&server, &ctx).await.unwrap();
+
+        let config = server
+            .get::<ConfigMap>(NAMESPACE, "web")
+            .unwrap()
+            .data
+            .unwrap();
+        assert!(config[LOCAL_CONFIG_FILE].contains("web.example.com"));
+        assert!(!config[LOCAL_CONFIG_FILE].contains("gone.example.com"));
+
+        let accepted = |name: &str| {
+            server
+                .get::<TunnelIngress>(NAMESPACE, name)
+                .and_then(|tunnel_ingress| tunnel_ingress.status)
+                .and_then(|status| {
+                    status
+                        .conditions
+                        .into_iter()
+                        .find(|condition| condition.type_ == ACCEPTED_CONDITION)
+                })
+                .unwrap()
+        };
+        assert_eq!(accepted("web").status, "True");
+        let gone = accepted("gone");
+        assert_eq!(gone.status, "False");
+        assert_eq!(
+            gone.message.as_deref(),
+            Some("references missing Service default/gone")
+        );
+    }
+
+    #[tokio::test]
+    async fn cross_namespace_tunnel_ingress_waits_for_the_tunnel_owner() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        server.update::<Tunnel>(
+            NAMESPACE,
+            "web",
+            json!({ "spec": { "configSource": "local" } }),
+        );
+        let tunnel_ingress: TunnelIngress = serde_json::from_value(json!({
+            "metadata": { "name": "app", "namespace": "team" },
+            "spec": {
+                "tunnelRef": { "name": "web", "namespace": "default" },
+                "rules": [{ "hostname": "app.example.com", "service": "http://app.team:80" }],
+            },
+        }))
+        .unwrap();
+        server.insert(&tunnel_ingress);
+        let config = |server: &ApiServer| {
+            server
+                .get::<ConfigMap>(NAMESPACE, "web")
+                .unwrap()
+                .data
+                .unwrap()[LOCAL_CONFIG_FILE]
+                .clone()
+        };
+        let accepted = |server: &ApiServer| {
+            server
+                .get::<TunnelIngress>(Some("team"), "app")
+                .and_then(|tunnel_ingress| tunnel_ingress.status)
+                .and_then(|status| {
+                    status
+                        .conditions
+                        .into_iter()
+                        .find(|condition| condition.type_ == ACCEPTED_CONDITION)
+                })
+                .unwrap()
+        };
+
+        provision(&server, &ctx).await;
+        reconcile(&server, &ctx).await.unwrap();
+        assert!(!config(&server).contains("app.example.com"));
+        let denied = accepted(&server);
+        assert_eq!(denied.status, "False");
+        assert!(denied
+            .message
+            .unwrap()
+            .starts_with("Tunnel default/web doesn't allow references from other namespaces"));
+
+        server.update::<Tunnel>(
+            NAMESPACE,
+            "web",
+            json!({ "metadata": { "annotations": {
+                "cloudflare.ar2ro.io/allow-cross-namespace": "true",
+            } } }),
+        );
+        reconcile(&server, &ctx).await.unwrap();
+        assert!(config(&server).contains("app.example.com"));
+        assert_eq!(accepted(&server).status, "True");
+    }
+
+    fn tunnel_ingress_to(name: &str, namespace: &str, tunnel_ref: Value) -> TunnelIngress {
+        serde_json::from_value(json!({
+            "metadata": { "name": name, "namespace": namespace },
+            "spec": { "tunnelRef": tunnel_ref },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn tunnel_ingress_maps_to_its_tunnel_of_the_watched_kind() {
+        let local = tunnel_ingress_to("local", "default", json!({ "name": "web" }));
+        assert_eq!(
+            referenced_tunnel::<Tunnel>(&local),
+            Some(ObjectRef::new("web").within("default"))
+        );
+        assert_eq!(referenced_tunnel::<ClusterTunnel>(&local), None);
+
+        let remote = tunnel_ingress_to(
+            "remote",
+            "team",
+            json!({ "name": "shared", "namespace": "infra" }),
+        );
+        assert_eq!(
+            referenced_tunnel::<Tunnel>(&remote),
+            Some(ObjectRef::new("shared").within("infra"))
+        );
+
+        let cluster = tunnel_ingress_to(
+            "cluster",
+            "team",
+            json!({ "kind": "ClusterTunnel", "name": "shared" }),
+        );
+        assert_eq!(
+            referenced_tunnel::<ClusterTunnel>(&cluster),
+            Some(ObjectRef::new("shared"))
+        );
+        assert_eq!(referenced_tunnel::<Tunnel>(&cluster), None);
+    }
+
+    #[tokio::test]
+    async fn tunnel_ingress_changes_queue_their_tunnel_once() {
+        use kube::runtime::scheduler::{scheduler, ScheduleRequest};
+
+        let changed = [
+            tunnel_ingress_to("a", "default", json!({ "name": "web" })),
+            tunnel_ingress_to("b", "default", json!({ "name": "web" })),
+            tunnel_ingress_to(
+                "c",
+                "team",
+                json!({ "name": "web", "namespace": "default" }),
+            ),
+            tunnel_ingress_to("d", "default", json!({ "name": "api" })),
+        ];
+        let now = tokio::time::Instant::now();
+        let requests = changed
+            .iter()
+            .filter_map(referenced_tunnel::<Tunnel>)
+            .map(|message| ScheduleRequest {
+                message,
+                run_at: now,
+            });
+
+        // INFO: The controller keeps its queue open, an ended stream would drop what's pending.
+        let requests = futures::stream::iter(requests).chain(futures::stream::pending());
+        let mut queued: Vec<ObjectRef<Tunnel>> = scheduler(requests)
+            .take_until(tokio::time::sleep(Duration::from_millis(50)))
+            .collect()
+            .await;
+        queued.sort_by_key(|obj_ref| obj_ref.name.clone());
+
+        assert_eq!(
+            queued,
+            [
+                ObjectRef::new("api").within("default"),
+                ObjectRef::new("web").within("default"),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn tunnel_ingresses_fall_back_to_the_store_without_field_selectors() {
+        let (client, server) = ApiServer::start();
+        let mut ctx = Arc::into_inner(context(client, MockCloudflareClient::new())).unwrap();
+        seed(&server, json!({}));
+        let (store, mut writer) = reflector::store::<TunnelIngress>();
+        for tunnel_ingress in [
+            tunnel_ingress_to("web", "default", json!({ "name": "web" })),
+            tunnel_ingress_to("api", "default", json!({ "name": "api" })),
+        ] {
+            writer.apply_watcher_event(&kube::runtime::watcher::Event::Apply(tunnel_ingress));
+        }
+        ctx.tunnel_ingress_store = store;
+        let tunnel = stored(&server).unwrap();
+
+        server.fail_once("GET", "tunnelingresses", StatusCode::BAD_REQUEST);
+        let tunnel_ingresses = tunnel_ingresses(&tunnel, &ctx).await.unwrap();
+
+        assert_eq!(
+            tunnel_ingresses
+                .iter()
+                .map(|tunnel_ingress| tunnel_ingress.name_any())
+                .collect::<Vec<_>>(),
+            ["web"]
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_uuid_patch_reuses_the_created_tunnel() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        // INFO: With the finalizer in place the uuid patch is the only write to the object.
+        server.update::<Tunnel>(
+            NAMESPACE,
+            "web",
+            json!({ "metadata": { "finalizers": [FINALIZER_NAME] } }),
+        );
+
+        server.fail_once("PATCH", "tunnels", StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(reconcile(&server, &ctx).await.is_err());
+        assert!(stored(&server).unwrap().spec.uuid.is_none());
+
+        reconcile(&server, &ctx).await.unwrap();
+
+        let tunnel_ids = ctx.cloudflare_client.tunnel_ids();
+        assert_eq!(ctx.cloudflare_client.calls("create_tunnel"), 1);
+        assert_eq!(tunnel_ids.len(), 1);
+        assert_eq!(stored(&server).unwrap().spec.uuid, Some(tunnel_ids[0]));
+    }
+
+    #[tokio::test]
+    async fn steady_state_sync_skips_cloudflare() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        provision(&server, &ctx).await;
+        reconcile(&server, &ctx).await.unwrap();
+        let token_fetches = ctx.cloudflare_client.calls("get_tunnel_token");
+        let connection_lists = ctx.cloudflare_client.calls("list_connections");
+
+        reconcile(&server, &ctx).await.unwrap();
+        reconcile(&server, &ctx).await.unwrap();
+
+        assert_eq!(
+            ctx.cloudflare_client.calls("get_tunnel_token"),
+            token_fetches
+        );
+        assert_eq!(
+            ctx.cloudflare_client.calls("list_connections") - connection_lists,
+            0
+        );
+
+        let generation = stored(&server).unwrap().metadata.generation.unwrap();
+        server.update::<Tunnel>(
+            NAMESPACE,
+            "web",
+            json!({ "metadata": { "generation": generation + 1 }, "spec": { "replicas": 3 } }),
+        );
+        reconcile(&server, &ctx).await.unwrap();
+
+        assert_eq!(
+            ctx.cloudflare_client.calls("get_tunnel_token"),
+            token_fetches + 1
+        );
+
+        let last_sync = Utc::now() - CLOUDFLARE_RESYNC_INTERVAL;
+        server.update::<Tunnel>(
+            NAMESPACE,
+            "web",
+            json!({ "status": { "lastCloudflareSync": last_sync.to_rfc3339() } }),
+        );
+        reconcile(&server, &ctx).await.unwrap();
+
+        assert_eq!(
+            ctx.cloudflare_client.calls("get_tunnel_token"),
+            token_fetches + 2
+        );
+        assert_eq!(
+            ctx.cloudflare_client.calls("list_connections"),
+            connection_lists + 2
+        );
+    }
+
+    #[tokio::test]
+    async fn token_is_fetched_again_when_the_secret_is_recreated() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        provision(&server, &ctx).await;
+        // INFO: The create passes share one fetch through the cache.
+        assert_eq!(ctx.cloudflare_client.calls("get_tunnel_token"), 1);
+        reconcile(&server, &ctx).await.unwrap();
+        let token_fetches = ctx.cloudflare_client.calls("get_tunnel_token");
+
+        server.delete::<Secret>(NAMESPACE, "web");
+        reconcile(&server, &ctx).await.unwrap();
+
+        assert_eq!(
+            ctx.cloudflare_client.calls("get_tunnel_token"),
+            token_fetches + 1
+        );
+        assert!(server.get::<Secret>(NAMESPACE, "web").is_some());
+    }
+
+    #[tokio::test]
+    async fn delete_waits_for_a_slow_create() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(
+            client,
+            MockCloudflareClient::new().with_create_delay(Duration::from_millis(200)),
+        );
+        seed(&server, json!({}));
+
+        let create = tokio::spawn({
+            let tunnel = Arc::new(stored(&server).unwrap());
+            let ctx = ctx.clone();
+            async move { reconciler(tunnel, ctx).await }
+        });
+        while ctx.cloudflare_client.calls("create_tunnel") == 0 {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+
+        // INFO: The finalizer is already on, so the delete only marks the object.
+        server.delete::<Tunnel>(NAMESPACE, "web");
+        assert!(ctx.cloudflare_client.tunnel_ids().is_empty());
+        let delete = reconcile(&server, &ctx).await;
+
+        assert!(create.await.unwrap().is_ok());
+        assert!(delete.is_ok());
+        assert_eq!(ctx.cloudflare_client.calls("create_tunnel"), 1);
+        assert_eq!(ctx.cloudflare_client.calls("delete_tunnel"), 1);
+        assert!(ctx.cloudflare_client.tunnel_ids().is_empty());
+        assert!(stored(&server).is_none());
+    }
+
+    fn event_reasons(server: &ApiServer) -> Vec<String> {
+        server
+            .get_all::<k8s_openapi::api::events::v1::Event>(NAMESPACE)
+            .into_iter()
+            .filter_map(|event| event.reason)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn deleted_secret_is_recreated_without_a_new_tunnel() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        provision(&server, &ctx).await;
+        reconcile(&server, &ctx).await.unwrap();
+        let secret = server.get::<Secret>(NAMESPACE, "web").unwrap();
+
+        server.delete::<Secret>(NAMESPACE, "web");
+        assert!(server.get::<Secret>(NAMESPACE, "web").is_none());
+        reconcile(&server, &ctx).await.unwrap();
+
+        let recreated = server.get::<Secret>(NAMESPACE, "web").unwrap();
+        assert_eq!(recreated.data, secret.data);
+        assert_eq!(recreated.metadata.labels, secret.metadata.labels);
+        assert_eq!(
+            recreated.metadata.owner_references,
+            secret.metadata.owner_references
+        );
+        assert_eq!(ctx.cloudflare_client.calls("create_tunnel"), 1);
+        assert_eq!(ctx.cloudflare_client.tunnel_ids().len(), 1);
+        assert!(event_reasons(&server).contains(&"SecretRecreated".to_owned()));
+    }
+
+    // INFO: Provisions a tunnel with a live connector and marks it deleted.
+    async fn connected_and_deleted(
+        server: &ApiServer,
+        ctx: &Arc<Context<MockCloudflareClient>>,
+    ) -> Uuid {
+        seed(server, json!({}));
+        provision(server, ctx).await;
+        let tunnel_id = stored(server).unwrap().spec.uuid.unwrap();
+        ctx.cloudflare_client.connect(tunnel_id);
+        server.delete::<Tunnel>(NAMESPACE, "web");
+        tunnel_id
+    }
+
+    #[tokio::test]
+    async fn delete_waits_for_connections_to_drain() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        let tunnel_id = connected_and_deleted(&server, &ctx).await;
+
+        let action = reconcile(&server, &ctx).await.unwrap();
+
+        assert_eq!(
+            action,
+            Action::requeue(Duration::from_secs(CONNECTION_DRAIN_POLL))
+        );
+        assert_eq!(deployment_replicas(&server), Some(0));
+        assert_eq!(ctx.cloudflare_client.calls("delete_tunnel"), 0);
+        assert!(stored(&server).is_some());
+
+        ctx.cloudflare_client.disconnect(tunnel_id);
+        reconcile(&server, &ctx).await.unwrap();
+
+        assert_eq!(ctx.cloudflare_client.calls("cleanup_connections"), 0);
+        assert_eq!(ctx.cloudflare_client.calls("delete_tunnel"), 1);
+        assert!(stored(&server).is_none());
+    }
+
+    #[tokio::test]
+    async fn delete_cleans_up_connections_after_the_drain_timeout() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        connected_and_deleted(&server, &ctx).await;
+
+        let deleted_at = Utc::now() - Duration::from_secs(CONNECTION_DRAIN_TIMEOUT as u64 + 1);
+        server.update::<Tunnel>(
+            NAMESPACE,
+            "web",
+            json!({ "metadata": { "deletionTimestamp": deleted_at.to_rfc3339() } }),
+        );
+        reconcile(&server, &ctx).await.unwrap();
+
+        assert_eq!(ctx.cloudflare_client.calls("cleanup_connections"), 1);
+        assert_eq!(ctx.cloudflare_client.calls("delete_tunnel"), 1);
+        assert!(ctx.cloudflare_client.tunnel_ids().is_empty());
+        assert!(stored(&server).is_none());
+    }
+
+    #[tokio::test]
+    async fn delete_drains_without_a_deployment() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        seed(&server, json!({}));
+        provision(&server, &ctx).await;
+
+        server.delete::<Deployment>(NAMESPACE, "web");
+        server.delete::<Tunnel>(NAMESPACE, "web");
+        reconcile(&server, &ctx).await.unwrap();
+
+        assert!(server.get::<Deployment>(NAMESPACE, "web").is_none());
+        assert_eq!(ctx.cloudflare_client.calls("delete_tunnel"), 1);
+        assert!(stored(&server).is_none());
+    }
+
+    #[tokio::test]
+    async fn delete_without_cascade_waits_for_the_connectors() {
+        let (client, server) = ApiServer::start();
+        let ctx = context(client, MockCloudflareClient::new());
+        let tunnel_id = connected_and_deleted(&server, &ctx).await;
+
+        let deleted_at = Utc::now() - Duration::from_secs(CONNECTION_DRAIN_TIMEOUT as u64 + 1);
+        server.update::<Tunnel>(
+            NAMESPACE,
+            "web",
+            json!({
+                "metadata": { "deletionTimestamp": deleted_at.to_rfc3339() },
+                "spec": { "deleteCascade": false },
+            }),
+        );
+        let action = reconcile(&server, &ctx).await.unwrap();
+
+        assert_eq!(
+            action,
+            Action::requeue(Duration::from_secs(RECONCILE_TIMER))
+        );
+        assert_eq!(ctx.cloudflare_client.calls("cleanup_connections"), 0);
+        assert_eq!(ctx.cloudflare_client.tunnel_ids(), vec![tunnel_id]);
+        assert!(stored(&server).is_some());
+        let degraded = condition(&server, DEGRADED_CONDITION).unwrap();
+        assert_eq!(degraded.status, "True");
+        assert_eq!(degraded.reason.as_deref(), Some("ConnectionsRemain"));
+
+        ctx.cloudflare_client.disconnect(tunnel_id);
+        reconcile(&server, &ctx).await.unwrap();
+
+        assert!(ctx.cloudflare_client.tunnel_ids().is_empty());
+        assert!(stored(&server).is_none());
+    }
+}
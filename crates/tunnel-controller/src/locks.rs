@@ -0,0 +1,30 @@
+//! Per object locks so reconciles of the same tunnel never run concurrently.
+use dashmap::DashMap;
+use kube::core::DynamicObject;
+use kube::runtime::reflector::ObjectRef;
+use std::sync::Arc;
+use tokio::sync::{Mutex, OwnedMutexGuard};
+
+/// Keys are erased so a Tunnel and a ClusterTunnel never share a lock.
+#[derive(Debug, Default)]
+pub struct ObjectLocks {
+    locks: DashMap<ObjectRef<DynamicObject>, Arc<Mutex<()>>>,
+}
+
+impl ObjectLocks {
+    pub fn new() -> ObjectLocks {
+        ObjectLocks::default()
+    }
+
+    /// Waits until no other reconcile holds `object`, the lock is released with the guard.
+    pub async fn lock(&self, object: ObjectRef<DynamicObject>) -> OwnedMutexGuard<()> {
+        let lock = Arc::clone(&self.locks.entry(object).or_default());
+        lock.lock_owned().await
+    }
+
+    /// Drops the lock of a deleted object unless another reconcile is still waiting on it.
+    pub fn forget(&self, object: &ObjectRef<DynamicObject>) {
+        self.locks
+            .remove_if(object, |_, lock| Arc::strong_count(lock) == 1);
+    }
+}
@@ -0,0 +1,235 @@
+//! Process wide metrics, kept as plain atomics so they can be read without a metrics backend.
+use crate::crd::status::{status_decode_failures, Milestone};
+use cloudflarext::cfd_tunnel::configuration_conflicts;
+use std::fmt::Write;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Upper bounds in seconds, anything slower lands in the last overflow bucket.
+pub const TIME_TO_READY_BUCKETS: [u64; 10] = [1, 5, 10, 30, 60, 120, 300, 600, 1800, 3600];
+
+/// Fixed bucket histogram, bucket counts are not cumulative.
+pub struct Histogram {
+    buckets: [AtomicU64; TIME_TO_READY_BUCKETS.len() + 1],
+    sum_millis: AtomicU64,
+    count: AtomicU64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HistogramSnapshot {
+    /// `(upper bound in seconds, count)`, the overflow bucket has no bound.
+    pub buckets: Vec<(Option<u64>, u64)>,
+    pub sum_millis: u64,
+    pub count: u64,
+}
+
+impl Histogram {
+    pub const fn new() -> Histogram {
+        Histogram {
+            buckets: [const { AtomicU64::new(0) }; TIME_TO_READY_BUCKETS.len() + 1],
+            sum_millis: AtomicU64::new(0),
+            count: AtomicU64::new(0),
+        }
+    }
+
+    pub fn observe(&self, millis: u64) {
+        let index = TIME_TO_READY_BUCKETS
+            .iter()
+            .position(|bound| millis <= bound * 1000)
+            .unwrap_or(TIME_TO_READY_BUCKETS.len());
+
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.sum_millis.fetch_add(millis, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let bounds = TIME_TO_READY_BUCKETS
+            .iter()
+            .copied()
+            .map(Some)
+            .chain([None]);
+
+        HistogramSnapshot {
+            buckets: bounds
+                .zip(&self.buckets)
+                .map(|(bound, count)| (bound, count.load(Ordering::Relaxed)))
+                .collect(),
+            sum_millis: self.sum_millis.load(Ordering::Relaxed),
+            count: self.count.load(Ordering::Relaxed),
+        }
+    }
+}
+
+impl Default for Histogram {
+    fn default() -> Histogram {
+        Histogram::new()
+    }
+}
+
+static TUNNEL_PROVISIONED: Histogram = Histogram::new();
+static WORKLOAD_READY: Histogram = Histogram::new();
+static CONFIG_APPLIED: Histogram = Histogram::new();
+static DNS_READY: Histogram = Histogram::new();
+static TIME_TO_READY: Histogram = Histogram::new();
+
+/// Time from the previous milestone to `milestone`, nothing precedes `Created`.
+pub fn milestone_histogram(milestone: Milestone) -> Option<&'static Histogram> {
+    match milestone {
+        Milestone::Created => None,
+        Milestone::TunnelProvisioned => Some(&TUNNEL_PROVISIONED),
+        Milestone::WorkloadReady => Some(&WORKLOAD_READY),
+        Milestone::ConfigApplied => Some(&CONFIG_APPLIED),
+        Milestone::DnsReady => Some(&DNS_READY),
+    }
+}
+
+/// Total time from creation to every milestone being reached.
+pub fn time_to_ready() -> &'static Histogram {
+    &TIME_TO_READY
+}
+
+fn counter(out: &mut String, name: &str, help: &str, value: u64) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} counter", name);
+    let _ = writeln!(out, "{} {}", name, value);
+}
+
+fn milestone_label(milestone: Milestone) -> &'static str {
+    match milestone {
+        Milestone::Created => "created",
+        Milestone::TunnelProvisioned => "tunnel_provisioned",
+        Milestone::WorkloadReady => "workload_ready",
+        Milestone::ConfigApplied => "config_applied",
+        Milestone::DnsReady => "dns_ready",
+    }
+}
+
+// INFO: Prometheus buckets are cumulative and in seconds, the snapshot keeps plain counts.
+fn histogram_samples(out: &mut String, name: &str, labels: &str, snapshot: &HistogramSnapshot) {
+    let mut cumulative = 0;
+    for (bound, count) in &snapshot.buckets {
+        cumulative += count;
+        let le = bound.map_or_else(|| "+Inf".to_owned(), |bound| bound.to_string());
+        let _ = writeln!(
+            out,
+            "{}_bucket{{{}le=\"{}\"}} {}",
+            name, labels, le, cumulative
+        );
+    }
+
+    let labels = labels.trim_end_matches(',');
+    let labels = match labels.is_empty() {
+        true => String::new(),
+        false => format!("{{{}}}", labels),
+    };
+    let _ = writeln!(
+        out,
+        "{}_sum{} {}",
+        name,
+        labels,
+        snapshot.sum_millis as f64 / 1000.0
+    );
+    let _ = writeln!(out, "{}_count{} {}", name, labels, snapshot.count);
+}
+
+fn histogram_header(out: &mut String, name: &str, help: &str) {
+    let _ = writeln!(out, "# HELP {} {}", name, help);
+    let _ = writeln!(out, "# TYPE {} histogram", name);
+}
+
+/// Every metric of the process in the Prometheus text format.
+pub fn render() -> String {
+    let mut out = String::new();
+    counter(
+        &mut out,
+        "cloudflare_operator_configuration_conflicts_total",
+        "Remote configuration writes that lost a race with another writer and were retried.",
+        configuration_conflicts(),
+    );
+    counter(
+        &mut out,
+        "cloudflare_operator_status_decode_failures_total",
+        "Status fields written by another operator version that couldn't be decoded and were ignored.",
+        status_decode_failures(),
+    );
+
+    let name = "cloudflare_operator_tunnel_milestone_seconds";
+    histogram_header(
+        &mut out,
+        name,
+        "Time from the previous milestone until a tunnel reached this one.",
+    );
+    for milestone in Milestone::ALL {
+        if let Some(histogram) = milestone_histogram(milestone) {
+            let labels = format!("milestone=\"{}\",", milestone_label(milestone));
+            histogram_samples(&mut out, name, &labels, &histogram.snapshot());
+        }
+    }
+
+    let name = "cloudflare_operator_tunnel_time_to_ready_seconds";
+    histogram_header(
+        &mut out,
+        name,
+        "Time from creation until a tunnel reached every milestone.",
+    );
+    histogram_samples(&mut out, name, "", &time_to_ready().snapshot());
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn render_exposes_configuration_conflicts() {
+        let rendered = render();
+
+        assert!(
+            rendered.contains("# TYPE cloudflare_operator_configuration_conflicts_total counter")
+        );
+        assert!(rendered.contains(&format!(
+            "cloudflare_operator_configuration_conflicts_total {}",
+            configuration_conflicts()
+        )));
+    }
+
+    #[test]
+    fn histogram_buckets_are_cumulative() {
+        let histogram = Histogram::new();
+        histogram.observe(500);
+        histogram.observe(4_000);
+        histogram.observe(7_200_000);
+
+        let mut out = String::new();
+        histogram_samples(&mut out, "test", "", &histogram.snapshot());
+
+        assert!(out.contains("test_bucket{le=\"1\"} 1\n"));
+        assert!(out.contains("test_bucket{le=\"5\"} 2\n"));
+        assert!(out.contains("test_bucket{le=\"3600\"} 2\n"));
+        assert!(out.contains("test_bucket{le=\"+Inf\"} 3\n"));
+        assert!(out.contains("test_sum 7204.5\n"));
+        assert!(out.contains("test_count 3\n"));
+    }
+
+    #[test]
+    fn render_exposes_the_milestone_histograms() {
+        let rendered = render();
+
+        assert!(rendered.contains("# TYPE cloudflare_operator_tunnel_milestone_seconds histogram"));
+        assert!(rendered.contains(
+            "cloudflare_operator_tunnel_milestone_seconds_count{milestone=\"dns_ready\"}"
+        ));
+        assert!(
+            rendered.contains("# TYPE cloudflare_operator_tunnel_time_to_ready_seconds histogram")
+        );
+        assert!(rendered
+            .contains("cloudflare_operator_tunnel_time_to_ready_seconds_bucket{le=\"+Inf\"}"));
+    }
+
+    #[test]
+    fn render_exposes_status_decode_failures() {
+        assert!(
+            render().contains("# TYPE cloudflare_operator_status_decode_failures_total counter")
+        );
+    }
+}
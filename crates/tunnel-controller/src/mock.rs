@@ -0,0 +1,780 @@
+//! In-memory stand-ins for Cloudflare and the Kubernetes api server, so reconciles can be run
+//! against a `Context` in tests without a cluster or an account.
+use crate::backoff::Backoff;
+use crate::crd::credentials::Credentials;
+use crate::locks::ObjectLocks;
+use crate::resources::deployment::ImagePolicy;
+use crate::state::OperatorState;
+use crate::token_cache::TokenCache;
+use crate::{Context, DEFAULT_CLUSTER_TUNNEL_NAMESPACE};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use cloudflare::endpoints::cfd_tunnel::{ConfigurationSrc, Tunnel, TunnelToken};
+use cloudflare::framework::auth::Credentials as CloudflareCredentials;
+use cloudflare::framework::response::{ApiErrors, ApiFailure};
+use cloudflarext::cfd_tunnel::{
+    CloudflaredTunnel, TunnelConfigurations, TunnelConnection, TunnelConnector,
+};
+use cloudflarext::tunnel_configuration::{TunnelConfiguration, TunnelConfigurationResult};
+use dashmap::DashMap;
+use http::{Request, Response, StatusCode};
+use k8s_openapi::chrono::Utc;
+use kube::client::Body;
+use kube::runtime::events::{Recorder, Reporter};
+use kube::runtime::reflector;
+use kube::{Api, Client, Resource};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tower_test::mock;
+use uuid::Uuid;
+
+/// Cloudflare account with tunnels kept in memory. Every call is recorded by name, a method
+/// can be made to fail with a status and creates can be slowed down.
+#[derive(Default)]
+pub struct MockCloudflareClient {
+    tunnels: Mutex<BTreeMap<Uuid, String>>,
+    configurations: Mutex<BTreeMap<Uuid, (i64, Option<TunnelConfiguration>)>>,
+    failures: Mutex<BTreeMap<&'static str, StatusCode>>,
+    calls: Mutex<Vec<&'static str>>,
+    connected: Mutex<BTreeSet<Uuid>>,
+    create_delay: Duration,
+}
+
+impl MockCloudflareClient {
+    pub fn new() -> MockCloudflareClient {
+        MockCloudflareClient::default()
+    }
+
+    /// Makes `create_tunnel` wait before answering, the tunnel only exists once it returns.
+    pub fn with_create_delay(mut self, delay: Duration) -> MockCloudflareClient {
+        self.create_delay = delay;
+        self
+    }
+
+    /// Every later call of `method` fails with `status`.
+    pub fn fail(&self, method: &'static str, status: StatusCode) {
+        self.failures.lock().unwrap().insert(method, status);
+    }
+
+    pub fn calls(&self, method: &str) -> usize {
+        self.calls
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|call| **call == method)
+            .count()
+    }
+
+    /// The tunnel gets a connector with one edge connection.
+    pub fn connect(&self, tunnel_id: Uuid) {
+        self.connected.lock().unwrap().insert(tunnel_id);
+    }
+
+    /// The connector of the tunnel goes away, as when cloudflared stops.
+    pub fn disconnect(&self, tunnel_id: Uuid) {
+        self.connected.lock().unwrap().remove(&tunnel_id);
+    }
+
+    pub fn tunnel_ids(&self) -> Vec<Uuid> {
+        self.tunnels.lock().unwrap().keys().copied().collect()
+    }
+
+    /// The last configuration written to the tunnel.
+    pub fn configuration(&self, tunnel_id: Uuid) -> Option<TunnelConfiguration> {
+        self.configurations
+            .lock()
+            .unwrap()
+            .get(&tunnel_id)
+            .and_then(|(_, config)| config.clone())
+    }
+
+    /// Names of the tunnels on the account, sorted.
+    pub fn tunnel_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.tunnels.lock().unwrap().values().cloned().collect();
+        names.sort();
+        names
+    }
+
+    // INFO: Tunnel names are unique per account, Cloudflare answers a taken one with a 409.
+    fn claim_name(&self, tunnel_id: Uuid, name: &str) -> Result<(), ApiFailure> {
+        let mut tunnels = self.tunnels.lock().unwrap();
+        if tunnels
+            .iter()
+            .any(|(id, tunnel_name)| *id != tunnel_id && tunnel_name == name)
+        {
+            return Err(ApiFailure::Error(
+                StatusCode::CONFLICT,
+                ApiErrors::default(),
+            ));
+        }
+        tunnels.insert(tunnel_id, name.to_owned());
+        Ok(())
+    }
+
+    fn call(&self, method: &'static str) -> Result<(), ApiFailure> {
+        self.calls.lock().unwrap().push(method);
+        match self.failures.lock().unwrap().get(method) {
+            Some(status) => Err(ApiFailure::Error(*status, ApiErrors::default())),
+            None => Ok(()),
+        }
+    }
+
+    fn tunnel(&self, tunnel_id: Uuid) -> Result<Tunnel, ApiFailure> {
+        match self.tunnels.lock().unwrap().get(&tunnel_id) {
+            Some(name) => Ok(tunnel(tunnel_id, name)),
+            None => Err(ApiFailure::Error(
+                StatusCode::NOT_FOUND,
+                ApiErrors::default(),
+            )),
+        }
+    }
+}
+
+fn tunnel(id: Uuid, name: &str) -> Tunnel {
+    Tunnel {
+        id,
+        created_at: Utc::now(),
+        deleted_at: None,
+        name: name.to_owned(),
+        connections: Vec::new(),
+        metadata: Value::Null,
+    }
+}
+
+fn parse_id(tunnel_id: &str) -> Result<Uuid, ApiFailure> {
+    tunnel_id
+        .parse()
+        .map_err(|_| ApiFailure::Error(StatusCode::NOT_FOUND, ApiErrors::default()))
+}
+
+impl TunnelConfigurations for MockCloudflareClient {
+    async fn get_configuration(
+        &self,
+        _credentials: &CloudflareCredentials,
+        _account_id: &str,
+        tunnel_id: Uuid,
+    ) -> Result<TunnelConfigurationResult, ApiFailure> {
+        self.call("get_configuration")?;
+        let (version, config) = self
+            .configurations
+            .lock()
+            .unwrap()
+            .get(&tunnel_id)
+            .cloned()
+            .unwrap_or_default();
+        Ok(TunnelConfigurationResult {
+            tunnel_id,
+            version,
+            config,
+        })
+    }
+
+    async fn update_configuration(
+        &self,
+        _credentials: &CloudflareCredentials,
+        _account_id: &str,
+        tunnel_id: Uuid,
+        config: &TunnelConfiguration,
+    ) -> Result<TunnelConfigurationResult, ApiFailure> {
+        self.call("update_configuration")?;
+        let mut configurations = self.configurations.lock().unwrap();
+        let stored = configurations.entry(tunnel_id).or_default();
+        *stored = (stored.0 + 1, Some(config.clone()));
+        Ok(TunnelConfigurationResult {
+            tunnel_id,
+            version: stored.0,
+            config: stored.1.clone(),
+        })
+    }
+}
+
+impl CloudflaredTunnel for MockCloudflareClient {
+    async fn create_tunnel(
+        &self,
+        _credentials: &CloudflareCredentials,
+        _account_id: &str,
+        name: &str,
+        _tunnel_secret: Option<&[u8]>,
+        _config_src: ConfigurationSrc,
+    ) -> Result<Tunnel, ApiFailure> {
+        self.call("create_tunnel")?;
+        tokio::time::sleep(self.create_delay).await;
+
+        let id = Uuid::new_v4();
+        self.claim_name(id, name)?;
+        Ok(tunnel(id, name))
+    }
+
+    async fn delete_tunnel(
+        &self,
+        _credentials: &CloudflareCredentials,
+        _account_id: &str,
+        tunnel_id: Uuid,
+        cascade: bool,
+    ) -> Result<(), ApiFailure> {
+        self.call("delete_tunnel")?;
+        self.tunnel(tunnel_id)?;
+        if !cascade && self.connected.lock().unwrap().contains(&tunnel_id) {
+            return Err(ApiFailure::Error(
+                StatusCode::BAD_REQUEST,
+                ApiErrors::default(),
+            ));
+        }
+        self.tunnels.lock().unwrap().remove(&tunnel_id);
+        Ok(())
+    }
+
+    async fn get_tunnel_token(
+        &self,
+        _credentials: &CloudflareCredentials,
+        account_id: &str,
+        tunnel_id: &str,
+    ) -> Result<TunnelToken, ApiFailure> {
+        self.call("get_tunnel_token")?;
+        let tunnel = self.tunnel(parse_id(tunnel_id)?)?;
+        let claims = json!({ "a": account_id, "s": STANDARD.encode("secret"), "t": tunnel.id });
+        Ok(serde_json::from_value(json!(STANDARD.encode(claims.to_string()))).unwrap())
+    }
+
+    async fn get_tunnel(
+        &self,
+        _credentials: &CloudflareCredentials,
+        _account_id: &str,
+        tunnel_id: &str,
+    ) -> Result<Tunnel, ApiFailure> {
+        self.call("get_tunnel")?;
+        self.tunnel(parse_id(tunnel_id)?)
+    }
+
+    async fn list_tunnels_paginated(
+        &self,
+        _credentials: &CloudflareCredentials,
+        _account_id: &str,
+        _page_size: u32,
+        _max_pages: Option<u32>,
+    ) -> Result<Vec<Tunnel>, ApiFailure> {
+        self.call("list_tunnels_paginated")?;
+        Ok(self
+            .tunnels
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, name)| tunnel(*id, name))
+            .collect())
+    }
+
+    async fn rotate_tunnel_secret(
+        &self,
+        _credentials: &CloudflareCredentials,
+        _account_id: &str,
+        tunnel_id: Uuid,
+        _tunnel_secret: &[u8],
+    ) -> Result<Tunnel, ApiFailure> {
+        self.call("rotate_tunnel_secret")?;
+        self.tunnel(tunnel_id)
+    }
+
+    async fn rename_tunnel(
+        &self,
+        _credentials: &CloudflareCredentials,
+        _account_id: &str,
+        tunnel_id: Uuid,
+        name: &str,
+    ) -> Result<Tunnel, ApiFailure> {
+        self.call("rename_tunnel")?;
+        self.tunnel(tunnel_id)?;
+        self.claim_name(tunnel_id, name)?;
+        Ok(tunnel(tunnel_id, name))
+    }
+
+    async fn find_tunnel_by_name(
+        &self,
+        _credentials: &CloudflareCredentials,
+        _account_id: &str,
+        name: &str,
+    ) -> Result<Option<Tunnel>, ApiFailure> {
+        self.call("find_tunnel_by_name")?;
+        Ok(self
+            .tunnels
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|(_, tunnel_name)| *tunnel_name == name)
+            .map(|(id, name)| tunnel(*id, name)))
+    }
+
+    async fn list_connections(
+        &self,
+        _credentials: &CloudflareCredentials,
+        _account_id: &str,
+        tunnel_id: Uuid,
+    ) -> Result<Vec<TunnelConnector>, ApiFailure> {
+        self.call("list_connections")?;
+        self.tunnel(tunnel_id)?;
+        if !self.connected.lock().unwrap().contains(&tunnel_id) {
+            return Ok(Vec::new());
+        }
+
+        Ok(vec![TunnelConnector {
+            id: Uuid::new_v4(),
+            conns: vec![TunnelConnection {
+                id: Uuid::new_v4(),
+                colo_name: "ams01".to_owned(),
+                is_pending_reconnect: false,
+            }],
+        }])
+    }
+
+    async fn cleanup_connections(
+        &self,
+        _credentials: &CloudflareCredentials,
+        _account_id: &str,
+        _tunnel_id: Uuid,
+    ) -> Result<(), ApiFailure> {
+        self.call("cleanup_connections")
+    }
+}
+
+/// Identifies a stored object, the namespace is empty for cluster scoped kinds.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+struct Key {
+    prefix: String,
+    plural: String,
+    namespace: String,
+    name: String,
+}
+
+/// Where a request points to, `name` is None for collections.
+struct Target {
+    prefix: String,
+    plural: String,
+    namespace: Option<String>,
+    name: Option<String>,
+    subresource: Option<String>,
+}
+
+impl Target {
+    fn parse(path: &str) -> Target {
+        let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+        let prefix_len = if segments[0] == "api" { 2 } else { 3 };
+        let prefix = format!("/{}", segments[..prefix_len].join("/"));
+        let mut rest = &segments[prefix_len..];
+
+        let mut namespace = None;
+        if rest.len() >= 3 && rest[0] == "namespaces" {
+            namespace = Some(rest[1].to_owned());
+            rest = &rest[2..];
+        }
+
+        Target {
+            prefix,
+            plural: rest[0].to_owned(),
+            namespace,
+            name: rest.get(1).map(|name| (*name).to_owned()),
+            subresource: rest.get(2).map(|subresource| (*subresource).to_owned()),
+        }
+    }
+
+    fn key(&self, name: &str) -> Key {
+        Key {
+            prefix: self.prefix.clone(),
+            plural: self.plural.clone(),
+            namespace: self.namespace.clone().unwrap_or_default(),
+            name: name.to_owned(),
+        }
+    }
+}
+
+/// Json merge patch as in RFC 7386, nulls remove fields.
+fn merge(target: &mut Value, patch: &Value) {
+    let patch = match patch {
+        Value::Object(patch) => patch,
+        patch => {
+            *target = patch.clone();
+            return;
+        }
+    };
+
+    if !target.is_object() {
+        *target = Value::Object(Map::new());
+    }
+    let target = target.as_object_mut().unwrap();
+    for (key, value) in patch {
+        if value.is_null() {
+            target.remove(key);
+        } else {
+            merge(target.entry(key.clone()).or_insert(Value::Null), value);
+        }
+    }
+}
+
+fn lookup<'a>(object: &'a Value, path: &str) -> Option<&'a Value> {
+    path.split('.')
+        .try_fold(object, |value, segment| value.get(segment))
+}
+
+/// Equality selectors only, `a=b,c=d` for labels and fields alike.
+fn selected(object: &Value, labels: Option<&str>, fields: Option<&str>) -> bool {
+    let labels_match = labels.into_iter().flat_map(|s| s.split(',')).all(|term| {
+        let (key, value) = term.split_once('=').unwrap_or((term, ""));
+        object["metadata"]["labels"]
+            .get(key)
+            .and_then(Value::as_str)
+            == Some(value)
+    });
+    let fields_match = fields.into_iter().flat_map(|s| s.split(',')).all(|term| {
+        let (path, value) = term.split_once('=').unwrap_or((term, ""));
+        lookup(object, path).and_then(Value::as_str) == Some(value)
+    });
+    labels_match && fields_match
+}
+
+fn respond(status: StatusCode, body: &Value) -> Response<Body> {
+    Response::builder()
+        .status(status)
+        .header("content-type", "application/json")
+        .body(Body::from(serde_json::to_vec(body).unwrap()))
+        .unwrap()
+}
+
+fn failure(status: StatusCode, reason: &str, message: String) -> Response<Body> {
+    respond(
+        status,
+        &json!({
+            "apiVersion": "v1",
+            "kind": "Status",
+            "metadata": {},
+            "status": "Failure",
+            "reason": reason,
+            "message": message,
+            "code": status.as_u16(),
+        }),
+    )
+}
+
+/// Api server answering reads, merge and apply patches, creates and deletes from a map of
+/// objects. Resource versions are checked, finalizers hold deleted objects until removed and
+/// `status` is only written through the status subresource.
+#[derive(Clone, Default)]
+pub struct ApiServer {
+    objects: Arc<Mutex<BTreeMap<Key, Value>>>,
+    resource_version: Arc<AtomicU64>,
+    failures: Arc<Mutex<Vec<Failure>>>,
+    // INFO: Objects whose spec.replicas was last set outside the operator, as `kubectl scale`
+    // does, an apply changing it conflicts until a plain patch takes the field back.
+    scaled: Arc<Mutex<BTreeSet<Key>>>,
+}
+
+/// Method and plural of a request to fail, with the status it gets.
+type Failure = (&'static str, &'static str, StatusCode);
+
+impl ApiServer {
+    /// A client whose requests are answered by a new, empty api server.
+    pub fn start() -> (Client, ApiServer) {
+        let (service, handle) = mock::pair::<Request<Body>, Response<Body>>();
+        let server = ApiServer::default();
+
+        let handler = server.clone();
+        tokio::spawn(async move {
+            let mut handle = std::pin::pin!(handle);
+            while let Some((request, send)) = handle.next_request().await {
+                send.send_response(handler.handle(request).await);
+            }
+        });
+
+        (Client::new(service, "default"), server)
+    }
+
+    fn key_of<K>(namespace: Option<&str>, name: &str) -> Key
+    where
+        K: Resource<DynamicType = ()>,
+    {
+        Target::parse(&K::url_path(&(), namespace)).key(name)
+    }
+
+    /// The next `method` request on the `plural` resource itself, not a subresource, fails with
+    /// `status`.
+    pub fn fail_once(&self, method: &'static str, plural: &'static str, status: StatusCode) {
+        self.failures.lock().unwrap().push((method, plural, status));
+    }
+
+    /// Stores `object` as if it had been created through the api.
+    pub fn insert<K>(&self, object: &K)
+    where
+        K: Resource<DynamicType = ()> + Serialize,
+    {
+        let meta = object.meta();
+        let key = Self::key_of::<K>(meta.namespace.as_deref(), meta.name.as_deref().unwrap());
+        let mut value = serde_json::to_value(object).unwrap();
+        value["apiVersion"] = json!(K::api_version(&()));
+        value["kind"] = json!(K::kind(&()));
+        self.store(key, value);
+    }
+
+    pub fn get<K>(&self, namespace: Option<&str>, name: &str) -> Option<K>
+    where
+        K: Resource<DynamicType = ()> + DeserializeOwned,
+    {
+        self.objects
+            .lock()
+            .unwrap()
+            .get(&Self::key_of::<K>(namespace, name))
+            .map(|value| serde_json::from_value(value.clone()).unwrap())
+    }
+
+    /// Every stored object of the kind, limited to `namespace` when given.
+    pub fn get_all<K>(&self, namespace: Option<&str>) -> Vec<K>
+    where
+        K: Resource<DynamicType = ()> + DeserializeOwned,
+    {
+        let target = Target::parse(&K::url_path(&(), namespace));
+        self.objects
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(key, _)| key.prefix == target.prefix && key.plural == target.plural)
+            .filter(|(key, _)| namespace.is_none_or(|namespace| namespace == key.namespace))
+            .map(|(_, value)| serde_json::from_value(value.clone()).unwrap())
+            .collect()
+    }
+
+    /// Deletes like the api would, an object with finalizers only gets a deletionTimestamp.
+    pub fn delete<K>(&self, namespace: Option<&str>, name: &str)
+    where
+        K: Resource<DynamicType = ()>,
+    {
+        self.remove(&Self::key_of::<K>(namespace, name));
+    }
+
+    /// Merges `patch` into a stored object, including its status.
+    pub fn update<K>(&self, namespace: Option<&str>, name: &str, patch: Value)
+    where
+        K: Resource<DynamicType = ()>,
+    {
+        let key = Self::key_of::<K>(namespace, name);
+        if !patch["spec"]["replicas"].is_null() {
+            self.scaled.lock().unwrap().insert(key.clone());
+        }
+        let mut value = self.objects.lock().unwrap()[&key].clone();
+        merge(&mut value, &patch);
+        self.store(key, value);
+    }
+
+    fn store(&self, key: Key, mut value: Value) -> Value {
+        let resource_version = self.resource_version.fetch_add(1, Ordering::Relaxed) + 1;
+        let metadata = &mut value["metadata"];
+        metadata["name"] = json!(key.name);
+        if !key.namespace.is_empty() {
+            metadata["namespace"] = json!(key.namespace);
+        }
+        metadata["resourceVersion"] = json!(resource_version.to_string());
+        if metadata.get("uid").is_none() {
+            metadata["uid"] = json!(Uuid::new_v4());
+            metadata["creationTimestamp"] = json!(Utc::now().to_rfc3339());
+            metadata["generation"] = json!(1);
+        }
+
+        let finalizers = metadata["finalizers"]
+            .as_array()
+            .is_some_and(|finalizers| !finalizers.is_empty());
+        let mut objects = self.objects.lock().unwrap();
+        if metadata.get("deletionTimestamp").is_some() && !finalizers {
+            objects.remove(&key);
+        } else {
+            objects.insert(key, value.clone());
+        }
+        value
+    }
+
+    fn remove(&self, key: &Key) -> Option<Value> {
+        let mut value = self.objects.lock().unwrap().get(key)?.clone();
+        value["metadata"]["deletionTimestamp"] = json!(Utc::now().to_rfc3339());
+        Some(self.store(key.clone(), value))
+    }
+
+    async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        let (parts, body) = request.into_parts();
+        let body = body.collect_bytes().await.unwrap();
+        let body: Value = match body.is_empty() {
+            true => Value::Null,
+            false => serde_json::from_slice(&body).unwrap(),
+        };
+        let query: BTreeMap<String, String> = parts
+            .uri
+            .query()
+            .map(|query| serde_urlencoded::from_str(query).unwrap())
+            .unwrap_or_default();
+        let target = Target::parse(parts.uri.path());
+
+        if target.subresource.is_none() {
+            let mut failures = self.failures.lock().unwrap();
+            if let Some(index) = failures.iter().position(|(method, plural, _)| {
+                *method == parts.method.as_str() && *plural == target.plural
+            }) {
+                let (_, _, status) = failures.remove(index);
+                return failure(status, "InternalError", format!("{} failed", parts.uri));
+            }
+        }
+
+        let name = match (&target.name, parts.method.as_str()) {
+            (None, "GET") => return self.list(&target, &query),
+            (None, "POST") => body["metadata"]["name"].as_str().unwrap_or_default(),
+            (Some(name), _) => name.as_str(),
+            (None, method) => panic!("unexpected {} {}", method, parts.uri),
+        }
+        .to_owned();
+        let key = target.key(&name);
+        let current = self.objects.lock().unwrap().get(&key).cloned();
+        let not_found = || {
+            failure(
+                StatusCode::NOT_FOUND,
+                "NotFound",
+                format!("{} {} not found", target.plural, name),
+            )
+        };
+
+        match (parts.method.as_str(), current) {
+            ("GET", Some(current)) => respond(StatusCode::OK, &current),
+            ("POST", Some(_)) => failure(
+                StatusCode::CONFLICT,
+                "AlreadyExists",
+                format!("{} {} already exists", target.plural, name),
+            ),
+            ("POST", None) => respond(StatusCode::CREATED, &self.store(key, body)),
+            ("DELETE", Some(_)) => respond(StatusCode::OK, &self.remove(&key).unwrap_or_default()),
+            ("PATCH", current) => {
+                let content_type = parts.headers["content-type"].to_str().unwrap();
+                let current = match current {
+                    Some(current) => current,
+                    // INFO: Server side apply creates what doesn't exist yet.
+                    None if content_type.starts_with("application/apply-patch") => json!({}),
+                    None => return not_found(),
+                };
+
+                let expected = body["metadata"]["resourceVersion"].as_str();
+                if expected.is_some_and(|expected| {
+                    Some(expected) != current["metadata"]["resourceVersion"].as_str()
+                }) {
+                    return failure(
+                        StatusCode::CONFLICT,
+                        "Conflict",
+                        format!("{} {} was modified", target.plural, name),
+                    );
+                }
+
+                let replicas = &body["spec"]["replicas"];
+                if !replicas.is_null() {
+                    let mut scaled = self.scaled.lock().unwrap();
+                    let apply = content_type.starts_with("application/apply-patch");
+                    let force = query.get("force").is_some_and(|force| force == "true");
+                    if !apply {
+                        scaled.remove(&key);
+                    } else if !force
+                        && scaled.contains(&key)
+                        && *replicas != current["spec"]["replicas"]
+                    {
+                        return failure(
+                            StatusCode::CONFLICT,
+                            "Conflict",
+                            "Apply failed with 1 conflict: .spec.replicas".to_owned(),
+                        );
+                    }
+                }
+
+                let mut patch = body;
+                if let Some(metadata) = patch.get_mut("metadata").and_then(Value::as_object_mut) {
+                    metadata.remove("resourceVersion");
+                }
+                let patch = match target.subresource.as_deref() {
+                    Some("status") => match patch["status"].take() {
+                        Value::Null => json!({}),
+                        status => json!({ "status": status }),
+                    },
+                    _ => {
+                        patch.as_object_mut().unwrap().remove("status");
+                        patch
+                    }
+                };
+
+                let mut updated = current.clone();
+                merge(&mut updated, &patch);
+                if updated.get("spec") != current.get("spec") && current.get("spec").is_some() {
+                    let generation = current["metadata"]["generation"].as_i64().unwrap_or(1);
+                    updated["metadata"]["generation"] = json!(generation + 1);
+                }
+                respond(StatusCode::OK, &self.store(key, updated))
+            }
+            (_, None) => not_found(),
+            (method, Some(_)) => panic!("unexpected {} {}", method, parts.uri),
+        }
+    }
+
+    fn list(&self, target: &Target, query: &BTreeMap<String, String>) -> Response<Body> {
+        let items: Vec<Value> = self
+            .objects
+            .lock()
+            .unwrap()
+            .iter()
+            .filter(|(key, _)| key.prefix == target.prefix && key.plural == target.plural)
+            .filter(|(key, _)| {
+                target
+                    .namespace
+                    .as_ref()
+                    .is_none_or(|namespace| *namespace == key.namespace)
+            })
+            .map(|(_, value)| value)
+            .filter(|value| {
+                selected(
+                    value,
+                    query.get("labelSelector").map(String::as_str),
+                    query.get("fieldSelector").map(String::as_str),
+                )
+            })
+            .cloned()
+            .collect();
+
+        respond(
+            StatusCode::OK,
+            &json!({
+                "apiVersion": "v1",
+                "kind": "List",
+                "metadata": {
+                    "resourceVersion": self.resource_version.load(Ordering::Relaxed).to_string(),
+                },
+                "items": items,
+            }),
+        )
+    }
+}
+
+/// Reconcile context around `cloudflare_client` with the operator's defaults.
+pub fn context<C: CloudflaredTunnel>(client: Client, cloudflare_client: C) -> Arc<Context<C>> {
+    let (tunnel_ingress_store, _) = reflector::store();
+    Arc::new(Context {
+        kubernetes_client: client.clone(),
+        cloudflare_client,
+        credentials_api: Api::<Credentials>::all(client.clone()),
+        tunnel_api: Api::all(client.clone()),
+        tunnel_ingress_store,
+        cluster_tunnel_namespace: DEFAULT_CLUSTER_TUNNEL_NAMESPACE.to_owned(),
+        hibernate_all: false,
+        allow_cross_namespace_refs: false,
+        backoff: Backoff::default(),
+        token_cache: TokenCache::default(),
+        image_policy: ImagePolicy::default(),
+        state: OperatorState::disabled(),
+        locks: ObjectLocks::new(),
+        validated_credentials: DashMap::new(),
+        recorder: Recorder::new(
+            client,
+            Reporter {
+                controller: "cloudflare-tunnel-operator".into(),
+                instance: None,
+            },
+        ),
+    })
+}
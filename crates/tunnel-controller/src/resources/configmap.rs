@@ -0,0 +1,59 @@
+use super::{
+    apply_params, delete_params, hash_entries, ignore_not_found, owner_references,
+    resource_annotations,
+};
+use crate::crd::tunnel::TunnelResource;
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::api::{ObjectMeta, Patch};
+use kube::Api;
+use std::collections::BTreeMap;
+
+pub fn data_hash(data: &BTreeMap<String, String>) -> String {
+    hash_entries(
+        data.iter()
+            .map(|(key, value)| (key.as_str(), value.as_bytes())),
+    )
+}
+
+pub async fn apply<K: TunnelResource>(
+    kubernetes_client: kube::Client,
+    tunnel: &K,
+    namespace: &str,
+    labels: BTreeMap<String, String>,
+    data: BTreeMap<String, String>,
+) -> Result<ConfigMap, kube::Error> {
+    let name = tunnel.child_name();
+
+    let config_map = ConfigMap {
+        metadata: ObjectMeta {
+            name: Some(name.clone()),
+            namespace: Some(namespace.to_owned()),
+            labels: Some(labels),
+            annotations: resource_annotations(tunnel),
+            owner_references: owner_references(tunnel),
+            ..ObjectMeta::default()
+        },
+        data: Some(data),
+        ..ConfigMap::default()
+    };
+
+    let config_map_api: Api<ConfigMap> = Api::namespaced(kubernetes_client, namespace);
+    config_map_api
+        .patch(&name, &apply_params(), &Patch::Apply(&config_map))
+        .await
+}
+
+pub async fn delete<K: TunnelResource>(
+    kubernetes_client: kube::Client,
+    tunnel: &K,
+    namespace: &str,
+) -> Result<(), kube::Error> {
+    let config_map_api: Api<ConfigMap> = Api::namespaced(kubernetes_client, namespace);
+
+    ignore_not_found(
+        config_map_api
+            .delete(&tunnel.child_name(), &delete_params())
+            .await
+            .map(|_| ()),
+    )
+}
@@ -0,0 +1,732 @@
+use super::{
+    apply_params, delete_params, ignore_not_found, owner_references, resource_annotations,
+    update_params, CREDENTIALS_DIR, LOCAL_CONFIG_DIR,
+};
+use crate::crd::tunnel::{TunnelCrd, TunnelRegion, TunnelResource};
+use k8s_openapi::api::apps::v1::{Deployment, DeploymentSpec, DeploymentStatus};
+use k8s_openapi::api::core::v1::{
+    ConfigMapVolumeSource, Container, EnvFromSource, PodSpec, PodTemplateSpec, SecretEnvSource,
+    SecretVolumeSource, Volume, VolumeMount,
+};
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::LabelSelector;
+use kube::api::{ListParams, ObjectMeta, Patch};
+use kube::Api;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+const TOKEN_HASH_ANNOTATION: &str = "cloudflare.ar2ro.io/token-hash";
+const CONFIG_HASH_ANNOTATION: &str = "cloudflare.ar2ro.io/config-hash";
+const REGION_LABEL: &str = "cloudflare.ar2ro.io/region";
+
+pub const DEFAULT_IMAGE: &str = "cloudflare/cloudflared:latest";
+
+/// Operator wide choice of the cloudflared image, `spec.image` only wins when overrides are
+/// allowed.
+#[derive(Debug, Clone)]
+pub struct ImagePolicy {
+    pub default_image: String,
+    pub allow_override: bool,
+}
+
+// INFO: Drops the tag or digest, a `:` before the last `/` belongs to the registry port.
+fn image_repository(image: &str) -> &str {
+    let image = image
+        .split_once('@')
+        .map_or(image, |(repository, _)| repository);
+    match image.rsplit_once(':') {
+        Some((repository, tag)) if !tag.contains('/') => repository,
+        _ => image,
+    }
+}
+
+impl ImagePolicy {
+    // INFO: `spec.version` pins the repository of the default image and wins over `spec.image`.
+    fn requested(&self, spec: &TunnelCrd) -> Option<String> {
+        match (&spec.version, &spec.image) {
+            (Some(version), _) => Some(format!(
+                "{}:{}",
+                image_repository(&self.default_image),
+                version
+            )),
+            (None, image) => image.clone(),
+        }
+    }
+
+    pub fn resolve(&self, spec: &TunnelCrd) -> String {
+        match self.requested(spec) {
+            Some(image) if self.allow_override => image,
+            _ => self.default_image.clone(),
+        }
+    }
+
+    /// The image `spec.image` or `spec.version` asked for when the policy ignores it.
+    pub fn ignored_override(&self, spec: &TunnelCrd) -> Option<String> {
+        self.requested(spec)
+            .filter(|image| !self.allow_override && *image != self.default_image)
+    }
+}
+
+impl Default for ImagePolicy {
+    fn default() -> ImagePolicy {
+        ImagePolicy {
+            default_image: DEFAULT_IMAGE.to_owned(),
+            allow_override: true,
+        }
+    }
+}
+
+// INFO: The token goes through the environment, a local tunnel mounts its config and
+// credentials file instead.
+fn credentials(name: &str, local: bool) -> (Vec<EnvFromSource>, Vec<Volume>, Vec<VolumeMount>) {
+    if !local {
+        let env = vec![EnvFromSource {
+            secret_ref: Some(SecretEnvSource {
+                name: name.to_owned(),
+                optional: Some(false),
+            }),
+            ..EnvFromSource::default()
+        }];
+
+        return (env, Vec::new(), Vec::new());
+    }
+
+    let volumes = vec![
+        Volume {
+            name: "config".to_owned(),
+            config_map: Some(ConfigMapVolumeSource {
+                name: name.to_owned(),
+                ..ConfigMapVolumeSource::default()
+            }),
+            ..Volume::default()
+        },
+        Volume {
+            name: "credentials".to_owned(),
+            secret: Some(SecretVolumeSource {
+                secret_name: Some(name.to_owned()),
+                ..SecretVolumeSource::default()
+            }),
+            ..Volume::default()
+        },
+    ];
+
+    let mounts = vec![
+        VolumeMount {
+            name: "config".to_owned(),
+            mount_path: LOCAL_CONFIG_DIR.to_owned(),
+            read_only: Some(true),
+            ..VolumeMount::default()
+        },
+        VolumeMount {
+            name: "credentials".to_owned(),
+            mount_path: CREDENTIALS_DIR.to_owned(),
+            read_only: Some(true),
+            ..VolumeMount::default()
+        },
+    ];
+
+    (Vec::new(), volumes, mounts)
+}
+
+/// Name and replica count of every Deployment the spec asks for, one per region when regions
+/// are set.
+pub fn desired_replicas<K: TunnelResource>(tunnel: &K) -> Vec<(String, i32)> {
+    let spec = tunnel.tunnel_spec();
+    match spec.regions() {
+        [] => vec![(tunnel.child_name(), spec.replicas)],
+        regions => regions
+            .iter()
+            .map(|region| (region_name(tunnel, region), region.replicas))
+            .collect(),
+    }
+}
+
+fn region_name<K: TunnelResource>(tunnel: &K, region: &TunnelRegion) -> String {
+    format!("{}-{}", tunnel.child_name(), region.cluster_name)
+}
+
+fn deployment<K: TunnelResource>(
+    tunnel: &K,
+    namespace: &str,
+    mut labels: BTreeMap<String, String>,
+    token_hash: &str,
+    config_hash: Option<&str>,
+    image: &str,
+    region: Option<&TunnelRegion>,
+) -> Deployment {
+    let spec = tunnel.tunnel_spec();
+    let name = match region {
+        Some(region) => region_name(tunnel, region),
+        None => tunnel.child_name(),
+    };
+    // INFO: The region label keeps the selectors of the region Deployments apart.
+    if let Some(region) = region {
+        labels.insert(REGION_LABEL.to_owned(), region.cluster_name.clone());
+    }
+
+    let (env, volumes, volume_mounts) = credentials(&tunnel.child_name(), spec.is_local());
+    let topology_spread_constraints = spec.topology_spread_constraints(&labels);
+
+    let mut annotations = spec.pod_annotations.clone().unwrap_or_default();
+    annotations.insert(TOKEN_HASH_ANNOTATION.to_owned(), token_hash.to_owned());
+    if let Some(config_hash) = config_hash {
+        annotations.insert(CONFIG_HASH_ANNOTATION.to_owned(), config_hash.to_owned());
+    }
+
+    Deployment {
+        metadata: ObjectMeta {
+            name: Some(name.to_owned()),
+            namespace: Some(namespace.to_owned()),
+            labels: Some(labels.clone()),
+            annotations: resource_annotations(tunnel),
+            owner_references: owner_references(tunnel),
+            ..ObjectMeta::default()
+        },
+        spec: Some(DeploymentSpec {
+            replicas: Some(region.map_or(spec.replicas, |region| region.replicas)),
+            selector: LabelSelector {
+                match_labels: Some(labels.clone()),
+                ..LabelSelector::default()
+            },
+            template: PodTemplateSpec {
+                metadata: Some(ObjectMeta {
+                    name: Some(name.to_owned()),
+                    namespace: Some(namespace.to_owned()),
+                    labels: Some(labels),
+                    annotations: Some(annotations),
+                    ..ObjectMeta::default()
+                }),
+                spec: Some(PodSpec {
+                    containers: vec![Container {
+                        name: "cloudflared".to_owned(),
+                        image: Some(image.to_owned()),
+                        image_pull_policy: Some(
+                            spec.image_pull_policy
+                                .unwrap_or_default()
+                                .as_str()
+                                .to_owned(),
+                        ),
+                        env_from: Some(env).filter(|env| !env.is_empty()),
+                        env: Some(spec.env.clone()).filter(|env| !env.is_empty()),
+                        volume_mounts: Some(volume_mounts).filter(|mounts| !mounts.is_empty()),
+                        command: Some(spec.container_command()),
+                        liveness_probe: Some(spec.liveness_probe()),
+                        readiness_probe: Some(spec.readiness_probe()),
+                        lifecycle: Some(spec.lifecycle()),
+                        security_context: Some(spec.container_security_context()),
+                        ..Container::default()
+                    }],
+                    volumes: Some(volumes).filter(|volumes| !volumes.is_empty()),
+                    termination_grace_period_seconds: Some(spec.termination_grace_period()),
+                    image_pull_secrets: Some(spec.image_pull_secrets.clone())
+                        .filter(|secrets| !secrets.is_empty()),
+                    service_account_name: Some(tunnel.service_account_name()),
+                    priority_class_name: spec.priority_class_name.clone(),
+                    security_context: Some(spec.pod_security_context()),
+                    topology_spread_constraints,
+                    node_selector: region
+                        .map(|region| region.node_selector.clone())
+                        .filter(|node_selector| !node_selector.is_empty()),
+                    ..PodSpec::default()
+                }),
+            },
+            ..DeploymentSpec::default()
+        }),
+        ..Deployment::default()
+    }
+}
+
+pub async fn apply<K: TunnelResource>(
+    kubernetes_client: kube::Client,
+    tunnel: &K,
+    namespace: &str,
+    labels: BTreeMap<String, String>,
+    token_hash: &str,
+    config_hash: Option<&str>,
+    image: &str,
+) -> Result<Deployment, kube::Error> {
+    let deployment_api: Api<Deployment> = Api::namespaced(kubernetes_client, namespace);
+    let regions = tunnel.tunnel_spec().regions();
+    let targets = match regions {
+        [] => vec![None],
+        regions => regions.iter().map(Some).collect(),
+    };
+    let selector = labels
+        .iter()
+        .map(|(key, value)| format!("{}={}", key, value))
+        .collect::<Vec<_>>()
+        .join(",");
+    let existing = deployment_api
+        .list(&ListParams::default().labels(&selector))
+        .await?
+        .items;
+
+    let mut applied = Vec::new();
+    for region in targets {
+        let deployment = deployment(
+            tunnel,
+            namespace,
+            labels.clone(),
+            token_hash,
+            config_hash,
+            image,
+            region,
+        );
+        let name = deployment.metadata.name.clone().unwrap_or_default();
+        log_protocol_change(&existing, &deployment);
+        applied.push(
+            deployment_api
+                .patch(&name, &apply_params(), &Patch::Apply(&deployment))
+                .await?,
+        );
+    }
+
+    prune(&deployment_api, &existing, &applied).await?;
+
+    match regions {
+        [] => Ok(applied.pop().unwrap_or_default()),
+        _ => Ok(combined(applied)),
+    }
+}
+
+// INFO: The `--protocol` argument of the cloudflared container, `None` when cloudflared picks.
+fn protocol(deployment: &Deployment) -> Option<&str> {
+    let command = deployment
+        .spec
+        .as_ref()?
+        .template
+        .spec
+        .as_ref()?
+        .containers
+        .first()?
+        .command
+        .as_ref()?;
+    command
+        .iter()
+        .position(|arg| arg == "--protocol")
+        .and_then(|index| command.get(index + 1))
+        .map(String::as_str)
+}
+
+// INFO: Only a protocol that differs from the running Deployment is logged, not every reconcile.
+fn log_protocol_change(existing: &[Deployment], deployment: &Deployment) {
+    let previous = existing
+        .iter()
+        .find(|existing| existing.metadata.name == deployment.metadata.name)
+        .and_then(protocol);
+    let name = deployment.metadata.name.as_deref().unwrap_or_default();
+    match protocol(deployment) {
+        current if current == previous => {}
+        Some(protocol) => tracing::info!(
+            "Deployment {} runs cloudflared with --protocol {}",
+            name,
+            protocol
+        ),
+        None => tracing::info!("Deployment {} lets cloudflared pick its protocol", name),
+    }
+}
+
+// INFO: Removes the Deployments of regions that were dropped from the spec, and the single
+// Deployment once regions are set or the region ones once they're removed again.
+async fn prune(
+    deployment_api: &Api<Deployment>,
+    existing: &[Deployment],
+    applied: &[Deployment],
+) -> Result<(), kube::Error> {
+    for stale in existing.iter().filter(|deployment| {
+        !applied
+            .iter()
+            .any(|applied| applied.metadata.name == deployment.metadata.name)
+    }) {
+        let name = stale.metadata.name.clone().unwrap_or_default();
+        println!("Deleting stale cloudflared Deployment {}", name);
+        ignore_not_found(
+            deployment_api
+                .delete(&name, &delete_params())
+                .await
+                .map(|_| ()),
+        )?;
+    }
+
+    Ok(())
+}
+
+// INFO: The replica counts of every region added up, that's what the tunnel status reports.
+fn combined(deployments: Vec<Deployment>) -> Deployment {
+    let sum = |count: fn(&DeploymentStatus) -> Option<i32>| {
+        deployments
+            .iter()
+            .filter_map(|deployment| deployment.status.as_ref().and_then(count))
+            .sum::<i32>()
+    };
+
+    Deployment {
+        status: Some(DeploymentStatus {
+            replicas: Some(sum(|status| status.replicas)),
+            ready_replicas: Some(sum(|status| status.ready_replicas)),
+            available_replicas: Some(sum(|status| status.available_replicas)),
+            updated_replicas: Some(sum(|status| status.updated_replicas)),
+            ..DeploymentStatus::default()
+        }),
+        ..Deployment::default()
+    }
+}
+
+pub async fn get(
+    kubernetes_client: kube::Client,
+    namespace: &str,
+    name: &str,
+) -> Result<Option<Deployment>, kube::Error> {
+    let deployment_api: Api<Deployment> = Api::namespaced(kubernetes_client, namespace);
+    deployment_api.get_opt(name).await
+}
+
+// INFO: `kubectl scale` takes spec.replicas from the apply field manager, applying a different
+// count afterwards would be rejected as a conflict, so the count is taken back first.
+pub async fn scale(
+    kubernetes_client: kube::Client,
+    namespace: &str,
+    name: &str,
+    replicas: i32,
+) -> Result<Deployment, kube::Error> {
+    let deployment_api: Api<Deployment> = Api::namespaced(kubernetes_client, namespace);
+    deployment_api
+        .patch(
+            name,
+            &update_params(),
+            &Patch::Merge(json!({ "spec": { "replicas": replicas } })),
+        )
+        .await
+}
+
+/// Scales every Deployment of the tunnel to zero, ones that are already there aren't touched.
+pub async fn scale_down<K: TunnelResource>(
+    kubernetes_client: kube::Client,
+    tunnel: &K,
+    namespace: &str,
+) -> Result<(), kube::Error> {
+    let deployment_api: Api<Deployment> = Api::namespaced(kubernetes_client, namespace);
+    for (name, _) in desired_replicas(tunnel) {
+        let replicas = match deployment_api.get_opt(&name).await? {
+            Some(deployment) => deployment.spec.and_then(|spec| spec.replicas),
+            None => continue,
+        };
+        if replicas != Some(0) {
+            deployment_api
+                .patch(
+                    &name,
+                    &update_params(),
+                    &Patch::Merge(json!({ "spec": { "replicas": 0 } })),
+                )
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+pub async fn delete<K: TunnelResource>(
+    kubernetes_client: kube::Client,
+    tunnel: &K,
+    namespace: &str,
+) -> Result<(), kube::Error> {
+    let deployment_api: Api<Deployment> = Api::namespaced(kubernetes_client, namespace);
+
+    for (name, _) in desired_replicas(tunnel) {
+        ignore_not_found(
+            deployment_api
+                .delete(&name, &delete_params())
+                .await
+                .map(|_| ()),
+        )?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crd::tunnel::Tunnel;
+    use crate::resources::secret;
+    use k8s_openapi::api::core::v1::LocalObjectReference;
+    use k8s_openapi::ByteString;
+    use serde_json::Value;
+
+    fn tunnel(fields: Value) -> Tunnel {
+        let mut spec = json!({ "credentials": "creds" });
+        spec.as_object_mut()
+            .unwrap()
+            .extend(fields.as_object().unwrap().clone());
+        let mut tunnel = Tunnel::new("tunnel", serde_json::from_value(spec).unwrap());
+        tunnel.metadata.namespace = Some("default".to_owned());
+        tunnel
+    }
+
+    fn render(tunnel: &Tunnel) -> Deployment {
+        deployment(
+            tunnel,
+            "default",
+            BTreeMap::new(),
+            "token-hash",
+            None,
+            DEFAULT_IMAGE,
+            None,
+        )
+    }
+
+    fn pod_spec(deployment: &Deployment) -> &PodSpec {
+        deployment
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.template.spec.as_ref())
+            .unwrap()
+    }
+
+    #[test]
+    fn protocol_is_read_back_from_the_command() {
+        for (tunnel_protocol, arg) in [("Quic", Some("quic")), ("Http2", Some("http2"))] {
+            let deployment = render(&tunnel(json!({ "tunnelProtocol": tunnel_protocol })));
+            assert_eq!(protocol(&deployment), arg);
+        }
+        assert_eq!(
+            protocol(&render(&tunnel(json!({ "tunnelProtocol": "Auto" })))),
+            None
+        );
+        assert_eq!(protocol(&render(&tunnel(json!({})))), None);
+        assert_eq!(protocol(&Deployment::default()), None);
+    }
+
+    #[test]
+    fn image_pull_secrets_reach_the_pod() {
+        let deployment = render(&tunnel(
+            json!({ "imagePullSecrets": [{ "name": "mirror" }] }),
+        ));
+
+        assert_eq!(
+            pod_spec(&deployment).image_pull_secrets,
+            Some(vec![LocalObjectReference {
+                name: "mirror".to_owned(),
+            }])
+        );
+        assert_eq!(
+            pod_spec(&render(&tunnel(json!({})))).image_pull_secrets,
+            None
+        );
+    }
+
+    #[test]
+    fn service_account_name_reaches_the_pod() {
+        let deployment = render(&tunnel(json!({ "serviceAccountName": "restricted" })));
+        assert_eq!(
+            pod_spec(&deployment).service_account_name.as_deref(),
+            Some("restricted")
+        );
+
+        let deployment = render(&tunnel(json!({})));
+        assert_eq!(
+            pod_spec(&deployment).service_account_name.as_deref(),
+            Some("tunnel-cloudflared")
+        );
+    }
+
+    #[test]
+    fn priority_class_name_reaches_the_pod() {
+        let deployment = render(&tunnel(
+            json!({ "priorityClassName": "system-cluster-critical" }),
+        ));
+
+        assert_eq!(
+            pod_spec(&deployment).priority_class_name.as_deref(),
+            Some("system-cluster-critical")
+        );
+        assert_eq!(
+            pod_spec(&render(&tunnel(json!({})))).priority_class_name,
+            None
+        );
+    }
+
+    fn pod_annotation<'a>(deployment: &'a Deployment, key: &str) -> Option<&'a str> {
+        deployment
+            .spec
+            .as_ref()
+            .and_then(|spec| spec.template.metadata.as_ref())
+            .and_then(|metadata| metadata.annotations.as_ref())
+            .and_then(|annotations| annotations.get(key))
+            .map(String::as_str)
+    }
+
+    #[test]
+    fn token_hash_follows_the_secret_content() {
+        let token = |token: &str| {
+            BTreeMap::from([(
+                "TUNNEL_TOKEN".to_owned(),
+                ByteString(token.as_bytes().to_vec()),
+            )])
+        };
+        let tunnel = tunnel(json!({}));
+        let render = |hash: &str| {
+            deployment(
+                &tunnel,
+                "default",
+                BTreeMap::new(),
+                hash,
+                None,
+                DEFAULT_IMAGE,
+                None,
+            )
+        };
+
+        let first = render(&secret::data_hash(&token("first")));
+        let unchanged = render(&secret::data_hash(&token("first")));
+        let rotated = render(&secret::data_hash(&token("second")));
+
+        let hash = |deployment: &Deployment| {
+            pod_annotation(deployment, TOKEN_HASH_ANNOTATION)
+                .unwrap()
+                .to_owned()
+        };
+        assert_eq!(hash(&first), hash(&unchanged));
+        assert_ne!(hash(&first), hash(&rotated));
+        assert_eq!(first.spec, unchanged.spec);
+    }
+
+    #[test]
+    fn local_tunnel_mounts_its_config_and_credentials() {
+        let deployment = render(&tunnel(json!({ "configurationSource": "local" })));
+        let pod_spec = pod_spec(&deployment);
+        let container = &pod_spec.containers[0];
+
+        let volumes: Vec<_> = pod_spec
+            .volumes
+            .iter()
+            .flatten()
+            .map(|volume| {
+                (
+                    volume.name.as_str(),
+                    volume
+                        .config_map
+                        .as_ref()
+                        .map(|config_map| config_map.name.as_str()),
+                    volume
+                        .secret
+                        .as_ref()
+                        .and_then(|secret| secret.secret_name.as_deref()),
+                )
+            })
+            .collect();
+        assert_eq!(
+            volumes,
+            [
+                ("config", Some("tunnel"), None),
+                ("credentials", None, Some("tunnel")),
+            ]
+        );
+
+        let mounts: Vec<_> = container
+            .volume_mounts
+            .iter()
+            .flatten()
+            .map(|mount| (mount.name.as_str(), mount.mount_path.as_str()))
+            .collect();
+        assert_eq!(
+            mounts,
+            [
+                ("config", LOCAL_CONFIG_DIR),
+                ("credentials", CREDENTIALS_DIR)
+            ]
+        );
+        assert_eq!(container.env_from, None);
+        assert!(container
+            .command
+            .iter()
+            .flatten()
+            .any(|arg| arg == "/etc/cloudflared/config.yaml"));
+    }
+
+    #[test]
+    fn remote_tunnel_takes_the_token_from_the_environment() {
+        let deployment = render(&tunnel(json!({})));
+        let pod_spec = pod_spec(&deployment);
+        let container = &pod_spec.containers[0];
+
+        assert_eq!(pod_spec.volumes, None);
+        assert_eq!(container.volume_mounts, None);
+        let env_from = container.env_from.as_ref().unwrap();
+        assert_eq!(
+            env_from[0]
+                .secret_ref
+                .as_ref()
+                .map(|secret| secret.name.as_str()),
+            Some("tunnel")
+        );
+    }
+
+    fn policy(default_image: &str, allow_override: bool) -> ImagePolicy {
+        ImagePolicy {
+            default_image: default_image.to_owned(),
+            allow_override,
+        }
+    }
+
+    #[test]
+    fn image_precedence() {
+        let policy = policy("registry.local:5000/cloudflared:2024.1.0", true);
+
+        assert_eq!(
+            policy.resolve(&tunnel(json!({})).spec),
+            "registry.local:5000/cloudflared:2024.1.0"
+        );
+        assert_eq!(
+            policy.resolve(&tunnel(json!({ "image": "mirror/cloudflared:1" })).spec),
+            "mirror/cloudflared:1"
+        );
+        assert_eq!(
+            policy.resolve(
+                &tunnel(json!({ "image": "mirror/cloudflared:1", "version": "2024.6.1" })).spec
+            ),
+            "registry.local:5000/cloudflared:2024.6.1"
+        );
+        assert_eq!(
+            ImagePolicy::default().resolve(&tunnel(json!({})).spec),
+            DEFAULT_IMAGE
+        );
+    }
+
+    #[test]
+    fn disallowed_overrides_fall_back_to_the_default() {
+        let policy = policy("cloudflare/cloudflared:2024.1.0", false);
+        let pinned = tunnel(json!({ "image": "mirror/cloudflared:1" }));
+
+        assert_eq!(
+            policy.resolve(&pinned.spec),
+            "cloudflare/cloudflared:2024.1.0"
+        );
+        assert_eq!(
+            policy.ignored_override(&pinned.spec),
+            Some("mirror/cloudflared:1".to_owned())
+        );
+        assert_eq!(policy.ignored_override(&tunnel(json!({})).spec), None);
+        assert_eq!(
+            policy.ignored_override(
+                &tunnel(json!({ "image": "cloudflare/cloudflared:2024.1.0" })).spec
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn image_repository_keeps_the_registry_port() {
+        assert_eq!(
+            image_repository("registry.local:5000/cloudflared:2024.1.0"),
+            "registry.local:5000/cloudflared"
+        );
+        assert_eq!(
+            image_repository("registry.local:5000/cloudflared"),
+            "registry.local:5000/cloudflared"
+        );
+        assert_eq!(
+            image_repository("cloudflare/cloudflared@sha256:abc"),
+            "cloudflare/cloudflared"
+        );
+    }
+}
@@ -0,0 +1,130 @@
+use crate::crd::tunnel::TunnelResource;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::{ObjectMeta, OwnerReference};
+use kube::api::{DeleteParams, PatchParams};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+pub mod configmap;
+pub mod deployment;
+pub mod secret;
+pub mod serviceaccount;
+
+// INFO: Where a locally configured cloudflared finds its config and credentials, the
+// credentials are mounted below the config so both fit under one directory.
+pub const LOCAL_CONFIG_DIR: &str = "/etc/cloudflared";
+pub const LOCAL_CONFIG_FILE: &str = "config.yaml";
+pub const LOCAL_CONFIG_PATH: &str = "/etc/cloudflared/config.yaml";
+pub const CREDENTIALS_DIR: &str = "/etc/cloudflared/creds";
+pub const CREDENTIALS_FILE: &str = "credentials.json";
+pub const CREDENTIALS_PATH: &str = "/etc/cloudflared/creds/credentials.json";
+
+/// Version of the operator build, stamped on every resource it applies.
+pub const OPERATOR_VERSION: &str = env!("CARGO_PKG_VERSION");
+pub const OPERATOR_VERSION_ANNOTATION: &str = "cloudflare.ar2ro.io/operator-version";
+
+const FIELD_MANAGER: &str = "cloudflare-tunnel-operator";
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// Makes every write to Kubernetes go through the api server's dry run, set once at startup.
+pub fn set_dry_run(dry_run: bool) {
+    DRY_RUN.store(dry_run, Ordering::Relaxed);
+}
+
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+// INFO: Server side apply creates or updates the object, force stays off so fields owned by
+// other managers are reported as conflicts instead of being taken over.
+pub(crate) fn apply_params() -> PatchParams {
+    PatchParams {
+        dry_run: is_dry_run(),
+        ..PatchParams::apply(FIELD_MANAGER)
+    }
+}
+
+pub fn patch_params() -> PatchParams {
+    PatchParams {
+        dry_run: is_dry_run(),
+        ..PatchParams::default()
+    }
+}
+
+// INFO: Plain patches under the apply field manager take over fields another manager changed,
+// the next apply then agrees with them instead of conflicting.
+pub(crate) fn update_params() -> PatchParams {
+    PatchParams {
+        field_manager: Some(FIELD_MANAGER.to_owned()),
+        ..patch_params()
+    }
+}
+
+pub(crate) fn delete_params() -> DeleteParams {
+    DeleteParams {
+        dry_run: is_dry_run(),
+        ..DeleteParams::default()
+    }
+}
+
+// INFO: Hashes keys and values in order so the result only changes when the data does, it ends
+// up on the pod template to roll the Deployment when mounted or injected data changes.
+fn hash_entries<'a>(entries: impl IntoIterator<Item = (&'a str, &'a [u8])>) -> String {
+    let mut hasher = Sha256::new();
+    for (key, value) in entries {
+        hasher.update(key.as_bytes());
+        hasher.update([0]);
+        hasher.update(value);
+        hasher.update([0]);
+    }
+
+    format!("{:x}", hasher.finalize())
+}
+
+fn operator_annotations() -> Option<BTreeMap<String, String>> {
+    Some(BTreeMap::from([(
+        OPERATOR_VERSION_ANNOTATION.to_owned(),
+        OPERATOR_VERSION.to_owned(),
+    )]))
+}
+
+// INFO: The operator's own annotations win over the ones from the spec with the same key.
+fn resource_annotations<K: TunnelResource>(tunnel: &K) -> Option<BTreeMap<String, String>> {
+    let mut annotations = tunnel.tunnel_spec().annotations.clone().unwrap_or_default();
+    annotations.extend(operator_annotations().unwrap_or_default());
+    Some(annotations)
+}
+
+/// Whether every annotation the spec of `tunnel` asks for is set on the object.
+pub fn has_spec_annotations<K: TunnelResource>(metadata: &ObjectMeta, tunnel: &K) -> bool {
+    let current = metadata.annotations.clone().unwrap_or_default();
+    tunnel
+        .tunnel_spec()
+        .annotations
+        .iter()
+        .flatten()
+        .all(|(key, value)| current.get(key) == Some(value))
+}
+
+/// Whether the object was last applied by this build of the operator.
+pub fn applied_by_current_version(metadata: &ObjectMeta) -> bool {
+    metadata
+        .annotations
+        .as_ref()
+        .and_then(|annotations| annotations.get(OPERATOR_VERSION_ANNOTATION))
+        .is_some_and(|version| version == OPERATOR_VERSION)
+}
+
+// INFO: Makes the tunnel the controller of its children so `owns` maps their events back to it.
+fn owner_references<K: TunnelResource>(tunnel: &K) -> Option<Vec<OwnerReference>> {
+    tunnel.controller_owner_ref(&()).map(|owner| vec![owner])
+}
+
+// INFO: Deletes are retried until they succeed so an already deleted object counts as success.
+fn ignore_not_found(result: Result<(), kube::Error>) -> Result<(), kube::Error> {
+    match result {
+        Err(kube::Error::Api(err)) if err.code == 404 => Ok(()),
+        result => result,
+    }
+}
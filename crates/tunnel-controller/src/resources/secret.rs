@@ -0,0 +1,68 @@
+use super::{
+    apply_params, delete_params, hash_entries, ignore_not_found, owner_references,
+    resource_annotations,
+};
+use crate::crd::tunnel::TunnelResource;
+use k8s_openapi::{api::core::v1::Secret, ByteString};
+use kube::api::{ObjectMeta, Patch};
+use kube::Api;
+use std::collections::BTreeMap;
+
+pub fn data_hash(data: &BTreeMap<String, ByteString>) -> String {
+    hash_entries(
+        data.iter()
+            .map(|(key, value)| (key.as_str(), value.0.as_slice())),
+    )
+}
+
+pub async fn get<K: TunnelResource>(
+    kubernetes_client: kube::Client,
+    tunnel: &K,
+    namespace: &str,
+) -> Result<Option<Secret>, kube::Error> {
+    let secret_api: Api<Secret> = Api::namespaced(kubernetes_client, namespace);
+    secret_api.get_opt(&tunnel.child_name()).await
+}
+
+pub async fn apply<K: TunnelResource>(
+    kubernetes_client: kube::Client,
+    tunnel: &K,
+    namespace: &str,
+    labels: BTreeMap<String, String>,
+    data: BTreeMap<String, ByteString>,
+) -> Result<Secret, kube::Error> {
+    let name = tunnel.child_name();
+
+    let secret = Secret {
+        metadata: ObjectMeta {
+            name: Some(name.clone()),
+            namespace: Some(namespace.to_owned()),
+            labels: Some(labels),
+            annotations: resource_annotations(tunnel),
+            owner_references: owner_references(tunnel),
+            ..ObjectMeta::default()
+        },
+        data: Some(data),
+        ..Secret::default()
+    };
+
+    let secret_api: Api<Secret> = Api::namespaced(kubernetes_client, namespace);
+    secret_api
+        .patch(&name, &apply_params(), &Patch::Apply(&secret))
+        .await
+}
+
+pub async fn delete<K: TunnelResource>(
+    kubernetes_client: kube::Client,
+    tunnel: &K,
+    namespace: &str,
+) -> Result<(), kube::Error> {
+    let secret_api: Api<Secret> = Api::namespaced(kubernetes_client, namespace);
+
+    ignore_not_found(
+        secret_api
+            .delete(&tunnel.child_name(), &delete_params())
+            .await
+            .map(|_| ()),
+    )
+}
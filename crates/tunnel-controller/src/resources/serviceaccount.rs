@@ -0,0 +1,63 @@
+use super::{
+    apply_params, delete_params, ignore_not_found, operator_annotations, owner_references,
+};
+use crate::crd::tunnel::TunnelResource;
+use k8s_openapi::api::core::v1::ServiceAccount;
+use kube::api::{ObjectMeta, Patch};
+use kube::Api;
+use std::collections::BTreeMap;
+
+/// Name of the ServiceAccount created when the spec doesn't name one.
+pub fn generated_name<K: TunnelResource>(tunnel: &K) -> String {
+    format!("{}-cloudflared", tunnel.child_name())
+}
+
+// INFO: cloudflared never talks to the api server, the account only exists so pods don't fall
+// back to the namespace default one and whatever it has been granted.
+pub async fn apply<K: TunnelResource>(
+    kubernetes_client: kube::Client,
+    tunnel: &K,
+    namespace: &str,
+    labels: BTreeMap<String, String>,
+) -> Result<Option<ServiceAccount>, kube::Error> {
+    if tunnel.tunnel_spec().service_account_name.is_some() {
+        return Ok(None);
+    }
+
+    let name = generated_name(tunnel);
+
+    let service_account = ServiceAccount {
+        metadata: ObjectMeta {
+            name: Some(name.clone()),
+            namespace: Some(namespace.to_owned()),
+            labels: Some(labels),
+            annotations: operator_annotations(),
+            owner_references: owner_references(tunnel),
+            ..ObjectMeta::default()
+        },
+        automount_service_account_token: Some(false),
+        ..ServiceAccount::default()
+    };
+
+    let service_account_api: Api<ServiceAccount> = Api::namespaced(kubernetes_client, namespace);
+    service_account_api
+        .patch(&name, &apply_params(), &Patch::Apply(&service_account))
+        .await
+        .map(Some)
+}
+
+// INFO: Only the generated account is ever deleted, a named one belongs to the user.
+pub async fn delete<K: TunnelResource>(
+    kubernetes_client: kube::Client,
+    tunnel: &K,
+    namespace: &str,
+) -> Result<(), kube::Error> {
+    let service_account_api: Api<ServiceAccount> = Api::namespaced(kubernetes_client, namespace);
+
+    ignore_not_found(
+        service_account_api
+            .delete(&generated_name(tunnel), &delete_params())
+            .await
+            .map(|_| ()),
+    )
+}
@@ -0,0 +1,158 @@
+//! Reconciled tunnels persisted to a ConfigMap, a restarted operator skips the ones that didn't
+//! change while it was down instead of going to Cloudflare for every one of them.
+use crate::crd::tunnel::TunnelStatus;
+use crate::resources::apply_params;
+use dashmap::DashMap;
+use k8s_openapi::api::core::v1::ConfigMap;
+use kube::api::{ObjectMeta, Patch};
+use kube::core::DynamicObject;
+use kube::runtime::reflector::ObjectRef;
+use kube::{Api, Client};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+const STATE_KEY: &str = "state.json";
+
+/// What a tunnel looked like after its last successful reconcile.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct ReconciledTunnel {
+    pub resource_version: String,
+    #[serde(default)]
+    pub tunnel_id: Option<Uuid>,
+    #[serde(default)]
+    pub status: Option<TunnelStatus>,
+}
+
+/// Keys are the erased object references so Tunnels and ClusterTunnels share one snapshot.
+#[derive(Debug, Default)]
+pub struct OperatorState {
+    config_map: Option<(Api<ConfigMap>, String)>,
+    restored: DashMap<String, ReconciledTunnel>,
+    reconciled: DashMap<String, ReconciledTunnel>,
+    // INFO: Every write sends the whole snapshot, concurrent reconciles would otherwise drop
+    // each other's entries.
+    writes: Mutex<()>,
+}
+
+impl OperatorState {
+    /// Never persists anything, every tunnel is reconciled after a restart.
+    pub fn disabled() -> OperatorState {
+        OperatorState::default()
+    }
+
+    /// Restores the snapshot from the ConfigMap `name` in `namespace`, it's created on the first
+    /// write when it doesn't exist yet.
+    pub async fn load(
+        kubernetes_client: Client,
+        namespace: &str,
+        name: &str,
+    ) -> Result<OperatorState, kube::Error> {
+        let api: Api<ConfigMap> = Api::namespaced(kubernetes_client, namespace);
+        let snapshot = api
+            .get_opt(name)
+            .await?
+            .and_then(|config_map| config_map.data)
+            .and_then(|mut data| data.remove(STATE_KEY));
+
+        // INFO: A snapshot that can't be read only costs a full reconcile, it's overwritten by
+        // the next write.
+        let restored: BTreeMap<String, ReconciledTunnel> =
+            match snapshot.map(|snapshot| serde_json::from_str(&snapshot)) {
+                Some(Ok(restored)) => restored,
+                Some(Err(err)) => {
+                    println!("Ignoring unreadable operator state in {}: {}", name, err);
+                    BTreeMap::new()
+                }
+                None => BTreeMap::new(),
+            };
+        println!(
+            "Restored {} reconciled tunnels from {}",
+            restored.len(),
+            name
+        );
+
+        let state = OperatorState {
+            config_map: Some((api, name.to_owned())),
+            ..OperatorState::default()
+        };
+        for (key, tunnel) in restored {
+            state.restored.insert(key.clone(), tunnel.clone());
+            state.reconciled.insert(key, tunnel);
+        }
+
+        Ok(state)
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.config_map.is_some()
+    }
+
+    /// Whether `object` still has the resourceVersion the previous run reconciled, only the first
+    /// reconcile after a restart can be skipped.
+    pub fn unchanged(&self, object: &ObjectRef<DynamicObject>, resource_version: &str) -> bool {
+        self.restored
+            .remove(&object.to_string())
+            .is_some_and(|(_, tunnel)| tunnel.resource_version == resource_version)
+    }
+
+    pub async fn record(&self, object: &ObjectRef<DynamicObject>, tunnel: ReconciledTunnel) {
+        let key = object.to_string();
+        if self
+            .reconciled
+            .get(&key)
+            .is_some_and(|recorded| recorded.resource_version == tunnel.resource_version)
+        {
+            return;
+        }
+
+        self.reconciled.insert(key, tunnel);
+        self.persist().await;
+    }
+
+    pub async fn forget(&self, object: &ObjectRef<DynamicObject>) {
+        let key = object.to_string();
+        self.restored.remove(&key);
+        if self.reconciled.remove(&key).is_some() {
+            self.persist().await;
+        }
+    }
+
+    // INFO: Failing to write only means more work after the next restart, it doesn't fail the
+    // reconcile.
+    async fn persist(&self) {
+        let (api, name) = match self.config_map.as_ref() {
+            Some(config_map) => config_map,
+            None => return,
+        };
+
+        let _guard = self.writes.lock().await;
+        let snapshot = self
+            .reconciled
+            .iter()
+            .map(|entry| (entry.key().clone(), entry.value().clone()))
+            .collect::<BTreeMap<_, _>>();
+        let snapshot = match serde_json::to_string(&snapshot) {
+            Ok(snapshot) => snapshot,
+            Err(err) => return println!("Failed to serialize operator state: {}", err),
+        };
+
+        let config_map = ConfigMap {
+            metadata: ObjectMeta {
+                name: Some(name.clone()),
+                ..ObjectMeta::default()
+            },
+            data: Some(BTreeMap::from([(STATE_KEY.to_owned(), snapshot)])),
+            ..ConfigMap::default()
+        };
+
+        if let Err(err) = api
+            .patch(name, &apply_params(), &Patch::Apply(&config_map))
+            .await
+        {
+            println!("Failed to write operator state to {}: {}", name, err);
+        }
+    }
+}
@@ -0,0 +1,329 @@
+//! Lookups over the reflector stores of both tunnel kinds, shared by the tunnel controller and
+//! everything that references a tunnel.
+use crate::crd::cluster_tunnel::ClusterTunnel;
+use crate::crd::tunnel::{Tunnel, TunnelCrd, TunnelResource};
+use crate::is_truthy;
+use cloudflare_controller_common::DEFAULT_ANNOTATION;
+use kube::core::DynamicObject;
+use kube::runtime::reflector::{store::WriterDropped, ObjectRef, Store};
+use kube::ResourceExt;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// More than one tunnel carries the default annotation, none of them is picked.
+#[derive(Debug, thiserror::Error)]
+#[error("more than one default tunnel: {}", .0.join(", "))]
+pub struct DefaultTunnelError(pub Vec<String>);
+
+impl DefaultTunnelError {
+    // INFO: Stores hand out their objects in no particular order, sorting keeps the message the
+    // same from one reconcile to the next.
+    fn new(mut tunnels: Vec<String>) -> DefaultTunnelError {
+        tunnels.sort();
+        DefaultTunnelError(tunnels)
+    }
+}
+
+fn tunnel_display_name<K: TunnelResource>(tunnel: &K) -> String {
+    match tunnel.namespace() {
+        Some(namespace) => format!("{} {}/{}", K::kind(&()), namespace, tunnel.name_any()),
+        None => format!("{} {}", K::kind(&()), tunnel.name_any()),
+    }
+}
+
+// INFO: A single default is picked, more than one is ambiguous.
+fn single_default<T>(
+    mut tunnels: Vec<T>,
+    display_name: impl Fn(&T) -> String,
+) -> Result<Option<T>, DefaultTunnelError> {
+    match tunnels.len() {
+        0 | 1 => Ok(tunnels.pop()),
+        _ => Err(DefaultTunnelError::new(
+            tunnels.iter().map(display_name).collect(),
+        )),
+    }
+}
+
+/// Tunnel lookups over the store of one tunnel kind, [`TunnelStores`] combines both.
+pub trait TunnelStoreExt<K> {
+    /// `namespace` is `None` for a `ClusterTunnel`.
+    fn get_tunnel(&self, name: &str, namespace: Option<&str>) -> Option<Arc<K>>;
+
+    fn find_by_uuid(&self, uuid: Uuid) -> Option<Arc<K>>;
+
+    fn default_tunnels(&self) -> Vec<Arc<K>>;
+
+    fn default_tunnel(&self) -> Result<Option<Arc<K>>, DefaultTunnelError>;
+}
+
+impl<K: TunnelResource> TunnelStoreExt<K> for Store<K> {
+    fn get_tunnel(&self, name: &str, namespace: Option<&str>) -> Option<Arc<K>> {
+        let mut obj_ref = ObjectRef::new(name);
+        obj_ref.namespace = namespace.map(str::to_owned);
+        self.get(&obj_ref)
+    }
+
+    fn find_by_uuid(&self, uuid: Uuid) -> Option<Arc<K>> {
+        self.state()
+            .into_iter()
+            .find(|tunnel| tunnel.get_uuid() == Some(uuid))
+    }
+
+    fn default_tunnels(&self) -> Vec<Arc<K>> {
+        self.state()
+            .into_iter()
+            .filter(|tunnel| {
+                tunnel
+                    .annotations()
+                    .get(DEFAULT_ANNOTATION)
+                    .is_some_and(|value| is_truthy(value))
+            })
+            .collect::<_>()
+    }
+
+    fn default_tunnel(&self) -> Result<Option<Arc<K>>, DefaultTunnelError> {
+        single_default(self.default_tunnels(), |tunnel| {
+            tunnel_display_name(tunnel.as_ref())
+        })
+    }
+}
+
+/// A reference to either tunnel kind, resolved from a [`TunnelStores`].
+#[derive(Debug, Clone)]
+pub enum AnyTunnel {
+    Tunnel(Arc<Tunnel>),
+    ClusterTunnel(Arc<ClusterTunnel>),
+}
+
+impl AnyTunnel {
+    pub fn spec(&self) -> &TunnelCrd {
+        match self {
+            AnyTunnel::Tunnel(tunnel) => tunnel.tunnel_spec(),
+            AnyTunnel::ClusterTunnel(tunnel) => tunnel.tunnel_spec(),
+        }
+    }
+
+    #[inline]
+    pub fn get_uuid(&self) -> Option<Uuid> {
+        self.spec().uuid
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AnyTunnel::Tunnel(_) => "Tunnel",
+            AnyTunnel::ClusterTunnel(_) => "ClusterTunnel",
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            AnyTunnel::Tunnel(tunnel) => tunnel.name_any(),
+            AnyTunnel::ClusterTunnel(tunnel) => tunnel.name_any(),
+        }
+    }
+
+    pub fn namespace(&self) -> Option<String> {
+        match self {
+            AnyTunnel::Tunnel(tunnel) => tunnel.namespace(),
+            AnyTunnel::ClusterTunnel(_) => None,
+        }
+    }
+
+    /// Erased so references to both kinds can be compared and stored together.
+    pub fn object_ref(&self) -> ObjectRef<DynamicObject> {
+        match self {
+            AnyTunnel::Tunnel(tunnel) => ObjectRef::from_obj(tunnel.as_ref()).erase(),
+            AnyTunnel::ClusterTunnel(tunnel) => ObjectRef::from_obj(tunnel.as_ref()).erase(),
+        }
+    }
+
+    fn display_name(&self) -> String {
+        match self {
+            AnyTunnel::Tunnel(tunnel) => tunnel_display_name(tunnel.as_ref()),
+            AnyTunnel::ClusterTunnel(tunnel) => tunnel_display_name(tunnel.as_ref()),
+        }
+    }
+}
+
+/// Read side of both tunnel controllers, this is what anything referencing a tunnel resolves
+/// against.
+#[derive(Clone)]
+pub struct TunnelStores {
+    pub tunnels: Store<Tunnel>,
+    pub cluster_tunnels: Store<ClusterTunnel>,
+}
+
+impl TunnelStores {
+    /// Resolves a tunnel by kind, `namespace` is ignored for a `ClusterTunnel`.
+    pub fn get(&self, kind: &str, name: &str, namespace: Option<&str>) -> Option<AnyTunnel> {
+        match kind {
+            "Tunnel" => self
+                .tunnels
+                .get_tunnel(name, namespace)
+                .map(AnyTunnel::Tunnel),
+            "ClusterTunnel" => self
+                .cluster_tunnels
+                .get_tunnel(name, None)
+                .map(AnyTunnel::ClusterTunnel),
+            _ => None,
+        }
+    }
+
+    /// Resolves once both stores received their initial list, until then every lookup misses.
+    pub async fn wait_until_ready(&self) -> Result<(), WriterDropped> {
+        self.tunnels.wait_until_ready().await?;
+        self.cluster_tunnels.wait_until_ready().await
+    }
+
+    /// The tunnel of either kind that manages the Cloudflare tunnel `uuid`.
+    pub fn find_by_uuid(&self, uuid: Uuid) -> Option<AnyTunnel> {
+        self.tunnels
+            .find_by_uuid(uuid)
+            .map(AnyTunnel::Tunnel)
+            .or_else(|| {
+                self.cluster_tunnels
+                    .find_by_uuid(uuid)
+                    .map(AnyTunnel::ClusterTunnel)
+            })
+    }
+
+    // INFO: The default annotation is counted across both kinds, more than one default is
+    // ambiguous.
+    pub fn default_tunnel(&self) -> Result<Option<AnyTunnel>, DefaultTunnelError> {
+        let tunnels = self
+            .tunnels
+            .default_tunnels()
+            .into_iter()
+            .map(AnyTunnel::Tunnel)
+            .chain(
+                self.cluster_tunnels
+                    .default_tunnels()
+                    .into_iter()
+                    .map(AnyTunnel::ClusterTunnel),
+            )
+            .collect();
+
+        single_default(tunnels, AnyTunnel::display_name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kube::runtime::reflector;
+    use kube::runtime::watcher::Event;
+    use serde_json::json;
+
+    fn annotated_tunnel(name: &str, default: Option<&str>) -> Tunnel {
+        let annotations = match default {
+            Some(value) => json!({ DEFAULT_ANNOTATION: value }),
+            None => json!({}),
+        };
+        serde_json::from_value(json!({
+            "metadata": { "name": name, "namespace": "default", "annotations": annotations },
+            "spec": { "credentials": "creds" },
+        }))
+        .unwrap()
+    }
+
+    fn default_tunnel_of(tunnels: Vec<Tunnel>) -> Result<Option<String>, DefaultTunnelError> {
+        let (store, mut writer) = reflector::store::<Tunnel>();
+        for tunnel in tunnels {
+            writer.apply_watcher_event(&Event::Apply(tunnel));
+        }
+        store
+            .default_tunnel()
+            .map(|tunnel| tunnel.map(|tunnel| tunnel.name_any()))
+    }
+
+    #[test]
+    fn default_tunnel_counts_truthy_annotations() {
+        assert_eq!(default_tunnel_of(vec![]).unwrap(), None);
+        assert_eq!(
+            default_tunnel_of(vec![
+                annotated_tunnel("plain", None),
+                annotated_tunnel("off", Some("false")),
+                annotated_tunnel("no", Some("No")),
+            ])
+            .unwrap(),
+            None
+        );
+
+        for value in ["true", "True", "TRUE", " yes ", "1", "On"] {
+            let tunnel = default_tunnel_of(vec![
+                annotated_tunnel("plain", None),
+                annotated_tunnel("default", Some(value)),
+            ]);
+            assert_eq!(tunnel.unwrap().as_deref(), Some("default"), "{:?}", value);
+        }
+
+        let err = default_tunnel_of(vec![
+            annotated_tunnel("first", Some("True")),
+            annotated_tunnel("second", Some("yes")),
+            annotated_tunnel("off", Some("0")),
+        ])
+        .unwrap_err();
+        assert_eq!(err.0, vec!["Tunnel default/first", "Tunnel default/second"]);
+    }
+
+    #[test]
+    fn references_resolve_to_the_requested_kind() {
+        let (tunnels, mut tunnel_writer) = reflector::store::<Tunnel>();
+        let (cluster_tunnels, mut cluster_writer) = reflector::store::<ClusterTunnel>();
+        let tunnel: Tunnel = serde_json::from_value(json!({
+            "metadata": { "name": "shared", "namespace": "default" },
+            "spec": { "credentials": "creds", "uuid": Uuid::new_v4() },
+        }))
+        .unwrap();
+        let cluster_tunnel: ClusterTunnel = serde_json::from_value(json!({
+            "metadata": { "name": "shared", "annotations": { DEFAULT_ANNOTATION: "true" } },
+            "spec": { "credentials": "creds", "uuid": Uuid::new_v4() },
+        }))
+        .unwrap();
+        tunnel_writer.apply_watcher_event(&Event::Apply(tunnel.clone()));
+        cluster_writer.apply_watcher_event(&Event::Apply(cluster_tunnel.clone()));
+        let stores = TunnelStores {
+            tunnels,
+            cluster_tunnels,
+        };
+
+        let resolved = stores.get("ClusterTunnel", "shared", Some("team")).unwrap();
+        assert_eq!(resolved.kind(), "ClusterTunnel");
+        assert_eq!(resolved.namespace(), None);
+
+        let resolved = stores.get("Tunnel", "shared", Some("default")).unwrap();
+        assert_eq!(resolved.kind(), "Tunnel");
+        assert!(stores.get("Tunnel", "shared", Some("team")).is_none());
+        assert!(stores.get("Ingress", "shared", Some("default")).is_none());
+
+        let found = stores.find_by_uuid(tunnel.spec.uuid.unwrap());
+        assert_eq!(found.map(|tunnel| tunnel.kind()), Some("Tunnel"));
+        let found = stores.find_by_uuid(cluster_tunnel.spec.uuid.unwrap());
+        assert_eq!(found.map(|tunnel| tunnel.kind()), Some("ClusterTunnel"));
+        assert!(stores.find_by_uuid(Uuid::new_v4()).is_none());
+
+        let default = stores.default_tunnel().unwrap().unwrap();
+        assert_eq!(default.kind(), "ClusterTunnel");
+    }
+
+    #[test]
+    fn defaults_across_both_kinds_are_ambiguous() {
+        let (tunnels, mut tunnel_writer) = reflector::store::<Tunnel>();
+        let (cluster_tunnels, mut cluster_writer) = reflector::store::<ClusterTunnel>();
+        tunnel_writer.apply_watcher_event(&Event::Apply(annotated_tunnel("web", Some("true"))));
+        cluster_writer.apply_watcher_event(&Event::Apply(
+            serde_json::from_value(json!({
+                "metadata": { "name": "shared", "annotations": { DEFAULT_ANNOTATION: "true" } },
+                "spec": { "credentials": "creds" },
+            }))
+            .unwrap(),
+        ));
+        let stores = TunnelStores {
+            tunnels,
+            cluster_tunnels,
+        };
+
+        let err = stores.default_tunnel().unwrap_err();
+        assert_eq!(err.0, vec!["ClusterTunnel shared", "Tunnel default/web"]);
+    }
+}
@@ -0,0 +1,106 @@
+//! In memory cache of tunnel tokens so retries of the create path don't refetch them.
+use dashmap::DashMap;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+pub const DEFAULT_TOKEN_TTL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug)]
+pub struct TokenCache {
+    ttl: Duration,
+    enabled: bool,
+    tokens: DashMap<Uuid, (String, Instant)>,
+}
+
+impl TokenCache {
+    pub fn new(ttl: Duration) -> TokenCache {
+        TokenCache {
+            ttl,
+            enabled: true,
+            tokens: DashMap::new(),
+        }
+    }
+
+    /// Never stores anything, every lookup goes to Cloudflare.
+    pub fn disabled() -> TokenCache {
+        TokenCache {
+            enabled: false,
+            ..TokenCache::default()
+        }
+    }
+
+    pub fn get(&self, tunnel_id: Uuid) -> Option<String> {
+        let (token, fetched_at) = self.tokens.get(&tunnel_id).map(|entry| entry.clone())?;
+        if fetched_at.elapsed() < self.ttl {
+            return Some(token);
+        }
+
+        self.tokens.remove(&tunnel_id);
+        None
+    }
+
+    pub fn insert(&self, tunnel_id: Uuid, token: String) {
+        if self.enabled {
+            self.tokens.insert(tunnel_id, (token, Instant::now()));
+        }
+    }
+
+    pub fn invalidate(&self, tunnel_id: Uuid) {
+        self.tokens.remove(&tunnel_id);
+    }
+}
+
+impl Default for TokenCache {
+    fn default() -> TokenCache {
+        TokenCache::new(DEFAULT_TOKEN_TTL)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cached_token_is_returned() {
+        let cache = TokenCache::default();
+        let tunnel_id = Uuid::new_v4();
+
+        assert_eq!(cache.get(tunnel_id), None);
+        cache.insert(tunnel_id, "token".to_owned());
+
+        assert_eq!(cache.get(tunnel_id), Some("token".to_owned()));
+        assert_eq!(cache.get(Uuid::new_v4()), None);
+    }
+
+    #[test]
+    fn expired_token_is_dropped() {
+        let cache = TokenCache::new(Duration::from_millis(10));
+        let tunnel_id = Uuid::new_v4();
+        cache.insert(tunnel_id, "token".to_owned());
+
+        std::thread::sleep(Duration::from_millis(20));
+
+        assert_eq!(cache.get(tunnel_id), None);
+        assert!(cache.tokens.is_empty());
+    }
+
+    #[test]
+    fn invalidated_token_is_fetched_again() {
+        let cache = TokenCache::default();
+        let tunnel_id = Uuid::new_v4();
+        cache.insert(tunnel_id, "token".to_owned());
+
+        cache.invalidate(tunnel_id);
+
+        assert_eq!(cache.get(tunnel_id), None);
+    }
+
+    #[test]
+    fn disabled_cache_stores_nothing() {
+        let cache = TokenCache::disabled();
+        let tunnel_id = Uuid::new_v4();
+        cache.insert(tunnel_id, "token".to_owned());
+
+        assert_eq!(cache.get(tunnel_id), None);
+    }
+}
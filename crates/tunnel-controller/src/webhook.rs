@@ -0,0 +1,674 @@
+//! Validating admission webhook for checks the CRD schema can't express.
+use crate::crd::cluster_tunnel::ClusterTunnel;
+use crate::crd::tunnel::{Tunnel, TunnelResource};
+use crate::crd::tunnel_ingress::TunnelIngress;
+use crate::resources::apply_params;
+use bytes::Bytes;
+use futures::Future;
+use http_body_util::{BodyExt, Full};
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Method, Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use k8s_openapi::api::admissionregistration::v1::{
+    RuleWithOperations, ServiceReference, ValidatingWebhook, ValidatingWebhookConfiguration,
+    WebhookClientConfig,
+};
+use k8s_openapi::ByteString;
+use kube::api::{ListParams, ObjectMeta, Patch};
+use kube::core::admission::{AdmissionRequest, AdmissionResponse, AdmissionReview, Operation};
+use kube::{Api, Client, Resource, ResourceExt};
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::ServerConfig;
+use serde::de::DeserializeOwned;
+use std::convert::Infallible;
+use std::future::IntoFuture;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio_rustls::TlsAcceptor;
+use tokio_util::sync::CancellationToken;
+
+const WEBHOOK_CONFIGURATION_NAME: &str = "cloudflare-tunnel-operator";
+const VALIDATE_TUNNEL_PATH: &str = "/validate-tunnel";
+const VALIDATE_TUNNEL_INGRESS_PATH: &str = "/validate-tunnelingress";
+
+/// Where the api server reaches the webhook, the Service has to route to `addr`.
+#[derive(Debug, Clone)]
+pub struct WebhookService {
+    pub namespace: String,
+    pub name: String,
+    pub port: i32,
+}
+
+pub struct WebhookServer {
+    kubernetes_client: Client,
+    addr: SocketAddr,
+    cert_path: PathBuf,
+    key_path: PathBuf,
+    service: WebhookService,
+    shutdown: CancellationToken,
+}
+
+// INFO: Cloudflare tunnel names are unique per account, not per namespace, so two Tunnels with
+// the same name only fail once the second one reaches the Cloudflare api with a 409. ClusterTunnels
+// take names on the same account. The spec checks the reconciler runs are applied up front as well.
+pub async fn validate_tunnel(
+    tunnel_api: &Api<Tunnel>,
+    cluster_tunnel_api: &Api<ClusterTunnel>,
+    request: &AdmissionRequest<Tunnel>,
+) -> AdmissionResponse {
+    let response = AdmissionResponse::from(request);
+    if let (Operation::Create | Operation::Update, Some(tunnel)) =
+        (&request.operation, &request.object)
+    {
+        if let Err(err) = tunnel.spec.validate() {
+            return response.deny(err.to_string());
+        }
+    }
+    if request.operation != Operation::Create {
+        return response;
+    }
+
+    let name = match &request.object {
+        Some(tunnel) => tunnel.cloudflare_name(),
+        None => request.name.clone(),
+    };
+
+    let tunnels = match tunnel_api.list(&ListParams::default()).await {
+        Ok(tunnels) => tunnels,
+        Err(err) => return unchecked(response, format!("failed to list tunnels: {}", err)),
+    };
+    let cluster_tunnels = match cluster_tunnel_api.list(&ListParams::default()).await {
+        Ok(cluster_tunnels) => cluster_tunnels,
+        Err(err) => return unchecked(response, format!("failed to list cluster tunnels: {}", err)),
+    };
+
+    let conflict = tunnels
+        .iter()
+        .find(|tunnel| tunnel.cloudflare_name() == name && tunnel.namespace() != request.namespace)
+        .map(|tunnel| {
+            format!(
+                "a Tunnel named {} already exists in namespace {}",
+                tunnel.name_any(),
+                tunnel.namespace().unwrap_or_default()
+            )
+        })
+        .or_else(|| {
+            cluster_tunnels
+                .iter()
+                .find(|cluster_tunnel| cluster_tunnel.cloudflare_name() == name)
+                .map(|cluster_tunnel| {
+                    format!(
+                        "ClusterTunnel {} already uses the Cloudflare tunnel name {}",
+                        cluster_tunnel.name_any(),
+                        name
+                    )
+                })
+        });
+
+    match conflict {
+        Some(conflict) => response.deny(format!(
+            "{}, Cloudflare tunnel names are unique per account",
+            conflict
+        )),
+        None => response,
+    }
+}
+
+// INFO: The webhooks are registered with failurePolicy Ignore, a check that can't run admits the
+// object like an unreachable webhook would, with a warning instead of silently.
+fn unchecked(mut response: AdmissionResponse, reason: String) -> AdmissionResponse {
+    tracing::warn!("Admitting without validation, {}", reason);
+    response.warnings = Some(vec![format!("not validated, {}", reason)]);
+    response
+}
+
+// INFO: The merged configuration would route a hostname claimed twice to whichever rule comes
+// first. Catch-all rules have no hostname and aren't part of TunnelIngress objects.
+pub async fn validate_tunnel_ingress(
+    tunnel_ingress_api: &Api<TunnelIngress>,
+    request: &AdmissionRequest<TunnelIngress>,
+) -> AdmissionResponse {
+    let response = AdmissionResponse::from(request);
+    let tunnel_ingress = match (&request.operation, &request.object) {
+        (Operation::Create | Operation::Update, Some(tunnel_ingress)) => tunnel_ingress,
+        _ => return response,
+    };
+
+    let invalid = tunnel_ingress.validate();
+    if !invalid.is_empty() {
+        return response.deny(invalid.join(", "));
+    }
+
+    let tunnel_ingresses = match tunnel_ingress_api.list(&ListParams::default()).await {
+        Ok(tunnel_ingresses) => tunnel_ingresses,
+        Err(err) => {
+            return unchecked(
+                response,
+                format!("failed to list tunnel ingresses: {}", err),
+            )
+        }
+    };
+
+    let tunnel_ref = &tunnel_ingress.spec.tunnel_ref;
+    let conflict = tunnel_ingresses
+        .iter()
+        .filter(|other| other.name_any() != request.name || other.namespace() != request.namespace)
+        .filter(|other| {
+            other.spec.tunnel_ref.kind == tunnel_ref.kind
+                && other.spec.tunnel_ref.name == tunnel_ref.name
+                && other.tunnel_namespace() == tunnel_ingress.tunnel_namespace()
+        })
+        .find_map(|other| {
+            other
+                .spec
+                .rules
+                .iter()
+                .find(|rule| {
+                    tunnel_ingress
+                        .spec
+                        .rules
+                        .iter()
+                        .any(|own| !own.hostname.is_empty() && own.hostname == rule.hostname)
+                })
+                .map(|rule| (other, &rule.hostname))
+        });
+
+    match conflict {
+        Some((other, hostname)) => response.deny(format!(
+            "hostname {} is already routed through {} {} by TunnelIngress {}/{}",
+            hostname,
+            tunnel_ref.kind.as_str(),
+            tunnel_ref.name,
+            other.namespace().unwrap_or_default(),
+            other.name_any()
+        )),
+        None => response,
+    }
+}
+
+fn admission_request<K>(body: &[u8]) -> Result<AdmissionRequest<K>, String>
+where
+    K: Resource + DeserializeOwned,
+{
+    serde_json::from_slice::<AdmissionReview<K>>(body)
+        .map_err(|err| err.to_string())
+        .and_then(|review| review.try_into().map_err(|err| format!("{}", err)))
+}
+
+async fn handle(
+    kubernetes_client: Client,
+    request: Request<Incoming>,
+) -> Result<Response<Full<Bytes>>, Infallible> {
+    let path = request.uri().path().to_owned();
+    if request.method() != Method::POST
+        || (path != VALIDATE_TUNNEL_PATH && path != VALIDATE_TUNNEL_INGRESS_PATH)
+    {
+        return Ok(status_response(StatusCode::NOT_FOUND));
+    }
+
+    let body = match request.into_body().collect().await {
+        Ok(body) => body.to_bytes(),
+        Err(err) => {
+            println!("Failed to read admission review: {}", err);
+            return Ok(status_response(StatusCode::BAD_REQUEST));
+        }
+    };
+
+    let review = match path.as_str() {
+        VALIDATE_TUNNEL_PATH => match admission_request::<Tunnel>(&body) {
+            Ok(admission_request) => validate_tunnel(
+                &Api::all(kubernetes_client.clone()),
+                &Api::all(kubernetes_client),
+                &admission_request,
+            )
+            .await
+            .into_review(),
+            Err(err) => AdmissionResponse::invalid(err).into_review(),
+        },
+        _ => match admission_request::<TunnelIngress>(&body) {
+            Ok(admission_request) => {
+                validate_tunnel_ingress(&Api::all(kubernetes_client), &admission_request)
+                    .await
+                    .into_review()
+            }
+            Err(err) => AdmissionResponse::invalid(err).into_review(),
+        },
+    };
+
+    match serde_json::to_vec(&review) {
+        Ok(body) => Ok(Response::builder()
+            .header("content-type", "application/json")
+            .body(Full::new(Bytes::from(body)))
+            .unwrap_or_else(|_| status_response(StatusCode::INTERNAL_SERVER_ERROR))),
+        Err(err) => {
+            println!("Failed to encode admission review: {}", err);
+            Ok(status_response(StatusCode::INTERNAL_SERVER_ERROR))
+        }
+    }
+}
+
+fn status_response(status: StatusCode) -> Response<Full<Bytes>> {
+    let mut response = Response::new(Full::default());
+    *response.status_mut() = status;
+    response
+}
+
+impl WebhookServer {
+    pub fn new(
+        kubernetes_client: Client,
+        addr: SocketAddr,
+        cert_path: PathBuf,
+        key_path: PathBuf,
+        service: WebhookService,
+        shutdown: CancellationToken,
+    ) -> WebhookServer {
+        Self {
+            kubernetes_client,
+            addr,
+            cert_path,
+            key_path,
+            service,
+            shutdown,
+        }
+    }
+
+    fn tls_config(&self) -> anyhow::Result<ServerConfig> {
+        let certs =
+            CertificateDer::pem_file_iter(&self.cert_path)?.collect::<Result<Vec<_>, _>>()?;
+        let key = PrivateKeyDer::from_pem_file(&self.key_path)?;
+
+        Ok(
+            ServerConfig::builder_with_provider(Arc::new(rustls::crypto::ring::default_provider()))
+                .with_safe_default_protocol_versions()?
+                .with_no_client_auth()
+                .with_single_cert(certs, key)?,
+        )
+    }
+
+    // INFO: The serving certificate doubles as the CA bundle, which holds for self signed
+    // certificates and for cert-manager issued ones that include their CA in the chain.
+    fn configuration(&self) -> anyhow::Result<ValidatingWebhookConfiguration> {
+        let ca_bundle = std::fs::read(&self.cert_path)?;
+
+        Ok(ValidatingWebhookConfiguration {
+            metadata: ObjectMeta {
+                name: Some(WEBHOOK_CONFIGURATION_NAME.to_owned()),
+                ..ObjectMeta::default()
+            },
+            webhooks: Some(vec![
+                ValidatingWebhook {
+                    name: "tunnels.cloudflare.ar2ro.io".to_owned(),
+                    admission_review_versions: vec!["v1".to_owned()],
+                    client_config: WebhookClientConfig {
+                        ca_bundle: Some(ByteString(ca_bundle.clone())),
+                        service: Some(ServiceReference {
+                            namespace: self.service.namespace.clone(),
+                            name: self.service.name.clone(),
+                            path: Some(VALIDATE_TUNNEL_PATH.to_owned()),
+                            port: Some(self.service.port),
+                        }),
+                        ..WebhookClientConfig::default()
+                    },
+                    rules: Some(vec![RuleWithOperations {
+                        api_groups: Some(vec!["cloudflare.ar2ro.io".to_owned()]),
+                        api_versions: Some(vec!["v1".to_owned()]),
+                        operations: Some(vec!["CREATE".to_owned(), "UPDATE".to_owned()]),
+                        resources: Some(vec!["tunnels".to_owned()]),
+                        scope: Some("Namespaced".to_owned()),
+                    }]),
+                    // INFO: Only guards against a confusing error, an unreachable operator shouldn't
+                    // block creating tunnels.
+                    failure_policy: Some("Ignore".to_owned()),
+                    side_effects: "None".to_owned(),
+                    ..ValidatingWebhook::default()
+                },
+                ValidatingWebhook {
+                    name: "tunnelingresses.cloudflare.ar2ro.io".to_owned(),
+                    admission_review_versions: vec!["v1".to_owned()],
+                    client_config: WebhookClientConfig {
+                        ca_bundle: Some(ByteString(ca_bundle)),
+                        service: Some(ServiceReference {
+                            namespace: self.service.namespace.clone(),
+                            name: self.service.name.clone(),
+                            path: Some(VALIDATE_TUNNEL_INGRESS_PATH.to_owned()),
+                            port: Some(self.service.port),
+                        }),
+                        ..WebhookClientConfig::default()
+                    },
+                    rules: Some(vec![RuleWithOperations {
+                        api_groups: Some(vec!["cloudflare.ar2ro.io".to_owned()]),
+                        api_versions: Some(vec!["v1".to_owned()]),
+                        operations: Some(vec!["CREATE".to_owned(), "UPDATE".to_owned()]),
+                        resources: Some(vec!["tunnelingresses".to_owned()]),
+                        scope: Some("Namespaced".to_owned()),
+                    }]),
+                    failure_policy: Some("Ignore".to_owned()),
+                    side_effects: "None".to_owned(),
+                    ..ValidatingWebhook::default()
+                },
+            ]),
+        })
+    }
+
+    pub async fn start(self) -> anyhow::Result<()> {
+        println!("Starting Webhook Server on {}", self.addr);
+        let acceptor = TlsAcceptor::from(Arc::new(self.tls_config()?));
+
+        let configuration_api: Api<ValidatingWebhookConfiguration> =
+            Api::all(self.kubernetes_client.clone());
+        configuration_api
+            .patch(
+                WEBHOOK_CONFIGURATION_NAME,
+                &apply_params(),
+                &Patch::Apply(&self.configuration()?),
+            )
+            .await?;
+
+        let listener = TcpListener::bind(self.addr).await?;
+
+        loop {
+            let (stream, remote) = tokio::select! {
+                accepted = listener.accept() => accepted?,
+                _ = self.shutdown.cancelled() => return Ok(()),
+            };
+
+            let acceptor = acceptor.clone();
+            let kubernetes_client = self.kubernetes_client.clone();
+            tokio::spawn(async move {
+                let stream = match acceptor.accept(stream).await {
+                    Ok(stream) => stream,
+                    Err(err) => {
+                        println!("TLS handshake with {} failed: {}", remote, err);
+                        return;
+                    }
+                };
+
+                let service = service_fn(move |request| handle(kubernetes_client.clone(), request));
+                if let Err(err) = http1::Builder::new()
+                    .serve_connection(TokioIo::new(stream), service)
+                    .await
+                {
+                    println!("Webhook connection from {} failed: {}", remote, err);
+                }
+            });
+        }
+    }
+}
+
+impl IntoFuture for WebhookServer {
+    type Output = anyhow::Result<()>;
+    type IntoFuture = Pin<Box<dyn Future<Output = Self::Output>>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.start())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::ApiServer;
+    use serde::Serialize;
+    use serde_json::{json, Value};
+
+    fn admission<K>(operation: &str, object: &K) -> AdmissionRequest<K>
+    where
+        K: Resource<DynamicType = ()> + Serialize + DeserializeOwned,
+    {
+        let review = json!({
+            "apiVersion": "admission.k8s.io/v1",
+            "kind": "AdmissionReview",
+            "request": {
+                "uid": "review",
+                "kind": { "group": "cloudflare.ar2ro.io", "version": "v1", "kind": K::kind(&()) },
+                "resource": {
+                    "group": "cloudflare.ar2ro.io",
+                    "version": "v1",
+                    "resource": K::plural(&()),
+                },
+                "name": object.meta().name,
+                "namespace": object.meta().namespace,
+                "operation": operation,
+                "userInfo": {},
+                "object": object,
+            },
+        });
+        admission_request(&serde_json::to_vec(&review).unwrap()).unwrap()
+    }
+
+    fn tunnel(namespace: &str, name: &str) -> Tunnel {
+        serde_json::from_value(json!({
+            "apiVersion": "cloudflare.ar2ro.io/v1",
+            "kind": "Tunnel",
+            "metadata": { "name": name, "namespace": namespace },
+            "spec": { "credentials": "creds" },
+        }))
+        .unwrap()
+    }
+
+    fn cluster_tunnel(name: &str) -> ClusterTunnel {
+        serde_json::from_value(json!({
+            "apiVersion": "cloudflare.ar2ro.io/v1",
+            "kind": "ClusterTunnel",
+            "metadata": { "name": name },
+            "spec": { "credentials": "creds" },
+        }))
+        .unwrap()
+    }
+
+    async fn review_tunnel(client: &Client, operation: &str, tunnel: &Tunnel) -> AdmissionResponse {
+        validate_tunnel(
+            &Api::all(client.clone()),
+            &Api::all(client.clone()),
+            &admission(operation, tunnel),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn tunnel_with_an_unused_name_is_allowed() {
+        let (client, server) = ApiServer::start();
+        server.insert(&tunnel("default", "web"));
+        server.insert(&cluster_tunnel("web"));
+
+        let response = review_tunnel(&client, "CREATE", &tunnel("apps", "api")).await;
+        assert!(response.allowed);
+        assert_eq!(response.warnings, None);
+
+        // INFO: The ClusterTunnel web is named cluster-tunnel-web on Cloudflare.
+        let response = review_tunnel(&client, "CREATE", &tunnel("default", "web")).await;
+        assert!(response.allowed);
+    }
+
+    #[tokio::test]
+    async fn tunnel_name_taken_in_another_namespace_is_denied() {
+        let (client, server) = ApiServer::start();
+        server.insert(&tunnel("default", "web"));
+
+        let response = review_tunnel(&client, "CREATE", &tunnel("apps", "web")).await;
+        assert!(!response.allowed);
+        assert!(response.result.message.contains("namespace default"));
+
+        // INFO: Only creates claim a name, the existing one keeps being updatable.
+        let response = review_tunnel(&client, "UPDATE", &tunnel("apps", "web")).await;
+        assert!(response.allowed);
+    }
+
+    #[tokio::test]
+    async fn tunnel_name_taken_by_a_cluster_tunnel_is_denied() {
+        let (client, server) = ApiServer::start();
+        server.insert(&cluster_tunnel("web"));
+
+        let response =
+            review_tunnel(&client, "CREATE", &tunnel("default", "cluster-tunnel-web")).await;
+        assert!(!response.allowed);
+        assert!(response.result.message.contains("ClusterTunnel web"));
+    }
+
+    #[tokio::test]
+    async fn tunnel_is_allowed_with_a_warning_when_listing_fails() {
+        let (client, server) = ApiServer::start();
+        server.insert(&tunnel("default", "web"));
+
+        for plural in ["tunnels", "clustertunnels"] {
+            server.fail_once("GET", plural, StatusCode::INTERNAL_SERVER_ERROR);
+            let response = review_tunnel(&client, "CREATE", &tunnel("apps", "web")).await;
+            assert!(response.allowed);
+            assert!(response.warnings.unwrap()[0].starts_with("not validated"));
+        }
+    }
+
+    #[tokio::test]
+    async fn tunnel_with_an_invalid_spec_is_denied() {
+        let (client, _server) = ApiServer::start();
+
+        let mut invalid = tunnel("default", "web");
+        invalid.spec.metrics_port = Some(0);
+
+        for operation in ["CREATE", "UPDATE"] {
+            let response = review_tunnel(&client, operation, &invalid).await;
+            assert!(!response.allowed);
+        }
+    }
+
+    fn tunnel_ingress(
+        namespace: &str,
+        name: &str,
+        tunnel_ref: Value,
+        hostname: &str,
+    ) -> TunnelIngress {
+        serde_json::from_value(json!({
+            "apiVersion": "cloudflare.ar2ro.io/v1",
+            "kind": "TunnelIngress",
+            "metadata": { "name": name, "namespace": namespace },
+            "spec": {
+                "tunnelRef": tunnel_ref,
+                "rules": [{ "hostname": hostname, "service": "http://web.default:80" }],
+            },
+        }))
+        .unwrap()
+    }
+
+    async fn review_tunnel_ingress(
+        client: &Client,
+        operation: &str,
+        tunnel_ingress: &TunnelIngress,
+    ) -> AdmissionResponse {
+        validate_tunnel_ingress(
+            &Api::all(client.clone()),
+            &admission(operation, tunnel_ingress),
+        )
+        .await
+    }
+
+    #[tokio::test]
+    async fn hostname_claimed_on_the_same_tunnel_is_denied() {
+        let (client, server) = ApiServer::start();
+        server.insert(&tunnel_ingress(
+            "default",
+            "web",
+            json!({ "name": "web" }),
+            "web.example.com",
+        ));
+
+        for operation in ["CREATE", "UPDATE"] {
+            let claim = tunnel_ingress(
+                "default",
+                "api",
+                json!({ "name": "web" }),
+                "web.example.com",
+            );
+            let response = review_tunnel_ingress(&client, operation, &claim).await;
+            assert!(!response.allowed);
+            assert!(response
+                .result
+                .message
+                .contains("TunnelIngress default/web"));
+        }
+
+        // INFO: The reference resolves to the same Tunnel from another namespace.
+        let claim = tunnel_ingress(
+            "apps",
+            "api",
+            json!({ "name": "web", "namespace": "default" }),
+            "web.example.com",
+        );
+        let response = review_tunnel_ingress(&client, "CREATE", &claim).await;
+        assert!(!response.allowed);
+    }
+
+    #[tokio::test]
+    async fn hostname_on_another_tunnel_or_the_same_object_is_allowed() {
+        let (client, server) = ApiServer::start();
+        server.insert(&tunnel_ingress(
+            "default",
+            "web",
+            json!({ "name": "web" }),
+            "web.example.com",
+        ));
+
+        for tunnel_ref in [
+            json!({ "name": "api" }),
+            json!({ "name": "web", "kind": "ClusterTunnel" }),
+        ] {
+            let claim = tunnel_ingress("default", "api", tunnel_ref, "web.example.com");
+            let response = review_tunnel_ingress(&client, "CREATE", &claim).await;
+            assert!(response.allowed);
+        }
+
+        let update = tunnel_ingress(
+            "default",
+            "web",
+            json!({ "name": "web" }),
+            "web.example.com",
+        );
+        let response = review_tunnel_ingress(&client, "UPDATE", &update).await;
+        assert!(response.allowed);
+    }
+
+    #[tokio::test]
+    async fn invalid_tunnel_ingress_is_denied() {
+        let (client, _server) = ApiServer::start();
+
+        let mut invalid = tunnel_ingress(
+            "default",
+            "web",
+            json!({ "name": "web" }),
+            "web..example.com",
+        );
+        invalid.spec.rules[0].path = Some("(".to_owned());
+
+        let response = review_tunnel_ingress(&client, "CREATE", &invalid).await;
+        assert!(!response.allowed);
+        assert!(response.result.message.contains("isn't a DNS name"));
+        assert!(response.result.message.contains("isn't a valid regex"));
+    }
+
+    #[tokio::test]
+    async fn tunnel_ingress_is_allowed_with_a_warning_when_listing_fails() {
+        let (client, server) = ApiServer::start();
+        server.insert(&tunnel_ingress(
+            "default",
+            "web",
+            json!({ "name": "web" }),
+            "web.example.com",
+        ));
+        server.fail_once("GET", "tunnelingresses", StatusCode::INTERNAL_SERVER_ERROR);
+
+        let claim = tunnel_ingress(
+            "default",
+            "api",
+            json!({ "name": "web" }),
+            "web.example.com",
+        );
+        let response = review_tunnel_ingress(&client, "CREATE", &claim).await;
+        assert!(response.allowed);
+        assert!(response.warnings.unwrap()[0].starts_with("not validated"));
+    }
+}